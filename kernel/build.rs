@@ -1,6 +1,39 @@
+use std::process::Command;
+use std::time::{SystemTime, UNIX_EPOCH};
+
 fn main() {
     // Tell cargo to pass the linker script to the linker..
     println!("cargo:rustc-link-arg=-Tlinker.ld");
     // ..and to re-run if it changes.
     println!("cargo:rerun-if-changed=linker.ld");
+
+    // Build information reported by the version module
+    println!("cargo:rustc-env=TOAST_BUILD_TARGET={}", std::env::var("TARGET").unwrap());
+
+    if let Some(git_hash) = git(&["rev-parse", "--short", "HEAD"]) {
+        println!("cargo:rustc-env=TOAST_GIT_HASH={}", git_hash);
+    }
+
+    // The hash changes when HEAD moves to another branch or when the branch it points to moves
+    if let Some(git_dir) = git(&["rev-parse", "--git-dir"]) {
+        println!("cargo:rerun-if-changed={}/HEAD", git_dir);
+
+        if let Some(head_ref) = git(&["symbolic-ref", "-q", "HEAD"]) {
+            println!("cargo:rerun-if-changed={}/{}", git_dir, head_ref);
+        }
+    }
+
+    if let Ok(duration) = SystemTime::now().duration_since(UNIX_EPOCH) {
+        println!("cargo:rustc-env=TOAST_BUILD_TIMESTAMP={}", duration.as_secs());
+    }
+}
+
+/// Runs git with the given arguments and returns its trimmed output, if it succeeded
+fn git(args: &[&str]) -> Option<String> {
+    let output = Command::new("git").args(args).output().ok()?;
+    if !output.status.success() {
+        return None;
+    }
+
+    Some(String::from_utf8_lossy(&output.stdout).trim().to_string())
 }
@@ -0,0 +1,161 @@
+use crate::arch::x86_64::port_manager::{io_wait, Port};
+use crate::arch::x86_64::port_manager::ReadWriteStatus::ReadWrite;
+use crate::drivers::ps2::{read_controller_output, set_first_port_enabled, write_controller_output};
+use crate::HHDM_OFFSET;
+
+/// Physical address compared with `HIGH_TEST_ADDRESS`, the two alias when the A20 line is disabled
+const LOW_TEST_ADDRESS: usize = 0x0;
+const HIGH_TEST_ADDRESS: usize = 0x100000;
+const LOW_TEST_PATTERN: u32 = 0x0000_A20A;
+const HIGH_TEST_PATTERN: u32 = 0xA20A_0000;
+
+/// System control port A, holding the fast A20 gate
+const FAST_A20_PORT: u16 = 0x92;
+const FAST_A20_ENABLE: u8 = 1 << 1;
+/// Writing this bit to system control port A resets the machine
+const FAST_A20_RESET: u8 = 1 << 0;
+/// A20 gate of the keyboard controller output port
+const CONTROLLER_OUTPUT_A20: u8 = 1 << 1;
+
+/// Physical memory the A20 check writes to
+trait A20Memory {
+    fn read(&self, address: usize) -> u32;
+
+    fn write(&mut self, address: usize, value: u32);
+}
+
+/// Physical memory accessed through the higher half direct map
+struct PhysicalMemory;
+
+impl A20Memory for PhysicalMemory {
+    fn read(&self, address: usize) -> u32 {
+        unsafe { ((address + *HHDM_OFFSET) as *const u32).read_volatile() }
+    }
+
+    fn write(&mut self, address: usize, value: u32) {
+        unsafe { ((address + *HHDM_OFFSET) as *mut u32).write_volatile(value) }
+    }
+}
+
+/// Returns whether the A20 line is enabled, meaning that addresses past 1MiB do not wrap around
+pub fn is_enabled() -> bool {
+    is_enabled_in(&mut PhysicalMemory)
+}
+
+/// Enables the A20 line if it is disabled, first through the fast A20 gate and then through the
+/// keyboard controller. Limine always enables it, so this only matters on other boot paths.
+pub fn enable() -> Result<(), &'static str> {
+    if is_enabled() {
+        return Ok(());
+    }
+
+    let mut port = Port::<u8>::new(FAST_A20_PORT, ReadWrite);
+    let control = port.read().unwrap();
+    port.write(fast_a20_value(control)).unwrap();
+    io_wait();
+
+    if is_enabled() {
+        return Ok(());
+    }
+
+    set_first_port_enabled(false);
+    let output = read_controller_output();
+    write_controller_output(output | CONTROLLER_OUTPUT_A20);
+    set_first_port_enabled(true);
+    io_wait();
+
+    match is_enabled() {
+        true => Ok(()),
+        false => Err("a20: could not enable the A20 line"),
+    }
+}
+
+/// Writes different values below and above 1MiB and checks that they did not land in the same
+/// place. The original contents are restored afterwards.
+fn is_enabled_in(memory: &mut impl A20Memory) -> bool {
+    let saved_low = memory.read(LOW_TEST_ADDRESS);
+    let saved_high = memory.read(HIGH_TEST_ADDRESS);
+
+    memory.write(LOW_TEST_ADDRESS, LOW_TEST_PATTERN);
+    memory.write(HIGH_TEST_ADDRESS, HIGH_TEST_PATTERN);
+    let is_enabled = memory.read(LOW_TEST_ADDRESS) != memory.read(HIGH_TEST_ADDRESS);
+
+    // When the addresses alias, the low one is restored last so it keeps its original value
+    memory.write(HIGH_TEST_ADDRESS, saved_high);
+    memory.write(LOW_TEST_ADDRESS, saved_low);
+
+    is_enabled
+}
+
+/// Value of system control port A enabling the A20 gate without resetting the machine
+fn fast_a20_value(control: u8) -> u8 {
+    (control | FAST_A20_ENABLE) & !FAST_A20_RESET
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::collections::BTreeMap;
+    use crate::arch::x86_64::a20::{A20Memory, fast_a20_value, HIGH_TEST_ADDRESS, is_enabled_in, LOW_TEST_ADDRESS};
+
+    /// Memory dropping bit 20 of the addresses when the A20 line is disabled
+    struct SimulatedMemory {
+        a20_enabled: bool,
+        words: BTreeMap<usize, u32>,
+    }
+
+    impl SimulatedMemory {
+        fn new(a20_enabled: bool) -> Self {
+            Self { a20_enabled, words: BTreeMap::from([(LOW_TEST_ADDRESS, 0x1234), (HIGH_TEST_ADDRESS, 0x5678)]) }
+        }
+
+        fn physical_address(&self, address: usize) -> usize {
+            if self.a20_enabled { address } else { address & !(1 << 20) }
+        }
+    }
+
+    impl A20Memory for SimulatedMemory {
+        fn read(&self, address: usize) -> u32 {
+            self.words.get(&self.physical_address(address)).copied().unwrap_or(0)
+        }
+
+        fn write(&mut self, address: usize, value: u32) {
+            self.words.insert(self.physical_address(address), value);
+        }
+    }
+
+    #[test_case]
+    fn distinct_addresses_mean_a20_is_enabled() {
+        // GIVEN
+        let mut memory = SimulatedMemory::new(true);
+
+        // WHEN
+        let is_enabled = is_enabled_in(&mut memory);
+
+        // THEN
+        assert!(is_enabled);
+        assert_eq!(memory.read(LOW_TEST_ADDRESS), 0x1234);
+        assert_eq!(memory.read(HIGH_TEST_ADDRESS), 0x5678);
+    }
+
+    #[test_case]
+    fn wrapped_around_addresses_mean_a20_is_disabled() {
+        // GIVEN
+        let mut memory = SimulatedMemory::new(false);
+
+        // WHEN
+        let is_enabled = is_enabled_in(&mut memory);
+
+        // THEN
+        assert!(!is_enabled);
+        assert_eq!(memory.read(LOW_TEST_ADDRESS), 0x1234);
+    }
+
+    #[test_case]
+    fn fast_a20_sets_gate_without_reset() {
+        // WHEN
+        let values = [0x00, 0x01, 0x03, 0xF0].map(fast_a20_value);
+
+        // THEN
+        assert_eq!(values, [0x02, 0x02, 0x02, 0xF2]);
+    }
+}
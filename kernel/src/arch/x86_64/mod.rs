@@ -1,2 +1,4 @@
+pub mod a20;
 pub mod port_manager;
-pub mod registers;
\ No newline at end of file
+pub mod registers;
+pub mod power;
\ No newline at end of file
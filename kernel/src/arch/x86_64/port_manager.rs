@@ -1,5 +1,6 @@
 use core::arch::asm;
 use core::marker::PhantomData;
+use core::mem::size_of;
 
 pub enum ReadWriteStatus {
     ReadOnly,
@@ -15,6 +16,15 @@ pub struct Port<T: InOut> {
 
 impl<T: InOut> Port<T> {
     pub const fn new(port: u16, read_write_status: ReadWriteStatus) -> Port<T> {
+        Self::with_width(port, read_write_status, size_of::<T>())
+    }
+
+    /// Port whose device only supports accesses of `width` bytes. Declaring it through a type of
+    /// another width fails to compile when the port is built in a constant or a static, instead of
+    /// sending accesses the device could hang on.
+    pub const fn with_width(port: u16, read_write_status: ReadWriteStatus, width: usize) -> Port<T> {
+        assert!(size_of::<T>() == width, "port: access width does not match the width of the port");
+
         Port {
             read_write_status,
             port,
@@ -94,4 +104,28 @@ unsafe fn outl(value: u32, port: u16) {
 
 pub fn io_wait() {
     unsafe { outb(0, 0x80); }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::arch::x86_64::port_manager::Port;
+    use crate::arch::x86_64::port_manager::ReadWriteStatus::{ReadOnly, WriteOnly};
+
+    /// Built at compile time, a width other than 4 would fail the build
+    const CONFIG_DATA_PORT: Port<u32> = Port::with_width(0xCFC, ReadOnly, 4);
+
+    #[test_case]
+    fn port_of_matching_width_keeps_its_access_rights() {
+        // GIVEN
+        let mut read_only = CONFIG_DATA_PORT;
+        let mut write_only = Port::<u8>::with_width(0x60, WriteOnly, 1);
+
+        // WHEN
+        let write = read_only.write(0);
+        let read = write_only.read();
+
+        // THEN
+        assert_eq!(write, Err("Tried to write to a read only port..."));
+        assert_eq!(read.err(), Some("Tried to read from a write only port..."));
+    }
+}
@@ -0,0 +1,33 @@
+use crate::arch::x86_64::port_manager::Port;
+use crate::arch::x86_64::port_manager::ReadWriteStatus::WriteOnly;
+use crate::drivers::ps2::COMMAND_REGISTER;
+use crate::fs::ext2::unmount_mounted_filesystem;
+use crate::utils::hcf;
+
+/// Port of the ACPI power management control block emulated by QEMU
+const QEMU_POWER_CONTROL_PORT_ADDRESS: u16 = 0x604;
+/// Value requesting a soft power off (S5) from QEMU's power management block
+const QEMU_SHUTDOWN_VALUE: u16 = 0x2000;
+/// PS/2 controller command pulsing the CPU reset line
+const PULSE_RESET_LINE: u8 = 0xFE;
+
+/// Powers the machine off. The ACPI tables are not parsed yet so only QEMU is supported,
+/// on other machines this halts instead.
+pub fn shutdown() -> ! {
+    info!("power: shutting down...");
+    unmount_mounted_filesystem();
+
+    Port::<u16>::new(QEMU_POWER_CONTROL_PORT_ADDRESS, WriteOnly).write(QEMU_SHUTDOWN_VALUE).unwrap();
+
+    hcf();
+}
+
+/// Restarts the machine by pulsing the reset line through the PS/2 controller
+pub fn reboot() -> ! {
+    info!("power: rebooting...");
+    unmount_mounted_filesystem();
+
+    COMMAND_REGISTER.lock().write(PULSE_RESET_LINE).unwrap();
+
+    hcf();
+}
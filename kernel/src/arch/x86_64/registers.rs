@@ -61,13 +61,93 @@ pub fn cr4() -> usize {
 }
 
 pub fn efer() -> usize {
-    let cr4: usize;
-    unsafe {
-        asm! {
-        "mov {}, cr4",
-        out(reg) cr4,
-        }
+    unsafe { read_msr(IA32_EFER) as usize }
+}
+
+pub const IA32_TSC: u32 = 0x10;
+pub const IA32_APIC_BASE: u32 = 0x1B;
+pub const IA32_PAT: u32 = 0x277;
+pub const IA32_EFER: u32 = 0xC000_0080;
+pub const IA32_STAR: u32 = 0xC000_0081;
+pub const IA32_LSTAR: u32 = 0xC000_0082;
+pub const IA32_FMASK: u32 = 0xC000_0084;
+pub const IA32_FS_BASE: u32 = 0xC000_0100;
+pub const IA32_GS_BASE: u32 = 0xC000_0101;
+pub const IA32_KERNEL_GS_BASE: u32 = 0xC000_0102;
+
+/// Reads a model specific register.
+///
+/// # Safety
+/// Reading an MSR the CPU does not implement raises a general protection fault
+pub unsafe fn read_msr(msr: u32) -> u64 {
+    let (high, low): (u32, u32);
+    asm! {
+        "rdmsr",
+        in("ecx") msr,
+        out("edx") high,
+        out("eax") low,
+        options(nomem, nostack, preserves_flags),
     }
 
-    cr4
-}
\ No newline at end of file
+    combine_msr_value(high, low)
+}
+
+/// Writes a model specific register.
+///
+/// # Safety
+/// Writing an unimplemented MSR or a reserved bit raises a general protection fault, and most MSRs
+/// change the behaviour of the CPU
+pub unsafe fn write_msr(msr: u32, value: u64) {
+    let (high, low) = split_msr_value(value);
+    asm! {
+        "wrmsr",
+        in("ecx") msr,
+        in("edx") high,
+        in("eax") low,
+        options(nostack, preserves_flags),
+    }
+}
+
+/// Splits a value into the EDX:EAX halves expected by `wrmsr`
+fn split_msr_value(value: u64) -> (u32, u32) {
+    ((value >> 32) as u32, value as u32)
+}
+
+/// Joins the EDX:EAX halves returned by `rdmsr`
+fn combine_msr_value(high: u32, low: u32) -> u64 {
+    (high as u64) << 32 | low as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::arch::x86_64::registers::{combine_msr_value, IA32_KERNEL_GS_BASE, read_msr, split_msr_value, write_msr};
+
+    #[test_case]
+    fn msr_value_is_split_into_edx_and_eax() {
+        // WHEN
+        let (high, low) = split_msr_value(0x1234_5678_9ABC_DEF0);
+
+        // THEN
+        assert_eq!(high, 0x1234_5678);
+        assert_eq!(low, 0x9ABC_DEF0);
+        assert_eq!(combine_msr_value(high, low), 0x1234_5678_9ABC_DEF0);
+    }
+
+    #[test_case]
+    fn msr_write_then_read_round_trips_both_halves() {
+        // GIVEN
+        let original = unsafe { read_msr(IA32_KERNEL_GS_BASE) };
+        let value = 0xFFFF_8000_1234_5678;
+
+        // WHEN
+        let read_back = unsafe {
+            write_msr(IA32_KERNEL_GS_BASE, value);
+            let read_back = read_msr(IA32_KERNEL_GS_BASE);
+            write_msr(IA32_KERNEL_GS_BASE, original);
+            read_back
+        };
+
+        // THEN
+        assert_eq!(read_back, value);
+    }
+}
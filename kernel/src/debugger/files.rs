@@ -0,0 +1,240 @@
+use alloc::format;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::fmt::Write;
+use crate::fs::{Vfs, VfsNodeRef};
+use crate::task::executor::with_task_context;
+use crate::task::TaskContext;
+
+/// Largest amount of bytes `cat` prints, the heap could not hold a larger copy of a device like a
+/// framebuffer
+const MAX_CAT_SIZE: usize = 64 * 1024;
+
+/// Changes the working directory of the shell task, the path may be relative to it
+pub fn change_directory(args: &[&str]) {
+    let path = args.first().copied().unwrap_or("/");
+
+    match with_task_context(|context| change_context_directory(context, path)) {
+        Some(Ok(())) => (),
+        Some(Err(err)) => println!("{}", err),
+        None => println!("cd: no working directory outside of a task"),
+    }
+    print!(">");
+}
+
+pub fn print_working_directory() {
+    match with_task_context(|context| Vfs::get_absolute_path(context.cwd.clone())) {
+        Some(path) => println!("{}", path),
+        None => println!("pwd: no working directory outside of a task"),
+    }
+    print!(">");
+}
+
+/// Lists the given directory, or the working directory without argument
+pub fn list_directory(args: &[&str]) {
+    let path = args.first().copied().unwrap_or(".");
+
+    match with_task_context(|context| directory_listing(context.cwd.clone(), path)) {
+        Some(Ok(lines)) => lines.iter().for_each(|line| println!("{}", line)),
+        Some(Err(err)) => println!("{}", err),
+        None => println!("ls: no working directory outside of a task"),
+    }
+    print!(">");
+}
+
+/// Prints the contents of a file, bytes that are not printable are escaped
+pub fn print_file(args: &[&str]) {
+    let Some(path) = args.first() else {
+        println!("usage: cat <path>");
+        print!(">");
+        return;
+    };
+
+    match with_task_context(|context| read_file(context.cwd.clone(), path)) {
+        Some(Ok((contents, is_truncated))) => {
+            println!("{}", escape_non_printable(&contents));
+            if is_truncated {
+                println!("cat: only the first {} bytes were printed", MAX_CAT_SIZE);
+            }
+        },
+        Some(Err(err)) => println!("{}", err),
+        None => println!("cat: no working directory outside of a task"),
+    }
+    print!(">");
+}
+
+/// Reads at most `MAX_CAT_SIZE` bytes of the file at the path, and whether the file was longer
+fn read_file(cwd: VfsNodeRef, path: &str) -> Result<(Vec<u8>, bool), &'static str> {
+    let file = Vfs::resolve_path(cwd, path).ok_or("cat: no such file")?;
+    let (is_directory, size) = {
+        let file = file.lock();
+        (file.is_directory(), file.size())
+    };
+    if is_directory {
+        return Err("cat: is a directory");
+    }
+
+    // Device nodes like /dev/fb0 are read through their driver
+    let mut contents = vec![0u8; size.min(MAX_CAT_SIZE)];
+    Vfs::read_node(&file, contents.as_mut_ptr(), contents.len(), 0)?;
+
+    Ok((contents, size > MAX_CAT_SIZE))
+}
+
+/// Keeps printable ASCII, newlines and tabs, and writes every other byte as \xNN
+fn escape_non_printable(bytes: &[u8]) -> String {
+    let mut escaped = String::with_capacity(bytes.len());
+    for byte in bytes {
+        match byte {
+            b'\n' | b'\t' | 0x20..=0x7E => escaped.push(*byte as char),
+            _ => write!(escaped, "\\x{:02X}", byte).unwrap(),
+        }
+    }
+
+    escaped
+}
+
+/// Resolves a path against the working directory of the context and makes it the new working
+/// directory if it is a directory
+fn change_context_directory(context: &mut TaskContext, path: &str) -> Result<(), &'static str> {
+    let directory = Vfs::resolve_path(context.cwd.clone(), path).ok_or("cd: no such directory")?;
+    if !directory.lock().is_directory() {
+        return Err("cd: not a directory");
+    }
+
+    context.cwd = directory;
+    Ok(())
+}
+
+/// One line per entry of the directory at the path, with the size of files and a trailing slash
+/// after directory names
+fn directory_listing(cwd: VfsNodeRef, path: &str) -> Result<Vec<String>, &'static str> {
+    let directory = Vfs::resolve_path(cwd, path).ok_or("ls: no such file or directory")?;
+    let mut directory = directory.lock();
+    if !directory.is_directory() {
+        return Ok(Vec::from([format!("{:>8}  {}", directory.size(), directory.name())]));
+    }
+
+    Ok(directory.children().iter().map(|child| {
+        let child = child.lock();
+        match child.is_directory() {
+            true => format!("{:>8}  {}/", "-", child.name()),
+            false => format!("{:>8}  {}", child.size(), child.name()),
+        }
+    }).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::format;
+    use alloc::string::String;
+    use alloc::vec;
+    use alloc::vec::Vec;
+    use crate::debugger::files::{change_context_directory, directory_listing, escape_non_printable, read_file};
+    use crate::fs::Vfs;
+    use crate::task::TaskContext;
+
+    /// Builds /<name> holding a docs directory and a notes.txt file
+    fn mock_tree(name: &str) -> String {
+        let root = format!("/{}", name);
+        Vfs::create_directory(&root).unwrap();
+        Vfs::create_directory(&format!("{}/docs", root)).unwrap();
+        Vfs::create(&format!("{}/notes.txt", root)).unwrap();
+
+        root
+    }
+
+    #[test_case]
+    fn ls_lists_files_and_directories() {
+        // GIVEN
+        let root = mock_tree("ls_test");
+
+        // WHEN
+        let absolute = directory_listing(Vfs::root_directory().clone(), &root);
+        let relative = directory_listing(Vfs::find_from_absolute_path(&root).unwrap(), ".");
+
+        // THEN
+        let expected = vec![String::from("       -  docs/"), String::from("       0  notes.txt")];
+        assert_eq!(absolute, Ok(expected.clone()));
+        assert_eq!(relative, Ok(expected));
+        assert_eq!(directory_listing(Vfs::root_directory().clone(), "/missing"), Err("ls: no such file or directory"));
+
+        Vfs::remove(&root).unwrap();
+    }
+
+    #[test_case]
+    fn cd_into_subdirectory_updates_pwd() {
+        // GIVEN
+        let root = mock_tree("cd_test");
+        let mut context = TaskContext::new(Vfs::find_from_absolute_path(&root).unwrap());
+
+        // WHEN
+        let result = change_context_directory(&mut context, "docs");
+
+        // THEN
+        assert_eq!(result, Ok(()));
+        assert_eq!(Vfs::get_absolute_path(context.cwd.clone()), "/cd_test/docs");
+
+        Vfs::remove(&root).unwrap();
+    }
+
+    #[test_case]
+    fn cd_rejects_files_and_missing_paths() {
+        // GIVEN
+        let root = mock_tree("cd_reject_test");
+        let mut context = TaskContext::new(Vfs::find_from_absolute_path(&root).unwrap());
+
+        // WHEN
+        let file = change_context_directory(&mut context, "notes.txt");
+        let missing = change_context_directory(&mut context, "missing");
+
+        // THEN
+        assert_eq!(file, Err("cd: not a directory"));
+        assert_eq!(missing, Err("cd: no such directory"));
+        assert_eq!(Vfs::get_absolute_path(context.cwd.clone()), "/cd_reject_test");
+
+        Vfs::remove(&root).unwrap();
+    }
+
+    #[test_case]
+    fn cat_reads_file_relative_to_cwd() {
+        // GIVEN
+        let root = mock_tree("cat_test");
+        Vfs::create_file(&format!("{}/hello.txt", root), Vec::from(*b"hello\nworld")).unwrap();
+
+        // WHEN
+        let result = read_file(Vfs::find_from_absolute_path(&root).unwrap(), "hello.txt");
+
+        // THEN
+        assert_eq!(result, Ok((Vec::from(*b"hello\nworld"), false)));
+
+        Vfs::remove(&root).unwrap();
+    }
+
+    #[test_case]
+    fn cat_rejects_directories_and_missing_files() {
+        // GIVEN
+        let root = mock_tree("cat_reject_test");
+        let cwd = Vfs::find_from_absolute_path(&root).unwrap();
+
+        // WHEN
+        let directory = read_file(cwd.clone(), "docs");
+        let missing = read_file(cwd, "missing.txt");
+
+        // THEN
+        assert_eq!(directory, Err("cat: is a directory"));
+        assert_eq!(missing, Err("cat: no such file"));
+
+        Vfs::remove(&root).unwrap();
+    }
+
+    #[test_case]
+    fn non_printable_bytes_are_escaped() {
+        // WHEN
+        let escaped = escape_non_printable(b"ok\tline\n\x00\x7F\xFFend");
+
+        // THEN
+        assert_eq!(escaped, "ok\tline\n\\x00\\x7F\\xFFend");
+    }
+}
@@ -0,0 +1,81 @@
+use core::fmt;
+use core::fmt::{Display, Formatter};
+
+const BYTES_PER_LINE: usize = 16;
+
+/// Formats a buffer in the canonical hex+ASCII layout, 16 bytes per line, each line starting with
+/// the address of its first byte
+pub struct Hexdump<'a> {
+    bytes: &'a [u8],
+    base_address: usize,
+}
+
+impl<'a> Hexdump<'a> {
+    pub fn new(bytes: &'a [u8], base_address: usize) -> Self {
+        Self { bytes, base_address }
+    }
+}
+
+impl Display for Hexdump<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        for (line_number, line) in self.bytes.chunks(BYTES_PER_LINE).enumerate() {
+            write!(f, "{:08X}  ", self.base_address + line_number * BYTES_PER_LINE)?;
+
+            for index in 0..BYTES_PER_LINE {
+                match line.get(index) {
+                    Some(byte) => write!(f, "{:02X} ", byte)?,
+                    None => write!(f, "   ")?,
+                }
+
+                // Split the line in two groups of 8 bytes
+                if index == BYTES_PER_LINE / 2 - 1 {
+                    write!(f, " ")?;
+                }
+            }
+
+            write!(f, " |")?;
+            for byte in line {
+                let character = if byte.is_ascii_graphic() || *byte == b' ' { *byte as char } else { '.' };
+                write!(f, "{}", character)?;
+            }
+            writeln!(f, "|")?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::format;
+    use alloc::vec::Vec;
+    use crate::debugger::hexdump::Hexdump;
+
+    #[test_case]
+    fn hexdump_formats_offset_hex_and_ascii_columns() {
+        // GIVEN
+        let bytes = b"Hello, hexdump!\n\x00\x01\xFF";
+
+        // WHEN
+        let dump = format!("{}", Hexdump::new(bytes, 0));
+
+        // THEN
+        assert_eq!(dump, "00000000  48 65 6C 6C 6F 2C 20 68  65 78 64 75 6D 70 21 0A  |Hello, hexdump!.|\n\
+                          00000010  00 01 FF                                          |...|\n");
+    }
+
+    #[test_case]
+    fn hexdump_offsets_start_at_base_address() {
+        // GIVEN
+        let bytes = [0xAB; 20];
+
+        // WHEN
+        let dump = format!("{}", Hexdump::new(&bytes, 0xFFFF8000));
+
+        // THEN
+        let lines: Vec<&str> = dump.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert!(lines[0].starts_with("FFFF8000  AB AB"));
+        assert!(lines[1].starts_with("FFFF8010  AB AB AB AB "));
+    }
+}
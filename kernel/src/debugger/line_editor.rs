@@ -0,0 +1,542 @@
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+use crate::utils::ring_buffer::{OverflowPolicy, RingBuffer};
+
+/// Character erasing the previous one on the console and serial terminals
+const BACKSPACE: char = '\x08';
+/// Maximum number of committed lines kept in the history, the oldest ones are dropped first
+const HISTORY_CAPACITY: usize = 32;
+/// Drawn in place of every character of the line when echo is masked
+const MASK: char = '*';
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum LineEditorKey {
+    Character(char),
+    Backspace,
+    Left,
+    Right,
+    Up,
+    Down,
+    Tab,
+    Enter,
+}
+
+/// How the characters typed are displayed
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Echo {
+    On,
+    /// Nothing is drawn, for input such as passwords
+    Off,
+    /// Every character is drawn as a mask
+    Masked,
+}
+
+/// Result of completing the line being edited
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum Completion {
+    NoMatch,
+    /// The whole completed line
+    Complete(String),
+    /// Possible completions of the last word when there are several
+    Candidates(Vec<String>),
+}
+
+/// Line buffer with a movable cursor used to read shell commands. Edits are echoed to the writer
+/// passed along with each key by erasing and redrawing the part of the line following the edit.
+#[derive(Debug, Clone)]
+pub struct LineEditor {
+    line: Vec<char>,
+    cursor: usize,
+    /// Number of characters of the line currently displayed
+    rendered_length: usize,
+    echo: Echo,
+
+    /// Previously committed lines, from oldest to newest
+    history: RingBuffer<String, HISTORY_CAPACITY>,
+    /// Index in `history` of the line being displayed, `None` while editing a new line
+    history_index: Option<usize>,
+    /// Line that was being typed before navigating the history
+    draft: Vec<char>,
+
+    /// Redrawn before the line after listing completion candidates
+    prompt: &'static str,
+    completer: Option<fn(&str) -> Completion>,
+}
+
+impl LineEditor {
+    pub fn new() -> Self {
+        Self {
+            line: Vec::new(),
+            cursor: 0,
+            rendered_length: 0,
+            echo: Echo::On,
+
+            history: RingBuffer::new(OverflowPolicy::Overwrite),
+            history_index: None,
+            draft: Vec::new(),
+
+            prompt: "",
+            completer: None,
+        }
+    }
+
+    /// Completes the line with the given function when Tab is pressed
+    pub fn with_completion(mut self, prompt: &'static str, completer: fn(&str) -> Completion) -> Self {
+        self.prompt = prompt;
+        self.completer = Some(completer);
+        self
+    }
+
+    /// Sets how the characters typed from now on are displayed. Lines typed without echo are
+    /// neither completed nor kept in the history.
+    pub fn set_echo(&mut self, echo: Echo) {
+        self.echo = echo;
+    }
+
+    /// Applies the key to the line and renders the result. Returns the completed line when Enter is pressed.
+    pub fn handle_key<W: fmt::Write>(&mut self, key: LineEditorKey, writer: &mut W) -> Option<String> {
+        match key {
+            LineEditorKey::Character(character) => {
+                self.line.insert(self.cursor, character);
+                self.cursor += 1;
+                self.render_from(self.cursor - 1, writer);
+            },
+            LineEditorKey::Backspace => if self.cursor > 0 {
+                self.cursor -= 1;
+                self.line.remove(self.cursor);
+                self.render_from(self.cursor, writer);
+            },
+            LineEditorKey::Left => self.cursor = self.cursor.saturating_sub(1),
+            LineEditorKey::Right => self.cursor = (self.cursor + 1).min(self.line.len()),
+            LineEditorKey::Up => self.show_previous_entry(writer),
+            LineEditorKey::Down => self.show_next_entry(writer),
+            LineEditorKey::Tab => self.complete(writer),
+            LineEditorKey::Enter => {
+                let _ = writer.write_char('\n');
+
+                let line: String = self.line.drain(..).collect();
+                self.cursor = 0;
+                self.rendered_length = 0;
+                self.history_index = None;
+                self.draft.clear();
+                self.add_to_history(&line);
+
+                return Some(line);
+            },
+        }
+
+        None
+    }
+
+    pub fn line(&self) -> String {
+        self.line.iter().collect()
+    }
+
+    pub fn cursor(&self) -> usize {
+        self.cursor
+    }
+
+    /// Replaces the line with the history entry preceding the one displayed, or with the most
+    /// recent one when editing a new line
+    fn show_previous_entry<W: fmt::Write>(&mut self, writer: &mut W) {
+        let previous_index = match self.history_index {
+            None if self.history.is_empty() => return,
+            None => {
+                self.draft = self.line.clone();
+                self.history.len() - 1
+            },
+            Some(0) => return,
+            Some(index) => index - 1,
+        };
+
+        self.history_index = Some(previous_index);
+        let entry = self.history[previous_index].chars().collect();
+        self.replace_line(entry, writer);
+    }
+
+    /// Replaces the line with the history entry following the one displayed, or with the line
+    /// being typed before navigating the history when going past the most recent entry
+    fn show_next_entry<W: fmt::Write>(&mut self, writer: &mut W) {
+        let Some(index) = self.history_index else {
+            return;
+        };
+
+        if index + 1 < self.history.len() {
+            self.history_index = Some(index + 1);
+            let entry = self.history[index + 1].chars().collect();
+            self.replace_line(entry, writer);
+        }
+        else {
+            self.history_index = None;
+            let draft = core::mem::take(&mut self.draft);
+            self.replace_line(draft, writer);
+        }
+    }
+
+    /// Completes the line when the cursor is at its end. Candidates are listed on their own line
+    /// when the completion is ambiguous.
+    fn complete<W: fmt::Write>(&mut self, writer: &mut W) {
+        let Some(completer) = self.completer else {
+            return;
+        };
+        if self.cursor != self.line.len() || self.echo != Echo::On {
+            return;
+        }
+
+        match completer(&self.line()) {
+            Completion::NoMatch => (),
+            Completion::Complete(line) => self.replace_line(line.chars().collect(), writer),
+            Completion::Candidates(candidates) => {
+                let _ = writeln!(writer);
+                for candidate in candidates {
+                    let _ = write!(writer, "{}  ", candidate);
+                }
+                let _ = write!(writer, "\n{}", self.prompt);
+
+                self.rendered_length = 0;
+                self.render_from(0, writer);
+            },
+        }
+    }
+
+    /// Stores a committed line, skipping empty lines and repetitions of the previous line
+    fn add_to_history(&mut self, line: &str) {
+        if line.is_empty() || self.echo != Echo::On || self.history.last().is_some_and(|last| last == line) {
+            return;
+        }
+
+        self.history.push(String::from(line));
+    }
+
+    fn replace_line<W: fmt::Write>(&mut self, line: Vec<char>, writer: &mut W) {
+        self.line = line;
+        self.cursor = self.line.len();
+        self.render_from(0, writer);
+    }
+
+    /// Erases the displayed line starting at `position` and draws the current line from there
+    fn render_from<W: fmt::Write>(&mut self, position: usize, writer: &mut W) {
+        for _ in position..self.rendered_length {
+            let _ = writer.write_char(BACKSPACE);
+        }
+
+        if self.echo == Echo::Off {
+            self.rendered_length = position.min(self.rendered_length);
+            return;
+        }
+
+        for character in &self.line[position..] {
+            let _ = writer.write_char(if self.echo == Echo::Masked { MASK } else { *character });
+        }
+
+        self.rendered_length = self.line.len();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::format;
+    use alloc::string::String;
+    use alloc::vec;
+    use crate::debugger::line_editor::{Completion, Echo, HISTORY_CAPACITY, LineEditor, LineEditorKey};
+
+    fn type_text(editor: &mut LineEditor, text: &str, output: &mut String) {
+        for character in text.chars() {
+            editor.handle_key(LineEditorKey::Character(character), output);
+        }
+    }
+
+    #[test_case]
+    fn insert_in_middle_of_line() {
+        // GIVEN
+        let mut editor = LineEditor::new();
+        let mut output = String::new();
+        type_text(&mut editor, "meminfo", &mut output);
+        for _ in 0..4 {
+            editor.handle_key(LineEditorKey::Left, &mut output);
+        }
+        output.clear();
+
+        // WHEN
+        editor.handle_key(LineEditorKey::Character('-'), &mut output);
+
+        // THEN
+        assert_eq!(editor.line(), "mem-info");
+        assert_eq!(editor.cursor(), 4);
+        assert_eq!(output, "\x08\x08\x08\x08-info");
+    }
+
+    #[test_case]
+    fn backspace_at_start_of_line_does_nothing() {
+        // GIVEN
+        let mut editor = LineEditor::new();
+        let mut output = String::new();
+        type_text(&mut editor, "ls", &mut output);
+        editor.handle_key(LineEditorKey::Left, &mut output);
+        editor.handle_key(LineEditorKey::Left, &mut output);
+        output.clear();
+
+        // WHEN
+        editor.handle_key(LineEditorKey::Backspace, &mut output);
+
+        // THEN
+        assert_eq!(editor.line(), "ls");
+        assert_eq!(editor.cursor(), 0);
+        assert!(output.is_empty());
+    }
+
+    #[test_case]
+    fn backspace_removes_character_before_cursor() {
+        // GIVEN
+        let mut editor = LineEditor::new();
+        let mut output = String::new();
+        type_text(&mut editor, "cpuinfo", &mut output);
+        editor.handle_key(LineEditorKey::Left, &mut output);
+        output.clear();
+
+        // WHEN
+        editor.handle_key(LineEditorKey::Backspace, &mut output);
+
+        // THEN
+        assert_eq!(editor.line(), "cpuino");
+        assert_eq!(editor.cursor(), 5);
+        assert_eq!(output, "\x08\x08o");
+    }
+
+    #[test_case]
+    fn cursor_movement_stays_within_line() {
+        // GIVEN
+        let mut editor = LineEditor::new();
+        let mut output = String::new();
+        type_text(&mut editor, "abc", &mut output);
+
+        // WHEN
+        editor.handle_key(LineEditorKey::Right, &mut output);
+        let cursor_after_right = editor.cursor();
+        for _ in 0..5 {
+            editor.handle_key(LineEditorKey::Left, &mut output);
+        }
+        let cursor_after_left = editor.cursor();
+
+        // THEN
+        assert_eq!(cursor_after_right, 3);
+        assert_eq!(cursor_after_left, 0);
+    }
+
+    #[test_case]
+    fn enter_returns_line_and_resets_editor() {
+        // GIVEN
+        let mut editor = LineEditor::new();
+        let mut output = String::new();
+        type_text(&mut editor, "meminfo alloc", &mut output);
+
+        // WHEN
+        let line = editor.handle_key(LineEditorKey::Enter, &mut output);
+
+        // THEN
+        assert_eq!(line.as_deref(), Some("meminfo alloc"));
+        assert_eq!(editor.line(), "");
+        assert_eq!(editor.cursor(), 0);
+        assert!(output.ends_with('\n'));
+    }
+
+    fn commit(editor: &mut LineEditor, text: &str, output: &mut String) {
+        type_text(editor, text, output);
+        editor.handle_key(LineEditorKey::Enter, output);
+    }
+
+    #[test_case]
+    fn up_cycles_from_most_recent_entry() {
+        // GIVEN
+        let mut editor = LineEditor::new();
+        let mut output = String::new();
+        commit(&mut editor, "meminfo alloc", &mut output);
+        commit(&mut editor, "cpuinfo regs", &mut output);
+
+        // WHEN
+        editor.handle_key(LineEditorKey::Up, &mut output);
+        let first_up = editor.line();
+        editor.handle_key(LineEditorKey::Up, &mut output);
+        let second_up = editor.line();
+        editor.handle_key(LineEditorKey::Up, &mut output);
+        let third_up = editor.line();
+
+        // THEN
+        assert_eq!(first_up, "cpuinfo regs");
+        assert_eq!(second_up, "meminfo alloc");
+        assert_eq!(third_up, "meminfo alloc");
+        assert_eq!(editor.cursor(), "meminfo alloc".len());
+    }
+
+    #[test_case]
+    fn down_past_newest_restores_typed_line() {
+        // GIVEN
+        let mut editor = LineEditor::new();
+        let mut output = String::new();
+        commit(&mut editor, "meminfo alloc", &mut output);
+        commit(&mut editor, "meminfo map", &mut output);
+        type_text(&mut editor, "cpu", &mut output);
+
+        // WHEN
+        editor.handle_key(LineEditorKey::Up, &mut output);
+        editor.handle_key(LineEditorKey::Up, &mut output);
+        editor.handle_key(LineEditorKey::Down, &mut output);
+        let after_down = editor.line();
+        editor.handle_key(LineEditorKey::Down, &mut output);
+        let after_second_down = editor.line();
+        editor.handle_key(LineEditorKey::Down, &mut output);
+
+        // THEN
+        assert_eq!(after_down, "meminfo map");
+        assert_eq!(after_second_down, "cpu");
+        assert_eq!(editor.line(), "cpu");
+    }
+
+    #[test_case]
+    fn history_skips_empty_lines_and_consecutive_duplicates() {
+        // GIVEN
+        let mut editor = LineEditor::new();
+        let mut output = String::new();
+        commit(&mut editor, "meminfo alloc", &mut output);
+        commit(&mut editor, "cpuinfo regs", &mut output);
+        commit(&mut editor, "cpuinfo regs", &mut output);
+        commit(&mut editor, "", &mut output);
+
+        // WHEN
+        editor.handle_key(LineEditorKey::Up, &mut output);
+        let first_up = editor.line();
+        editor.handle_key(LineEditorKey::Up, &mut output);
+        let second_up = editor.line();
+
+        // THEN
+        assert_eq!(first_up, "cpuinfo regs");
+        assert_eq!(second_up, "meminfo alloc");
+    }
+
+    #[test_case]
+    fn history_drops_oldest_entries_when_full() {
+        // GIVEN
+        let mut editor = LineEditor::new();
+        let mut output = String::new();
+        for index in 0..=HISTORY_CAPACITY {
+            commit(&mut editor, &format!("command {}", index), &mut output);
+        }
+
+        // WHEN
+        for _ in 0..HISTORY_CAPACITY + 5 {
+            editor.handle_key(LineEditorKey::Up, &mut output);
+        }
+
+        // THEN
+        assert_eq!(editor.line(), "command 1");
+    }
+
+    #[test_case]
+    fn recalled_entry_replaces_displayed_line() {
+        // GIVEN
+        let mut editor = LineEditor::new();
+        let mut output = String::new();
+        commit(&mut editor, "ls", &mut output);
+        type_text(&mut editor, "abc", &mut output);
+        output.clear();
+
+        // WHEN
+        editor.handle_key(LineEditorKey::Up, &mut output);
+
+        // THEN
+        assert_eq!(output, "\x08\x08\x08ls");
+    }
+
+    fn complete_fruit(line: &str) -> Completion {
+        match line {
+            "ap" => Completion::Complete(String::from("apple ")),
+            "b" => Completion::Candidates(vec![String::from("banana"), String::from("blueberry")]),
+            _ => Completion::NoMatch,
+        }
+    }
+
+    #[test_case]
+    fn tab_replaces_line_with_completion() {
+        // GIVEN
+        let mut editor = LineEditor::new().with_completion(">", complete_fruit);
+        let mut output = String::new();
+        type_text(&mut editor, "ap", &mut output);
+        output.clear();
+
+        // WHEN
+        editor.handle_key(LineEditorKey::Tab, &mut output);
+
+        // THEN
+        assert_eq!(editor.line(), "apple ");
+        assert_eq!(editor.cursor(), 6);
+        assert_eq!(output, "\x08\x08apple ");
+    }
+
+    #[test_case]
+    fn tab_lists_ambiguous_candidates_and_redraws_line() {
+        // GIVEN
+        let mut editor = LineEditor::new().with_completion(">", complete_fruit);
+        let mut output = String::new();
+        type_text(&mut editor, "b", &mut output);
+        output.clear();
+
+        // WHEN
+        editor.handle_key(LineEditorKey::Tab, &mut output);
+
+        // THEN
+        assert_eq!(editor.line(), "b");
+        assert_eq!(output, "\nbanana  blueberry  \n>b");
+    }
+
+    #[test_case]
+    fn typed_characters_are_not_drawn_with_echo_off() {
+        // GIVEN
+        let mut editor = LineEditor::new();
+        editor.set_echo(Echo::Off);
+        let mut output = String::new();
+
+        // WHEN
+        type_text(&mut editor, "hunter22", &mut output);
+        editor.handle_key(LineEditorKey::Backspace, &mut output);
+        editor.handle_key(LineEditorKey::Left, &mut output);
+        editor.handle_key(LineEditorKey::Character('x'), &mut output);
+
+        // THEN
+        assert_eq!(editor.line(), "hunterx2");
+        assert!(output.is_empty());
+    }
+
+    #[test_case]
+    fn masked_echo_draws_a_mask_for_every_character() {
+        // GIVEN
+        let mut editor = LineEditor::new();
+        editor.set_echo(Echo::Masked);
+        let mut output = String::new();
+        type_text(&mut editor, "abc", &mut output);
+
+        // WHEN
+        editor.handle_key(LineEditorKey::Backspace, &mut output);
+
+        // THEN
+        assert_eq!(editor.line(), "ab");
+        assert_eq!(output, "***\x08");
+    }
+
+    #[test_case]
+    fn line_typed_without_echo_is_not_kept_in_history() {
+        // GIVEN
+        let mut editor = LineEditor::new();
+        let mut output = String::new();
+        commit(&mut editor, "ls", &mut output);
+        editor.set_echo(Echo::Off);
+        commit(&mut editor, "secret", &mut output);
+        editor.set_echo(Echo::On);
+
+        // WHEN
+        editor.handle_key(LineEditorKey::Up, &mut output);
+
+        // THEN
+        assert_eq!(editor.line(), "ls");
+    }
+}
@@ -1,14 +1,67 @@
 use alloc::string::String;
+use alloc::vec;
 use alloc::vec::Vec;
+use core::fmt;
+use core::fmt::{Display, Formatter};
+use core::mem::size_of;
 use limine::memory_map::EntryType;
 use x86_64::instructions::tables::sgdt;
+use crate::arch::x86_64::power::{reboot, shutdown};
 use crate::arch::x86_64::registers::{cr0, cr2, cr3, cr4};
-use crate::graphics::framebuffer_device::Writer;
-use crate::memory::{MemoryManager, PAGE_SIZE};
-use crate::MEMORY_MAP_REQUEST;
+use crate::drivers::fbdev::FB_DEVICES;
+use crate::fs::{Vfs, VfsNode};
+use crate::graphics::framebuffer_device::{Rgb8, Writer};
+use crate::graphics::screenshot;
+use crate::interrupts::{breakpoint_count, local_apic};
+use crate::memory::virtual_memory::paging::entry::EntryFlags;
+use crate::memory::{MemoryManager, PAGE_SIZE, parse_address};
+use crate::memory::physical_memory::memory_map::sanitize_memory_map;
+use crate::debugger::files::{change_directory, list_directory, print_file, print_working_directory};
+use crate::debugger::hexdump::Hexdump;
+use crate::debugger::line_editor::Completion;
+use crate::debugger::trap::{trap, Trap};
+use crate::utils::units::format_bytes;
+use crate::{MEMORY_MAP_REQUEST, version};
+
+pub mod files;
+pub mod hexdump;
+pub mod line_editor;
+pub mod remote;
+pub mod trap;
+
+/// Commands understood by `run_command` along with their subcommands
+pub const COMMANDS: [(&str, &[&str]); 19] = [
+    ("meminfo", &["alloc", "virtual", "physical", "map"]),
+    ("cpuinfo", &["regs"]),
+    ("hexdump", &[]),
+    ("translate", &[]),
+    ("peek", &[]),
+    ("poke", &[]),
+    ("shutdown", &[]),
+    ("reboot", &[]),
+    ("uname", &[]),
+    ("fontscale", &[]),
+    ("bgcolor", &[]),
+    ("screenshot", &[]),
+    ("corrupttest", &[]),
+    ("intstats", &[]),
+    ("trap", &Trap::NAMES),
+    ("cd", &[]),
+    ("pwd", &[]),
+    ("ls", &[]),
+    ("cat", &[]),
+];
+
+/// File written by `screenshot` when no path is given
+const DEFAULT_SCREENSHOT_PATH: &str = "/screenshot.tga";
+
+/// Number of heap allocations performed by `corrupttest`
+const CORRUPT_TEST_ALLOCATIONS: usize = 64;
 
 pub fn run_debug_shell() {
-    Writer::instance().unwrap().lock().clear_screen();
+    if let Some(writer) = Writer::instance() {
+        writer.lock().clear_screen();
+    }
     println!("TOAST DEBUGGING ENVIRONMENT");
     print!(">")
 }
@@ -19,6 +72,23 @@ pub fn run_command(command: &String) {
     match command_parts[0] {
         "meminfo" => { mem_info(&command_parts[1..]); },
         "cpuinfo" => { cpu_info(&command_parts[1..]); },
+        "hexdump" => { hexdump(&command_parts[1..]); },
+        "translate" => { translate(&command_parts[1..]); },
+        "peek" => { peek(&command_parts[1..]); },
+        "poke" => { poke(&command_parts[1..]); },
+        "shutdown" => { shutdown(); },
+        "reboot" => { reboot(); },
+        "uname" => { uname(); },
+        "fontscale" => { font_scale(&command_parts[1..]); },
+        "bgcolor" => { background_color(&command_parts[1..]); },
+        "screenshot" => { screenshot(&command_parts[1..]); },
+        "corrupttest" => { corrupt_test(); },
+        "intstats" => { interrupt_stats(); },
+        "trap" => { trap(&command_parts[1..]); },
+        "cd" => { change_directory(&command_parts[1..]); },
+        "pwd" => { print_working_directory(); },
+        "ls" => { list_directory(&command_parts[1..]); },
+        "cat" => { print_file(&command_parts[1..]); },
         _ => {
             println!("unrecognized command \"{}\"", command_parts[0]);
             print!(">");
@@ -26,12 +96,39 @@ pub fn run_command(command: &String) {
     }
 }
 
+/// Completes the command or subcommand being typed at the end of the line against `COMMANDS`
+pub fn complete_command(line: &str) -> Completion {
+    let (candidates, typed_word): (Vec<&str>, &str) = match line.split(' ').collect::<Vec<&str>>()[..] {
+        [command] => (COMMANDS.iter().map(|(name, _)| *name).collect(), command),
+        [command, subcommand] => match COMMANDS.iter().find(|(name, _)| *name == command) {
+            Some((_, subcommands)) => (subcommands.to_vec(), subcommand),
+            None => return Completion::NoMatch,
+        },
+        _ => return Completion::NoMatch,
+    };
+
+    let matches: Vec<&str> = candidates.into_iter().filter(|candidate| candidate.starts_with(typed_word)).collect();
+    match matches[..] {
+        [] => Completion::NoMatch,
+        [completed_word] => {
+            let mut completed_line = String::from(&line[..line.len() - typed_word.len()]);
+            completed_line.push_str(completed_word);
+            completed_line.push(' ');
+
+            Completion::Complete(completed_line)
+        },
+        _ => Completion::Candidates(matches.into_iter().map(String::from).collect()),
+    }
+}
+
 pub fn mem_info(args: &[&str]) {
     match args[0] {
         "alloc" => {
-            let allocated_memory = MemoryManager::get_allocated_memory_amount();
-            println!("physical memory allocated: {} bytes ({} frames)", allocated_memory.0, allocated_memory.0 / PAGE_SIZE);
-            println!("virtual memory allocated: {} bytes ({} pages)", allocated_memory.1, allocated_memory.1 / PAGE_SIZE);
+            let stats = MemoryManager::memory_stats();
+            println!("physical memory allocated: {} ({} frames)", format_bytes(stats.physical_allocated), stats.physical_allocated / PAGE_SIZE);
+            println!("physical memory free: {} of {}", format_bytes(stats.physical_free), format_bytes(stats.physical_total));
+            println!("virtual memory allocated: {} ({} pages)", format_bytes(stats.virtual_allocated), stats.virtual_allocated / PAGE_SIZE);
+            println!("heap: {} live, {} peak", format_bytes(stats.heap_live), format_bytes(stats.heap_peak));
         },
         "virtual" => {
             MemoryManager::instance().lock().virtual_memory_manager.display_memory();
@@ -73,18 +170,468 @@ pub fn cpu_info(args: &[&str]) {
     }
 }
 
+/// Dumps `length` bytes of memory starting at `address`, both given in decimal or 0x-prefixed hexadecimal
+pub fn hexdump(args: &[&str]) {
+    let (Some(address), Some(length)) = (args.first().and_then(|arg| parse_address(arg)), args.get(1).and_then(|arg| parse_address(arg))) else {
+        println!("usage: hexdump <address> <length>");
+        print!(">");
+        return;
+    };
+
+    let Some(end_address) = address.checked_add(length.max(1) - 1) else {
+        println!("range overflows the address space");
+        print!(">");
+        return;
+    };
+
+    // Reading an unmapped page would fault, so check the whole range first
+    let first_page = address / PAGE_SIZE;
+    let last_page = end_address / PAGE_SIZE;
+    if let Some(page) = (first_page..=last_page).find(|page| !MemoryManager::is_mapped(page * PAGE_SIZE)) {
+        println!("address 0x{:X} is not mapped", page * PAGE_SIZE);
+        print!(">");
+        return;
+    }
+
+    let bytes = unsafe { core::slice::from_raw_parts(address as *const u8, length) };
+    print!("{}", Hexdump::new(bytes, address));
+    print!(">");
+}
+
+/// Prints the physical address a virtual address is mapped to
+pub fn translate(args: &[&str]) {
+    let Some(address) = args.first().and_then(|arg| parse_address(arg)) else {
+        println!("usage: translate <address>");
+        print!(">");
+        return;
+    };
+
+    if (0x0000_8000_0000_0000..0xFFFF_8000_0000_0000).contains(&address) {
+        println!("address 0x{:X} is not canonical", address);
+        print!(">");
+        return;
+    }
+
+    match MemoryManager::translate(address) {
+        Some(physical_address) => match MemoryManager::page_flags(address) {
+            Some(flags) => println!("0x{:X} -> 0x{:X} [{}]", address, physical_address, flags),
+            // Huge pages are translated but their flags are not looked up
+            None => println!("0x{:X} -> 0x{:X}", address, physical_address),
+        },
+        None => println!("address 0x{:X} is not mapped", address),
+    }
+    print!(">");
+}
+
+/// Reasons `peek` and `poke` refuse to access an address
+#[derive(Debug, Eq, PartialEq)]
+enum MemoryAccessError {
+    NotCanonical(usize),
+    NotMapped(usize),
+    NotWritable(usize),
+}
+
+impl Display for MemoryAccessError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            MemoryAccessError::NotCanonical(address) => write!(f, "address 0x{:X} is not canonical", address),
+            MemoryAccessError::NotMapped(address) => write!(f, "address 0x{:X} is not mapped", address),
+            MemoryAccessError::NotWritable(address) => write!(f, "address 0x{:X} is not writable", address),
+        }
+    }
+}
+
+/// Parses an access size in bytes, which must be 1, 2, 4 or 8
+fn parse_access_size(arg: &str) -> Option<usize> {
+    arg.parse::<usize>().ok().filter(|size| matches!(size, 1 | 2 | 4 | 8))
+}
+
+/// Parses `<address> <size>`
+fn parse_peek_args(args: &[&str]) -> Option<(usize, usize)> {
+    match args {
+        [address, size] => Some((parse_address(address)?, parse_access_size(size)?)),
+        _ => None,
+    }
+}
+
+/// Parses `<address> <size> <value>`, the value must fit in the given size
+fn parse_poke_args(args: &[&str]) -> Option<(usize, usize, u64)> {
+    match args {
+        [address, size, value] => {
+            let size = parse_access_size(size)?;
+            let value = parse_address(value)? as u64;
+            if size < 8 && value >> (size * 8) != 0 {
+                return None;
+            }
+
+            Some((parse_address(address)?, size, value))
+        },
+        _ => None,
+    }
+}
+
+/// Checks that every page touched by an access of `size` bytes at `address` is mapped, and writable
+/// if the access is a write
+fn check_access(address: usize, size: usize, write: bool) -> Result<(), MemoryAccessError> {
+    let last_address = address.checked_add(size - 1).ok_or(MemoryAccessError::NotCanonical(address))?;
+
+    for page_address in [address, last_address] {
+        if (0x0000_8000_0000_0000..0xFFFF_8000_0000_0000).contains(&page_address) {
+            return Err(MemoryAccessError::NotCanonical(page_address));
+        }
+
+        if !MemoryManager::is_mapped(page_address) {
+            return Err(MemoryAccessError::NotMapped(page_address));
+        }
+
+        // Huge pages have no flags looked up, so their writability cannot be checked
+        let is_writable = MemoryManager::page_flags(page_address).is_some_and(|flags| flags.contains(EntryFlags::WRITABLE));
+        if write && !is_writable {
+            return Err(MemoryAccessError::NotWritable(page_address));
+        }
+    }
+
+    Ok(())
+}
+
+/// Reads a value of `size` bytes, the address must have gone through `check_access`
+unsafe fn read_value(address: usize, size: usize) -> u64 {
+    match size {
+        1 => (address as *const u8).read_volatile() as u64,
+        2 => (address as *const u16).read_unaligned() as u64,
+        4 => (address as *const u32).read_unaligned() as u64,
+        _ => (address as *const u64).read_unaligned(),
+    }
+}
+
+/// Writes a value of `size` bytes, the address must have gone through `check_access`
+unsafe fn write_value(address: usize, size: usize, value: u64) {
+    match size {
+        1 => (address as *mut u8).write_volatile(value as u8),
+        2 => (address as *mut u16).write_unaligned(value as u16),
+        4 => (address as *mut u32).write_unaligned(value as u32),
+        _ => (address as *mut u64).write_unaligned(value),
+    }
+}
+
+/// Prints the 1, 2, 4 or 8 byte value stored at an address
+pub fn peek(args: &[&str]) {
+    let Some((address, size)) = parse_peek_args(args) else {
+        println!("usage: peek <address> <1|2|4|8>");
+        print!(">");
+        return;
+    };
+
+    match check_access(address, size, false) {
+        Ok(()) => println!("0x{:X}: 0x{:0width$X}", address, unsafe { read_value(address, size) }, width = size * 2),
+        Err(err) => println!("{}", err),
+    }
+    print!(">");
+}
+
+/// Writes a 1, 2, 4 or 8 byte value to an address
+pub fn poke(args: &[&str]) {
+    let Some((address, size, value)) = parse_poke_args(args) else {
+        println!("usage: poke <address> <1|2|4|8> <value>");
+        print!(">");
+        return;
+    };
+
+    match check_access(address, size, true) {
+        Ok(()) => unsafe { write_value(address, size, value) },
+        Err(err) => println!("{}", err),
+    }
+    print!(">");
+}
+
+pub fn interrupt_stats() {
+    println!("spurious: {}", local_apic::spurious_interrupt_count());
+    println!("breakpoint: {}", breakpoint_count());
+    print!(">");
+}
+
+pub fn uname() {
+    println!("{}", version::uname());
+    print!(">");
+}
+
+pub fn font_scale(args: &[&str]) {
+    let Some(font_scale) = args.first().and_then(|arg| arg.parse::<usize>().ok()) else {
+        println!("usage: fontscale <1-4>");
+        print!(">");
+        return;
+    };
+
+    let Some(writer) = Writer::instance() else {
+        println!("no framebuffer found");
+        print!(">");
+        return;
+    };
+
+    // The result is printed once the lock is released
+    let result = writer.lock().set_font_scale(font_scale);
+    if let Err(err) = result {
+        println!("{}", err);
+    }
+    print!(">");
+}
+
+pub fn background_color(args: &[&str]) {
+    let Some(color) = args.first().and_then(|arg| parse_color(arg)) else {
+        println!("usage: bgcolor <RRGGBB>");
+        print!(">");
+        return;
+    };
+
+    match Writer::instance() {
+        Some(writer) => writer.lock().set_background(color),
+        None => println!("no framebuffer found"),
+    }
+    print!(">");
+}
+
+/// Parses a 24 bit 0xRRGGBB color, the `0x` or `#` prefix is optional
+fn parse_color(value: &str) -> Option<Rgb8> {
+    let hex_digits = value.strip_prefix("0x").or_else(|| value.strip_prefix('#')).unwrap_or(value);
+    if hex_digits.is_empty() || hex_digits.len() > 6 || !hex_digits.bytes().all(|digit| digit.is_ascii_hexdigit()) {
+        return None;
+    }
+
+    u32::from_str_radix(hex_digits, 16).ok().map(Rgb8)
+}
+
+/// Saves the contents of the first framebuffer as a TGA image in the ramfs, at `/screenshot.tga`
+/// unless another absolute path is given
+pub fn screenshot(args: &[&str]) {
+    let path = args.first().copied().unwrap_or(DEFAULT_SCREENSHOT_PATH);
+
+    let result = match FB_DEVICES.lock().first() {
+        Some(framebuffer) => screenshot::capture(framebuffer),
+        None => Err("screenshot: no framebuffer to capture"),
+    };
+
+    match result.and_then(|image| Vfs::create_file(path, image)) {
+        Ok(()) => println!("screenshot saved to {}", path),
+        Err(err) => println!("{}", err),
+    }
+    print!(">");
+}
+
+/// Reproduces the sequence that was seen overwriting the name of the framebuffer device: the device
+/// is initialized and registered at boot, allocations are performed here, then its name is checked
+/// both in the device list and in the vfs
+pub fn corrupt_test() {
+    perform_test_allocations();
+
+    let device_name = FB_DEVICES.lock().first().map(|device| check_name(device.name(), "fb0"));
+    match device_name {
+        Some(Ok(())) => println!("fb0: name intact"),
+        Some(Err(raw)) => println!("fb0: name corrupted, raw bytes {:02X?}", raw),
+        None => println!("fb0: no framebuffer device"),
+    }
+
+    match Vfs::find_from_absolute_path("/dev/fb0") {
+        Some(node) => match check_name(node.lock().name(), "fb0") {
+            Ok(()) => println!("/dev/fb0: name intact"),
+            Err(raw) => println!("/dev/fb0: name corrupted, raw bytes {:02X?}", raw),
+        },
+        None => println!("/dev/fb0: not found"),
+    }
+    print!(">");
+}
+
+/// Mixes heap allocations of various sizes with page allocations, writing a pattern to each of them
+fn perform_test_allocations() {
+    let allocations: Vec<Vec<u8>> = (0..CORRUPT_TEST_ALLOCATIONS).map(|i| vec![0xA5; 8 << (i % 8)]).collect();
+
+    let size = 4 * PAGE_SIZE;
+    if let Some(address) = MemoryManager::vmm_alloc(size, EntryFlags::WRITABLE) {
+        unsafe { core::ptr::write_bytes(address as *mut u8, 0x5A, size) };
+        MemoryManager::vmm_free(size, address).expect("debugger: could not free test allocation");
+    }
+
+    drop(allocations);
+}
+
+/// Returns the raw bytes of the string itself, pointer, capacity and length, if it does not match
+/// the expected name
+fn check_name(name: &String, expected: &str) -> Result<(), Vec<u8>> {
+    if name.as_bytes() == expected.as_bytes() {
+        return Ok(());
+    }
+
+    let raw = unsafe { core::slice::from_raw_parts(name as *const String as *const u8, size_of::<String>()) };
+    Err(raw.to_vec())
+}
+
 fn print_memory_map() {
-    MEMORY_MAP_REQUEST.get_response().unwrap().entries().iter().for_each(|entry| {
-        match entry.entry_type {
-            EntryType::USABLE => println!("0x{:016X} - 0x{:016X} ({:016X}) : usable", entry.base, entry.base + entry.length, entry.length),
-            EntryType::RESERVED => println!("0x{:016X} - 0x{:016X} ({:016X}) : reserved",  entry.base, entry.base + entry.length, entry.length),
-            EntryType::ACPI_RECLAIMABLE => println!("0x{:016X} - 0x{:016X} ({:016X}) : acpi reclaimable",  entry.base, entry.base + entry.length, entry.length),
-            EntryType::ACPI_NVS => println!("0x{:016X} - 0x{:016X} ({:016X}) : acpi nvs",  entry.base, entry.base + entry.length, entry.length),
-            EntryType::BAD_MEMORY => println!("0x{:016X} - 0x{:016X} ({:016X}) : bad memory",  entry.base, entry.base + entry.length, entry.length),
-            EntryType::BOOTLOADER_RECLAIMABLE => println!("0x{:016X} - 0x{:016X} ({:016X}) : bootloader reclaimable",  entry.base, entry.base + entry.length, entry.length),
-            EntryType::KERNEL_AND_MODULES => println!("0x{:016X} - 0x{:016X} ({:016X}) : kernel",  entry.base, entry.base + entry.length, entry.length),
-            EntryType::FRAMEBUFFER => println!("0x{:016X} - 0x{:016X} ({:016X}) : framebuffer",  entry.base, entry.base + entry.length, entry.length),
-            _ => ()
+    sanitize_memory_map(MEMORY_MAP_REQUEST.get_response().unwrap().entries()).iter().for_each(|entry| {
+        if let Some(name) = memory_type_name(entry.entry_type) {
+            println!("0x{:016X} - 0x{:016X} ({:016X}) : {}", entry.base, entry.end(), entry.length, name);
         }
     });
-}
\ No newline at end of file
+}
+
+fn memory_type_name(entry_type: EntryType) -> Option<&'static str> {
+    match entry_type {
+        EntryType::USABLE => Some("usable"),
+        EntryType::RESERVED => Some("reserved"),
+        EntryType::ACPI_RECLAIMABLE => Some("acpi reclaimable"),
+        EntryType::ACPI_NVS => Some("acpi nvs"),
+        EntryType::BAD_MEMORY => Some("bad memory"),
+        EntryType::BOOTLOADER_RECLAIMABLE => Some("bootloader reclaimable"),
+        EntryType::KERNEL_AND_MODULES => Some("kernel"),
+        EntryType::FRAMEBUFFER => Some("framebuffer"),
+        _ => None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::string::String;
+    use alloc::vec;
+    use crate::debugger::{check_access, complete_command, MemoryAccessError, parse_color, parse_peek_args, parse_poke_args, read_value, write_value};
+    use crate::graphics::framebuffer_device::Rgb8;
+    use crate::debugger::line_editor::Completion;
+    use crate::memory::{MemoryManager, PAGE_SIZE};
+    use crate::memory::virtual_memory::paging::entry::EntryFlags;
+
+    #[test_case]
+    fn unique_command_prefix_completes_fully() {
+        // WHEN
+        let completion = complete_command("mem");
+
+        // THEN
+        assert_eq!(completion, Completion::Complete(String::from("meminfo ")));
+    }
+
+    #[test_case]
+    fn unique_subcommand_prefix_completes_fully() {
+        // WHEN
+        let completion = complete_command("meminfo ph");
+
+        // THEN
+        assert_eq!(completion, Completion::Complete(String::from("meminfo physical ")));
+    }
+
+    #[test_case]
+    fn ambiguous_prefix_lists_candidates() {
+        // WHEN
+        let commands = complete_command("");
+        let subcommands = complete_command("meminfo ");
+
+        // THEN
+        assert_eq!(commands, Completion::Candidates(vec![
+            String::from("meminfo"), String::from("cpuinfo"), String::from("hexdump"),
+            String::from("translate"), String::from("peek"), String::from("poke"), String::from("shutdown"), String::from("reboot"),
+            String::from("uname"), String::from("fontscale"), String::from("bgcolor"), String::from("screenshot"), String::from("corrupttest"),
+            String::from("intstats"), String::from("trap"),
+            String::from("cd"), String::from("pwd"), String::from("ls"), String::from("cat"),
+        ]));
+        assert_eq!(subcommands, Completion::Candidates(vec![
+            String::from("alloc"), String::from("virtual"), String::from("physical"), String::from("map"),
+        ]));
+    }
+
+    #[test_case]
+    fn unknown_prefix_has_no_completion() {
+        // WHEN
+        let unknown_command = complete_command("xyz");
+        let unknown_parent = complete_command("xyz al");
+        let too_many_words = complete_command("meminfo alloc a");
+
+        // THEN
+        assert_eq!(unknown_command, Completion::NoMatch);
+        assert_eq!(unknown_parent, Completion::NoMatch);
+        assert_eq!(too_many_words, Completion::NoMatch);
+    }
+
+    #[test_case]
+    fn peek_arguments_are_parsed() {
+        // THEN
+        assert_eq!(parse_peek_args(&["0xFFFF800000001000", "4"]), Some((0xFFFF_8000_0000_1000, 4)));
+        assert_eq!(parse_peek_args(&["4096", "8"]), Some((4096, 8)));
+        assert_eq!(parse_peek_args(&["0x1000", "3"]), None);
+        assert_eq!(parse_peek_args(&["0x1000"]), None);
+        assert_eq!(parse_peek_args(&["zz", "1"]), None);
+    }
+
+    #[test_case]
+    fn poke_arguments_are_parsed() {
+        // THEN
+        assert_eq!(parse_poke_args(&["0x1000", "2", "0xBEEF"]), Some((0x1000, 2, 0xBEEF)));
+        assert_eq!(parse_poke_args(&["0x1000", "8", "0xFFFFFFFFFFFFFFFF"]), Some((0x1000, 8, u64::MAX)));
+        assert_eq!(parse_poke_args(&["0x1000", "1", "256"]), None);
+        assert_eq!(parse_poke_args(&["0x1000", "1"]), None);
+    }
+
+    #[test_case]
+    fn access_to_writable_page_is_allowed() {
+        // GIVEN
+        let address = MemoryManager::vmm_alloc(PAGE_SIZE, EntryFlags::WRITABLE).unwrap();
+
+        // WHEN
+        let read = check_access(address, 8, false);
+        let write = check_access(address, 8, true);
+        let value = unsafe {
+            write_value(address + 4, 4, 0xDEAD_BEEF);
+            read_value(address + 4, 4)
+        };
+
+        // THEN
+        assert_eq!(read, Ok(()));
+        assert_eq!(write, Ok(()));
+        assert_eq!(value, 0xDEAD_BEEF);
+
+        MemoryManager::vmm_free(PAGE_SIZE, address).unwrap();
+    }
+
+    #[test_case]
+    fn write_to_read_only_page_is_rejected() {
+        // GIVEN
+        let address = MemoryManager::vmm_alloc(PAGE_SIZE, EntryFlags::empty()).unwrap();
+
+        // WHEN
+        let read = check_access(address, 1, false);
+        let write = check_access(address, 1, true);
+
+        // THEN
+        assert_eq!(read, Ok(()));
+        assert_eq!(write, Err(MemoryAccessError::NotWritable(address)));
+
+        MemoryManager::vmm_free(PAGE_SIZE, address).unwrap();
+    }
+
+    #[test_case]
+    fn access_to_unmapped_or_non_canonical_address_is_rejected() {
+        // GIVEN
+        let address = MemoryManager::vmm_alloc(PAGE_SIZE, EntryFlags::WRITABLE).unwrap();
+        MemoryManager::vmm_free(PAGE_SIZE, address).unwrap();
+
+        // WHEN
+        let unmapped = check_access(address, 4, false);
+        let non_canonical = check_access(0x0000_8000_0000_0000, 4, false);
+
+        // THEN
+        assert_eq!(unmapped, Err(MemoryAccessError::NotMapped(address)));
+        assert_eq!(non_canonical, Err(MemoryAccessError::NotCanonical(0x0000_8000_0000_0000)));
+    }
+
+    #[test_case]
+    fn colors_parse_with_or_without_prefix() {
+        // WHEN
+        let colors = ["1E1E2E", "0x00FF00", "#ffffff", "0"].map(parse_color);
+
+        // THEN
+        assert_eq!(colors, [Some(Rgb8(0x1E1E2E)), Some(Rgb8(0x00FF00)), Some(Rgb8(0xFFFFFF)), Some(Rgb8(0))]);
+    }
+
+    #[test_case]
+    fn invalid_colors_are_rejected() {
+        // WHEN
+        let colors = ["", "0x", "#", "1000000", "+12", "blue"].map(parse_color);
+
+        // THEN
+        assert!(colors.iter().all(|color| color.is_none()));
+    }
+}
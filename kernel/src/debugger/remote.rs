@@ -0,0 +1,205 @@
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+use crate::debugger::run_command;
+use crate::graphics::framebuffer_device::capture_output;
+use crate::serial::SERIAL1;
+
+/// Starts a frame sent by the host, followed by the length of the command and the command itself
+const COMMAND_TAG: &[u8] = b"CMD ";
+/// Starts the reply to a command that was run, followed by the length of its output and the output
+const OK_TAG: &str = "OK";
+/// Starts the reply to a malformed frame, followed by the length of the error and the error
+const ERROR_TAG: &str = "ERR";
+/// Longest command accepted, longer frames are rejected before their payload is read
+const MAX_COMMAND_LENGTH: usize = 256;
+/// Printed by the commands once they are done, it is not part of their output
+const PROMPT: char = '>';
+
+/// Byte stream the host and the kernel exchange frames over
+pub trait SerialTransport {
+    /// Returns the next byte sent by the host, or None once the host closed the connection
+    fn read_byte(&mut self) -> Option<u8>;
+    fn write_bytes(&mut self, bytes: &[u8]);
+}
+
+/// Exchanges the frames over COM1, waiting for the host to send each byte
+pub struct Com1Transport;
+
+impl SerialTransport for Com1Transport {
+    fn read_byte(&mut self) -> Option<u8> {
+        Some(SERIAL1.lock().receive())
+    }
+
+    fn write_bytes(&mut self, bytes: &[u8]) {
+        let mut serial_port = SERIAL1.lock();
+        bytes.iter().for_each(|&byte| serial_port.send_raw(byte));
+    }
+}
+
+/// Why a command frame could not be read
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum FrameError {
+    /// The host closed the connection
+    Closed,
+    Malformed(&'static str),
+}
+
+/// Reads a `CMD <len> <bytes>` frame and returns the command it carries
+pub fn read_command(transport: &mut impl SerialTransport) -> Result<String, FrameError> {
+    for &expected in COMMAND_TAG {
+        if next_byte(transport)? != expected {
+            return Err(FrameError::Malformed("remote: frame does not start with CMD"));
+        }
+    }
+
+    let mut length: usize = 0;
+    loop {
+        match next_byte(transport)? {
+            b' ' => break,
+            digit @ b'0'..=b'9' => {
+                length = length * 10 + (digit - b'0') as usize;
+                if length > MAX_COMMAND_LENGTH {
+                    return Err(FrameError::Malformed("remote: command is too long"));
+                }
+            },
+            _ => return Err(FrameError::Malformed("remote: invalid command length")),
+        }
+    }
+
+    let command: Vec<u8> = (0..length).map(|_| next_byte(transport)).collect::<Result<_, _>>()?;
+    String::from_utf8(command).map_err(|_| FrameError::Malformed("remote: command is not valid UTF-8"))
+}
+
+/// Sends the `OK <len> <output>` frame replying to a command
+pub fn write_reply(transport: &mut impl SerialTransport, output: &str) {
+    write_frame(transport, OK_TAG, output);
+}
+
+/// Sends the `ERR <len> <error>` frame replying to a malformed frame
+pub fn write_error(transport: &mut impl SerialTransport, error: &str) {
+    write_frame(transport, ERROR_TAG, error);
+}
+
+/// Runs a command with `run_command` and returns what it printed, without the trailing prompt
+pub fn execute(command: &String) -> String {
+    let output = capture_output(|| run_command(command));
+
+    match output.strip_suffix(PROMPT) {
+        Some(output) => String::from(output),
+        None => output,
+    }
+}
+
+/// Serves the commands sent over the transport until the host closes it
+pub fn serve(transport: &mut impl SerialTransport) {
+    loop {
+        match read_command(transport) {
+            Ok(command) => write_reply(transport, &execute(&command)),
+            Err(FrameError::Malformed(err)) => write_error(transport, err),
+            Err(FrameError::Closed) => return,
+        }
+    }
+}
+
+fn next_byte(transport: &mut impl SerialTransport) -> Result<u8, FrameError> {
+    transport.read_byte().ok_or(FrameError::Closed)
+}
+
+fn write_frame(transport: &mut impl SerialTransport, tag: &str, payload: &str) {
+    transport.write_bytes(format!("{} {} ", tag, payload.len()).as_bytes());
+    transport.write_bytes(payload.as_bytes());
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::collections::VecDeque;
+    use alloc::format;
+    use alloc::string::String;
+    use alloc::vec::Vec;
+    use crate::debugger::remote::{FrameError, read_command, serve, SerialTransport, write_reply};
+    use crate::version;
+
+    /// Feeds the bytes queued by the test and records the ones written back
+    struct MockTransport {
+        input: VecDeque<u8>,
+        output: Vec<u8>,
+    }
+
+    impl MockTransport {
+        fn new(input: &[u8]) -> Self {
+            Self { input: input.iter().copied().collect(), output: Vec::new() }
+        }
+    }
+
+    impl SerialTransport for MockTransport {
+        fn read_byte(&mut self) -> Option<u8> {
+            self.input.pop_front()
+        }
+
+        fn write_bytes(&mut self, bytes: &[u8]) {
+            self.output.extend_from_slice(bytes);
+        }
+    }
+
+    #[test_case]
+    fn command_frame_is_parsed() {
+        // GIVEN
+        let mut transport = MockTransport::new(b"CMD 11 meminfo mapCMD 3 pwd");
+
+        // WHEN
+        let first = read_command(&mut transport);
+        let second = read_command(&mut transport);
+        let third = read_command(&mut transport);
+
+        // THEN
+        assert_eq!(first, Ok(String::from("meminfo map")));
+        assert_eq!(second, Ok(String::from("pwd")));
+        assert_eq!(third, Err(FrameError::Closed));
+    }
+
+    #[test_case]
+    fn malformed_frames_are_rejected() {
+        // GIVEN
+        let mut wrong_tag = MockTransport::new(b"RUN 3 pwd");
+        let mut wrong_length = MockTransport::new(b"CMD 3x pwd");
+        let mut too_long = MockTransport::new(b"CMD 99999 ");
+        let mut truncated = MockTransport::new(b"CMD 10 pwd");
+
+        // WHEN
+        let results = [&mut wrong_tag, &mut wrong_length, &mut too_long, &mut truncated].map(|transport| read_command(transport));
+
+        // THEN
+        assert_eq!(results, [
+            Err(FrameError::Malformed("remote: frame does not start with CMD")),
+            Err(FrameError::Malformed("remote: invalid command length")),
+            Err(FrameError::Malformed("remote: command is too long")),
+            Err(FrameError::Closed),
+        ]);
+    }
+
+    #[test_case]
+    fn reply_frame_is_length_prefixed() {
+        // GIVEN
+        let mut transport = MockTransport::new(b"");
+
+        // WHEN
+        write_reply(&mut transport, "héllo\n");
+
+        // THEN
+        assert_eq!(transport.output, "OK 7 héllo\n".as_bytes());
+    }
+
+    #[test_case]
+    fn served_command_replies_with_its_output() {
+        // GIVEN
+        let mut transport = MockTransport::new(b"CMD 5 unameCMD 1");
+
+        // WHEN
+        serve(&mut transport);
+
+        // THEN
+        let expected_output = format!("{}\n", version::uname());
+        assert_eq!(transport.output, format!("OK {} {}", expected_output.len(), expected_output).as_bytes());
+    }
+}
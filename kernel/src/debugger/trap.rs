@@ -0,0 +1,97 @@
+use core::arch::asm;
+use crate::memory::MemoryManager;
+
+/// Address read to trigger a page fault, the null page is left unmapped
+const UNMAPPED_ADDRESS: usize = 0;
+
+/// Exceptions `trap` can deliberately trigger to exercise their handler
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Trap {
+    /// #BP, its handler returns
+    Breakpoint,
+    /// #DE
+    DivideError,
+    /// #PF
+    PageFault,
+    /// #UD
+    InvalidOpcode,
+}
+
+impl Trap {
+    /// Names accepted by the `trap` command, in the order of the variants
+    pub const NAMES: [&'static str; 4] = ["breakpoint", "divide", "pagefault", "opcode"];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "breakpoint" => Some(Trap::Breakpoint),
+            "divide" => Some(Trap::DivideError),
+            "pagefault" => Some(Trap::PageFault),
+            "opcode" => Some(Trap::InvalidOpcode),
+            _ => None,
+        }
+    }
+}
+
+/// Triggers the exception. Only the breakpoint returns, the handlers of the other exceptions halt.
+pub fn trigger(trap: Trap) -> Result<(), &'static str> {
+    match trap {
+        Trap::Breakpoint => unsafe { asm!("int3", options(nomem, nostack)) },
+        Trap::DivideError => unsafe {
+            asm!("div {divisor:e}", divisor = in(reg) 0u32, inout("eax") 1u32 => _, inout("edx") 0u32 => _, options(nomem, nostack));
+        },
+        Trap::PageFault => {
+            if MemoryManager::translate(UNMAPPED_ADDRESS).is_some() {
+                return Err("trap: the null page is mapped, reading it would not fault");
+            }
+
+            unsafe { (UNMAPPED_ADDRESS as *const u8).read_volatile() };
+        },
+        Trap::InvalidOpcode => unsafe { asm!("ud2", options(nomem, nostack)) },
+    }
+
+    Ok(())
+}
+
+/// Triggers the named exception to check that its handler reports it
+pub fn trap(args: &[&str]) {
+    let Some(trap) = args.first().and_then(|name| Trap::from_name(name)) else {
+        println!("usage: trap <{}>", Trap::NAMES.join("|"));
+        print!(">");
+        return;
+    };
+
+    match trigger(trap) {
+        Ok(()) => println!("trap: returned from the {:?} handler", trap),
+        Err(err) => println!("{}", err),
+    }
+    print!(">");
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::debugger::trap::{Trap, trigger};
+    use crate::interrupts::breakpoint_count;
+
+    #[test_case]
+    fn breakpoint_trap_returns_from_its_handler() {
+        // GIVEN
+        let breakpoints = breakpoint_count();
+
+        // WHEN
+        let result = trigger(Trap::Breakpoint);
+
+        // THEN
+        assert_eq!(result, Ok(()));
+        assert_eq!(breakpoint_count(), breakpoints + 1);
+    }
+
+    #[test_case]
+    fn every_trap_name_is_recognized() {
+        // WHEN
+        let traps: [Option<Trap>; 4] = Trap::NAMES.map(Trap::from_name);
+
+        // THEN
+        assert_eq!(traps, [Some(Trap::Breakpoint), Some(Trap::DivideError), Some(Trap::PageFault), Some(Trap::InvalidOpcode)]);
+        assert_eq!(Trap::from_name("nmi"), None);
+    }
+}
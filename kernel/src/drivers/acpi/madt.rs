@@ -0,0 +1,230 @@
+use alloc::vec::Vec;
+use core::mem::size_of;
+use core::slice;
+use crate::drivers::acpi::acpi_tables::ACPISDTHeader;
+use crate::HHDM_OFFSET;
+use crate::memory::PhysicalAddress;
+
+/// Offset of the first entry record, after the SDT header, the local APIC address and the flags
+const ENTRIES_OFFSET: usize = size_of::<ACPISDTHeader>() + 8;
+
+const LOCAL_APIC_ENTRY: u8 = 0;
+const IO_APIC_ENTRY: u8 = 1;
+const INTERRUPT_SOURCE_OVERRIDE_ENTRY: u8 = 2;
+const LOCAL_APIC_ADDRESS_OVERRIDE_ENTRY: u8 = 5;
+
+/// Length of the entry records of each known type, as defined by the ACPI specification
+const LOCAL_APIC_ENTRY_LENGTH: usize = 8;
+const IO_APIC_ENTRY_LENGTH: usize = 12;
+const INTERRUPT_SOURCE_OVERRIDE_ENTRY_LENGTH: usize = 10;
+const LOCAL_APIC_ADDRESS_OVERRIDE_ENTRY_LENGTH: usize = 12;
+
+/// Set in a local APIC entry's flags when the processor can be used
+const PROCESSOR_ENABLED: u32 = 1 << 0;
+/// Set in a local APIC entry's flags when a disabled processor can be brought online
+const PROCESSOR_ONLINE_CAPABLE: u32 = 1 << 1;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct IoApicInfo {
+    pub id: u8,
+    pub address: PhysicalAddress,
+    /// First global system interrupt handled by this IO-APIC
+    pub gsi_base: u32,
+}
+
+/// Mapping of an ISA interrupt to a different global system interrupt
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct InterruptSourceOverride {
+    pub bus: u8,
+    pub irq: u8,
+    pub gsi: u32,
+    pub flags: u16,
+}
+
+/// Interrupt controller information described by the Multiple APIC Description Table
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct MadtInfo {
+    pub local_apic_address: PhysicalAddress,
+    /// APIC ids of the usable processors
+    pub local_apic_ids: Vec<u8>,
+    pub io_apics: Vec<IoApicInfo>,
+    pub interrupt_source_overrides: Vec<InterruptSourceOverride>,
+}
+
+/// Reads the MADT located at the given physical address through the higher half direct map
+pub fn parse_madt(madt_address: PhysicalAddress) -> MadtInfo {
+    let virtual_address = madt_address + *HHDM_OFFSET;
+    let header = unsafe { &*(virtual_address as *const ACPISDTHeader) };
+    let bytes = unsafe { slice::from_raw_parts(virtual_address as *const u8, header.length() as usize) };
+
+    parse_madt_bytes(bytes)
+}
+
+/// Walks the entry records of a MADT. Unknown entry types are skipped, as are known ones too short
+/// to hold their fields.
+fn parse_madt_bytes(bytes: &[u8]) -> MadtInfo {
+    let mut madt_info = MadtInfo {
+        local_apic_address: read_u32(bytes, size_of::<ACPISDTHeader>()) as PhysicalAddress,
+        local_apic_ids: Vec::new(),
+        io_apics: Vec::new(),
+        interrupt_source_overrides: Vec::new(),
+    };
+
+    let mut offset = ENTRIES_OFFSET;
+    while offset + 2 <= bytes.len() {
+        let entry_type = bytes[offset];
+        let entry_length = bytes[offset + 1] as usize;
+        if entry_length < 2 || offset + entry_length > bytes.len() {
+            warn!("acpi: malformed MADT entry at offset {}", offset);
+            break;
+        }
+
+        let entry = &bytes[offset..offset + entry_length];
+        if entry_length < minimum_entry_length(entry_type) {
+            warn!("acpi: MADT entry of type {} at offset {} is too short ({} bytes)", entry_type, offset, entry_length);
+            offset += entry_length;
+            continue;
+        }
+
+        match entry_type {
+            LOCAL_APIC_ENTRY => {
+                let flags = read_u32(entry, 4);
+                if flags & (PROCESSOR_ENABLED | PROCESSOR_ONLINE_CAPABLE) != 0 {
+                    madt_info.local_apic_ids.push(entry[3]);
+                }
+            },
+            IO_APIC_ENTRY => madt_info.io_apics.push(IoApicInfo {
+                id: entry[2],
+                address: read_u32(entry, 4) as PhysicalAddress,
+                gsi_base: read_u32(entry, 8),
+            }),
+            INTERRUPT_SOURCE_OVERRIDE_ENTRY => madt_info.interrupt_source_overrides.push(InterruptSourceOverride {
+                bus: entry[2],
+                irq: entry[3],
+                gsi: read_u32(entry, 4),
+                flags: u16::from_le_bytes([entry[8], entry[9]]),
+            }),
+            LOCAL_APIC_ADDRESS_OVERRIDE_ENTRY => {
+                let address_bytes: [u8; 8] = entry[4..12].try_into().unwrap();
+                madt_info.local_apic_address = u64::from_le_bytes(address_bytes) as PhysicalAddress;
+            },
+            _ => (),
+        }
+
+        offset += entry_length;
+    }
+
+    madt_info
+}
+
+/// Smallest length of an entry record of the given type whose fields are read
+fn minimum_entry_length(entry_type: u8) -> usize {
+    match entry_type {
+        LOCAL_APIC_ENTRY => LOCAL_APIC_ENTRY_LENGTH,
+        IO_APIC_ENTRY => IO_APIC_ENTRY_LENGTH,
+        INTERRUPT_SOURCE_OVERRIDE_ENTRY => INTERRUPT_SOURCE_OVERRIDE_ENTRY_LENGTH,
+        LOCAL_APIC_ADDRESS_OVERRIDE_ENTRY => LOCAL_APIC_ADDRESS_OVERRIDE_ENTRY_LENGTH,
+        _ => 2,
+    }
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec;
+    use alloc::vec::Vec;
+    use crate::drivers::acpi::madt::{ENTRIES_OFFSET, InterruptSourceOverride, IoApicInfo, parse_madt_bytes};
+
+    /// Builds a MADT with the given entry records
+    fn madt_with_entries(local_apic_address: u32, entries: &[&[u8]]) -> Vec<u8> {
+        let mut madt = vec![0u8; ENTRIES_OFFSET];
+        madt[0..4].copy_from_slice(b"APIC");
+        madt[36..40].copy_from_slice(&local_apic_address.to_le_bytes());
+        for entry in entries {
+            madt.extend_from_slice(entry);
+        }
+
+        let length = madt.len() as u32;
+        madt[4..8].copy_from_slice(&length.to_le_bytes());
+        madt
+    }
+
+    #[test_case]
+    fn parse_madt_extracts_apics_and_overrides() {
+        // GIVEN
+        let madt = madt_with_entries(0xFEE00000, &[
+            &[0, 8, 0, 0, 1, 0, 0, 0], // Processor 0, APIC id 0, enabled
+            &[0, 8, 1, 1, 1, 0, 0, 0], // Processor 1, APIC id 1, enabled
+            &[0, 8, 2, 5, 0, 0, 0, 0], // Processor 2, APIC id 5, disabled
+            &[1, 12, 2, 0, 0x00, 0x00, 0xC0, 0xFE, 0, 0, 0, 0], // IO-APIC 2 at 0xFEC00000, GSI base 0
+            &[2, 10, 0, 0, 2, 0, 0, 0, 0, 0], // ISA IRQ 0 to GSI 2
+            &[2, 10, 0, 9, 9, 0, 0, 0, 0x0D, 0], // ISA IRQ 9 to GSI 9, level triggered, active high
+            &[9, 4, 0, 0], // Unknown entry type
+        ]);
+
+        // WHEN
+        let madt_info = parse_madt_bytes(&madt);
+
+        // THEN
+        assert_eq!(madt_info.local_apic_address, 0xFEE00000);
+        assert_eq!(madt_info.local_apic_ids, vec![0, 1]);
+        assert_eq!(madt_info.io_apics, vec![IoApicInfo { id: 2, address: 0xFEC00000, gsi_base: 0 }]);
+        assert_eq!(madt_info.interrupt_source_overrides, vec![
+            InterruptSourceOverride { bus: 0, irq: 0, gsi: 2, flags: 0 },
+            InterruptSourceOverride { bus: 0, irq: 9, gsi: 9, flags: 0x0D },
+        ]);
+    }
+
+    #[test_case]
+    fn parse_madt_applies_local_apic_address_override() {
+        // GIVEN
+        let madt = madt_with_entries(0xFEE00000, &[
+            &[5, 12, 0, 0, 0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00],
+        ]);
+
+        // WHEN
+        let madt_info = parse_madt_bytes(&madt);
+
+        // THEN
+        assert_eq!(madt_info.local_apic_address, 0x1_0000_0000);
+    }
+
+    #[test_case]
+    fn parse_madt_stops_at_truncated_entry() {
+        // GIVEN
+        let madt = madt_with_entries(0xFEE00000, &[
+            &[0, 8, 0, 0, 1, 0, 0, 0],
+            &[1, 12, 2, 0],
+        ]);
+
+        // WHEN
+        let madt_info = parse_madt_bytes(&madt);
+
+        // THEN
+        assert_eq!(madt_info.local_apic_ids, vec![0]);
+        assert!(madt_info.io_apics.is_empty());
+    }
+
+    #[test_case]
+    fn parse_madt_skips_entries_too_short_for_their_type() {
+        // GIVEN
+        let madt = madt_with_entries(0xFEE00000, &[
+            &[1, 4, 2, 0], // IO-APIC entry without its address
+            &[2, 6, 0, 0, 2, 0], // Interrupt source override without its flags
+            &[5, 4, 0, 0], // Local APIC address override without the address
+            &[0, 8, 0, 3, 1, 0, 0, 0], // Processor 0, APIC id 3, enabled
+        ]);
+
+        // WHEN
+        let madt_info = parse_madt_bytes(&madt);
+
+        // THEN
+        assert_eq!(madt_info.local_apic_address, 0xFEE00000);
+        assert_eq!(madt_info.local_apic_ids, vec![3]);
+        assert!(madt_info.io_apics.is_empty());
+        assert!(madt_info.interrupt_source_overrides.is_empty());
+    }
+}
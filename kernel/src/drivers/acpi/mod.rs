@@ -1,5 +1,6 @@
 pub mod root_system_descriptor_pointer;
 pub mod acpi_tables;
+pub mod madt;
 
 pub fn init_acpi() {/*
     let rsdp = find_rsdp(boot_info).expect("Error finding RSDP");
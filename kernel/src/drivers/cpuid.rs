@@ -1,3 +1,4 @@
+use alloc::format;
 use alloc::string::{String, ToString};
 use core::str;
 use core::arch::asm;
@@ -28,9 +29,56 @@ struct BrandStringResponse {
     edx3: u32,
 }
 
+#[derive(Debug, Clone, Eq, PartialEq)]
 pub enum CPUVendor {
     Amd,
     Intel,
+    /// Any other vendor, holding the raw vendor string
+    Unknown(String),
+}
+
+/// Registers returned by the cpuid instruction
+#[derive(Debug, Copy, Clone)]
+struct CpuidResult {
+    eax: u32,
+    ebx: u32,
+    ecx: u32,
+    edx: u32,
+}
+
+/// Executes cpuid for the given leaf. The instruction and the reads of its results must be part of
+/// the same asm block, otherwise the compiler is free to reuse the registers in between. rbx is
+/// reserved by LLVM, so it is saved and restored around the instruction.
+fn cpuid(leaf: u32) -> CpuidResult {
+    let eax: u32;
+    let ebx: u64;
+    let ecx: u32;
+    let edx: u32;
+
+    unsafe {
+        asm!(
+            "mov {rbx_backup}, rbx",
+            "cpuid",
+            "xchg {rbx_backup}, rbx",
+            rbx_backup = out(reg) ebx,
+            inout("eax") leaf => eax,
+            inout("ecx") 0 => ecx,
+            out("edx") edx,
+            options(nomem, nostack, preserves_flags),
+        );
+    }
+
+    CpuidResult { eax, ebx: ebx as u32, ecx, edx }
+}
+
+impl CPUVendor {
+    fn from_vendor_string(vendor_string: &str) -> Self {
+        match vendor_string {
+            "AuthenticAMD" => CPUVendor::Amd,
+            "GenuineIntel" => CPUVendor::Intel,
+            _ => CPUVendor::Unknown(String::from(vendor_string)),
+        }
+    }
 }
 
 pub struct CPUInfo {
@@ -57,79 +105,51 @@ impl CPUInfo {
         info!("cpu: getting cpu info...");
 
         unsafe {
+            let vendor = Self::get_vendor();
+
+            // Only rely on the extended leaves of known vendors
+            let brand_string = match &vendor {
+                CPUVendor::Unknown(vendor_string) => {
+                    warn!("cpu: unknown vendor \"{}\"", vendor_string);
+                    format!("unknown {} CPU", vendor_string)
+                },
+                _ => Self::get_brand_string(),
+            };
+
             Self {
-                vendor: Self::get_vendor(),
+                vendor,
                 is_apic_supported: Self::get_apic_support(),
-                brand_string: Self::get_brand_string(),
+                brand_string,
             }
         }
     }
 
     unsafe fn get_vendor() -> CPUVendor {
-        let ebx: u32;
-        let ecx: u32;
-        let edx: u32;
-
-        asm!("mov eax, 0x0; cpuid;");
-
-        asm!("mov {:e}, ebx", out(reg) ebx, options(nomem, nostack, preserves_flags));
-        asm!("mov {:e}, ecx", out(reg) ecx, options(nomem, nostack, preserves_flags));
-        asm!("mov {:e}, edx", out(reg) edx, options(nomem, nostack, preserves_flags));
+        CPUVendor::from_vendor_string(&Self::get_vendor_string())
+    }
 
-        let vendor_response = CPUVendorResponse { ebx, edx, ecx };
-        let vendor_string = str::from_utf8(any_as_u8_slice(&vendor_response)).unwrap();
+    /// Reads the 12 characters vendor string from leaf 0
+    unsafe fn get_vendor_string() -> String {
+        let leaf = cpuid(0x0);
 
-        match vendor_string {
-            "AuthenticAMD" => CPUVendor::Amd,
-            "GenuineIntel" => CPUVendor::Intel,
-            _ => panic!("Unsupported CPU"),
-        }
+        let vendor_response = CPUVendorResponse { ebx: leaf.ebx, edx: leaf.edx, ecx: leaf.ecx };
+        String::from_utf8_lossy(any_as_u8_slice(&vendor_response)).to_string()
     }
 
     unsafe fn get_apic_support() -> bool {
-        let edx: u32;
-
-        asm!("mov eax, 0x1; cpuid;");
-        asm!("mov {:e}, edx", out(reg) edx, options(nomem, nostack, preserves_flags));
-
-        is_nth_bit_set(edx as usize, 9)
+        is_nth_bit_set(cpuid(0x1).edx as usize, 9)
     }
 
     pub unsafe fn get_brand_string() -> String {
-        let eax: u32;
-        let ebx: u32;
-        let ecx: u32;
-        let edx: u32;
-
-        asm!("mov eax, 0x80000002; cpuid;");
-        asm!("mov {:e}, eax", out(reg) eax, options(nomem, nostack, preserves_flags));
-        asm!("mov {:e}, ebx", out(reg) ebx, options(nomem, nostack, preserves_flags));
-        asm!("mov {:e}, ecx", out(reg) ecx, options(nomem, nostack, preserves_flags));
-        asm!("mov {:e}, edx", out(reg) edx, options(nomem, nostack, preserves_flags));
-
-        let eax2: u32;
-        let ebx2: u32;
-        let ecx2: u32;
-        let edx2: u32;
-
-        asm!("mov eax, 0x80000003; cpuid;");
-        asm!("mov {:e}, eax", out(reg) eax2, options(nomem, nostack, preserves_flags));
-        asm!("mov {:e}, ebx", out(reg) ebx2, options(nomem, nostack, preserves_flags));
-        asm!("mov {:e}, ecx", out(reg) ecx2, options(nomem, nostack, preserves_flags));
-        asm!("mov {:e}, edx", out(reg) edx2, options(nomem, nostack, preserves_flags));
-
-        let eax3: u32;
-        let ebx3: u32;
-        let ecx3: u32;
-        let edx3: u32;
-
-        asm!("mov eax, 0x80000004; cpuid;");
-        asm!("mov {:e}, eax", out(reg) eax3, options(nomem, nostack, preserves_flags));
-        asm!("mov {:e}, ebx", out(reg) ebx3, options(nomem, nostack, preserves_flags));
-        asm!("mov {:e}, ecx", out(reg) ecx3, options(nomem, nostack, preserves_flags));
-        asm!("mov {:e}, edx", out(reg) edx3, options(nomem, nostack, preserves_flags));
-
-        let brand_response = BrandStringResponse { eax, ebx, ecx, edx, eax2, ebx2, ecx2, edx2, eax3, ebx3, ecx3, edx3, };
+        let first = cpuid(0x80000002);
+        let second = cpuid(0x80000003);
+        let third = cpuid(0x80000004);
+
+        let brand_response = BrandStringResponse {
+            eax: first.eax, ebx: first.ebx, ecx: first.ecx, edx: first.edx,
+            eax2: second.eax, ebx2: second.ebx, ecx2: second.ecx, edx2: second.edx,
+            eax3: third.eax, ebx3: third.ebx, ecx3: third.ecx, edx3: third.edx,
+        };
         str::from_utf8(any_as_u8_slice(&brand_response)).unwrap().to_string()
     }
 
@@ -141,4 +161,40 @@ impl CPUInfo {
             Err(err) => error!("{}", err),
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::string::String;
+    use crate::drivers::cpuid::{CPUInfo, CPUVendor};
+
+    #[test_case]
+    fn known_vendor_strings_are_recognized() {
+        // WHEN
+        let amd = CPUVendor::from_vendor_string("AuthenticAMD");
+        let intel = CPUVendor::from_vendor_string("GenuineIntel");
+
+        // THEN
+        assert_eq!(amd, CPUVendor::Amd);
+        assert_eq!(intel, CPUVendor::Intel);
+    }
+
+    #[test_case]
+    fn unknown_vendor_keeps_vendor_string() {
+        // WHEN
+        let vendor = CPUVendor::from_vendor_string("HygonGenuine");
+
+        // THEN
+        assert_eq!(vendor, CPUVendor::Unknown(String::from("HygonGenuine")));
+    }
+
+    #[test_case]
+    fn vendor_string_is_plausible() {
+        // WHEN
+        let vendor_string = unsafe { CPUInfo::get_vendor_string() };
+
+        // THEN
+        assert_eq!(vendor_string.len(), 12);
+        assert!(vendor_string.chars().all(|character| character.is_ascii_graphic()), "vendor string \"{}\"", vendor_string);
+    }
+}
@@ -1,23 +1,64 @@
 use alloc::boxed::Box;
 use alloc::string::String;
 use alloc::sync::Arc;
+use alloc::vec;
 use alloc::vec::Vec;
 use lazy_static::lazy_static;
 use limine::framebuffer::Framebuffer;
 use rlibc::memcpy;
 use spin::Mutex;
 use crate::fs::{Vfs, VfsNode, VfsNodeRef, VfsNodeWeakRef};
-use crate::memory::{PhysicalAddress, VirtualAddress};
+use crate::fs::device::{DeviceId, DeviceOperations, register_driver};
+use crate::HHDM_OFFSET;
+use crate::memory::{MemoryManager, VirtualAddress};
+use crate::memory::virtual_memory::paging::entry::EntryFlags;
 
 lazy_static! {
     pub static ref FB_DEVICES: Mutex<Vec<FrameBufferDevice>> = Mutex::new(Vec::new());
 }
 
+/// Major number of the framebuffer device nodes, the minor number tells the framebuffers apart
+pub const FRAMEBUFFER_MAJOR: u16 = 29;
+
+pub const FRAMEBUFFER_OPERATIONS: DeviceOperations = DeviceOperations {
+    read: read_framebuffer,
+    write: write_framebuffer,
+};
+
+/// Longest name a framebuffer device can have
+const MAX_NAME_LENGTH: usize = 32;
+const NAME_CANARY: u64 = 0xCAFE_F00D_FB0D_EAD5;
+
+/// Length-prefixed copy of the device name followed by a canary, so that writes running past the
+/// name can be detected
+#[derive(Clone)]
+#[repr(C)]
+struct GuardedName {
+    length: usize,
+    bytes: [u8; MAX_NAME_LENGTH],
+    canary: u64,
+}
+
+impl GuardedName {
+    fn new(name: &str) -> Self {
+        let mut bytes = [0; MAX_NAME_LENGTH];
+        bytes[..name.len()].copy_from_slice(name.as_bytes());
+
+        Self { length: name.len(), bytes, canary: NAME_CANARY }
+    }
+
+    fn is_intact(&self, name: &str) -> bool {
+        self.canary == NAME_CANARY && self.length <= MAX_NAME_LENGTH && &self.bytes[..self.length] == name.as_bytes()
+    }
+}
+
 #[derive(Clone)]
 pub struct FrameBufferDevice {
     name: String,
+    guarded_name: GuardedName,
     parent: Option<VfsNodeWeakRef>,
     children: Vec<VfsNodeRef>,
+    minor: u16,
     pub screen_info: FrameBufferScreenInfo,
 }
 
@@ -28,35 +69,177 @@ pub struct FrameBufferScreenInfo {
     pub height: u64,
     pub pitch: u64,
     pub bpp: u16,
+    pub red_shift: u8,
+    pub green_shift: u8,
+    pub blue_shift: u8,
+}
+
+impl FrameBufferScreenInfo {
+    /// Converts a 0xRRGGBBAA pixel to the layout of the framebuffer, dropping the alpha channel
+    pub fn native_pixel(&self, rgba: u32) -> u32 {
+        let red = rgba >> 24 & 0xFF;
+        let green = rgba >> 16 & 0xFF;
+        let blue = rgba >> 8 & 0xFF;
+
+        red << self.red_shift | green << self.green_shift | blue << self.blue_shift
+    }
+}
+
+/// Layout of the pixels passed to `FrameBufferDevice::blit_image`
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum PixelFormat {
+    /// 0xRRGGBBAA
+    Rgba,
+    /// Already in the layout of the framebuffer
+    Native,
+}
+
+/// Part of an image that remains on screen once clipped
+#[derive(Debug, Eq, PartialEq)]
+struct BlitRegion {
+    source_x: usize,
+    source_y: usize,
+    destination_x: usize,
+    destination_y: usize,
+    width: usize,
+    height: usize,
+}
+
+impl BlitRegion {
+    /// Clips an image placed at (x, y) to the screen, returns None if no pixel of it is visible
+    fn clip(x: isize, y: isize, width: usize, height: usize, screen_width: usize, screen_height: usize) -> Option<Self> {
+        let (source_x, destination_x, width) = clip_axis(x, width, screen_width)?;
+        let (source_y, destination_y, height) = clip_axis(y, height, screen_height)?;
+
+        Some(Self { source_x, source_y, destination_x, destination_y, width, height })
+    }
+
+    /// Index in the image of the first visible pixel of the given row
+    fn source_offset(&self, row: usize, image_width: usize) -> usize {
+        (self.source_y + row) * image_width + self.source_x
+    }
+
+    /// Offset in bytes in the framebuffer of the first visible pixel of the given row
+    fn destination_offset(&self, row: usize, pitch: usize, bytes_per_pixel: usize) -> usize {
+        (self.destination_y + row) * pitch + self.destination_x * bytes_per_pixel
+    }
+}
+
+/// Returns the first visible position in the image, its position on screen and the visible length
+fn clip_axis(position: isize, length: usize, screen_length: usize) -> Option<(usize, usize, usize)> {
+    let start = position.max(0);
+    let end = position.saturating_add(length as isize).min(screen_length as isize);
+
+    if end <= start {
+        return None;
+    }
+
+    Some(((start - position) as usize, start as usize, (end - start) as usize))
 }
 
 impl FrameBufferDevice {
-    /// Initialize a framebuffer device and add it to the list
+    /// Initialize a framebuffer device and add it to the list. The framebuffer is remapped as
+    /// write-combining, falling back to the bootloader mapping if that fails.
     pub fn init(framebuffer: &Framebuffer, name: String) {
+        let bootloader_address = framebuffer.addr() as VirtualAddress;
+        let size = (framebuffer.pitch() * framebuffer.height()) as usize;
+
+        let address = MemoryManager::map_physical(bootloader_address - *HHDM_OFFSET, size, EntryFlags::WRITABLE | EntryFlags::NO_EXECUTE | EntryFlags::WRITE_COMBINING)
+            .unwrap_or_else(|err| {
+                serial_println!("{}, {} stays mapped by the bootloader", err, name);
+                bootloader_address
+            });
+
         let screen_info = FrameBufferScreenInfo {
-            address: framebuffer.addr() as PhysicalAddress,
+            address,
             width: framebuffer.width(),
             height: framebuffer.height(),
             pitch: framebuffer.pitch(),
             bpp: framebuffer.bpp(),
+            red_shift: framebuffer.red_mask_shift(),
+            green_shift: framebuffer.green_mask_shift(),
+            blue_shift: framebuffer.blue_mask_shift(),
         };
 
-        let device = Self {
+        Self::add(Self::new(screen_info, name));
+    }
+
+    /// Adds the device to the list, numbering it after the devices already there. Returns its
+    /// minor number.
+    pub fn add(mut device: FrameBufferDevice) -> u16 {
+        let mut devices = FB_DEVICES.lock();
+        device.minor = devices.iter().map(|device| device.minor + 1).max().unwrap_or(0);
+        devices.push(device);
+
+        devices.last().unwrap().minor
+    }
+
+    pub fn new(screen_info: FrameBufferScreenInfo, name: String) -> Self {
+        assert!(name.len() <= MAX_NAME_LENGTH, "fbdev: device name is too long");
+
+        Self {
+            guarded_name: GuardedName::new(&name),
             name,
             parent: None,
             children: Vec::new(),
+            minor: 0,
             screen_info
+        }
+    }
+
+    /// Checks that neither the name nor its guarded copy were overwritten since the device was created
+    pub fn validate_name(&self) -> bool {
+        self.guarded_name.is_intact(&self.name)
+    }
+
+    /// Copies a width by height image to the framebuffer with its top left corner at (x, y).
+    /// Pixels falling outside the screen are skipped.
+    pub fn blit_image(&self, x: isize, y: isize, width: usize, height: usize, pixels: &[u32], format: PixelFormat) -> Result<(), &'static str> {
+        if pixels.len() < width * height {
+            return Err("fbdev: image is smaller than its dimensions");
+        }
+
+        let bytes_per_pixel = self.screen_info.bpp as usize / 8;
+        if bytes_per_pixel == 0 || bytes_per_pixel > 4 {
+            return Err("fbdev: unsupported pixel depth");
+        }
+
+        let Some(region) = BlitRegion::clip(x, y, width, height, self.screen_info.width as usize, self.screen_info.height as usize) else {
+            return Ok(());
         };
 
-        FB_DEVICES.lock().push(device);
+        let mut scanrow = vec![0u8; region.width * bytes_per_pixel];
+        for row in 0..region.height {
+            let source = &pixels[region.source_offset(row, width)..][..region.width];
+            for (pixel, bytes) in source.iter().zip(scanrow.chunks_exact_mut(bytes_per_pixel)) {
+                let pixel = match format {
+                    PixelFormat::Rgba => self.screen_info.native_pixel(*pixel),
+                    PixelFormat::Native => *pixel,
+                };
+                bytes.copy_from_slice(&pixel.to_le_bytes()[..bytes_per_pixel]);
+            }
+
+            let offset = region.destination_offset(row, self.screen_info.pitch as usize, bytes_per_pixel);
+            self.write(scanrow.as_ptr(), scanrow.len(), offset);
+        }
+
+        Ok(())
     }
 
     /// Registers all framebuffer devices previously initialized by adding them to the vfs
     pub fn register_devices() {
         let parent = Vfs::find_from_absolute_path("/dev").expect("fs: could not find /dev");
+        if let Err(err) = register_driver(FRAMEBUFFER_MAJOR, FRAMEBUFFER_OPERATIONS) {
+            error!("{}", err);
+        }
 
         let devices = FB_DEVICES.lock();
         devices.iter().for_each(|device| {
+            if !device.validate_name() {
+                // The corruption was first noticed after mounting the file system, see mount_filesystem in fs/ext2/mod.rs
+                error!("fbdev: name of framebuffer device corrupted, read {:02X?}", device.name.as_bytes());
+            }
+
             // Not sure cloning is the best idea here
             let fbdev = Arc::new(Mutex::new(Box::new(device.clone()) as Box<dyn VfsNode + Send>));
             Vfs::insert_child_node(parent.clone(), fbdev);
@@ -77,6 +260,18 @@ impl VfsNode for FrameBufferDevice {
         &mut self.children
     }
 
+    fn size(&self) -> usize {
+        (self.screen_info.pitch * self.screen_info.height) as usize
+    }
+
+    fn is_directory(&self) -> bool {
+        false
+    }
+
+    fn device_id(&self) -> Option<DeviceId> {
+        Some((FRAMEBUFFER_MAJOR, self.minor))
+    }
+
     fn open(&self) {
         todo!()
     }
@@ -85,11 +280,181 @@ impl VfsNode for FrameBufferDevice {
         todo!()
     }
 
-    fn read(&self, _buffer: *mut u8, _byte_count: usize, _offset: usize) {
-        todo!()
+    fn read(&self, buffer: *mut u8, byte_count: usize, offset: usize) {
+        if offset >= self.size() {
+            return;
+        }
+
+        let byte_count = byte_count.min(self.size() - offset);
+        unsafe { memcpy(buffer, (self.screen_info.address + offset) as *const u8, byte_count) };
     }
 
     fn write(&self, buffer: *const u8, byte_count: usize, offset: usize) {
         unsafe { memcpy((self.screen_info.address + offset) as *mut u8, buffer, byte_count) };
     }
-}
\ No newline at end of file
+}
+
+fn read_framebuffer(minor: u16, buffer: *mut u8, byte_count: usize, offset: usize) -> Result<(), &'static str> {
+    let devices = FB_DEVICES.lock();
+    let device = devices.iter().find(|device| device.minor == minor).ok_or("fbdev: no framebuffer with this minor number")?;
+
+    device.read(buffer, byte_count, offset);
+    Ok(())
+}
+
+fn write_framebuffer(minor: u16, buffer: *const u8, byte_count: usize, offset: usize) -> Result<(), &'static str> {
+    let devices = FB_DEVICES.lock();
+    let device = devices.iter().find(|device| device.minor == minor).ok_or("fbdev: no framebuffer with this minor number")?;
+
+    device.write(buffer, byte_count, offset);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::string::String;
+    use alloc::vec;
+    use crate::drivers::fbdev::{BlitRegion, FB_DEVICES, FRAMEBUFFER_MAJOR, FRAMEBUFFER_OPERATIONS, FrameBufferDevice, FrameBufferScreenInfo, MAX_NAME_LENGTH, PixelFormat};
+    use crate::fs::device::DeviceRegistry;
+    use crate::fs::VfsNode;
+
+    fn screen_info(width: u64, height: u64) -> FrameBufferScreenInfo {
+        FrameBufferScreenInfo { address: 0, width, height, pitch: width * 4, bpp: 32, red_shift: 16, green_shift: 8, blue_shift: 0 }
+    }
+
+    fn device(name: &str) -> FrameBufferDevice {
+        let screen_info = screen_info(0, 0);
+
+        FrameBufferDevice::new(screen_info, String::from(name))
+    }
+
+    #[test_case]
+    fn new_device_name_is_valid() {
+        // WHEN
+        let device = device("fb0");
+
+        // THEN
+        assert!(device.validate_name());
+    }
+
+    #[test_case]
+    fn write_past_name_buffer_is_detected() {
+        // GIVEN
+        let mut device = device("fb0");
+
+        // WHEN
+        unsafe {
+            let bytes = device.guarded_name.bytes.as_mut_ptr();
+            core::ptr::write_bytes(bytes, b'A', MAX_NAME_LENGTH + 1);
+        }
+
+        // THEN
+        assert!(!device.validate_name());
+    }
+
+    #[test_case]
+    fn overwritten_name_is_detected() {
+        // GIVEN
+        let mut device = device("fb0");
+
+        // WHEN
+        device.name = String::from("\u{1}\u{2}\u{3}");
+
+        // THEN
+        assert!(!device.validate_name());
+    }
+
+    #[test_case]
+    fn rgba_pixel_is_converted_to_framebuffer_layout() {
+        // GIVEN
+        let screen_info = screen_info(0, 0);
+
+        // WHEN
+        let pixel = screen_info.native_pixel(0x1122_33FF);
+
+        // THEN
+        assert_eq!(pixel, 0x0011_2233);
+    }
+
+    #[test_case]
+    fn image_on_screen_is_not_clipped() {
+        // WHEN
+        let region = BlitRegion::clip(10, 20, 4, 3, 100, 100).unwrap();
+
+        // THEN
+        assert_eq!(region, BlitRegion { source_x: 0, source_y: 0, destination_x: 10, destination_y: 20, width: 4, height: 3 });
+        assert_eq!(region.source_offset(2, 4), 8);
+        assert_eq!(region.destination_offset(2, 400, 4), 22 * 400 + 10 * 4);
+    }
+
+    #[test_case]
+    fn image_partially_off_screen_is_clipped() {
+        // WHEN
+        let region = BlitRegion::clip(-2, 97, 10, 8, 100, 100).unwrap();
+
+        // THEN
+        assert_eq!(region, BlitRegion { source_x: 2, source_y: 0, destination_x: 0, destination_y: 97, width: 8, height: 3 });
+        assert_eq!(region.source_offset(1, 10), 12);
+        assert_eq!(region.destination_offset(1, 400, 4), 98 * 400);
+    }
+
+    #[test_case]
+    fn image_past_bottom_right_corner_is_clipped() {
+        // WHEN
+        let region = BlitRegion::clip(95, -5, 10, 10, 100, 100).unwrap();
+
+        // THEN
+        assert_eq!(region, BlitRegion { source_x: 0, source_y: 5, destination_x: 95, destination_y: 0, width: 5, height: 5 });
+        assert_eq!(region.source_offset(0, 10), 50);
+    }
+
+    #[test_case]
+    fn image_fully_off_screen_is_skipped() {
+        // WHEN
+        let region = BlitRegion::clip(100, 0, 10, 10, 100, 100);
+
+        // THEN
+        assert_eq!(region, None);
+    }
+
+    #[test_case]
+    fn image_smaller_than_its_dimensions_is_rejected() {
+        // GIVEN
+        let device = device("fb0");
+
+        // WHEN
+        let result = device.blit_image(0, 0, 2, 2, &[0; 3], PixelFormat::Rgba);
+
+        // THEN
+        assert!(result.is_err());
+    }
+
+    #[test_case]
+    fn writes_are_routed_to_framebuffer_of_minor() {
+        // GIVEN
+        let first_pixels = vec![0u8; 16];
+        let second_pixels = vec![0u8; 16];
+        let device_at = |pixels: &[u8], name: &str| {
+            let screen_info = FrameBufferScreenInfo { address: pixels.as_ptr() as usize, ..screen_info(2, 2) };
+            FrameBufferDevice::new(screen_info, String::from(name))
+        };
+        let first_minor = FrameBufferDevice::add(device_at(&first_pixels, "fbtest0"));
+        let second_minor = FrameBufferDevice::add(device_at(&second_pixels, "fbtest1"));
+        let second_device = FB_DEVICES.lock().iter().find(|device| device.minor == second_minor).unwrap().clone();
+        let mut registry = DeviceRegistry::new();
+        registry.register(FRAMEBUFFER_MAJOR, FRAMEBUFFER_OPERATIONS).unwrap();
+
+        // WHEN
+        let first_write = registry.write((FRAMEBUFFER_MAJOR, first_minor), [1u8; 4].as_ptr(), 4, 0);
+        let second_write = registry.write(second_device.device_id().unwrap(), [2u8; 4].as_ptr(), 4, 8);
+        let missing_write = registry.write((FRAMEBUFFER_MAJOR, second_minor + 1), [3u8; 4].as_ptr(), 4, 0);
+        FB_DEVICES.lock().retain(|device| device.minor < first_minor);
+
+        // THEN
+        assert_eq!(second_minor, first_minor + 1);
+        assert_eq!((first_write, second_write), (Ok(()), Ok(())));
+        assert_eq!(missing_write, Err("fbdev: no framebuffer with this minor number"));
+        assert_eq!(first_pixels[..8], [1, 1, 1, 1, 0, 0, 0, 0]);
+        assert_eq!(second_pixels[..12], [0, 0, 0, 0, 0, 0, 0, 0, 2, 2, 2, 2]);
+    }
+}
@@ -0,0 +1,107 @@
+use conquer_once::spin::OnceCell;
+use core::ptr;
+use crate::memory::{MemoryManager, PhysicalAddress};
+use crate::memory::physical_memory::Frame;
+use crate::memory::virtual_memory::paging::entry::EntryFlags;
+
+const GENERAL_CAPABILITIES_REGISTER: usize = 0x00;
+const GENERAL_CONFIGURATION_REGISTER: usize = 0x10;
+const MAIN_COUNTER_REGISTER: usize = 0xF0;
+
+/// Set in the general configuration register to make the main counter run
+const ENABLE_COUNTER: u64 = 1 << 0;
+/// The specification caps the tick period at 100ns
+const MAX_TICK_PERIOD_FS: u64 = 100_000_000;
+const FEMTOSECONDS_PER_NANOSECOND: u128 = 1_000_000;
+
+static HPET: OnceCell<Hpet> = OnceCell::uninit();
+
+/// High Precision Event Timer, only its main counter is used
+pub struct Hpet {
+    base_address: PhysicalAddress,
+    /// Duration of a main counter tick in femtoseconds
+    tick_period_fs: u64,
+}
+
+impl Hpet {
+    /// Maps the HPET registers at the address found in the ACPI HPET table and starts the main counter
+    pub fn init(base_address: PhysicalAddress) -> Result<(), &'static str> {
+        MemoryManager::instance().lock().pmm_identity_map(Frame::containing_address(base_address), EntryFlags::WRITABLE | EntryFlags::NO_CACHE);
+
+        let mut hpet = Hpet { base_address, tick_period_fs: 0 };
+        hpet.tick_period_fs = hpet.read_register(GENERAL_CAPABILITIES_REGISTER) >> 32;
+        if hpet.tick_period_fs == 0 || hpet.tick_period_fs > MAX_TICK_PERIOD_FS {
+            return Err("hpet: invalid counter tick period");
+        }
+
+        let configuration = hpet.read_register(GENERAL_CONFIGURATION_REGISTER);
+        hpet.write_register(GENERAL_CONFIGURATION_REGISTER, configuration | ENABLE_COUNTER);
+
+        match HPET.try_init_once(|| hpet) {
+            Err(_) => Err("hpet: cannot initialize the hpet more than once"),
+            Ok(_) => Ok(())
+        }
+    }
+
+    pub fn instance() -> Option<&'static Hpet> {
+        HPET.get()
+    }
+
+    /// Reads the main counter
+    pub fn counter(&self) -> u64 {
+        self.read_register(MAIN_COUNTER_REGISTER)
+    }
+
+    /// Time elapsed since the main counter was started, in nanoseconds
+    pub fn now_ns(&self) -> u64 {
+        ticks_to_ns(self.counter(), self.tick_period_fs)
+    }
+
+    fn read_register(&self, offset: usize) -> u64 {
+        unsafe { ptr::read_volatile((self.base_address + offset) as *const u64) }
+    }
+
+    fn write_register(&self, offset: usize, value: u64) {
+        unsafe { ptr::write_volatile((self.base_address + offset) as *mut u64, value) }
+    }
+}
+
+/// Converts a number of main counter ticks to nanoseconds
+fn ticks_to_ns(ticks: u64, tick_period_fs: u64) -> u64 {
+    (ticks as u128 * tick_period_fs as u128 / FEMTOSECONDS_PER_NANOSECOND) as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::drivers::hpet::ticks_to_ns;
+
+    #[test_case]
+    fn ticks_to_ns_uses_femtosecond_period() {
+        // GIVEN
+        // QEMU's HPET runs at 100MHz, which is a 10ns period
+        let tick_period_fs = 10_000_000;
+
+        // WHEN
+        let one_tick = ticks_to_ns(1, tick_period_fs);
+        let one_second = ticks_to_ns(100_000_000, tick_period_fs);
+
+        // THEN
+        assert_eq!(one_tick, 10);
+        assert_eq!(one_second, 1_000_000_000);
+    }
+
+    #[test_case]
+    fn ticks_to_ns_handles_fractional_periods_without_overflow() {
+        // GIVEN
+        // A 14.31818MHz counter, common on real hardware
+        let tick_period_fs = 69_841_279;
+
+        // WHEN
+        let one_second = ticks_to_ns(14_318_180, tick_period_fs);
+        let large_count = ticks_to_ns(1 << 40, tick_period_fs);
+
+        // THEN
+        assert_eq!(one_second, 1_000_000_004);
+        assert_eq!(large_count, 76_791_298_359_247);
+    }
+}
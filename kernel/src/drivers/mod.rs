@@ -3,3 +3,23 @@ pub mod pci;
 pub mod cpuid;
 pub mod acpi;
 pub mod fbdev;
+pub mod pit;
+pub mod hpet;
+pub mod rtc;
+pub mod pcspeaker;
+pub mod watchdog;
+
+use alloc::vec::Vec;
+use core::ffi::c_void;
+use core::future::Future;
+
+/// A storage device addressed in bytes, regardless of its underlying sector size
+pub trait BlockDevice {
+    fn read_from_device(&mut self, byte_offset: u64, byte_count: u64, buffer: *mut c_void) -> usize;
+    fn write_to_device(&mut self, byte_offset: u64, byte_count: u64, buffer: *mut c_void);
+}
+
+/// A block device whose reads can be awaited, letting other tasks run while the transfer completes
+pub trait AsyncBlockDevice: BlockDevice {
+    fn read_async(&mut self, byte_offset: u64, byte_count: u64) -> impl Future<Output = Result<Vec<u8>, &'static str>>;
+}
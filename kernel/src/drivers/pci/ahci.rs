@@ -7,36 +7,135 @@
 
 #![allow(clippy::while_immutable_condition)]
 
+use alloc::string::String;
+use alloc::vec;
 use alloc::vec::Vec;
 use core::arch::asm;
+use core::fmt;
+use core::fmt::Formatter;
 use core::ffi::c_void;
+use core::future::{Future, poll_fn};
 use core::mem::size_of;
-use core::ptr;
-use crate::drivers::pci::{find_all_pci_devices, PCIDevice};
+use core::{ptr, slice};
+use core::task::Poll;
+use core::sync::atomic::{compiler_fence, Ordering};
+#[cfg(test)]
+use core::sync::atomic::AtomicUsize;
+use crate::drivers::{AsyncBlockDevice, BlockDevice};
+use volatile_register::{RO, RW};
+use crate::drivers::watchdog::WatchdogGuard;
+use crate::drivers::pci::{BaseAddress, find_all_pci_devices, PCIDevice};
 use crate::memory::{MemoryManager, PhysicalAddress};
 use crate::memory::physical_memory::Frame;
 use crate::memory::virtual_memory::paging::entry::EntryFlags;
+use crate::time;
 use crate::utils::bitutils::is_nth_bit_set;
 
+const PORT_TFD_BSY: u32 = 1 << 7;
+const PORT_TFD_DRQ: u32 = 1 << 3;
+const PORT_TFD_ERR: u32 = 1 << 0;
+const PORT_CMD_ST: u32 = 1 << 0;
+const PORT_CMD_CR: u32 = 1 << 15;
+const PORT_CMD_FRE: u32 = 1 << 4;
+const PORT_CMD_FR: u32 = 1 << 14;
+const PORT_SCTL_DET: u32 = 0xF;
+const PORT_SSTS_DET: u32 = 0xF;
+const PORT_SSTS_DET_PRESENT: u32 = 0x3;
+const GHC_HR: u32 = 1 << 0;
+const GHC_IE: u32 = 1 << 1;
+const GHC_AE: u32 = 1 << 31;
+
+/// Number of times a command is issued before giving up when the device keeps reporting errors
+const MAX_COMMAND_ATTEMPTS: usize = 4;
+/// Number of failed attempts recovered with a port reset, the following ones reset the whole controller
+const PORT_RESETS_BEFORE_CONTROLLER_RESET: usize = 2;
+/// Largest transfer described by a single PRDT entry, its byte count field is 22 bits wide
+const MAX_PRDT_BYTES: u64 = 4 * 1024 * 1024;
+/// Time given to the device to come back after a COMRESET
+const PORT_RESET_TIMEOUT_NS: u64 = 10_000_000;
+/// Time the HBA has to complete a reset, per the AHCI specification
+const HBA_RESET_TIMEOUT_NS: u64 = 1_000_000_000;
+/// Time a command is given to complete before it is aborted, unless the drive was configured otherwise
+const DEFAULT_COMMAND_TIMEOUT_NS: u64 = 5_000_000_000;
+/// Time the command engine has to stop when a command is aborted or the port reset, per the AHCI
+/// specification
+const COMMAND_ENGINE_STOP_TIMEOUT_NS: u64 = 500_000_000;
+/// Time a command may run past its timeout before the watchdog reports it, aborting it takes a while
+const COMMAND_WATCHDOG_SLACK_MS: u64 = 1000;
+
 const SATA_SIG_ATA: u32     = 0x00000101;   // SATA drive
 const SATA_SIG_ATAPI: u32   = 0xEB140101;   // SATAPI drive
 const SATA_SIG_SEMB: u32    = 0xC33C0101;   // Enclosure management bridge
 const SATA_SIG_PM: u32      = 0x96690101;    // Port multiplier
 
 
-const FIS_TYPE_REG_H2D: u8      = 0x27;
-const FIS_TYPE_REG_D2H: u8      = 0x34;
-const FIS_TYPE_DMA_ACT: u8      = 0x39;
-const FIS_TYPE_DMA_SETUP: u8    = 0x41;
-const FIS_TYPE_DATA: u8         = 0x46;
-const FIS_TYPE_BIST: u8         = 0x58;
-const FIS_TYPE_PIO_SETUP: u8   = 0x5F;
-const FIS_TYPE_DEV_BITS: u8     = 0xA1;
+#[repr(u8)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum FisType {
+    RegH2D = 0x27,      // Register FIS, host to device
+    RegD2H = 0x34,      // Register FIS, device to host
+    DmaAct = 0x39,      // DMA activate FIS, device to host
+    DmaSetup = 0x41,    // DMA setup FIS, bidirectional
+    Data = 0x46,        // Data FIS, bidirectional
+    Bist = 0x58,        // BIST activate FIS, bidirectional
+    PioSetup = 0x5F,    // PIO setup FIS, device to host
+    DevBits = 0xA1,     // Set device bits FIS, device to host
+}
+
+impl TryFrom<u8> for FisType {
+    type Error = &'static str;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0x27 => Ok(FisType::RegH2D),
+            0x34 => Ok(FisType::RegD2H),
+            0x39 => Ok(FisType::DmaAct),
+            0x41 => Ok(FisType::DmaSetup),
+            0x46 => Ok(FisType::Data),
+            0x58 => Ok(FisType::Bist),
+            0x5F => Ok(FisType::PioSetup),
+            0xA1 => Ok(FisType::DevBits),
+            _ => Err("ahci: unknown FIS type"),
+        }
+    }
+}
+
+/// ATA commands issued through a register host to device FIS, see ATA8-ACS section 7
+#[repr(u8)]
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum AtaCommand {
+    ReadDmaExt = 0x25,
+    WriteDmaExt = 0x35,
+    FlushCacheExt = 0xEA,
+    IdentifyDevice = 0xEC,
+}
+
+impl TryFrom<u8> for AtaCommand {
+    type Error = &'static str;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0x25 => Ok(AtaCommand::ReadDmaExt),
+            0x35 => Ok(AtaCommand::WriteDmaExt),
+            0xEA => Ok(AtaCommand::FlushCacheExt),
+            0xEC => Ok(AtaCommand::IdentifyDevice),
+            _ => Err("ahci: unknown ATA command"),
+        }
+    }
+}
+
+/// Writes the header of a register host to device FIS issuing the given command
+fn write_command_fis_header(command_fis: &mut [u8; 64], command: AtaCommand) {
+    command_fis.fill(0);
+    command_fis[0] = FisType::RegH2D as u8;
+    command_fis[1] = 1 << 7; // command, not control
+    command_fis[2] = command as u8;
+}
 
 
 #[repr(C)]
 struct FisRegH2D {
-    fis_type: u8,   // FIS_TYPE_REG_H2D
+    fis_type: u8,   // FisType::RegH2D
 
     flags: u8,     // Port multiplier
 
@@ -63,7 +162,7 @@ struct FisRegH2D {
 
 #[repr(C)]
 struct FisDmaSetup {
-    fis_type: u8,       // FIS_TYPE_REG_DMA_SETUP
+    fis_type: u8,       // FisType::DmaSetup
 
     flags: u8,         // Port multiplier
 
@@ -83,7 +182,7 @@ struct FisDmaSetup {
 
 #[repr(C)]
 struct FisPioSetup {
-    fis_type: u8,   // FIS_TYPE_REG_PIO_SETUP
+    fis_type: u8,   // FisType::PioSetup
 
     pmport: u8,     // Port multiplier
 
@@ -111,7 +210,7 @@ struct FisPioSetup {
 
 #[repr(C)]
 struct FisRegD2H {
-    fis_type: u8,   // FIS_TYPE_REG_D2H
+    fis_type: u8,   // FisType::RegD2H
 
     pmport: u8,     // Port multiplier
 
@@ -158,20 +257,19 @@ struct ReceivedFis {
 }
 
 #[repr(C)]
-#[derive(Debug)]
 struct HbaMemoryRegisters {
     // 0x00 - 0x2B, Generic Host Control
-    cap: u32,
-    ghc: u32,
-    is: u32,
-    pi: u32,
-    vs: u32,
-    ccc_ctl: u32,
-    ccc_pts: u32,
-    em_loc: u32,
-    em_ctl: u32,
-    cap2: u32,
-    bohc: u32,
+    cap: RO<u32>,
+    ghc: RW<u32>,
+    is: RW<u32>,
+    pi: RO<u32>,
+    vs: RO<u32>,
+    ccc_ctl: RW<u32>,
+    ccc_pts: RW<u32>,
+    em_loc: RW<u32>,
+    em_ctl: RW<u32>,
+    cap2: RO<u32>,
+    bohc: RW<u32>,
 
     // 0x2C - 0x9F, Reserved
     rsv: [u8; 0xA0-0x2C],
@@ -181,28 +279,27 @@ struct HbaMemoryRegisters {
 }
 
 #[repr(C)]
-#[derive(Debug)]
 pub struct PortRegisters {
-    clb: u32,
-    clbu: u32,
-    fb: u32,
-    fbu: u32,
-    is: u32,
-    ie: u32,
-    cmd: u32,
+    clb: RW<u32>,
+    clbu: RW<u32>,
+    fb: RW<u32>,
+    fbu: RW<u32>,
+    is: RW<u32>,
+    ie: RW<u32>,
+    cmd: RW<u32>,
 
     rsv: u32,
 
-    tfd: u32,
-    sig: u32,
-    ssts: u32,
-    sctl: u32,
-    serr: u32,
-    sact: u32,
-    ci: u32,
-    sntf: u32,
-    fbs: u32,
-    devslp: u32,
+    tfd: RO<u32>,
+    sig: RO<u32>,
+    ssts: RO<u32>,
+    sctl: RW<u32>,
+    serr: RW<u32>,
+    sact: RW<u32>,
+    ci: RW<u32>,
+    sntf: RW<u32>,
+    fbs: RW<u32>,
+    devslp: RW<u32>,
 
     // 0x48 - 6F, Reserved
     rsv2: [u8; 0x70-0x48],
@@ -211,6 +308,37 @@ pub struct PortRegisters {
     vendor: [u8; 0x80-0x71],
 }
 
+// SAFETY: every access to the registers is a single volatile read or write of 32 bits, which the
+// HBA handles atomically
+unsafe impl Sync for HbaMemoryRegisters {}
+
+impl fmt::Debug for HbaMemoryRegisters {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("HbaMemoryRegisters")
+            .field("cap", &self.cap.read())
+            .field("ghc", &self.ghc.read())
+            .field("is", &self.is.read())
+            .field("pi", &self.pi.read())
+            .field("vs", &self.vs.read())
+            .finish_non_exhaustive()
+    }
+}
+
+impl fmt::Debug for PortRegisters {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PortRegisters")
+            .field("is", &self.is.read())
+            .field("cmd", &self.cmd.read())
+            .field("tfd", &self.tfd.read())
+            .field("sig", &self.sig.read())
+            .field("ssts", &self.ssts.read())
+            .field("serr", &self.serr.read())
+            .field("sact", &self.sact.read())
+            .field("ci", &self.ci.read())
+            .finish_non_exhaustive()
+    }
+}
+
 
 type CommandList = [CommandHeader; 32];
 #[repr(C)]
@@ -371,7 +499,7 @@ struct AHCIIdentifyResponse {
 struct AHCIController {
     pci_device: PCIDevice,
 
-    bar5: u32,
+    abar: PhysicalAddress,
     version_maj: u32,
     version_min: u32,
     port_count: u32,
@@ -381,37 +509,57 @@ struct AHCIController {
 }
 
 impl AHCIController {
-    fn new(pci_device: PCIDevice) -> Self {
+    fn new(pci_device: PCIDevice) -> Result<Self, &'static str> {
+        let BaseAddress::Memory { address: abar, .. } = pci_device.bar(0, 5)? else {
+            return Err("ahci: ABAR is not a memory BAR");
+        };
+
         // Memory map HBA registers as uncacheable.
-        let bar5 = pci_device.bar5(0);
-        let start_frame = Frame::containing_address(bar5 as PhysicalAddress);
-        let end_frame = Frame::containing_address(bar5 as PhysicalAddress + 0x10FF);
-        for frame in Frame::range_inclusive(start_frame, end_frame) {
-            MemoryManager::instance().lock().pmm_identity_map(frame, EntryFlags::WRITABLE | EntryFlags::NO_CACHE);
-        }
+        let start_frame = Frame::containing_address(abar);
+        let end_frame = Frame::containing_address(abar + 0x10FF);
+        MemoryManager::instance().lock().pmm_identity_map_range(start_frame, end_frame, EntryFlags::WRITABLE | EntryFlags::NO_CACHE);
 
-        let hba = unsafe { &*(bar5 as *mut HbaMemoryRegisters) };
+        let hba = unsafe { &*(abar as *mut HbaMemoryRegisters) };
 
-        let version_maj = (hba.vs >> 16) & 0xFFFF;
-        let version_min = hba.vs & 0xFFFF;
-        let port_count = hba.cap & 0b11111;
-        let slot_count = (hba.cap >> 8) & 0b11111;
+        let version_maj = (hba.vs.read() >> 16) & 0xFFFF;
+        let version_min = hba.vs.read() & 0xFFFF;
+        let port_count = hba.cap.read() & 0b11111;
+        let slot_count = (hba.cap.read() >> 8) & 0b11111;
 
-        Self {
+        Ok(Self {
             pci_device,
 
-            bar5,
+            abar,
             version_maj,
             version_min,
             port_count,
             slot_count,
 
             hba
+        })
+    }
+
+    /// Recovers a wedged controller with an HBA reset, then enables AHCI mode and interrupts again.
+    /// The reset clears the registers of every port, the owner of each port writes the addresses of
+    /// its command list and received FIS back before its next command, see `PortOwner::restore_memory`.
+    fn reset(&self) -> Result<(), &'static str> {
+        unsafe { self.hba.ghc.modify(|ghc| ghc | GHC_HR) };
+        let deadline = time::now_ns() + HBA_RESET_TIMEOUT_NS;
+        if !wait_for_bits_clear(|| self.hba.ghc.read(), GHC_HR, || time::now_ns() >= deadline) {
+            return Err("ahci: controller did not complete its reset in time");
+        }
+
+        unsafe {
+            self.hba.ghc.modify(|ghc| ghc | GHC_AE);
+            self.hba.ghc.modify(|ghc| ghc | GHC_IE);
+            self.hba.is.write(u32::MAX);
         }
+
+        Ok(())
     }
 
     fn bios_os_handoff(&self) {
-        if !is_nth_bit_set(self.hba.cap2 as usize, 0) {
+        if !is_nth_bit_set(self.hba.cap2.read() as usize, 0) {
             warn!("ahci: bios/os handoff not supported");
         }
 
@@ -419,98 +567,209 @@ impl AHCIController {
     }
 }
 
+/// Stable identifier of a drive, made of the index of its controller in PCI enumeration order and
+/// of the port it is attached to
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd)]
+pub struct DriveId {
+    pub controller: usize,
+    pub port: usize,
+}
+
+impl fmt::Display for DriveId {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "ahci{}p{}", self.controller, self.port)
+    }
+}
+
 #[derive(Debug)]
 pub struct AHCIDevice {
+    id: DriveId,
     controller: AHCIController,
     port_index: usize,
 
     identity: Option<AHCIIdentifyResponse>,
+    command_timeout_ns: u64,
 
-    pub port_registers: &'static mut PortRegisters,
+    port: PortOwner,
+}
 
-    command_list: [AHCICommand; 32],
+impl BlockDevice for AHCIDevice {
+    fn read_from_device(&mut self, byte_offset: u64, byte_count: u64, buffer: *mut c_void) -> usize {
+        AHCIDevice::read_from_device(self, byte_offset, byte_count, buffer)
+    }
+
+    fn write_to_device(&mut self, byte_offset: u64, byte_count: u64, buffer: *mut c_void) {
+        AHCIDevice::write_to_device(self, byte_offset, byte_count, buffer)
+    }
 }
 
-impl AHCIDevice {
-    fn new(controller: AHCIController, port_index: usize, port_address: usize) -> Self {
-        let port_registers = unsafe { &mut *(port_address as *mut PortRegisters) };
+impl AsyncBlockDevice for AHCIDevice {
+    fn read_async(&mut self, byte_offset: u64, byte_count: u64) -> impl Future<Output = Result<Vec<u8>, &'static str>> {
+        AHCIDevice::read_async(self, byte_offset, byte_count)
+    }
+}
 
+impl AHCIDevice {
+    fn new(id: DriveId, controller: AHCIController, port_index: usize, port_address: usize) -> Self {
         Self {
+            id,
             controller,
             port_index,
 
             identity: None,
+            command_timeout_ns: DEFAULT_COMMAND_TIMEOUT_NS,
 
-            port_registers,
-
-            command_list: [AHCICommand::new(); 32],
+            port: PortOwner::new(port_address),
         }
     }
 
+    /// Sets the time commands sent to the drive are given to complete before being aborted
+    pub fn set_command_timeout(&mut self, timeout_ns: u64) {
+        self.command_timeout_ns = timeout_ns;
+    }
+
+    pub fn id(&self) -> DriveId {
+        self.id
+    }
+
+    /// Model number reported by the drive when it was identified
+    pub fn model(&self) -> Option<String> {
+        self.identity.map(|identity| ata_string(&identity.model))
+    }
+
+    /// Serial number reported by the drive when it was identified
+    pub fn serial(&self) -> Option<String> {
+        self.identity.map(|identity| ata_string(&identity.serial_no))
+    }
+
     /// Reads byte_count bytes from the device at address offset. Returns the number of bytes reads from the device
     pub fn read_from_device(&mut self, byte_offset: u64, byte_count: u64, buffer: *mut c_void) -> usize {
         let identity = &self.identity.expect("ahci: cannot read from an unidentified device");
         let sector_size = identity.sector_bytes as u64;
 
-        let start_block = byte_offset / sector_size;
-        let mut block_count = byte_count.div_ceil(sector_size);
-
-        if byte_count % sector_size == 0 && byte_offset % sector_size != 0 {
-            block_count += 1;
-        }
-        if byte_offset % sector_size + block_count > sector_size {
-            block_count += 1;
-        }
+        let (start_block, block_count) = sector_span(byte_offset, byte_count, sector_size);
 
         if block_count == 0 {
             return 0;
         }
 
-        let read_buffer_address = MemoryManager::pmm_identity(byte_count as usize, EntryFlags::WRITABLE)
+        let read_buffer_size = (block_count * sector_size) as usize;
+        let read_buffer_address = MemoryManager::pmm_identity(read_buffer_size, EntryFlags::WRITABLE)
             .expect("ahci: could not allocate the memory for device read");
 
-        let read_sectors = self.issue_read(start_block, block_count, read_buffer_address as *mut c_void);
+        let read_sectors = match self.issue_read(start_block, block_count, read_buffer_address as *mut c_void) {
+            Ok(read_sectors) => read_sectors,
+            Err(err) => {
+                error!("{}, reading 0x{:X} bytes at 0x{:X} failed", err, byte_count, byte_offset);
+                MemoryManager::pmm_free(read_buffer_size, read_buffer_address);
+                return 0;
+            }
+        };
 
         unsafe { ptr::copy_nonoverlapping((read_buffer_address + (byte_offset % sector_size) as usize) as *const c_void, buffer, byte_count as usize); }
 
-        MemoryManager::pmm_free(byte_count as usize, read_buffer_address);
+        MemoryManager::pmm_free(read_buffer_size, read_buffer_address);
 
         read_sectors - read_sectors.abs_diff(byte_count as usize)
     }
 
-    pub fn write_to_device(&mut self, byte_offset: u64, byte_count: u64, buffer: *mut c_void) {
-        let identity = &self.identity.expect("ahci: cannot write to an unidentified device");
+    /// Reads byte_count bytes from the device at address offset. Instead of busy-waiting, the command
+    /// completion is checked every time the future is polled, letting other tasks run during the
+    /// transfer. Dropping the future before the read completed aborts it.
+    pub async fn read_async(&mut self, byte_offset: u64, byte_count: u64) -> Result<Vec<u8>, &'static str> {
+        let identity = &self.identity.expect("ahci: cannot read from an unidentified device");
         let sector_size = identity.sector_bytes as u64;
 
-        let start_block = byte_offset / sector_size;
-        let block_count = byte_count.div_ceil(sector_size);
+        let (start_block, block_count) = sector_span(byte_offset, byte_count, sector_size);
+
+        if block_count == 0 {
+            return Ok(Vec::new());
+        }
+
+        let read_buffer_size = (block_count * sector_size) as usize;
+        let read_buffer_address = MemoryManager::pmm_identity(read_buffer_size, EntryFlags::WRITABLE)
+            .ok_or("ahci: could not allocate the memory for device read")?;
+
+        let command_number = self.prepare_read(start_block, block_count, read_buffer_address as *mut c_void);
+        let slot = self.port.command_list[command_number].slot;
+        let mut read = PendingRead { device: self, slot, buffer_address: read_buffer_address, buffer_size: read_buffer_size, in_flight: false };
+        let deadline = CommandDeadline::new(time::now_ns(), read.device.command_timeout_ns);
+
+        let completion = match read.device.start_command(command_number, &deadline) {
+            Ok(_) => {
+                read.in_flight = true;
+                let completion = poll_fn(|context| {
+                    if !is_command_pending(read.device.port.registers, slot) {
+                        return Poll::Ready(Ok(()));
+                    }
+
+                    let now = time::now_ns();
+                    if now >= deadline.deadline_ns {
+                        return Poll::Ready(Err(TimeoutError { elapsed_ns: now - deadline.start_ns }));
+                    }
+
+                    context.waker().wake_by_ref();
+                    Poll::Pending
+                }).await;
+                read.in_flight = false;
+
+                completion
+            }
+            Err(error) => Err(error),
+        };
 
-        let write_buffer_address = {
-            MemoryManager::pmm_identity(byte_count as usize, EntryFlags::WRITABLE)
-                .expect("ahci: could not allocate the memory for device write")
+        let device = &mut *read.device;
+        let result = match completion {
+            Ok(()) => device.finish_command(&deadline),
+            Err(error) => {
+                device.abort_timed_out_command(error);
+                Err(CommandError::Timeout(error))
+            }
         };
 
-        if byte_offset % sector_size != 0 {
-            self.read_from_device(byte_offset, sector_size, write_buffer_address as *mut c_void);
+        // Failed commands are retried synchronously, the failed attempt counts as the first one
+        if let Err(error) = result {
+            device.recover_command(error, 1);
+            device.issue_command_after(command_number, 1)?;
         }
-        if byte_count % sector_size != 0 {
-            self.read_from_device(byte_offset + block_count - 1, sector_size, write_buffer_address as *mut c_void);
+
+        let mut data = vec![0u8; byte_count as usize];
+        unsafe { ptr::copy_nonoverlapping((read.buffer_address + (byte_offset % sector_size) as usize) as *const u8, data.as_mut_ptr(), data.len()); }
+
+        Ok(data)
+    }
+
+    /// Writes byte_count bytes from the buffer to the device at address offset. Writes are split in
+    /// chunks small enough to be described by a single PRDT entry.
+    pub fn write_to_device(&mut self, byte_offset: u64, byte_count: u64, buffer: *mut c_void) {
+        let identity = &self.identity.expect("ahci: cannot write to an unidentified device");
+        let sector_size = identity.sector_bytes as u64;
+
+        let (_, block_count) = sector_span(byte_offset, byte_count, sector_size);
+
+        if block_count == 0 {
+            return;
         }
 
-        unsafe { ptr::copy_nonoverlapping(buffer, (write_buffer_address + (byte_offset % sector_size) as usize) as *mut c_void, byte_count as usize)};
+        let chunk_size = (block_count * sector_size).min(MAX_PRDT_BYTES - MAX_PRDT_BYTES % sector_size) as usize;
+        let chunk_address = MemoryManager::pmm_identity(chunk_size, EntryFlags::WRITABLE)
+            .expect("ahci: could not allocate the memory for device write");
 
-        self.issue_write(start_block, block_count, write_buffer_address as *mut c_void);
+        let chunk = unsafe { slice::from_raw_parts_mut(chunk_address as *mut u8, chunk_size) };
+        let data = unsafe { slice::from_raw_parts(buffer as *const u8, byte_count as usize) };
 
-        MemoryManager::pmm_free(byte_count as usize, write_buffer_address);
+        if let Err(err) = write_in_chunks(self, byte_offset, data, chunk) {
+            error!("{}, writing 0x{:X} bytes at 0x{:X} failed", err, byte_count, byte_offset);
+        }
 
-        //written_sectors - written_sectors.abs_diff(byte_count as usize)
+        MemoryManager::pmm_free(chunk_size, chunk_address);
     }
 
-    fn issue_identify(&mut self, identity: *mut AHCIIdentifyResponse) {
+    fn issue_identify(&mut self, identity: *mut AHCIIdentifyResponse) -> Result<(), &'static str> {
         let command_number = self.allocate_slot();
 
         {
-            let command = &mut self.command_list[command_number];
+            let command = &mut self.port.command_list[command_number];
 
             command.destination_address = identity as *mut c_void;
             command.data_length = 511;
@@ -524,22 +783,28 @@ impl AHCIDevice {
             let command_table = unsafe{ &mut *command.command_table };
             let command_pointer = &mut command_table.cfis;
 
-            command_pointer.fill(0);
-            command_pointer[0] = FIS_TYPE_REG_H2D; // FIS_TYPE
-            command_pointer[1] = 1 << 7;  // flags
-            command_pointer[2] = 0xEC;  // device
+            write_command_fis_header(command_pointer, AtaCommand::IdentifyDevice);
         }
 
 
         self.init_prdt(command_number);
-        self.issue_command(command_number);
+        self.issue_command(command_number)
     }
 
     /// Reads sector_count amount of sectors from the device and writes it to buffer. Returns the amount of sectors read from the device
-    fn issue_read(&mut self, sector_offset: u64, sector_count: u64, buffer: *mut c_void) -> usize {
+    fn issue_read(&mut self, sector_offset: u64, sector_count: u64, buffer: *mut c_void) -> Result<usize, &'static str> {
+        let command_number = self.prepare_read(sector_offset, sector_count, buffer);
+        self.issue_command(command_number)?;
+
+        let command_header = unsafe { &*self.port.command_list[command_number].command_header };
+        Ok(command_header.prdbc as usize)
+    }
+
+    /// Fills a command slot with a read of sector_count sectors into buffer and returns its number
+    fn prepare_read(&mut self, sector_offset: u64, sector_count: u64, buffer: *mut c_void) -> usize {
         let command_number = self.allocate_slot();
 
-        let command = &mut self.command_list[command_number];
+        let command = &mut self.port.command_list[command_number];
 
         command.destination_address = buffer;
         command.data_length = (sector_count * 0x200 - 1) as usize;
@@ -554,10 +819,7 @@ impl AHCIDevice {
         let command_table = unsafe{ &mut *command.command_table };
         let command_pointer = &mut command_table.cfis;
 
-        command_pointer.fill(0);
-        command_pointer[0] = FIS_TYPE_REG_H2D; // FIS_TYPE
-        command_pointer[1] = 1 << 7; // flags
-        command_pointer[2] = 0x25; // command
+        write_command_fis_header(command_pointer, AtaCommand::ReadDmaExt);
         command_pointer[7] = 1 << 6; // device
 
         command_pointer[4] = sector_offset as u8; // LBA0
@@ -571,17 +833,16 @@ impl AHCIDevice {
         command_pointer[13] = (sector_count >> 8) as u8; // counth
 
         self.init_prdt(command_number);
-        self.issue_command(command_number);
 
-        command_header.prdbc as usize
+        command_number
     }
 
     /// Writes sector_count amount of sectors from the buffer and writes it to the device
-    fn issue_write(&mut self, sector_offset: u64, sector_count: u64, buffer: *mut c_void) {
+    fn issue_write(&mut self, sector_offset: u64, sector_count: u64, buffer: *mut c_void) -> Result<(), &'static str> {
         let command_number = self.allocate_slot();
 
         {
-            let command = &mut self.command_list[command_number];
+            let command = &mut self.port.command_list[command_number];
 
             let command_header = unsafe{ &mut *command.command_header };
             command_header.flags &= !(0b11111 | (1 << 6));
@@ -596,10 +857,7 @@ impl AHCIDevice {
             let command_table = unsafe{ &mut *command.command_table };
             let command_pointer = &mut command_table.cfis;
 
-            command_pointer.fill(0);
-            command_pointer[0] = FIS_TYPE_REG_H2D; // FIS_TYPE
-            command_pointer[1] = 1 << 7; // flags
-            command_pointer[2] = 0x35; // command
+            write_command_fis_header(command_pointer, AtaCommand::WriteDmaExt);
             command_pointer[7] = 1 << 6; // device
 
             command_pointer[4] = sector_offset as u8; // LBA0
@@ -614,34 +872,16 @@ impl AHCIDevice {
         }
 
         self.init_prdt(command_number);
-        self.issue_command(command_number);
+        self.issue_command(command_number)
     }
 
     fn allocate_slot(&mut self) -> usize {
-        let slot_count = self.controller.slot_count;
-
-        for i in 0..slot_count {
-            // Find the first empty command slot
-            if !is_nth_bit_set(self.port_registers.sact as usize, i as usize) && !is_nth_bit_set(self.port_registers.ci as usize, i as usize) {
-                let command_header_address = (self.port_registers.clb as usize | ((self.port_registers.clbu as usize) << 32)) + i as usize * size_of::<CommandHeader>();
-                let command_header = unsafe { &*(command_header_address as *const CommandHeader )};
-
-                let command_table_address = (command_header.ctba as usize | ((command_header.ctbau as usize) << 32)) + i as usize * size_of::<CommandTable>();
-
-                self.command_list[i as usize].ahci_device = self as *mut AHCIDevice;
-                self.command_list[i as usize].command_header = command_header_address as *mut CommandHeader;
-                self.command_list[i as usize].command_table = command_table_address as *mut CommandTable;
-                self.command_list[i as usize].slot = i;
-
-                return i as usize
-            }
-        }
-
-        panic!("ahci: unable to allocate command slot");
+        self.port.allocate_slot(self.controller.slot_count)
+            .expect("ahci: unable to allocate command slot")
     }
 
     fn init_prdt(&mut self, command_number: usize) {
-        let command = &self.command_list[command_number];
+        let command = &self.port.command_list[command_number];
         let command_table = unsafe{ &mut *command.command_table };
 
         command_table.rsv.fill(0);
@@ -651,56 +891,396 @@ impl AHCIDevice {
         command_table.first_prdt_entry.reserved = 0;
     }
 
-    fn issue_command(&mut self, command_number: usize) {
-        const PORT_TFD_BSY: u32 = 1 << 7;
-        const PORT_TFD_DRQ: u32 = 1 << 3;
-        const PORT_TFD_ERR: u32 = 1 << 0;
-        const PORT_CMD_ST: u32 = 1 << 0;
-        const PORT_CMD_CR: u32 = 1 << 15;
-        const PORT_CMD_FRE: u32 = 1 << 4;
-        const PORT_CMD_FR: u32 = 1 << 14;
+    /// Issues the command and retries it when the device reports an error or does not answer in time.
+    /// The first failures reset the port, the controller is reset if that did not help.
+    fn issue_command(&mut self, command_number: usize) -> Result<(), &'static str> {
+        self.issue_command_after(command_number, 0)
+    }
+
+    /// Issues the command until it succeeds, `failed_attempts` of it were already made and recovered from
+    fn issue_command_after(&mut self, command_number: usize, mut failed_attempts: usize) -> Result<(), &'static str> {
+        retry_command(self, MAX_COMMAND_ATTEMPTS - failed_attempts, |device| device.try_issue_command(command_number), |device, error| {
+            failed_attempts += 1;
+            device.recover_command(error, failed_attempts);
+        })
+    }
+
+    /// Brings the port back to a state accepting commands after the failed attempt of a command,
+    /// `failed_attempts` counts it
+    fn recover_command(&mut self, error: CommandError, failed_attempts: usize) {
+        if failed_attempts <= PORT_RESETS_BEFORE_CONTROLLER_RESET {
+            warn!("ahci: command failed on port {} ({}), resetting the port", self.port_index, error);
+            self.reset_port();
+            return;
+        }
+
+        warn!("ahci: command still failing on port {} ({}), resetting the controller", self.port_index, error);
+        if let Err(err) = self.controller.reset() {
+            warn!("{}", err);
+        }
+    }
+
+    fn try_issue_command(&mut self, command_number: usize) -> Result<(), CommandError> {
+        let _watchdog = WatchdogGuard::arm("ahci: issue_command", self.command_timeout_ns.div_ceil(1_000_000) + COMMAND_WATCHDOG_SLACK_MS);
+        let deadline = CommandDeadline::new(time::now_ns(), self.command_timeout_ns);
 
-        let command = &self.command_list[command_number];
+        let completion = self.start_command(command_number, &deadline)
+            .and_then(|slot| wait_for_completion(self.port.registers, slot, deadline.start_ns, self.command_timeout_ns, time::now_ns));
+        if let Err(error) = completion {
+            self.abort_timed_out_command(error);
+            return Err(CommandError::Timeout(error));
+        }
+
+        self.finish_command(&deadline)
+    }
+
+    /// Aborts a command that did not complete in time, recovering the port is left to the caller
+    fn abort_timed_out_command(&mut self, error: TimeoutError) {
+        warn!("ahci: command on port {} timed out after {}ms, aborting it", self.port_index, error.elapsed_ns / 1_000_000);
+        if let Err(error) = abort_command(self.port.registers, &CommandDeadline::from_now(COMMAND_ENGINE_STOP_TIMEOUT_NS)) {
+            warn!("ahci: command engine of port {} did not stop after {}ms", self.port_index, error.elapsed_ns / 1_000_000);
+        }
+    }
+
+    /// Starts the command engine and rings the doorbell of the command, returns the slot it was issued in
+    fn start_command(&mut self, command_number: usize, deadline: &CommandDeadline) -> Result<u32, TimeoutError> {
+        let slot = self.port.command_list[command_number].slot;
 
         // Wait until busy and transfer requested flags are not set
-        while self.port_registers.tfd & PORT_TFD_BSY != 0 || self.port_registers.tfd & PORT_TFD_DRQ != 0 {
-            unsafe { asm!("pause;"); }
+        deadline.wait_for_bits_clear(|| self.port.registers.tfd.read(), PORT_TFD_BSY | PORT_TFD_DRQ)?;
+
+        self.stop_command_engine(deadline)?;
+        self.port.restore_memory();
+
+        unsafe { self.port.registers.cmd.modify(|cmd| cmd | PORT_CMD_FRE) };
+        // Waits for FR to be set
+        deadline.wait_for_bits_clear(|| !self.port.registers.cmd.read(), PORT_CMD_FR)?;
+        unsafe { self.port.registers.cmd.modify(|cmd| cmd | PORT_CMD_ST) };
+
+        ring_doorbell(self.port.registers, slot);
+
+        Ok(slot)
+    }
+
+    /// Reports the outcome of the command that just ended and stops the command engine
+    fn finish_command(&mut self, deadline: &CommandDeadline) -> Result<(), CommandError> {
+        // The data transferred by the device and the received FIS must not be read before the
+        // completion was observed
+        memory_barrier();
+
+        let result = if self.port.registers.tfd.read() & PORT_TFD_ERR != 0 {
+            Err(CommandError::Device(PortError { task_file: self.port.registers.tfd.read(), sata_error: self.port.registers.serr.read() }))
+        } else {
+            Ok(())
+        };
+
+        self.stop_command_engine(deadline)?;
+        unsafe { self.port.registers.cmd.modify(|cmd| cmd & !PORT_CMD_FRE) };
+
+        result
+    }
+
+    fn stop_command_engine(&mut self, deadline: &CommandDeadline) -> Result<(), TimeoutError> {
+        unsafe { self.port.registers.cmd.modify(|cmd| cmd & !PORT_CMD_ST) };
+        deadline.wait_for_bits_clear(|| self.port.registers.cmd.read(), PORT_CMD_CR)
+    }
+
+    /// Recovers the port from an error by sending a COMRESET and clearing its error registers
+    fn reset_port(&mut self) {
+        if let Err(error) = self.stop_command_engine(&CommandDeadline::from_now(COMMAND_ENGINE_STOP_TIMEOUT_NS)) {
+            warn!("ahci: command engine of port {} did not stop after {}ms, resetting the port anyway", self.port_index, error.elapsed_ns / 1_000_000);
         }
 
-        self.port_registers.cmd &= !PORT_CMD_ST;
-        while self.port_registers.cmd & PORT_CMD_CR != 0 {
-            unsafe { asm!("pause;"); }
-        } // good
+        // The COMRESET must be held for at least 1ms
+        unsafe { self.port.registers.sctl.modify(|sctl| (sctl & !PORT_SCTL_DET) | 1) };
+        time::busy_sleep(1_000_000);
+        unsafe { self.port.registers.sctl.modify(|sctl| sctl & !PORT_SCTL_DET) };
 
-        self.port_registers.cmd |= PORT_CMD_FRE;
-        while self.port_registers.cmd & PORT_CMD_FR == 0 {
+        let deadline = time::now_ns() + PORT_RESET_TIMEOUT_NS;
+        while self.port.registers.ssts.read() & PORT_SSTS_DET != PORT_SSTS_DET_PRESENT && time::now_ns() < deadline {
             unsafe { asm!("pause;"); }
         }
-        self.port_registers.cmd |= PORT_CMD_ST;
 
-        self.port_registers.ci = 1 << command.slot;
+        // Both registers are write one to clear
+        unsafe {
+            self.port.registers.serr.write(u32::MAX);
+            self.port.registers.is.write(u32::MAX);
+        }
+    }
+}
 
-        while self.port_registers.ci & (1 << command.slot) != 0 {
-            unsafe { asm!("pause;"); }
+/// Issues the command in the given slot. The HBA fetches the command header, table and PRDT as soon
+/// as the bit is set in CI, so every write to them must be visible before that, which neither the
+/// compiler nor the CPU would otherwise guarantee for normal memory.
+fn ring_doorbell(port_registers: &mut PortRegisters, slot: u32) {
+    memory_barrier();
+
+    unsafe { port_registers.ci.write(1 << slot) };
+}
+
+/// Whether the command issued in the slot is still being processed. A failed command is never
+/// marked as completed, so an error also ends it.
+fn is_command_pending(port_registers: &PortRegisters, slot: u32) -> bool {
+    port_registers.ci.read() & (1 << slot) != 0 && port_registers.tfd.read() & PORT_TFD_ERR == 0
+}
+
+/// Time at which a command started at `start_ns` times out
+fn command_deadline(start_ns: u64, timeout_ns: u64) -> u64 {
+    start_ns.saturating_add(timeout_ns)
+}
+
+/// Time by which a command must be done, every wait on the port during the command is bounded by it
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+struct CommandDeadline {
+    start_ns: u64,
+    deadline_ns: u64,
+}
+
+impl CommandDeadline {
+    fn new(start_ns: u64, timeout_ns: u64) -> Self {
+        Self { start_ns, deadline_ns: command_deadline(start_ns, timeout_ns) }
+    }
+
+    fn from_now(timeout_ns: u64) -> Self {
+        Self::new(time::now_ns(), timeout_ns)
+    }
+
+    /// Polls a port register until every bit of the mask is clear, giving up once the deadline is reached
+    fn wait_for_bits_clear(&self, read: impl FnMut() -> u32, mask: u32) -> Result<(), TimeoutError> {
+        let mut now = self.start_ns;
+        if wait_for_bits_clear(read, mask, || { now = time::now_ns(); now >= self.deadline_ns }) {
+            return Ok(());
         }
 
-        if self.port_registers.tfd & PORT_TFD_ERR  != 0{
-            panic!("ahci: an error has occured during command data transfer");
+        Err(TimeoutError { elapsed_ns: now.saturating_sub(self.start_ns) })
+    }
+}
+
+/// Polls the port until the command issued in the slot ends, giving up once the clock read with
+/// `now_ns` reaches the deadline
+fn wait_for_completion(port_registers: &PortRegisters, slot: u32, start_ns: u64, timeout_ns: u64, mut now_ns: impl FnMut() -> u64) -> Result<(), TimeoutError> {
+    let deadline = command_deadline(start_ns, timeout_ns);
+
+    while is_command_pending(port_registers, slot) {
+        let now = now_ns();
+        if now >= deadline {
+            return Err(TimeoutError { elapsed_ns: now - start_ns });
         }
 
-        self.port_registers.cmd &= !PORT_CMD_ST;
-        while self.port_registers.cmd & PORT_CMD_ST != 0 {
-            unsafe { asm!("pause;"); }
+        unsafe { asm!("pause;"); }
+    }
+
+    Ok(())
+}
+
+/// Aborts the command the port is processing. Clearing ST makes the HBA clear CI, FIS reception is
+/// then stopped as it is after a completed command. Gives up if the command engine is still running
+/// at the deadline.
+fn abort_command(port_registers: &mut PortRegisters, deadline: &CommandDeadline) -> Result<(), TimeoutError> {
+    unsafe { port_registers.cmd.modify(|cmd| cmd & !PORT_CMD_ST) };
+    deadline.wait_for_bits_clear(|| port_registers.cmd.read(), PORT_CMD_CR)?;
+    unsafe { port_registers.cmd.modify(|cmd| cmd & !PORT_CMD_FRE) };
+
+    // Both registers are write one to clear
+    unsafe {
+        port_registers.serr.write(u32::MAX);
+        port_registers.is.write(u32::MAX);
+    }
+
+    Ok(())
+}
+
+/// Prevents both the compiler and the CPU from reordering memory accesses across it
+fn memory_barrier() {
+    compiler_fence(Ordering::SeqCst);
+    unsafe { asm!("mfence", options(nostack, preserves_flags)); }
+
+    #[cfg(test)]
+    MEMORY_BARRIERS.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Number of barriers issued, used to check that the submission path goes through one
+#[cfg(test)]
+static MEMORY_BARRIERS: AtomicUsize = AtomicUsize::new(0);
+
+/// Polls a register until every bit of the mask is clear, giving up once the deadline is reached.
+/// Returns whether the bits cleared in time.
+fn wait_for_bits_clear(mut read: impl FnMut() -> u32, mask: u32, mut deadline_reached: impl FnMut() -> bool) -> bool {
+    while read() & mask != 0 {
+        if deadline_reached() {
+            return false;
         }
-        self.port_registers.cmd &= !PORT_CMD_FRE;
+
+        core::hint::spin_loop();
     }
+
+    true
 }
 
-#[derive(Debug, Copy, Clone)]
+/// Error registers of a port whose command failed
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+struct PortError {
+    task_file: u32,
+    sata_error: u32,
+}
+
+/// Command that did not complete before its timeout
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+struct TimeoutError {
+    elapsed_ns: u64,
+}
+
+/// Reason a command did not complete
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum CommandError {
+    Device(PortError),
+    Timeout(TimeoutError),
+}
+
+impl fmt::Display for CommandError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            CommandError::Device(error) => write!(f, "task file 0x{:X}, SATA error 0x{:X}", error.task_file, error.sata_error),
+            CommandError::Timeout(error) => write!(f, "timed out after {}ms", error.elapsed_ns / 1_000_000),
+        }
+    }
+}
+
+impl From<PortError> for CommandError {
+    fn from(error: PortError) -> Self {
+        CommandError::Device(error)
+    }
+}
+
+impl From<TimeoutError> for CommandError {
+    fn from(error: TimeoutError) -> Self {
+        CommandError::Timeout(error)
+    }
+}
+
+/// Runs `attempt` until it succeeds, at most `max_attempts` times, calling `recover` after every
+/// failed attempt but the last. The error returned depends on how the last attempt failed.
+fn retry_command<T, E: Into<CommandError>>(target: &mut T, max_attempts: usize, mut attempt: impl FnMut(&mut T) -> Result<(), E>, mut recover: impl FnMut(&mut T, CommandError)) -> Result<(), &'static str> {
+    let mut last_error = None;
+    for attempt_number in 1..=max_attempts {
+        match attempt(target).map_err(Into::into) {
+            Ok(()) => return Ok(()),
+            Err(error) if attempt_number < max_attempts => recover(target, error),
+            Err(error) => last_error = Some(error),
+        }
+    }
+
+    match last_error {
+        Some(CommandError::Timeout(_)) => Err("ahci: command timed out after resetting the port"),
+        _ => Err("ahci: command failed after resetting the port"),
+    }
+}
+
+/// Read started by `AHCIDevice::read_async`, which owns the buffer the HBA transfers the data to.
+/// When the future is dropped while the command is in flight, the command is aborted so that the
+/// HBA stops writing to the buffer before it is freed.
+struct PendingRead<'a> {
+    device: &'a mut AHCIDevice,
+    slot: u32,
+    buffer_address: usize,
+    buffer_size: usize,
+    in_flight: bool,
+}
+
+impl Drop for PendingRead<'_> {
+    fn drop(&mut self) {
+        if self.in_flight {
+            warn!("ahci: read in slot {} on port {} was cancelled, aborting it", self.slot, self.device.port_index);
+            // The port is reset either way, which also stops an engine the abort could not stop
+            let _ = abort_command(self.device.port.registers, &CommandDeadline::from_now(COMMAND_ENGINE_STOP_TIMEOUT_NS));
+            self.device.reset_port();
+        }
+
+        MemoryManager::pmm_free(self.buffer_size, self.buffer_address);
+    }
+}
+
+/// Registers of a port along with its command slots. The slots point into the command list and
+/// tables the HBA reads through DMA, only the owner of the port may touch them.
+#[derive(Debug)]
+struct PortOwner {
+    registers: &'static mut PortRegisters,
+    command_list: [AHCICommand; 32],
+    /// Physical addresses of the command list and received FIS of the port, kept to restore them
+    /// after an HBA reset
+    command_list_base: usize,
+    fis_base: usize,
+}
+
+// SAFETY: the registers and command memory of a port are reached through its owner only, which
+// must be borrowed mutably to issue a command. Moving the owner to another task moves that
+// exclusive access with it. It is not Sync, a drive shared between tasks must be behind a lock.
+unsafe impl Send for PortOwner {}
+
+impl PortOwner {
+    fn new(port_address: usize) -> Self {
+        Self {
+            registers: unsafe { &mut *(port_address as *mut PortRegisters) },
+            command_list: [const { AHCICommand::new() }; 32],
+            command_list_base: 0,
+            fis_base: 0,
+        }
+    }
+
+    /// Points the port at its command list and received FIS
+    fn set_memory(&mut self, command_list_base: usize, fis_base: usize) {
+        self.command_list_base = command_list_base;
+        self.fis_base = fis_base;
+
+        self.restore_memory();
+    }
+
+    /// Writes the addresses of the command list and received FIS back when they were cleared by an
+    /// HBA reset, which may have been started by the drive on another port of the controller. The
+    /// command engine and FIS receive must be stopped.
+    fn restore_memory(&mut self) {
+        let command_list_base = self.registers.clb.read() as usize | ((self.registers.clbu.read() as usize) << 32);
+        let fis_base = self.registers.fb.read() as usize | ((self.registers.fbu.read() as usize) << 32);
+        if command_list_base == self.command_list_base && fis_base == self.fis_base {
+            return;
+        }
+
+        unsafe {
+            self.registers.clb.write(self.command_list_base as u32);
+            self.registers.clbu.write((self.command_list_base >> 32) as u32);
+            self.registers.fb.write(self.fis_base as u32);
+            self.registers.fbu.write((self.fis_base >> 32) as u32);
+
+            // Both registers are write one to clear
+            self.registers.serr.write(u32::MAX);
+            self.registers.is.write(u32::MAX);
+        }
+    }
+
+    /// Points the first command slot that is neither active nor issued at its command header and
+    /// table, returns its number or None when all of the `slot_count` slots are busy
+    fn allocate_slot(&mut self, slot_count: u32) -> Option<usize> {
+        let busy_slots = self.registers.sact.read() | self.registers.ci.read();
+        let slot = (0..slot_count as usize).find(|slot| !is_nth_bit_set(busy_slots as usize, *slot))?;
+
+        let command_list_address = self.registers.clb.read() as usize | ((self.registers.clbu.read() as usize) << 32);
+        let command_header_address = command_list_address + slot * size_of::<CommandHeader>();
+        let command_header = unsafe { &*(command_header_address as *const CommandHeader) };
+        let command_table_address = command_header.ctba as usize | ((command_header.ctbau as usize) << 32);
+
+        let command = &mut self.command_list[slot];
+        command.command_header = command_header_address as *mut CommandHeader;
+        command.command_table = command_table_address as *mut CommandTable;
+        command.slot = slot as u32;
+
+        Some(slot)
+    }
+}
+
+#[derive(Debug)]
 struct AHCICommand {
     command_header: *mut CommandHeader,
     command_table: *mut CommandTable,
-    ahci_device: *mut AHCIDevice,
 
     destination_address: *mut c_void,
     data_length: usize,
@@ -711,11 +1291,10 @@ struct AHCICommand {
 
 
 impl AHCICommand {
-    fn new() -> Self {
+    const fn new() -> Self {
         Self {
             command_header: ptr::null_mut(),
             command_table: ptr::null_mut(),
-            ahci_device: ptr::null_mut(),
 
             destination_address: ptr::null_mut(),
             data_length: 0,
@@ -726,43 +1305,80 @@ impl AHCICommand {
     }
 }
 
-pub fn init() -> Vec<AHCIDevice> {
+/// Initializes every AHCI controller and returns the drives attached to them, ordered by
+/// controller then port
+pub fn init() -> Result<Vec<AHCIDevice>, &'static str> {
     info!("ahci: init...");
 
-    let ahci_pci_device = find_all_pci_devices().into_iter().find(is_ahci_controller).expect("ahci: could not locate the ahci controller");
-    let ahci_controller = AHCIController::new(ahci_pci_device);
+    let controllers: Vec<PCIDevice> = find_all_pci_devices().into_iter().filter(is_ahci_controller).collect();
+    if controllers.is_empty() {
+        return Err("ahci: could not locate the ahci controller");
+    }
+
+    let mut devices = Vec::new();
+    for (controller_index, pci_device) in controllers.into_iter().enumerate() {
+        match init_controller(controller_index, pci_device) {
+            Ok(controller_devices) => devices.extend(controller_devices),
+            Err(err) => warn!("{}, skipping controller {}", err, controller_index),
+        }
+    }
 
-    info!("ahci: controller version {}.{}", ahci_controller.version_maj, ahci_controller.version_min);
+    Ok(devices)
+}
+
+fn init_controller(controller_index: usize, ahci_pci_device: PCIDevice) -> Result<Vec<AHCIDevice>, &'static str> {
+    let ahci_controller = AHCIController::new(ahci_pci_device)?;
+
+    info!("ahci: controller {} version {}.{}", controller_index, ahci_controller.version_maj, ahci_controller.version_min);
 
     // Enable interrupts, DMA, and memory space access in the PCI command register
     let updated_command = (ahci_pci_device.command(0) | 0x2) & 0b1111101111111111;
     ahci_pci_device.set_command(0, updated_command);
 
     // Check if 64-bit DMA is supported
-    if !is_nth_bit_set(ahci_controller.hba.cap as usize, 31) {
-        panic!("ahci: controller not capable of 64 bit addressing... aborting")
+    if !is_nth_bit_set(ahci_controller.hba.cap.read() as usize, 31) {
+        return Err("ahci: controller not capable of 64 bit addressing");
     }
 
     ahci_controller.bios_os_handoff();
 
     // Initialize ports
-    let mut devices = Vec::new();
-    for port in 0..ahci_controller.port_count as usize {
-        if is_nth_bit_set(ahci_controller.hba.pi as usize, port) {
-            let device = init_port(&ahci_controller, port, ahci_controller.bar5 as usize + (0x100 + port * 0x80));
-            if let Some(ahci_device) = device {
-                devices.push(ahci_device);
-            }
-        }
-    }
+    let drive_ids = enumerate_drives(controller_index, ahci_controller.hba.pi.read(), |port| {
+        unsafe { &*(port_registers_address(&ahci_controller, port) as *const PortRegisters) }.sig.read()
+    });
+
+    Ok(drive_ids.into_iter()
+        .filter_map(|id| init_port(id, &ahci_controller, port_registers_address(&ahci_controller, id.port)))
+        .collect())
+}
+
+fn port_registers_address(controller: &AHCIController, port: usize) -> usize {
+    controller.abar + 0x100 + port * 0x80
+}
+
+/// Identifies the implemented ports of a controller that have a device attached, given the
+/// signature of each port
+fn enumerate_drives(controller_index: usize, ports_implemented: u32, signature: impl Fn(usize) -> u32) -> Vec<DriveId> {
+    (0..32)
+        .filter(|port| is_nth_bit_set(ports_implemented as usize, *port))
+        .filter(|port| matches!(signature(*port), SATA_SIG_ATA | SATA_SIG_ATAPI | SATA_SIG_SEMB | SATA_SIG_PM))
+        .map(|port| DriveId { controller: controller_index, port })
+        .collect()
+}
 
-    devices
+/// Decodes an identify string, whose characters are stored swapped in each 16 bits word and padded
+/// with spaces
+fn ata_string(raw: &[u8]) -> String {
+    let characters: String = raw.chunks(2).flat_map(|word| word.iter().rev()).map(|&byte| byte as char).collect();
+
+    String::from(characters.trim_end_matches([' ', '\0']).trim_start())
 }
 
-fn init_port(controller: &AHCIController, port_index: usize, port_address: usize) -> Option<AHCIDevice> {
-    let mut ahci_device = AHCIDevice::new(*controller, port_index, port_address); // TODO: Allocate on heap instead of cloning
+fn init_port(id: DriveId, controller: &AHCIController, port_address: usize) -> Option<AHCIDevice> {
+    let port_index = id.port;
+    let mut ahci_device = AHCIDevice::new(id, *controller, port_index, port_address); // TODO: Allocate on heap instead of cloning
 
-    match ahci_device.port_registers.sig {
+    match ahci_device.port.registers.sig.read() {
         SATA_SIG_ATA => ok!("ahci: sata drive found on port {}", port_index),
         SATA_SIG_ATAPI => ok!("ahci: satapi drive found on port {}", port_index),
         SATA_SIG_SEMB => ok!("ahci: enclosure management bridge found on port {}", port_index),
@@ -777,9 +1393,6 @@ fn init_port(controller: &AHCIController, port_index: usize, port_address: usize
             .unwrap_or_else(|| panic!("ahci: could not allocate the memory for the command list on port {}", port_index))
     };
 
-    ahci_device.port_registers.clb = command_list_base as u32;
-    ahci_device.port_registers.clbu = (command_list_base >> 32) as u32;
-
     // Allocate physical memory for the command tables
     for i in 0..32 {
         let header_address = command_list_base + i * size_of::<CommandHeader>();
@@ -801,18 +1414,21 @@ fn init_port(controller: &AHCIController, port_index: usize, port_address: usize
             .unwrap_or_else(|| panic!("ahci: could not allocate the memory for the FIS on port {}", port_index))
     };
 
-    ahci_device.port_registers.fb = fis_base_base_address as u32;
-    ahci_device.port_registers.fbu = (fis_base_base_address >> 32) as u32;
+    ahci_device.port.set_memory(command_list_base, fis_base_base_address);
 
     // Setting start and FIS receive enable flags
-    ahci_device.port_registers.cmd |= (1 << 0) | (1 << 4);
+    unsafe { ahci_device.port.registers.cmd.modify(|cmd| cmd | (1 << 0) | (1 << 4)) };
 
     let identity_address = {
         MemoryManager::pmm_identity(size_of::<AHCIIdentifyResponse>(), EntryFlags::WRITABLE | EntryFlags::NO_CACHE)
             .expect("ahci: could not allocate the memory for device identification")
     };
 
-    ahci_device.issue_identify(identity_address as *mut AHCIIdentifyResponse);
+    if let Err(err) = ahci_device.issue_identify(identity_address as *mut AHCIIdentifyResponse) {
+        warn!("{}, could not identify the device on port {}", err, port_index);
+        MemoryManager::pmm_free(1, identity_address);
+        return None;
+    }
 
     let sata_identify = unsafe{&*(identity_address as *mut AHCIIdentifyResponse)};
     ahci_device.identity = Some(*sata_identify);
@@ -824,4 +1440,640 @@ fn init_port(controller: &AHCIController, port_index: usize, port_address: usize
 
 fn is_ahci_controller(device: &PCIDevice) -> bool {
     device.class_code(0) == 0x01 && ((device.subclass(0) == 0x06) | (device.subclass(0) == 0x01))
-}
\ No newline at end of file
+}
+
+/// Device accessed a whole number of sectors at a time
+trait SectorDevice {
+    fn sector_size(&self) -> u64;
+
+    fn read_sectors(&mut self, sector_offset: u64, buffer: &mut [u8]) -> Result<(), &'static str>;
+
+    fn write_sectors(&mut self, sector_offset: u64, buffer: &[u8]) -> Result<(), &'static str>;
+}
+
+impl SectorDevice for AHCIDevice {
+    fn sector_size(&self) -> u64 {
+        self.identity.expect("ahci: cannot access an unidentified device").sector_bytes as u64
+    }
+
+    fn read_sectors(&mut self, sector_offset: u64, buffer: &mut [u8]) -> Result<(), &'static str> {
+        let sector_count = buffer.len() as u64 / self.sector_size();
+        self.issue_read(sector_offset, sector_count, buffer.as_mut_ptr() as *mut c_void).map(|_| ())
+    }
+
+    fn write_sectors(&mut self, sector_offset: u64, buffer: &[u8]) -> Result<(), &'static str> {
+        let sector_count = buffer.len() as u64 / self.sector_size();
+        self.issue_write(sector_offset, sector_count, buffer.as_ptr() as *mut c_void)
+    }
+}
+
+/// Writes the data at the byte offset, staging as many sectors as fit in the chunk buffer at a
+/// time. The sectors only partly covered by the data, which can only be at the start of the first
+/// chunk and the end of the last one, are read first so that their other bytes are preserved.
+/// Returns the number of bytes written.
+fn write_in_chunks(device: &mut impl SectorDevice, byte_offset: u64, data: &[u8], chunk: &mut [u8]) -> Result<usize, &'static str> {
+    let sector_size = device.sector_size();
+    let chunk_sectors = chunk.len() as u64 / sector_size;
+    if chunk_sectors == 0 {
+        return Err("ahci: write buffer is smaller than a sector");
+    }
+
+    let (start_sector, sector_count) = sector_span(byte_offset, data.len() as u64, sector_size);
+    let end_sector = start_sector + sector_count;
+    let mut bytes_written = 0;
+
+    for chunk_start in (start_sector..end_sector).step_by(chunk_sectors as usize) {
+        let chunk_end = (chunk_start + chunk_sectors).min(end_sector);
+        let chunk = &mut chunk[..((chunk_end - chunk_start) * sector_size) as usize];
+
+        // Only the first chunk can start in the middle of a sector
+        let chunk_data_offset = if chunk_start == start_sector { byte_offset % sector_size } else { 0 } as usize;
+        let chunk_data_length = (chunk.len() - chunk_data_offset).min(data.len() - bytes_written);
+
+        if chunk_data_offset != 0 {
+            device.read_sectors(chunk_start, &mut chunk[..sector_size as usize])?;
+        }
+        if chunk_end == end_sector && (chunk_data_offset + chunk_data_length) % sector_size as usize != 0 {
+            let last_sector_offset = chunk.len() - sector_size as usize;
+            device.read_sectors(chunk_end - 1, &mut chunk[last_sector_offset..])?;
+        }
+
+        chunk[chunk_data_offset..chunk_data_offset + chunk_data_length].copy_from_slice(&data[bytes_written..bytes_written + chunk_data_length]);
+        device.write_sectors(chunk_start, chunk)?;
+
+        bytes_written += chunk_data_length;
+    }
+
+    Ok(bytes_written)
+}
+
+/// Returns the first sector and the number of sectors that need to be transferred to cover
+/// `byte_count` bytes starting at `byte_offset`
+pub(crate) fn sector_span(byte_offset: u64, byte_count: u64, sector_size: u64) -> (u64, u64) {
+    let start_sector = byte_offset / sector_size;
+    let sector_count = (byte_offset % sector_size + byte_count).div_ceil(sector_size);
+
+    (start_sector, sector_count)
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::boxed::Box;
+    use alloc::collections::VecDeque;
+    use alloc::vec;
+    use alloc::vec::Vec;
+    use core::mem::{MaybeUninit, size_of};
+    use core::ptr;
+    use core::sync::atomic::Ordering;
+    use crate::drivers::pci::ahci::{abort_command, AHCIController, AHCIDevice, ata_string, AtaCommand, command_deadline, CommandDeadline, CommandError, CommandHeader, CommandList, DriveId, enumerate_drives, FisType, GHC_AE, GHC_HR, HbaMemoryRegisters, MEMORY_BARRIERS, PORT_CMD_CR, PORT_CMD_FRE, PORT_CMD_ST, PORT_TFD_BSY, PORT_TFD_DRQ, PortError, PortOwner, PortRegisters, retry_command, ring_doorbell, SATA_SIG_ATA, SATA_SIG_ATAPI, SectorDevice, sector_span, TimeoutError, wait_for_bits_clear, wait_for_completion, write_command_fis_header, write_in_chunks};
+    use crate::drivers::pci::PCIDevice;
+    use crate::memory::MemoryManager;
+    use crate::memory::virtual_memory::paging::entry::EntryFlags;
+
+    const PORT_ERROR: PortError = PortError { task_file: 0x51, sata_error: 0x0400_0000 };
+
+    /// Port returning the given outcomes in order and recording the resets it receives
+    struct ScriptedPort {
+        outcomes: VecDeque<Result<(), PortError>>,
+        attempts: usize,
+        resets: Vec<CommandError>,
+    }
+
+    const SECTOR_SIZE: usize = 512;
+
+    /// Drive backed by identity mapped memory, counting the write commands it receives
+    struct MemoryDrive {
+        sectors: &'static mut [u8],
+        writes: usize,
+    }
+
+    impl SectorDevice for MemoryDrive {
+        fn sector_size(&self) -> u64 {
+            SECTOR_SIZE as u64
+        }
+
+        fn read_sectors(&mut self, sector_offset: u64, buffer: &mut [u8]) -> Result<(), &'static str> {
+            let start = sector_offset as usize * SECTOR_SIZE;
+            buffer.copy_from_slice(&self.sectors[start..start + buffer.len()]);
+            Ok(())
+        }
+
+        fn write_sectors(&mut self, sector_offset: u64, buffer: &[u8]) -> Result<(), &'static str> {
+            let start = sector_offset as usize * SECTOR_SIZE;
+            self.sectors[start..start + buffer.len()].copy_from_slice(buffer);
+            self.writes += 1;
+            Ok(())
+        }
+    }
+
+    /// Allocates identity mapped memory, buffers of a few megabytes do not fit on the heap
+    fn identity_buffer(size: usize) -> &'static mut [u8] {
+        let address = MemoryManager::pmm_identity(size, EntryFlags::WRITABLE).unwrap();
+        unsafe { core::slice::from_raw_parts_mut(address as *mut u8, size) }
+    }
+
+    /// Port registers and command list in memory, the command table of every slot is at a
+    /// distinct address above 4GiB
+    fn port_memory() -> (Box<MaybeUninit<[u32; 0x20]>>, Box<CommandList>) {
+        let mut registers = Box::new(MaybeUninit::<[u32; 0x20]>::zeroed());
+        let mut command_list: Box<CommandList> = unsafe { Box::new(MaybeUninit::zeroed().assume_init()) };
+        for (slot, header) in command_list.iter_mut().enumerate() {
+            header.ctba = 0x10_0000 + slot as u32 * 0x1000;
+            header.ctbau = 0x1;
+        }
+
+        let port_registers = unsafe { &mut *(registers.as_mut_ptr() as *mut PortRegisters) };
+        let command_list_address = command_list.as_ptr() as usize;
+        unsafe {
+            port_registers.clb.write(command_list_address as u32);
+            port_registers.clbu.write((command_list_address >> 32) as u32);
+        }
+
+        (registers, command_list)
+    }
+
+    fn controller(slot_count: u32) -> AHCIController {
+        let hba: &'static MaybeUninit<HbaMemoryRegisters> = Box::leak(Box::new(MaybeUninit::zeroed()));
+
+        AHCIController {
+            pci_device: PCIDevice { bus: 0, device: 0 },
+            abar: 0,
+            version_maj: 1,
+            version_min: 3,
+            port_count: 1,
+            slot_count,
+            hba: unsafe { hba.assume_init_ref() },
+        }
+    }
+
+    fn assert_send<T: Send>() {}
+
+    impl ScriptedPort {
+        fn new(outcomes: &[Result<(), PortError>]) -> Self {
+            Self { outcomes: outcomes.iter().copied().collect(), attempts: 0, resets: Vec::new() }
+        }
+
+        fn retry(&mut self, max_attempts: usize) -> Result<(), &'static str> {
+            retry_command(self, max_attempts, |port| {
+                port.attempts += 1;
+                port.outcomes.pop_front().unwrap()
+            }, |port, error| port.resets.push(error))
+        }
+    }
+
+    #[test_case]
+    fn command_is_retried_after_transient_error() {
+        // GIVEN
+        let mut port = ScriptedPort::new(&[Err(PORT_ERROR), Ok(())]);
+
+        // WHEN
+        let result = port.retry(3);
+
+        // THEN
+        assert_eq!(result, Ok(()));
+        assert_eq!(port.attempts, 2);
+        assert_eq!(port.resets, [CommandError::Device(PORT_ERROR)]);
+    }
+
+    #[test_case]
+    fn persistent_error_gives_up_after_max_attempts() {
+        // GIVEN
+        let mut port = ScriptedPort::new(&[Err(PORT_ERROR), Err(PORT_ERROR), Err(PORT_ERROR), Ok(())]);
+
+        // WHEN
+        let result = port.retry(3);
+
+        // THEN
+        assert!(result.is_err());
+        assert_eq!(port.attempts, 3);
+        assert_eq!(port.resets.len(), 2);
+    }
+
+    #[test_case]
+    fn successful_command_is_not_retried() {
+        // GIVEN
+        let mut port = ScriptedPort::new(&[Ok(())]);
+
+        // WHEN
+        let result = port.retry(3);
+
+        // THEN
+        assert_eq!(result, Ok(()));
+        assert_eq!(port.attempts, 1);
+        assert!(port.resets.is_empty());
+    }
+
+    #[test_case]
+    fn ata_commands_match_spec_opcodes() {
+        // GIVEN
+        let expected = [
+            (AtaCommand::ReadDmaExt, 0x25),
+            (AtaCommand::WriteDmaExt, 0x35),
+            (AtaCommand::FlushCacheExt, 0xEA),
+            (AtaCommand::IdentifyDevice, 0xEC),
+        ];
+
+        for (command, opcode) in expected {
+            // WHEN
+            let parsed = AtaCommand::try_from(opcode);
+
+            // THEN
+            assert_eq!(command as u8, opcode);
+            assert_eq!(parsed, Ok(command));
+        }
+    }
+
+    #[test_case]
+    fn fis_types_match_spec_values() {
+        // GIVEN
+        let expected = [
+            (FisType::RegH2D, 0x27),
+            (FisType::RegD2H, 0x34),
+            (FisType::DmaAct, 0x39),
+            (FisType::DmaSetup, 0x41),
+            (FisType::Data, 0x46),
+            (FisType::Bist, 0x58),
+            (FisType::PioSetup, 0x5F),
+            (FisType::DevBits, 0xA1),
+        ];
+
+        for (fis_type, value) in expected {
+            // WHEN
+            let parsed = FisType::try_from(value);
+
+            // THEN
+            assert_eq!(fis_type as u8, value);
+            assert_eq!(parsed, Ok(fis_type));
+        }
+    }
+
+    #[test_case]
+    fn unknown_opcode_is_rejected() {
+        // WHEN
+        let command = AtaCommand::try_from(0x00);
+        let fis_type = FisType::try_from(0x00);
+
+        // THEN
+        assert!(command.is_err());
+        assert!(fis_type.is_err());
+    }
+
+    #[test_case]
+    fn command_fis_header_is_register_host_to_device() {
+        // GIVEN
+        let mut command_fis = [0xFF; 64];
+
+        // WHEN
+        write_command_fis_header(&mut command_fis, AtaCommand::ReadDmaExt);
+
+        // THEN
+        assert_eq!(command_fis[0], 0x27);
+        assert_eq!(command_fis[1], 0x80);
+        assert_eq!(command_fis[2], 0x25);
+        assert!(command_fis[3..].iter().all(|&byte| byte == 0));
+    }
+
+    #[test_case]
+    fn every_present_drive_is_enumerated_with_distinct_ids() {
+        // GIVEN
+        // Ports 0, 1, 3 and 5 are implemented, port 3 has nothing attached
+        let ports_implemented = 0b10_1011;
+        let signature = |port: usize| match port {
+            0 | 5 => SATA_SIG_ATA,
+            1 => SATA_SIG_ATAPI,
+            _ => 0xFFFF_FFFF,
+        };
+
+        // WHEN
+        let first_controller = enumerate_drives(0, ports_implemented, signature);
+        let second_controller = enumerate_drives(1, 0b1, signature);
+
+        // THEN
+        assert_eq!(first_controller, [
+            DriveId { controller: 0, port: 0 },
+            DriveId { controller: 0, port: 1 },
+            DriveId { controller: 0, port: 5 },
+        ]);
+        assert_eq!(second_controller, [DriveId { controller: 1, port: 0 }]);
+        assert_ne!(first_controller[0], second_controller[0]);
+    }
+
+    #[test_case]
+    fn ata_string_swaps_bytes_and_trims_padding() {
+        // GIVEN
+        let raw = *b"EQUM  ";
+
+        // WHEN
+        let string = ata_string(&raw);
+
+        // THEN
+        assert_eq!(string, "QEMU");
+    }
+
+    #[test_case]
+    fn port_register_accesses_reach_register_offsets() {
+        // GIVEN
+        let mut memory = MaybeUninit::<[u32; 0x20]>::zeroed();
+        let words = memory.as_mut_ptr() as *mut u32;
+        let port_registers = unsafe { &mut *(words as *mut PortRegisters) };
+
+        // WHEN
+        unsafe {
+            port_registers.cmd.modify(|cmd| cmd | PORT_CMD_FRE);
+            port_registers.cmd.modify(|cmd| cmd | PORT_CMD_ST);
+            port_registers.ci.write(1 << 3);
+        }
+        unsafe { ptr::write_volatile(words.add(0x24 / 4), SATA_SIG_ATA) };
+
+        // THEN
+        assert_eq!(size_of::<PortRegisters>(), 0x80);
+        assert_eq!(unsafe { ptr::read_volatile(words.add(0x18 / 4)) }, PORT_CMD_FRE | PORT_CMD_ST);
+        assert_eq!(unsafe { ptr::read_volatile(words.add(0x38 / 4)) }, 1 << 3);
+        assert_eq!(port_registers.sig.read(), SATA_SIG_ATA);
+    }
+
+    #[test_case]
+    fn reset_wait_succeeds_once_bit_clears() {
+        // GIVEN
+        // The reset bit reads as set three times, then clears
+        let mut reads = 0;
+        let mut polls = 0;
+
+        // WHEN
+        let cleared = wait_for_bits_clear(|| { reads += 1; if reads <= 3 { GHC_AE | GHC_HR } else { GHC_AE } }, GHC_HR, || { polls += 1; polls > 10 });
+
+        // THEN
+        assert!(cleared);
+        assert_eq!(reads, 4);
+    }
+
+    #[test_case]
+    fn reset_wait_times_out_when_bit_never_clears() {
+        // GIVEN
+        let mut polls = 0;
+
+        // WHEN
+        let cleared = wait_for_bits_clear(|| GHC_HR, GHC_HR, || { polls += 1; polls > 10 });
+
+        // THEN
+        assert!(!cleared);
+        assert_eq!(polls, 11);
+    }
+
+    #[test_case]
+    fn doorbell_is_rung_after_a_barrier() {
+        // GIVEN
+        let mut memory = MaybeUninit::<[u32; 0x20]>::zeroed();
+        let port_registers = unsafe { &mut *(memory.as_mut_ptr() as *mut PortRegisters) };
+        let barriers = MEMORY_BARRIERS.load(Ordering::Relaxed);
+
+        // WHEN
+        ring_doorbell(port_registers, 5);
+
+        // THEN
+        // The command structures must be visible to the HBA before CI is written
+        assert_eq!(MEMORY_BARRIERS.load(Ordering::Relaxed), barriers + 1);
+        assert_eq!(port_registers.ci.read(), 1 << 5);
+    }
+
+    #[test_case]
+    fn large_write_is_split_in_chunks_and_reads_back_intact() {
+        // GIVEN
+        let drive_size = 4 * 1024 * 1024;
+        let data = identity_buffer(3 * 1024 * 1024);
+        data.iter_mut().enumerate().for_each(|(index, byte)| *byte = (index * 7 + index / SECTOR_SIZE) as u8);
+        let mut drive = MemoryDrive { sectors: identity_buffer(drive_size), writes: 0 };
+        drive.sectors.fill(0xAA);
+        let mut chunk = vec![0u8; 64 * SECTOR_SIZE];
+        let byte_offset = 1000;
+
+        // WHEN
+        let written = write_in_chunks(&mut drive, byte_offset as u64, data, &mut chunk);
+
+        // THEN
+        assert_eq!(written, Ok(data.len()));
+        let (_, sector_count) = sector_span(byte_offset as u64, data.len() as u64, SECTOR_SIZE as u64);
+        assert_eq!(drive.writes as u64, sector_count.div_ceil(64));
+
+        assert!(drive.sectors[byte_offset..byte_offset + data.len()] == *data);
+        assert!(drive.sectors[..byte_offset].iter().all(|byte| *byte == 0xAA));
+        assert!(drive.sectors[byte_offset + data.len()..].iter().all(|byte| *byte == 0xAA));
+
+        MemoryManager::pmm_free(drive_size, drive.sectors.as_ptr() as usize);
+        MemoryManager::pmm_free(data.len(), data.as_ptr() as usize);
+    }
+
+    #[test_case]
+    fn write_within_one_sector_preserves_its_other_bytes() {
+        // GIVEN
+        let mut drive = MemoryDrive { sectors: identity_buffer(4 * SECTOR_SIZE), writes: 0 };
+        drive.sectors.fill(0xAA);
+        let mut chunk = vec![0u8; SECTOR_SIZE];
+
+        // WHEN
+        let written = write_in_chunks(&mut drive, 520, &[1, 2, 3], &mut chunk);
+
+        // THEN
+        assert_eq!(written, Ok(3));
+        assert_eq!(drive.writes, 1);
+        assert_eq!(&drive.sectors[518..525], &[0xAA, 0xAA, 1, 2, 3, 0xAA, 0xAA]);
+        assert!(drive.sectors[..518].iter().chain(&drive.sectors[525..]).all(|byte| *byte == 0xAA));
+
+        MemoryManager::pmm_free(4 * SECTOR_SIZE, drive.sectors.as_ptr() as usize);
+    }
+
+    #[test_case]
+    fn first_free_slot_is_allocated_with_its_command_structures() {
+        // GIVEN
+        let (mut registers, command_list) = port_memory();
+        let mut port = PortOwner::new(registers.as_mut_ptr() as usize);
+        // Slots 0 and 2 are issued, slot 1 is active
+        unsafe {
+            port.registers.ci.write(0b101);
+            port.registers.sact.write(0b010);
+        }
+
+        // WHEN
+        let slot = port.allocate_slot(32);
+
+        // THEN
+        assert_eq!(slot, Some(3));
+        assert_eq!(port.command_list[3].slot, 3);
+        assert_eq!(port.command_list[3].command_header as usize, command_list.as_ptr() as usize + 3 * size_of::<CommandHeader>());
+        assert_eq!(port.command_list[3].command_table as usize, 0x1_0010_3000);
+    }
+
+    #[test_case]
+    fn port_memory_is_restored_after_an_hba_reset() {
+        // GIVEN
+        let (mut registers, _command_list) = port_memory();
+        let mut port = PortOwner::new(registers.as_mut_ptr() as usize);
+        port.set_memory(0x1_2345_6000, 0x1_2345_7000);
+        // The reset clears the registers of every port
+        unsafe {
+            port.registers.clb.write(0);
+            port.registers.clbu.write(0);
+            port.registers.fb.write(0);
+            port.registers.fbu.write(0);
+        }
+
+        // WHEN
+        port.restore_memory();
+
+        // THEN
+        assert_eq!([port.registers.clb.read(), port.registers.clbu.read()], [0x2345_6000, 0x1]);
+        assert_eq!([port.registers.fb.read(), port.registers.fbu.read()], [0x2345_7000, 0x1]);
+        assert_eq!(port.registers.serr.read(), u32::MAX);
+    }
+
+    #[test_case]
+    fn moved_device_allocates_slots_up_to_the_controller_slot_count() {
+        // GIVEN
+        let (mut registers, _command_list) = port_memory();
+        let device = AHCIDevice::new(DriveId { controller: 0, port: 0 }, controller(2), 0, registers.as_mut_ptr() as usize);
+        // Drives are moved around after being initialized, no slot may point back at the device
+        let mut drives = vec![device];
+        assert_send::<AHCIDevice>();
+
+        // WHEN
+        let first = drives[0].allocate_slot();
+        unsafe { drives[0].port.registers.ci.write(1 << first) };
+        let second = drives[0].allocate_slot();
+        unsafe { drives[0].port.registers.ci.write((1 << first) | (1 << second)) };
+        let slot_count = drives[0].controller.slot_count;
+        let exhausted = drives[0].port.allocate_slot(slot_count);
+
+        // THEN
+        assert_eq!((first, second), (0, 1));
+        assert_eq!(drives[0].port.command_list[1].command_table as usize, 0x1_0010_1000);
+        assert_eq!(exhausted, None);
+    }
+
+    #[test_case]
+    fn command_deadline_is_timeout_after_start() {
+        // WHEN
+        let deadline = command_deadline(2_000, 5_000_000_000);
+        let saturated = command_deadline(2_000, u64::MAX);
+
+        // THEN
+        assert_eq!(deadline, 5_000_002_000);
+        assert_eq!(saturated, u64::MAX);
+    }
+
+    #[test_case]
+    fn command_never_completing_times_out_at_deadline() {
+        // GIVEN
+        // The command in slot 2 stays issued, the clock advances by 1ms on every poll
+        let mut memory = MaybeUninit::<[u32; 0x20]>::zeroed();
+        let port_registers = unsafe { &mut *(memory.as_mut_ptr() as *mut PortRegisters) };
+        unsafe { port_registers.ci.write(1 << 2) };
+        let mut now_ns = 10_000_000;
+
+        // WHEN
+        let result = wait_for_completion(port_registers, 2, 10_000_000, 5_000_000, || { now_ns += 1_000_000; now_ns });
+
+        // THEN
+        assert_eq!(result, Err(TimeoutError { elapsed_ns: 5_000_000 }));
+    }
+
+    #[test_case]
+    fn completed_command_does_not_time_out() {
+        // GIVEN
+        let mut memory = MaybeUninit::<[u32; 0x20]>::zeroed();
+        let port_registers = unsafe { &mut *(memory.as_mut_ptr() as *mut PortRegisters) };
+        unsafe { port_registers.ci.write(1 << 3) };
+
+        // WHEN
+        let result = wait_for_completion(port_registers, 2, 0, 0, || u64::MAX);
+
+        // THEN
+        assert_eq!(result, Ok(()));
+    }
+
+    #[test_case]
+    fn aborting_a_command_stops_the_command_engine() {
+        // GIVEN
+        let mut memory = MaybeUninit::<[u32; 0x20]>::zeroed();
+        let port_registers = unsafe { &mut *(memory.as_mut_ptr() as *mut PortRegisters) };
+        unsafe {
+            port_registers.cmd.write(PORT_CMD_FRE | PORT_CMD_ST);
+            port_registers.ci.write(1 << 2);
+        }
+
+        // WHEN
+        let result = abort_command(port_registers, &CommandDeadline::new(0, u64::MAX));
+
+        // THEN
+        assert_eq!(result, Ok(()));
+        assert_eq!(port_registers.cmd.read() & (PORT_CMD_FRE | PORT_CMD_ST), 0);
+        assert_eq!(port_registers.serr.read(), u32::MAX);
+    }
+
+    #[test_case]
+    fn aborting_a_command_gives_up_when_the_engine_keeps_running() {
+        // GIVEN
+        // CR is plain memory here, it stays set after ST is cleared as it does on a hung port
+        let mut memory = MaybeUninit::<[u32; 0x20]>::zeroed();
+        let port_registers = unsafe { &mut *(memory.as_mut_ptr() as *mut PortRegisters) };
+        unsafe { port_registers.cmd.write(PORT_CMD_FRE | PORT_CMD_ST | PORT_CMD_CR) };
+
+        // WHEN
+        let result = abort_command(port_registers, &CommandDeadline::new(0, 0));
+
+        // THEN
+        assert!(matches!(result, Err(TimeoutError { .. })));
+        assert_eq!(port_registers.cmd.read() & PORT_CMD_FRE, PORT_CMD_FRE);
+    }
+
+    #[test_case]
+    fn port_wait_times_out_at_the_command_deadline() {
+        // GIVEN
+        let expired = CommandDeadline::new(0, 0);
+        let pending = CommandDeadline::new(0, u64::MAX);
+
+        // WHEN
+        let busy = expired.wait_for_bits_clear(|| PORT_TFD_BSY, PORT_TFD_BSY | PORT_TFD_DRQ);
+        let idle = pending.wait_for_bits_clear(|| 0, PORT_TFD_BSY | PORT_TFD_DRQ);
+
+        // THEN
+        assert!(busy.is_err());
+        assert_eq!(idle, Ok(()));
+    }
+
+    #[test_case]
+    fn timed_out_command_is_retried_after_recovering() {
+        // GIVEN
+        let timeout = CommandError::Timeout(TimeoutError { elapsed_ns: 5_000_000_000 });
+        let mut attempts = 0;
+        let mut recoveries = Vec::new();
+
+        // WHEN
+        let result = retry_command(&mut attempts, 3, |attempts| {
+            *attempts += 1;
+            if *attempts < 3 { Err(timeout) } else { Ok(()) }
+        }, |_, error| recoveries.push(error));
+
+        // THEN
+        assert_eq!(result, Ok(()));
+        assert_eq!(attempts, 3);
+        assert_eq!(recoveries, [timeout, timeout]);
+    }
+
+    #[test_case]
+    fn command_timing_out_on_every_attempt_gives_up() {
+        // GIVEN
+        let mut attempts = 0;
+        let mut recoveries = 0;
+
+        // WHEN
+        let result = retry_command(&mut attempts, 4, |attempts| {
+            *attempts += 1;
+            Err(CommandError::Timeout(TimeoutError { elapsed_ns: 5_000_000_000 }))
+        }, |_, _| recoveries += 1);
+
+        // THEN
+        assert_eq!(result, Err("ahci: command timed out after resetting the port"));
+        assert_eq!(attempts, 4);
+        assert_eq!(recoveries, 3);
+    }
+}
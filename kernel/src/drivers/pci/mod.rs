@@ -2,17 +2,54 @@ use alloc::vec::Vec;
 use spin::Mutex;
 use crate::arch::x86_64::port_manager::Port;
 use crate::arch::x86_64::port_manager::ReadWriteStatus::ReadWrite;
+use crate::memory::PhysicalAddress;
 use crate::utils::bitutils::is_nth_bit_set;
 
 pub mod ahci;
 
 const CONFIG_ADDRESS: u16 = 0xCF8;
 const CONFIG_DATA: u16 = 0xCFC;
+/// The configuration mechanism only supports 32-bit accesses to both ports
+const CONFIG_PORT_WIDTH: usize = 4;
 
-static CONFIG_ADDRESS_PORT: Mutex<Port<u32>> = Mutex::new(Port::new(CONFIG_ADDRESS, ReadWrite));
-static CONFIG_DATA_PORT: Mutex<Port<u32>> = Mutex::new(Port::new(CONFIG_DATA, ReadWrite));
+static CONFIG_ADDRESS_PORT: Mutex<Port<u32>> = Mutex::new(Port::with_width(CONFIG_ADDRESS, ReadWrite, CONFIG_PORT_WIDTH));
+static CONFIG_DATA_PORT: Mutex<Port<u32>> = Mutex::new(Port::with_width(CONFIG_DATA, ReadWrite, CONFIG_PORT_WIDTH));
 
-#[derive(Debug, Copy, Clone)]
+/// Decoded base address register
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum BaseAddress {
+    Memory { address: PhysicalAddress, prefetchable: bool },
+    Io(u16),
+}
+
+impl BaseAddress {
+    /// Whether the raw BAR is a 64-bit memory BAR, whose high dword is in the following slot
+    pub fn is_64_bit(raw_bar: u32) -> bool {
+        !is_nth_bit_set(raw_bar as usize, 0) && (raw_bar >> 1) & 0b11 == 0b10
+    }
+
+    /// Decodes a BAR from its raw value and, for 64-bit memory BARs, the raw value of the next slot
+    pub fn decode(raw_bar: u32, next_raw_bar: Option<u32>) -> Result<Self, &'static str> {
+        if is_nth_bit_set(raw_bar as usize, 0) {
+            return Ok(BaseAddress::Io((raw_bar & 0xFFFC) as u16));
+        }
+
+        let low_address = (raw_bar & 0xFFFFFFF0) as PhysicalAddress;
+        let address = match (raw_bar >> 1) & 0b11 {
+            0b00 => low_address,
+            0b10 => {
+                let high_address = next_raw_bar.ok_or("pci: 64-bit BAR is missing its high dword")? as PhysicalAddress;
+                (high_address << 32) | low_address
+            },
+            _ => return Err("pci: reserved BAR memory type"),
+        };
+
+        Ok(BaseAddress::Memory { address, prefetchable: is_nth_bit_set(raw_bar as usize, 3) })
+    }
+}
+
+/// Devices are ordered by bus then device number, the order they are enumerated in
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd)]
 pub struct PCIDevice {
     pub bus: u8,
     pub device: u8,
@@ -60,42 +97,25 @@ impl PCIDevice {
         ((header_field & 0x00FF0000) >> 16) as u16
     }
 
-    pub fn bar5(&self, function: u8) -> u32 {
-        config_read_word(self.bus, self.device, function, 0x24)
-    }
-
-    pub fn interrupt_line(&self, function: u8) -> u8 {
-        let header_field = config_read_word(self.bus, self.device, function, 0x3C);
-        (header_field & 0x000000FF) as u8
-    }
-
-    pub fn check_device(&self) -> Vec<PCIDevice> {
-        let mut devices = Vec::new();
-
-        if self.vendor_id(0) == 0xFFFF {
-            return devices;
+    /// Reads and decodes the BAR at the given index, combining both slots of 64-bit BARs
+    pub fn bar(&self, function: u8, index: u8) -> Result<BaseAddress, &'static str> {
+        if index > 5 {
+            return Err("pci: BAR index out of range");
         }
 
-        devices.extend(self.check_function(0));
-
-        if is_nth_bit_set(self.header_type(0) as usize, 7) {
-            for function in 1..=7 {
-                if self.vendor_id(function) != 0xFFFF {
-                    devices.extend(self.check_function(function));
-                }
-            }
-        }
+        let raw_bar = config_read_word(self.bus, self.device, function, 0x10 + index * 4);
+        let next_raw_bar = if BaseAddress::is_64_bit(raw_bar) && index < 5 {
+            Some(config_read_word(self.bus, self.device, function, 0x10 + (index + 1) * 4))
+        } else {
+            None
+        };
 
-        devices
+        BaseAddress::decode(raw_bar, next_raw_bar)
     }
 
-    fn check_function(&self, function: u8) -> Vec<PCIDevice> {
-        if self.class_code(function) == 0x6 && self.subclass(function) == 0x4 {
-            let secondary_bus = (config_read_word(self.bus, self.device, 0, 0x18) & 0x0000FF00 >> 8) as u8;
-            return check_bus(secondary_bus);
-        }
-
-        Vec::new()
+    pub fn interrupt_line(&self, function: u8) -> u8 {
+        let header_field = config_read_word(self.bus, self.device, function, 0x3C);
+        (header_field & 0x000000FF) as u8
     }
 }
 
@@ -108,59 +128,105 @@ impl PCIDevice {
     }
 }
 
-pub fn check_all_buses() {
-    let mut devices = Vec::new();
+/// Source of PCI configuration space reads, so that bus enumeration can run over a simulated bus
+pub trait ConfigSpace {
+    fn read(&self, bus: u8, device: u8, function: u8, offset: u8) -> u32;
 
-    let header_device = PCIDevice::new(0, 0);
-    if is_nth_bit_set(header_device.header_type(0) as usize, 7) {
-        // Single PCI host controller
-        devices.extend(check_bus(0));
+    fn vendor_id(&self, bus: u8, device: u8, function: u8) -> u16 {
+        (self.read(bus, device, function, 0) & 0x0000FFFF) as u16
     }
-    else {
-        // Multiple PCI host controllers
-        for function in 0..=7 {
-            if header_device.vendor_id(function) != 0xFFFF {
-                break;
-            }
 
-            devices.extend(check_bus(function));
-        }
+    fn is_multifunction(&self, bus: u8, device: u8) -> bool {
+        is_nth_bit_set(self.read(bus, device, 0, 0xC) as usize, 23)
+    }
+
+    fn is_pci_bridge(&self, bus: u8, device: u8, function: u8) -> bool {
+        (self.read(bus, device, function, 0x8) >> 16) & 0xFFFF == 0x0604
     }
 
-    devices.iter().for_each(|device| info!("Device ID {:X}   Vendor ID: {:X}", device.device_id(0), device.vendor_id(0)));
+    fn secondary_bus(&self, bus: u8, device: u8, function: u8) -> u8 {
+        ((self.read(bus, device, function, 0x18) >> 8) & 0xFF) as u8
+    }
 }
 
-fn check_bus(bus: u8) -> Vec<PCIDevice> {
-    let mut pci_devices = Vec::new();
+/// Configuration space accessed through the legacy I/O ports
+pub struct PortConfigSpace;
 
-    for device_number in 0..=31 {
-        let device = PCIDevice::new(bus, device_number);
-        pci_devices.extend(device.check_device())
+impl ConfigSpace for PortConfigSpace {
+    fn read(&self, bus: u8, device: u8, function: u8, offset: u8) -> u32 {
+        config_read_word(bus, device, function, offset)
     }
+}
 
-    pci_devices
+/// How buses are discovered when enumerating devices
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum BusScan {
+    /// Scans the root bus of each host bridge and the buses behind PCI-to-PCI bridges
+    Recursive,
+    /// Probes every one of the 256 buses
+    Full,
 }
 
-// Todo: Get the recursive method to work instead
+/// Finds every device on the system. Only buses that are actually present are scanned, unless the
+/// `pci-full-scan` feature is enabled.
 pub fn find_all_pci_devices() -> Vec<PCIDevice> {
-    let mut pci_devices = Vec::new();
+    let scan = if cfg!(feature = "pci-full-scan") { BusScan::Full } else { BusScan::Recursive };
 
-    for bus in 0..=255 {
-        for device in 0..=31 {
-            if let Some(found_device) = get_device_if_exists(bus, device) {
-                pci_devices.push(found_device);
+    scan_buses(&PortConfigSpace, scan)
+}
+
+/// Returns the devices found on the buses selected by the scan mode, sorted and without duplicates
+/// since a bus can be reached through more than one bridge
+pub fn scan_buses(config: &impl ConfigSpace, scan: BusScan) -> Vec<PCIDevice> {
+    let mut devices = Vec::new();
+    let mut scanned_buses = [false; 256];
+
+    match scan {
+        BusScan::Full => (0..=255).for_each(|bus| scan_bus(config, bus, None, &mut devices)),
+        BusScan::Recursive if !config.is_multifunction(0, 0) => {
+            // Single host bridge, responsible for bus 0
+            scan_bus(config, 0, Some(&mut scanned_buses), &mut devices);
+        },
+        BusScan::Recursive => {
+            // Each function of the host bridge is responsible for the bus of the same number
+            for function in 0..=7 {
+                if config.vendor_id(0, 0, function) != 0xFFFF {
+                    scan_bus(config, function, Some(&mut scanned_buses), &mut devices);
+                }
             }
-        }
+        },
     }
 
-    pci_devices
+    devices.sort_unstable();
+    devices.dedup();
+
+    devices
 }
 
-fn get_device_if_exists(bus: u8, device_number: u8) -> Option<PCIDevice> {
-    let device = PCIDevice::new(bus, device_number);
-    if device.vendor_id(0) == 0xFFFF { return None; }
+/// Adds the devices present on the bus. When scanned buses are tracked, the buses behind bridges
+/// are scanned as well, each one at most once.
+fn scan_bus(config: &impl ConfigSpace, bus: u8, mut scanned_buses: Option<&mut [bool; 256]>, devices: &mut Vec<PCIDevice>) {
+    if let Some(scanned_buses) = scanned_buses.as_deref_mut() {
+        if scanned_buses[bus as usize] {
+            return;
+        }
+        scanned_buses[bus as usize] = true;
+    }
+
+    for device in 0..=31 {
+        if config.vendor_id(bus, device, 0) == 0xFFFF {
+            continue;
+        }
+        devices.push(PCIDevice::new(bus, device));
 
-    Some(device)
+        let Some(scanned_buses) = scanned_buses.as_deref_mut() else { continue };
+        let function_count = if config.is_multifunction(bus, device) { 8 } else { 1 };
+        for function in 0..function_count {
+            if config.vendor_id(bus, device, function) != 0xFFFF && config.is_pci_bridge(bus, device, function) {
+                scan_bus(config, config.secondary_bus(bus, device, function), Some(scanned_buses), devices);
+            }
+        }
+    }
 }
 
 fn config_read_word(bus: u8, slot: u8, func: u8, offset: u8) -> u32 {
@@ -181,3 +247,139 @@ fn config_write_word(bus: u8, slot: u8, func: u8, offset: u8, value: u32) {
 fn build_config_address(bus: u8, slot: u8, func: u8, offset: u8) -> u32 {
     ((bus as u32) << 16) | ((slot as u32) << 11) | ((func as u32) << 8) | ((offset as u32) & 0xFC) | 0x80000000u32
 }
+
+#[cfg(test)]
+mod tests {
+    use alloc::collections::BTreeMap;
+    use alloc::vec::Vec;
+    use core::cell::RefCell;
+    use crate::drivers::pci::{BaseAddress, BusScan, ConfigSpace, PCIDevice, scan_buses};
+
+    /// Config space made of the dwords of the present functions, recording every bus probed
+    struct SimulatedConfigSpace {
+        registers: BTreeMap<(u8, u8, u8, u8), u32>,
+        probed_buses: RefCell<Vec<u8>>,
+    }
+
+    impl SimulatedConfigSpace {
+        fn new() -> Self {
+            Self { registers: BTreeMap::new(), probed_buses: RefCell::new(Vec::new()) }
+        }
+
+        fn add_function(&mut self, bus: u8, device: u8, function: u8, class: u32, header_type: u32) {
+            self.registers.insert((bus, device, function, 0x0), 0x1234_8086);
+            self.registers.insert((bus, device, function, 0x8), class << 16);
+            self.registers.insert((bus, device, function, 0xC), header_type << 16);
+        }
+
+        fn add_bridge(&mut self, bus: u8, device: u8, secondary_bus: u8) {
+            self.add_function(bus, device, 0, 0x0604, 0x01);
+            self.registers.insert((bus, device, 0, 0x18), (secondary_bus as u32) << 8 | bus as u32);
+        }
+    }
+
+    impl ConfigSpace for SimulatedConfigSpace {
+        fn read(&self, bus: u8, device: u8, function: u8, offset: u8) -> u32 {
+            let mut probed_buses = self.probed_buses.borrow_mut();
+            if !probed_buses.contains(&bus) {
+                probed_buses.push(bus);
+            }
+
+            self.registers.get(&(bus, device, function, offset)).copied().unwrap_or(0xFFFF_FFFF)
+        }
+    }
+
+    #[test_case]
+    fn decodes_64_bit_memory_bar_pair() {
+        // GIVEN
+        let low = 0xFEB0_000C; // 64-bit, prefetchable
+        let high = 0x0000_0012;
+
+        // WHEN
+        let bar = BaseAddress::decode(low, Some(high));
+
+        // THEN
+        assert!(BaseAddress::is_64_bit(low));
+        assert_eq!(bar, Ok(BaseAddress::Memory { address: 0x12_FEB0_0000, prefetchable: true }));
+    }
+
+    #[test_case]
+    fn decodes_32_bit_memory_bar() {
+        // WHEN
+        let bar = BaseAddress::decode(0xFEBF_1000, None);
+
+        // THEN
+        assert_eq!(bar, Ok(BaseAddress::Memory { address: 0xFEBF_1000, prefetchable: false }));
+    }
+
+    #[test_case]
+    fn decodes_io_bar() {
+        // WHEN
+        let bar = BaseAddress::decode(0x0000_C041, None);
+
+        // THEN
+        assert_eq!(bar, Ok(BaseAddress::Io(0xC040)));
+    }
+
+    #[test_case]
+    fn wide_bar_without_high_dword_is_rejected() {
+        // WHEN
+        let bar = BaseAddress::decode(0xFEB0_0004, None);
+
+        // THEN
+        assert!(bar.is_err());
+    }
+
+    #[test_case]
+    fn single_host_bridge_scans_only_reachable_buses() {
+        // GIVEN
+        let mut config = SimulatedConfigSpace::new();
+        config.add_function(0, 0, 0, 0x0600, 0x00); // Host bridge
+        config.add_function(0, 3, 0, 0x0106, 0x00); // AHCI controller
+        config.add_bridge(0, 5, 2);
+        config.add_function(2, 0, 0, 0x0200, 0x00); // Network controller behind the bridge
+
+        // WHEN
+        let devices = scan_buses(&config, BusScan::Recursive);
+
+        // THEN
+        let found: Vec<(u8, u8)> = devices.iter().map(|device| (device.bus, device.device)).collect();
+        assert_eq!(found, [(0, 0), (0, 3), (0, 5), (2, 0)]);
+        assert_eq!(*config.probed_buses.borrow(), [0, 2]);
+    }
+
+    #[test_case]
+    fn full_scan_probes_every_bus() {
+        // GIVEN
+        let mut config = SimulatedConfigSpace::new();
+        config.add_function(0, 0, 0, 0x0600, 0x00);
+
+        // WHEN
+        let devices = scan_buses(&config, BusScan::Full);
+
+        // THEN
+        assert_eq!(devices.len(), 1);
+        assert_eq!(config.probed_buses.borrow().len(), 256);
+    }
+
+    #[test_case]
+    fn bus_reachable_through_two_paths_lists_its_devices_once_in_order() {
+        // GIVEN
+        // Bus 1 belongs to the second function of the host bridge and is also behind a bridge on bus 0
+        let mut config = SimulatedConfigSpace::new();
+        config.add_function(0, 0, 0, 0x0600, 0x80);
+        config.add_function(0, 0, 1, 0x0600, 0x80);
+        config.add_bridge(0, 4, 2);
+        config.add_bridge(0, 5, 1);
+        config.add_function(1, 0, 0, 0x0106, 0x00);
+        config.add_function(2, 0, 0, 0x0200, 0x00);
+
+        // WHEN
+        let devices = scan_buses(&config, BusScan::Recursive);
+
+        // THEN
+        assert_eq!(devices, [
+            PCIDevice::new(0, 0), PCIDevice::new(0, 4), PCIDevice::new(0, 5), PCIDevice::new(1, 0), PCIDevice::new(2, 0),
+        ]);
+    }
+}
@@ -0,0 +1,35 @@
+use spin::Mutex;
+use crate::arch::x86_64::port_manager::Port;
+use crate::arch::x86_64::port_manager::ReadWriteStatus::ReadWrite;
+use crate::drivers::pit;
+use crate::time;
+
+const SPEAKER_CONTROL_ADDRESS: u16 = 0x61;
+
+/// Bits of the speaker control port connecting PIT channel 2 to the speaker
+const SPEAKER_GATE: u8 = 0b11;
+
+static SPEAKER_CONTROL_PORT: Mutex<Port<u8>> = Mutex::new(Port::new(SPEAKER_CONTROL_ADDRESS, ReadWrite));
+
+/// Plays a tone of the given frequency on the PC speaker, blocking for its whole duration
+pub fn beep(frequency_hz: u32, duration_ms: u64) -> Result<(), &'static str> {
+    pit::set_channel_2_frequency(frequency_hz)?;
+
+    enable_speaker();
+    time::busy_sleep(duration_ms * 1_000_000);
+    disable_speaker();
+
+    Ok(())
+}
+
+fn enable_speaker() {
+    let mut control_port = SPEAKER_CONTROL_PORT.lock();
+    let control = control_port.read().unwrap();
+    control_port.write(control | SPEAKER_GATE).unwrap();
+}
+
+fn disable_speaker() {
+    let mut control_port = SPEAKER_CONTROL_PORT.lock();
+    let control = control_port.read().unwrap();
+    control_port.write(control & !SPEAKER_GATE).unwrap();
+}
@@ -0,0 +1,106 @@
+use core::sync::atomic::{AtomicU64, Ordering};
+use spin::Mutex;
+use crate::arch::x86_64::port_manager::Port;
+use crate::arch::x86_64::port_manager::ReadWriteStatus::WriteOnly;
+
+const CHANNEL_0_DATA_ADDRESS: u16 = 0x40;
+const CHANNEL_2_DATA_ADDRESS: u16 = 0x42;
+const MODE_COMMAND_ADDRESS: u16 = 0x43;
+
+/// Frequency in Hz of the oscillator driving the PIT
+const BASE_FREQUENCY: u32 = 1193182;
+/// Frequency in Hz at which IRQ0 is raised once the PIT is initialized
+pub const TICK_FREQUENCY: u32 = 100;
+
+static CHANNEL_0_DATA_PORT: Mutex<Port<u8>> = Mutex::new(Port::new(CHANNEL_0_DATA_ADDRESS, WriteOnly));
+static CHANNEL_2_DATA_PORT: Mutex<Port<u8>> = Mutex::new(Port::new(CHANNEL_2_DATA_ADDRESS, WriteOnly));
+static MODE_COMMAND_PORT: Mutex<Port<u8>> = Mutex::new(Port::new(MODE_COMMAND_ADDRESS, WriteOnly));
+
+static TICKS: AtomicU64 = AtomicU64::new(0);
+
+/// Programs channel 0 of the PIT to fire IRQ0 at `TICK_FREQUENCY`
+pub fn init() {
+    let divisor = (BASE_FREQUENCY / TICK_FREQUENCY) as u16;
+
+    // Channel 0, lobyte/hibyte access, mode 3 (square wave generator), binary mode
+    MODE_COMMAND_PORT.lock().write(0b00110110).unwrap();
+
+    let mut data_port = CHANNEL_0_DATA_PORT.lock();
+    data_port.write(divisor as u8).unwrap();
+    data_port.write((divisor >> 8) as u8).unwrap();
+}
+
+/// Programs channel 2 of the PIT, wired to the PC speaker, to output a square wave at the given
+/// frequency
+pub fn set_channel_2_frequency(frequency_hz: u32) -> Result<(), &'static str> {
+    let divisor = channel_2_divisor(frequency_hz)?;
+
+    // Channel 2, lobyte/hibyte access, mode 3 (square wave generator), binary mode
+    MODE_COMMAND_PORT.lock().write(0b10110110).unwrap();
+
+    let mut data_port = CHANNEL_2_DATA_PORT.lock();
+    data_port.write(divisor as u8).unwrap();
+    data_port.write((divisor >> 8) as u8).unwrap();
+
+    Ok(())
+}
+
+/// Reload value making a channel output the given frequency, rounded to the nearest divisor
+fn channel_2_divisor(frequency_hz: u32) -> Result<u16, &'static str> {
+    if frequency_hz == 0 {
+        return Err("pit: frequency must not be zero");
+    }
+
+    let divisor = (BASE_FREQUENCY + frequency_hz / 2) / frequency_hz;
+    match u16::try_from(divisor) {
+        Ok(divisor) if divisor > 0 => Ok(divisor),
+        _ => Err("pit: frequency out of range"),
+    }
+}
+
+/// Called from the IRQ0 handler on every timer interrupt
+pub fn tick() -> u64 {
+    TICKS.fetch_add(1, Ordering::Relaxed) + 1
+}
+
+/// Number of timer interrupts received since the PIT was initialized
+pub fn ticks() -> u64 {
+    TICKS.load(Ordering::Relaxed)
+}
+
+/// Converts a duration in milliseconds into a tick count, rounding up
+pub fn ms_to_ticks(ms: u64) -> u64 {
+    (ms * TICK_FREQUENCY as u64).div_ceil(1000)
+}
+
+/// Converts a tick count into milliseconds
+pub fn ticks_to_ms(ticks: u64) -> u64 {
+    ticks * 1000 / TICK_FREQUENCY as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::drivers::pit::channel_2_divisor;
+
+    #[test_case]
+    fn channel_2_divisor_for_concert_pitch() {
+        // WHEN
+        let divisor = channel_2_divisor(440);
+
+        // THEN
+        assert_eq!(divisor, Ok(2712));
+    }
+
+    #[test_case]
+    fn channel_2_divisor_rejects_out_of_range_frequencies() {
+        // WHEN
+        let zero = channel_2_divisor(0);
+        let too_low = channel_2_divisor(18);
+        let too_high = channel_2_divisor(3_000_000);
+
+        // THEN
+        assert!(zero.is_err());
+        assert!(too_low.is_err());
+        assert!(too_high.is_err());
+    }
+}
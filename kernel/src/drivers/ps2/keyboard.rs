@@ -1,8 +1,7 @@
-use alloc::string::String;
-use crate::debugger::{run_command, run_debug_shell};
-use crate::drivers::ps2::{DATA_PORT, PS2Device, PS2DeviceType, PS2Port};
+use bitflags::bitflags;
+use crate::utils::bitutils::is_nth_bit_set;
+use crate::drivers::ps2::{DATA_PORT, PS2Device, PS2DeviceType, PS2Port, STATUS_REGISTER};
 use crate::drivers::ps2::PS2DeviceType::MF2Keyboard;
-use crate::graphics::framebuffer_device;
 
 #[repr(u8)]
 enum Command {
@@ -51,6 +50,74 @@ const SCANCODE_SET_1: [char; 83] = [
     '\0', '\0', '7', '8', '9', '-', '4', '5', '6', '+', '1', '2', '3', '0', '.'
 ];
 
+/// Key identified by a scancode, regardless of the modifiers held
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum KeyCode {
+    /// Key producing a character, identified by its lowercase character
+    Character(char),
+    Function(u8),
+    Escape,
+    Enter,
+    Backspace,
+    Tab,
+    LeftShift,
+    RightShift,
+    LeftControl,
+    RightControl,
+    LeftAlt,
+    RightAlt,
+    CapsLock,
+    NumLock,
+    ScrollLock,
+    Up,
+    Down,
+    Left,
+    Right,
+    Unknown(u8),
+}
+
+bitflags! {
+    #[derive(Debug, Copy, Clone, Eq, PartialEq)]
+    pub struct Modifiers: u8 {
+        const SHIFT =       1 << 0;
+        const CONTROL =     1 << 1;
+        const ALT =         1 << 2;
+        const CAPS_LOCK =   1 << 3;
+        const NUM_LOCK =    1 << 4;
+        const SCROLL_LOCK = 1 << 5;
+    }
+}
+
+/// A key being pressed or released, along with the modifiers in effect once it was handled
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct KeyEvent {
+    pub code: KeyCode,
+    /// Character typed by the key with the modifiers applied, if it produces one
+    pub char: Option<char>,
+    pub modifiers: Modifiers,
+    pub pressed: bool,
+}
+
+/// Source of the scancode bytes sent by a keyboard
+pub trait ScancodeSource {
+    /// Returns the next byte sent by the keyboard, or None if there is none waiting
+    fn next_scancode(&mut self) -> Option<u8>;
+}
+
+/// Reads the bytes sent by the PS/2 devices from the controller data port while its output buffer
+/// is full
+pub struct DataPortSource;
+
+impl ScancodeSource for DataPortSource {
+    fn next_scancode(&mut self) -> Option<u8> {
+        if !is_nth_bit_set(STATUS_REGISTER.lock().read().unwrap() as usize, 0) {
+            return None;
+        }
+
+        Some(DATA_PORT.lock().read().unwrap())
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct PS2Keyboard {
     port: PS2Port,
@@ -66,9 +133,6 @@ pub struct PS2Keyboard {
     is_lalt: bool,
     is_ralt: bool,
 
-    current_line: String,
-    is_debug: bool,
-
     is_reading_extended_keycode: bool,
 }
 
@@ -88,71 +152,70 @@ impl PS2Keyboard {
             is_lalt: false,
             is_ralt: false,
 
-            current_line: String::from(""),
-            is_debug: false,
-
             is_reading_extended_keycode: false,
         }
     }
 
-    /// Directly reading a byte from the device port, this should only be called from an IRS to ensure that data is present
-    pub fn interrupt_read_byte() -> u8 {
-        DATA_PORT.lock().read().unwrap()
-    }
-
-    pub fn print_key_input(&mut self, scancode: u8) {
-        match scancode {
-            0x54..=0x56 | 0x59..=0x80 => (), // Not mapped, maybe want to ask to resend last byte?
-            0x01 => (), // Escape pressed,
-            0x1C => {
-                if self.is_debug {
-                    print!("\n");
-                    run_command(&self.current_line);
-                    self.current_line = String::from("");
-                }
-            }, // Enter pressed
-            0x3B..=0x44 | 0x57 => (), // Fn keys pressed
-            0x58 => {
-                self.is_debug = true;
-                run_debug_shell();
-            }, // F12
-            0x0E => {
-                self.current_line.pop();
-                framebuffer_device::backspace()
-            }, // Backspace pressed
-            0x0F => println!("  "), // Tab pressed
-            0x1D => self.is_lcontrol = true,
-
-            0x2A => self.is_lshift = true, // Left shift pressed
-            0x36 => self.is_rshift = true, // Right shift pressed
-            0x38 => self.is_lalt = true, // Left alt pressed
-            0x3A => self.is_caps_lock = true, // Caps lock pressed
-            0x45 => self.is_num_lock = true, // Num lock pressed
-            0x46 => self.is_scroll_lock = true, // Scroll lock pressed
-
-            0xAA => self.is_lshift = false, // Left shift released
-            0xB6 => self.is_rshift = false, // Right shift released
-            0xB8 => self.is_lalt = false, // Left all pressed
-            0xC5 => self.is_num_lock = false, // Num lock pressed
-            0xC6 => self.is_scroll_lock = false, // Scroll lock pressed
-
-            0xE0 =>  {
-                self.is_reading_extended_keycode = true;
-                //self.print_key_input();
-                self.is_reading_extended_keycode = false;
-            }, // E
-
-            _ => if scancode as usize <= SCANCODE_SET_1.len() {
-                if self.is_caps() {
-                    self.current_line.push(SCANCODE_SET_1[scancode as usize - 1]);
-                    print!("{}", SCANCODE_SET_1[scancode as usize - 1]);
-                }
-                else {
-                    self.current_line.push(SCANCODE_SET_1[scancode as usize - 1].to_ascii_lowercase());
-                    print!("{}", SCANCODE_SET_1[scancode as usize - 1].to_ascii_lowercase());
-                }
+    /// Reads bytes from the source until they form a key event, returns None once the source runs
+    /// out of bytes
+    pub fn read_event(&mut self, source: &mut impl ScancodeSource) -> Option<KeyEvent> {
+        while let Some(scancode) = source.next_scancode() {
+            if let Some(event) = self.decode(scancode) {
+                return Some(event);
             }
         }
+
+        None
+    }
+
+    /// Decodes a byte of scancode set 1, returning None for the prefix of extended keys
+    pub fn decode(&mut self, scancode: u8) -> Option<KeyEvent> {
+        if scancode == 0xE0 {
+            self.is_reading_extended_keycode = true;
+            return None;
+        }
+
+        let pressed = scancode & 0x80 == 0;
+        let code = if self.is_reading_extended_keycode {
+            self.is_reading_extended_keycode = false;
+            extended_key_code(scancode & 0x7F)
+        } else {
+            key_code(scancode & 0x7F)
+        };
+
+        match code {
+            KeyCode::LeftShift => self.is_lshift = pressed,
+            KeyCode::RightShift => self.is_rshift = pressed,
+            KeyCode::LeftControl => self.is_lcontrol = pressed,
+            KeyCode::RightControl => self.is_rcontrol = pressed,
+            KeyCode::LeftAlt => self.is_lalt = pressed,
+            KeyCode::RightAlt => self.is_ralt = pressed,
+            KeyCode::CapsLock if pressed => self.is_caps_lock = !self.is_caps_lock,
+            KeyCode::NumLock if pressed => self.is_num_lock = !self.is_num_lock,
+            KeyCode::ScrollLock if pressed => self.is_scroll_lock = !self.is_scroll_lock,
+            _ => (),
+        }
+
+        let char = match code {
+            KeyCode::Character(character) if self.is_caps() => Some(character.to_ascii_uppercase()),
+            KeyCode::Character(character) => Some(character),
+            KeyCode::Enter => Some('\n'),
+            _ => None,
+        };
+
+        Some(KeyEvent { code, char, modifiers: self.modifiers(), pressed })
+    }
+
+    fn modifiers(&self) -> Modifiers {
+        let mut modifiers = Modifiers::empty();
+        modifiers.set(Modifiers::SHIFT, self.is_lshift || self.is_rshift);
+        modifiers.set(Modifiers::CONTROL, self.is_lcontrol || self.is_rcontrol);
+        modifiers.set(Modifiers::ALT, self.is_lalt || self.is_ralt);
+        modifiers.set(Modifiers::CAPS_LOCK, self.is_caps_lock);
+        modifiers.set(Modifiers::NUM_LOCK, self.is_num_lock);
+        modifiers.set(Modifiers::SCROLL_LOCK, self.is_scroll_lock);
+
+        modifiers
     }
 
     fn is_caps(&self) -> bool {
@@ -160,6 +223,42 @@ impl PS2Keyboard {
     }
 }
 
+/// Key of a make code not prefixed by 0xE0
+fn key_code(make_code: u8) -> KeyCode {
+    match make_code {
+        0x01 => KeyCode::Escape,
+        0x0E => KeyCode::Backspace,
+        0x0F => KeyCode::Tab,
+        0x1C => KeyCode::Enter,
+        0x1D => KeyCode::LeftControl,
+        0x2A => KeyCode::LeftShift,
+        0x36 => KeyCode::RightShift,
+        0x38 => KeyCode::LeftAlt,
+        0x3A => KeyCode::CapsLock,
+        0x3B..=0x44 => KeyCode::Function(make_code - 0x3A),
+        0x45 => KeyCode::NumLock,
+        0x46 => KeyCode::ScrollLock,
+        0x57 => KeyCode::Function(11),
+        0x58 => KeyCode::Function(12),
+        0x01..=0x53 if SCANCODE_SET_1[make_code as usize - 1] != '\0' => KeyCode::Character(SCANCODE_SET_1[make_code as usize - 1].to_ascii_lowercase()),
+        _ => KeyCode::Unknown(make_code),
+    }
+}
+
+/// Key of a make code following a 0xE0 prefix
+fn extended_key_code(make_code: u8) -> KeyCode {
+    match make_code {
+        0x1C => KeyCode::Enter,
+        0x1D => KeyCode::RightControl,
+        0x38 => KeyCode::RightAlt,
+        0x48 => KeyCode::Up,
+        0x4B => KeyCode::Left,
+        0x4D => KeyCode::Right,
+        0x50 => KeyCode::Down,
+        _ => KeyCode::Unknown(make_code),
+    }
+}
+
 impl PS2Device for PS2Keyboard {
     fn device_type(&self) -> PS2DeviceType {
         MF2Keyboard
@@ -168,4 +267,239 @@ impl PS2Device for PS2Keyboard {
     fn port(&self) -> PS2Port {
         self.port
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::collections::VecDeque;
+    use alloc::vec::Vec;
+    use crate::drivers::ps2::keyboard::{KeyCode, KeyEvent, Modifiers, PS2Keyboard, ScancodeSource};
+    use crate::drivers::ps2::PS2Port;
+
+    /// Keyboard port replaying the given bytes
+    struct FakePort {
+        scancodes: VecDeque<u8>,
+    }
+
+    impl FakePort {
+        fn new(scancodes: &[u8]) -> Self {
+            Self { scancodes: scancodes.iter().copied().collect() }
+        }
+    }
+
+    impl ScancodeSource for FakePort {
+        fn next_scancode(&mut self) -> Option<u8> {
+            self.scancodes.pop_front()
+        }
+    }
+
+    fn decode_all(keyboard: &mut PS2Keyboard, scancodes: &[u8]) -> Vec<KeyEvent> {
+        scancodes.iter().filter_map(|scancode| keyboard.decode(*scancode)).collect()
+    }
+
+    fn read_all(keyboard: &mut PS2Keyboard, port: &mut FakePort) -> Vec<KeyEvent> {
+        core::iter::from_fn(|| keyboard.read_event(port)).collect()
+    }
+
+    fn press(code: KeyCode, char: Option<char>, modifiers: Modifiers) -> KeyEvent {
+        KeyEvent { code, char, modifiers, pressed: true }
+    }
+
+    fn release(code: KeyCode, char: Option<char>, modifiers: Modifiers) -> KeyEvent {
+        KeyEvent { code, char, modifiers, pressed: false }
+    }
+
+    #[test_case]
+    fn shifted_character_press_and_release() {
+        // GIVEN
+        let mut keyboard = PS2Keyboard::new(PS2Port::FirstPS2Port);
+
+        // WHEN
+        let events = decode_all(&mut keyboard, &[0x2A, 0x1E, 0x9E, 0xAA, 0x1E]);
+
+        // THEN
+        assert_eq!(events, [
+            KeyEvent { code: KeyCode::LeftShift, char: None, modifiers: Modifiers::SHIFT, pressed: true },
+            KeyEvent { code: KeyCode::Character('a'), char: Some('A'), modifiers: Modifiers::SHIFT, pressed: true },
+            KeyEvent { code: KeyCode::Character('a'), char: Some('A'), modifiers: Modifiers::SHIFT, pressed: false },
+            KeyEvent { code: KeyCode::LeftShift, char: None, modifiers: Modifiers::empty(), pressed: false },
+            KeyEvent { code: KeyCode::Character('a'), char: Some('a'), modifiers: Modifiers::empty(), pressed: true },
+        ]);
+    }
+
+    #[test_case]
+    fn extended_prefix_is_merged_into_next_event() {
+        // GIVEN
+        let mut keyboard = PS2Keyboard::new(PS2Port::FirstPS2Port);
+
+        // WHEN
+        let events = decode_all(&mut keyboard, &[0xE0, 0x48, 0xE0, 0xC8]);
+
+        // THEN
+        assert_eq!(events, [
+            KeyEvent { code: KeyCode::Up, char: None, modifiers: Modifiers::empty(), pressed: true },
+            KeyEvent { code: KeyCode::Up, char: None, modifiers: Modifiers::empty(), pressed: false },
+        ]);
+    }
+
+    #[test_case]
+    fn caps_lock_toggles_on_press() {
+        // GIVEN
+        let mut keyboard = PS2Keyboard::new(PS2Port::FirstPS2Port);
+
+        // WHEN
+        let events = decode_all(&mut keyboard, &[0x3A, 0xBA, 0x10, 0x3A, 0x10]);
+
+        // THEN
+        assert_eq!(events[2].char, Some('Q'));
+        assert_eq!(events[2].modifiers, Modifiers::CAPS_LOCK);
+        assert_eq!(events[4].char, Some('q'));
+    }
+
+    #[test_case]
+    fn typed_line_produces_expected_events() {
+        // GIVEN
+        let mut keyboard = PS2Keyboard::new(PS2Port::FirstPS2Port);
+        // Shift+H, I, Enter
+        let mut port = FakePort::new(&[0x2A, 0x23, 0xA3, 0xAA, 0x17, 0x97, 0x1C, 0x9C]);
+
+        // WHEN
+        let events = read_all(&mut keyboard, &mut port);
+
+        // THEN
+        assert_eq!(events, [
+            press(KeyCode::LeftShift, None, Modifiers::SHIFT),
+            press(KeyCode::Character('h'), Some('H'), Modifiers::SHIFT),
+            release(KeyCode::Character('h'), Some('H'), Modifiers::SHIFT),
+            release(KeyCode::LeftShift, None, Modifiers::empty()),
+            press(KeyCode::Character('i'), Some('i'), Modifiers::empty()),
+            release(KeyCode::Character('i'), Some('i'), Modifiers::empty()),
+            press(KeyCode::Enter, Some('\n'), Modifiers::empty()),
+            release(KeyCode::Enter, Some('\n'), Modifiers::empty()),
+        ]);
+    }
+
+    #[test_case]
+    fn read_event_skips_extended_prefix() {
+        // GIVEN
+        let mut keyboard = PS2Keyboard::new(PS2Port::FirstPS2Port);
+        let mut port = FakePort::new(&[0xE0, 0x4B, 0xE0]);
+
+        // WHEN
+        let first = keyboard.read_event(&mut port);
+        let second = keyboard.read_event(&mut port);
+
+        // THEN
+        // The trailing prefix is consumed but does not form an event on its own
+        assert_eq!(first, Some(press(KeyCode::Left, None, Modifiers::empty())));
+        assert_eq!(second, None);
+        assert!(port.scancodes.is_empty());
+    }
+
+    #[test_case]
+    fn extended_codes_decode_to_distinct_keys() {
+        // GIVEN
+        let mut keyboard = PS2Keyboard::new(PS2Port::FirstPS2Port);
+
+        // WHEN
+        let events = decode_all(&mut keyboard, &[0xE0, 0x1C, 0xE0, 0x50, 0xE0, 0x4D, 0xE0, 0x37, 0x1D, 0x9D]);
+
+        // THEN
+        assert_eq!(events, [
+            press(KeyCode::Enter, Some('\n'), Modifiers::empty()),
+            press(KeyCode::Down, None, Modifiers::empty()),
+            press(KeyCode::Right, None, Modifiers::empty()),
+            press(KeyCode::Unknown(0x37), None, Modifiers::empty()),
+            press(KeyCode::LeftControl, None, Modifiers::CONTROL),
+            release(KeyCode::LeftControl, None, Modifiers::empty()),
+        ]);
+    }
+
+    #[test_case]
+    fn extended_prefix_only_applies_to_next_byte() {
+        // GIVEN
+        let mut keyboard = PS2Keyboard::new(PS2Port::FirstPS2Port);
+
+        // WHEN
+        let events = decode_all(&mut keyboard, &[0xE0, 0x38, 0x38]);
+
+        // THEN
+        assert_eq!(events[0].code, KeyCode::RightAlt);
+        assert_eq!(events[1].code, KeyCode::LeftAlt);
+    }
+
+    #[test_case]
+    fn modifier_stays_held_until_both_sides_are_released() {
+        // GIVEN
+        let mut keyboard = PS2Keyboard::new(PS2Port::FirstPS2Port);
+
+        // WHEN
+        let events = decode_all(&mut keyboard, &[0x2A, 0x36, 0xAA, 0x1E, 0xB6, 0x1E]);
+
+        // THEN
+        assert_eq!(events[2].modifiers, Modifiers::SHIFT);
+        assert_eq!(events[3].char, Some('A'));
+        assert_eq!(events[4].modifiers, Modifiers::empty());
+        assert_eq!(events[5].char, Some('a'));
+    }
+
+    #[test_case]
+    fn control_and_alt_are_tracked_on_both_sides() {
+        // GIVEN
+        let mut keyboard = PS2Keyboard::new(PS2Port::FirstPS2Port);
+
+        // WHEN
+        let events = decode_all(&mut keyboard, &[0xE0, 0x1D, 0x38, 0x2E, 0xE0, 0x9D, 0xB8, 0x2E]);
+
+        // THEN
+        assert_eq!(events[2], press(KeyCode::Character('c'), Some('c'), Modifiers::CONTROL | Modifiers::ALT));
+        assert_eq!(events[5], press(KeyCode::Character('c'), Some('c'), Modifiers::empty()));
+    }
+
+    #[test_case]
+    fn shift_inverts_caps_lock() {
+        // GIVEN
+        let mut keyboard = PS2Keyboard::new(PS2Port::FirstPS2Port);
+
+        // WHEN
+        let events = decode_all(&mut keyboard, &[0x3A, 0xBA, 0x2A, 0x1F]);
+
+        // THEN
+        assert_eq!(events[3].char, Some('s'));
+        assert_eq!(events[3].modifiers, Modifiers::CAPS_LOCK | Modifiers::SHIFT);
+    }
+
+    #[test_case]
+    fn lock_keys_toggle_once_per_press() {
+        // GIVEN
+        let mut keyboard = PS2Keyboard::new(PS2Port::FirstPS2Port);
+
+        // WHEN
+        let events = decode_all(&mut keyboard, &[0x45, 0xC5, 0x46, 0xC6, 0x45]);
+
+        // THEN
+        assert_eq!(events[1].modifiers, Modifiers::NUM_LOCK);
+        assert_eq!(events[3].modifiers, Modifiers::NUM_LOCK | Modifiers::SCROLL_LOCK);
+        assert_eq!(events[4].modifiers, Modifiers::SCROLL_LOCK);
+    }
+
+    #[test_case]
+    fn function_and_unknown_make_codes() {
+        // GIVEN
+        let mut keyboard = PS2Keyboard::new(PS2Port::FirstPS2Port);
+
+        // WHEN
+        let events = decode_all(&mut keyboard, &[0x3B, 0x44, 0x57, 0xD8, 0x01, 0x5B]);
+
+        // THEN
+        let codes: Vec<(KeyCode, bool)> = events.iter().map(|event| (event.code, event.pressed)).collect();
+        assert_eq!(codes, [
+            (KeyCode::Function(1), true),
+            (KeyCode::Function(10), true),
+            (KeyCode::Function(11), true),
+            (KeyCode::Function(12), false),
+            (KeyCode::Escape, true),
+            (KeyCode::Unknown(0x5B), true),
+        ]);
+    }
+}
@@ -7,7 +7,8 @@ use downcast_rs::{Downcast, impl_downcast};
 use spin::Mutex;
 use crate::arch::x86_64::port_manager::Port;
 use crate::arch::x86_64::port_manager::ReadWriteStatus::*;
-use crate::drivers::ps2::keyboard::PS2Keyboard;
+use crate::drivers::ps2::keyboard::{DataPortSource, PS2Keyboard, ScancodeSource};
+use crate::drivers::watchdog::WatchdogGuard;
 use crate::drivers::ps2::PS2ControllerCommand::*;
 use crate::drivers::ps2::PS2DeviceType::*;
 use crate::drivers::ps2::PS2DeviceCommand::*;
@@ -17,10 +18,14 @@ use crate::utils::bitutils::is_nth_bit_set;
 const DATA_PORT_ADDRESS: u16 = 0x60;
 const STATUS_REGISTER_ADDRESS: u16 = 0x64;
 const COMMAND_REGISTER_ADDRESS: u16 = 0x64;
+/// The controller registers are 8 bits wide
+const REGISTER_WIDTH: usize = 1;
+/// Time the controller is given to fill or empty a buffer before the wait is reported as stuck
+const BUFFER_WAIT_WATCHDOG_MS: u64 = 100;
 
-pub static DATA_PORT: Mutex<Port<u8>> = Mutex::new(Port::new(DATA_PORT_ADDRESS, ReadWrite));
-pub static STATUS_REGISTER: Mutex<Port<u8>> = Mutex::new(Port::new(STATUS_REGISTER_ADDRESS, ReadOnly));
-pub static COMMAND_REGISTER: Mutex<Port<u8>> = Mutex::new(Port::new(COMMAND_REGISTER_ADDRESS, WriteOnly));
+pub static DATA_PORT: Mutex<Port<u8>> = Mutex::new(Port::with_width(DATA_PORT_ADDRESS, ReadWrite, REGISTER_WIDTH));
+pub static STATUS_REGISTER: Mutex<Port<u8>> = Mutex::new(Port::with_width(STATUS_REGISTER_ADDRESS, ReadOnly, REGISTER_WIDTH));
+pub static COMMAND_REGISTER: Mutex<Port<u8>> = Mutex::new(Port::with_width(COMMAND_REGISTER_ADDRESS, WriteOnly, REGISTER_WIDTH));
 
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
 pub enum PS2Port {
@@ -97,9 +102,11 @@ pub trait PS2Device: Downcast {
 
     /// Reading a byte from the device port, this method waits for the corresponding bit before doing anything
     fn read_byte(&self) -> u8 {
-        while !is_nth_bit_set(STATUS_REGISTER.lock().read().unwrap() as usize, 0) {}
-
-        DATA_PORT.lock().read().unwrap()
+        loop {
+            if let Some(byte) = DataPortSource.next_scancode() {
+                return byte;
+            }
+        }
     }
 
     fn write_byte(&self, command: u8) {
@@ -156,7 +163,11 @@ pub fn init_ps2_controller() -> (PS2DeviceOption, PS2DeviceOption) {
     disable_ps2_devices();
     flush_output_buffer();
     set_config_byte();
-    controller_self_test();
+    if !controller_self_test() {
+        warn!("ps2: controller self test failed, continuing without ps/2 devices");
+        return (None, None);
+    }
+
     let is_dual_channel = dual_channel_check();
     let devices = interface_test(is_dual_channel);
     enable_devices(&devices);
@@ -164,9 +175,17 @@ pub fn init_ps2_controller() -> (PS2DeviceOption, PS2DeviceOption) {
 
     ok!("ps2: successfully initialized ps/2 driver!");
 
-    let first_port_device = detect_device(&devices.0.unwrap());
+    let Some(first_port) = devices.0.as_ref() else {
+        warn!("ps2: no device connected to the first port");
+        return (None, None);
+    };
+
+    let first_port_device = detect_device(first_port);
 
-    ok!("ps2: detected {}", first_port_device.as_ref().unwrap().device_type());
+    match first_port_device.as_ref() {
+        Some(device) => ok!("ps2: detected {}", device.device_type()),
+        None => warn!("ps2: could not identify the device on the first port"),
+    }
 
     (first_port_device, None)
 }
@@ -191,15 +210,15 @@ fn set_config_byte() {
     update_config_byte(config_byte & !0b00100011);
 }
 
-fn controller_self_test() {
+fn controller_self_test() -> bool {
     let config_byte = send_command_for_response(ReadByteZero);
 
     let response = send_command_for_response(TestPS2Controller);
 
-    assert_eq!(response, 0x55);
-
     // Resetting the config byte for compatibility with some computers
     update_config_byte(config_byte);
+
+    response == 0x55
 }
 
 fn dual_channel_check() -> bool {
@@ -292,6 +311,26 @@ fn detect_device(generic_device: &GenericPS2Device) -> PS2DeviceOption {
 }
 
 
+/// Reads the controller output port, which holds the A20 gate in bit 1
+pub(crate) fn read_controller_output() -> u8 {
+    send_command_for_response(ReadControllerOutput)
+}
+
+/// Writes the controller output port, the first port must be disabled while doing so
+pub(crate) fn write_controller_output(value: u8) {
+    COMMAND_REGISTER.lock().write(WriteToControllerOutput as u8).unwrap();
+
+    wait_for_input_buffer();
+
+    DATA_PORT.lock().write(value).unwrap();
+}
+
+/// Stops or restarts the device on the first port
+pub(crate) fn set_first_port_enabled(enabled: bool) {
+    let command = if enabled { EnableFirstPS2 } else { DisableFirstPS2 };
+    COMMAND_REGISTER.lock().write(command as u8).unwrap();
+}
+
 fn send_command_for_response(command: PS2ControllerCommand) -> u8 {
     COMMAND_REGISTER.lock().write(command as u8).unwrap();
 
@@ -310,10 +349,12 @@ fn update_config_byte(config_byte: u8) {
 
 // TODO: When multithreading, set a timeout here
 fn wait_for_output_buffer() {
+    let _watchdog = WatchdogGuard::arm("ps2: wait_for_output_buffer", BUFFER_WAIT_WATCHDOG_MS);
     while !is_nth_bit_set(STATUS_REGISTER.lock().read().unwrap() as usize, 0) {}
 }
 
 // TODO: When multithreading, set a timeout here
 fn wait_for_input_buffer() {
+    let _watchdog = WatchdogGuard::arm("ps2: wait_for_input_buffer", BUFFER_WAIT_WATCHDOG_MS);
     while is_nth_bit_set(STATUS_REGISTER.lock().read().unwrap() as usize, 1) {}
 }
\ No newline at end of file
@@ -0,0 +1,102 @@
+use spin::Mutex;
+use crate::arch::x86_64::port_manager::Port;
+use crate::arch::x86_64::port_manager::ReadWriteStatus::{ReadWrite, WriteOnly};
+
+const CMOS_ADDRESS_PORT_ADDRESS: u16 = 0x70;
+const CMOS_DATA_PORT_ADDRESS: u16 = 0x71;
+
+const SECONDS_REGISTER: u8 = 0x00;
+const MINUTES_REGISTER: u8 = 0x02;
+const HOURS_REGISTER: u8 = 0x04;
+const DAY_REGISTER: u8 = 0x07;
+const MONTH_REGISTER: u8 = 0x08;
+const YEAR_REGISTER: u8 = 0x09;
+const STATUS_REGISTER_A: u8 = 0x0A;
+const STATUS_REGISTER_B: u8 = 0x0B;
+
+/// Set in status register A while the RTC is updating its registers
+const UPDATE_IN_PROGRESS: u8 = 1 << 7;
+/// Set in status register B when the registers hold binary values instead of BCD
+const BINARY_MODE: u8 = 1 << 2;
+/// Set in status register B when hours are in 24 hour format
+const HOUR_FORMAT_24: u8 = 1 << 1;
+/// Set in the hours register for PM hours in 12 hour format
+const HOUR_PM: u8 = 1 << 7;
+
+static CMOS_ADDRESS_PORT: Mutex<Port<u8>> = Mutex::new(Port::new(CMOS_ADDRESS_PORT_ADDRESS, WriteOnly));
+static CMOS_DATA_PORT: Mutex<Port<u8>> = Mutex::new(Port::new(CMOS_DATA_PORT_ADDRESS, ReadWrite));
+
+/// Reads the current date and time from the RTC as a Unix timestamp. The RTC is assumed to hold UTC.
+pub fn unix_time() -> u32 {
+    while read_register(STATUS_REGISTER_A) & UPDATE_IN_PROGRESS != 0 {}
+
+    let status = read_register(STATUS_REGISTER_B);
+    let read_value = |register| {
+        let value = read_register(register);
+        if status & BINARY_MODE != 0 { value } else { bcd_to_binary(value) }
+    };
+
+    let seconds = read_value(SECONDS_REGISTER) as u64;
+    let minutes = read_value(MINUTES_REGISTER) as u64;
+    let day = read_value(DAY_REGISTER) as u64;
+    let month = read_value(MONTH_REGISTER) as u64;
+    let year = 2000 + read_value(YEAR_REGISTER) as u64;
+
+    let raw_hours = read_register(HOURS_REGISTER);
+    let hours = if status & BINARY_MODE != 0 { raw_hours & !HOUR_PM } else { bcd_to_binary(raw_hours & !HOUR_PM) } as u64;
+    let hours = if status & HOUR_FORMAT_24 == 0 {
+        // 12 AM is midnight and 12 PM is noon
+        (hours % 12) + if raw_hours & HOUR_PM != 0 { 12 } else { 0 }
+    } else {
+        hours
+    };
+
+    (days_since_epoch(year, month, day) * 86400 + hours * 3600 + minutes * 60 + seconds) as u32
+}
+
+fn read_register(register: u8) -> u8 {
+    CMOS_ADDRESS_PORT.lock().write(register).unwrap();
+    CMOS_DATA_PORT.lock().read().unwrap()
+}
+
+fn bcd_to_binary(value: u8) -> u8 {
+    (value >> 4) * 10 + (value & 0x0F)
+}
+
+/// Number of days between January 1st 1970 and the given date
+fn days_since_epoch(year: u64, month: u64, day: u64) -> u64 {
+    let is_leap_year = |year: u64| (year % 4 == 0 && year % 100 != 0) || year % 400 == 0;
+    const DAYS_BEFORE_MONTH: [u64; 12] = [0, 31, 59, 90, 120, 151, 181, 212, 243, 273, 304, 334];
+
+    let days_before_year: u64 = (1970..year).map(|year| if is_leap_year(year) { 366 } else { 365 }).sum();
+    let leap_day = if month > 2 && is_leap_year(year) { 1 } else { 0 };
+
+    days_before_year + DAYS_BEFORE_MONTH[(month - 1) as usize] + leap_day + day - 1
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::drivers::rtc::{bcd_to_binary, days_since_epoch};
+
+    #[test_case]
+    fn days_since_epoch_counts_leap_days() {
+        // WHEN
+        let epoch = days_since_epoch(1970, 1, 1);
+        let before_leap_day = days_since_epoch(2024, 2, 28);
+        let after_leap_day = days_since_epoch(2024, 3, 1);
+
+        // THEN
+        assert_eq!(epoch, 0);
+        assert_eq!(before_leap_day, 19781);
+        assert_eq!(after_leap_day, 19783);
+    }
+
+    #[test_case]
+    fn bcd_values_are_decoded() {
+        // WHEN
+        let values = [0x00, 0x09, 0x10, 0x59].map(bcd_to_binary);
+
+        // THEN
+        assert_eq!(values, [0, 9, 10, 59]);
+    }
+}
@@ -0,0 +1,187 @@
+use core::fmt::Write;
+use spin::Mutex;
+use x86_64::instructions::interrupts::without_interrupts;
+use crate::drivers::pit;
+use crate::serial::SERIAL1;
+
+/// Most sections that can be watched at the same time, sections armed past it are not watched
+const MAX_WATCHED_SECTIONS: usize = 8;
+
+static WATCHDOG: Mutex<Watchdog> = Mutex::new(Watchdog::new());
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+struct WatchedSection {
+    name: &'static str,
+    armed_tick: u64,
+    timeout_ticks: u64,
+    /// Whether the section running past its timeout was reported already
+    is_reported: bool,
+}
+
+impl WatchedSection {
+    fn has_expired(&self, current_tick: u64) -> bool {
+        current_tick - self.armed_tick > self.timeout_ticks
+    }
+}
+
+/// Sections of code expected to finish within a timeout, checked on every timer interrupt
+struct Watchdog {
+    sections: [Option<WatchedSection>; MAX_WATCHED_SECTIONS],
+}
+
+impl Watchdog {
+    const fn new() -> Self {
+        Self { sections: [None; MAX_WATCHED_SECTIONS] }
+    }
+
+    /// Starts watching a section and returns the slot to disarm it with
+    fn arm(&mut self, name: &'static str, current_tick: u64, timeout_ticks: u64) -> Result<usize, &'static str> {
+        let slot = self.sections.iter().position(Option::is_none).ok_or("watchdog: too many watched sections")?;
+        self.sections[slot] = Some(WatchedSection { name, armed_tick: current_tick, timeout_ticks, is_reported: false });
+
+        Ok(slot)
+    }
+
+    /// Stops watching the section, returns the ticks it ran for if it ran past its timeout
+    fn disarm(&mut self, slot: usize, current_tick: u64) -> Option<u64> {
+        let section = self.sections[slot].take()?;
+
+        section.has_expired(current_tick).then_some(current_tick - section.armed_tick)
+    }
+
+    /// Calls `report` with the name and running time in ticks of every section past its timeout
+    /// that was not reported yet. A section stays unreported as long as `report` returns false.
+    fn check(&mut self, current_tick: u64, mut report: impl FnMut(&'static str, u64) -> bool) {
+        for section in self.sections.iter_mut().flatten() {
+            if !section.is_reported && section.has_expired(current_tick) {
+                section.is_reported = report(section.name, current_tick - section.armed_tick);
+            }
+        }
+    }
+}
+
+/// Watches the section of code running until the guard is dropped, a warning is printed on the
+/// serial port if it is still running after the timeout
+pub struct WatchdogGuard {
+    slot: Option<usize>,
+    name: &'static str,
+}
+
+impl WatchdogGuard {
+    pub fn arm(name: &'static str, timeout_ms: u64) -> Self {
+        let slot = without_interrupts(|| WATCHDOG.lock().arm(name, pit::ticks(), pit::ms_to_ticks(timeout_ms)));
+
+        Self { slot: slot.ok(), name }
+    }
+}
+
+impl Drop for WatchdogGuard {
+    fn drop(&mut self) {
+        let Some(slot) = self.slot else { return };
+
+        if let Some(ticks) = without_interrupts(|| WATCHDOG.lock().disarm(slot, pit::ticks())) {
+            warn!("watchdog: {} finished after {} ms, past its timeout", self.name, pit::ticks_to_ms(ticks));
+        }
+    }
+}
+
+/// Called from the IRQ0 handler, reports the sections running past their timeout. The interrupted
+/// code might be holding the watchdog or the serial port, in which case reporting waits for a later tick.
+pub fn check_sections(current_tick: u64) {
+    let Some(mut watchdog) = WATCHDOG.try_lock() else { return };
+
+    watchdog.check(current_tick, |name, ticks| {
+        let Some(mut serial) = SERIAL1.try_lock() else { return false };
+        writeln!(serial, "watchdog: stuck in {} for {} ms", name, pit::ticks_to_ms(ticks)).is_ok()
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec::Vec;
+    use crate::drivers::watchdog::{MAX_WATCHED_SECTIONS, Watchdog};
+
+    fn reported_sections(watchdog: &mut Watchdog, current_tick: u64) -> Vec<(&'static str, u64)> {
+        let mut reports = Vec::new();
+        watchdog.check(current_tick, |name, ticks| {
+            reports.push((name, ticks));
+            true
+        });
+
+        reports
+    }
+
+    #[test_case]
+    fn section_is_reported_once_past_its_timeout() {
+        // GIVEN
+        let mut watchdog = Watchdog::new();
+        watchdog.arm("ahci", 100, 10).unwrap();
+
+        // WHEN
+        let on_time = reported_sections(&mut watchdog, 110);
+        let late = reported_sections(&mut watchdog, 111);
+        let later = reported_sections(&mut watchdog, 150);
+
+        // THEN
+        assert!(on_time.is_empty());
+        assert_eq!(late, [("ahci", 11)]);
+        assert!(later.is_empty());
+    }
+
+    #[test_case]
+    fn disarmed_section_is_not_reported() {
+        // GIVEN
+        let mut watchdog = Watchdog::new();
+        let slot = watchdog.arm("ps2", 0, 5).unwrap();
+
+        // WHEN
+        let overrun = watchdog.disarm(slot, 3);
+
+        // THEN
+        assert_eq!(overrun, None);
+        assert!(reported_sections(&mut watchdog, 100).is_empty());
+    }
+
+    #[test_case]
+    fn disarming_expired_section_returns_its_running_time() {
+        // GIVEN
+        let mut watchdog = Watchdog::new();
+        let slot = watchdog.arm("ps2", 20, 5).unwrap();
+
+        // WHEN
+        let overrun = watchdog.disarm(slot, 40);
+
+        // THEN
+        assert_eq!(overrun, Some(20));
+        assert_eq!(watchdog.arm("ahci", 40, 5), Ok(slot));
+    }
+
+    #[test_case]
+    fn failed_report_is_retried() {
+        // GIVEN
+        let mut watchdog = Watchdog::new();
+        watchdog.arm("ahci", 0, 1).unwrap();
+        watchdog.check(5, |_, _| false);
+
+        // WHEN
+        let reports = reported_sections(&mut watchdog, 6);
+
+        // THEN
+        assert_eq!(reports, [("ahci", 6)]);
+    }
+
+    #[test_case]
+    fn arming_fails_once_every_slot_is_used() {
+        // GIVEN
+        let mut watchdog = Watchdog::new();
+        for _ in 0..MAX_WATCHED_SECTIONS {
+            watchdog.arm("ahci", 0, 1).unwrap();
+        }
+
+        // WHEN
+        let result = watchdog.arm("ps2", 0, 1);
+
+        // THEN
+        assert_eq!(result, Err("watchdog: too many watched sections"));
+    }
+}
@@ -0,0 +1,117 @@
+use alloc::collections::BTreeMap;
+use spin::Mutex;
+
+/// Major and minor number of a device node. The major number selects the driver and the minor one
+/// the instance handled by that driver.
+pub type DeviceId = (u16, u16);
+
+static DRIVERS: Mutex<DeviceRegistry> = Mutex::new(DeviceRegistry::new());
+
+/// Entry points of a driver for the nodes of its devices, the minor number selects the instance
+#[derive(Copy, Clone)]
+pub struct DeviceOperations {
+    pub read: fn(minor: u16, buffer: *mut u8, byte_count: usize, offset: usize) -> Result<(), &'static str>,
+    pub write: fn(minor: u16, buffer: *const u8, byte_count: usize, offset: usize) -> Result<(), &'static str>,
+}
+
+/// Maps major numbers to the driver handling them
+pub struct DeviceRegistry {
+    drivers: BTreeMap<u16, DeviceOperations>,
+}
+
+impl DeviceRegistry {
+    pub const fn new() -> Self {
+        Self { drivers: BTreeMap::new() }
+    }
+
+    pub fn register(&mut self, major: u16, operations: DeviceOperations) -> Result<(), &'static str> {
+        if self.drivers.contains_key(&major) {
+            return Err("fs: major number already registered");
+        }
+
+        self.drivers.insert(major, operations);
+        Ok(())
+    }
+
+    pub fn read(&self, (major, minor): DeviceId, buffer: *mut u8, byte_count: usize, offset: usize) -> Result<(), &'static str> {
+        (self.driver(major)?.read)(minor, buffer, byte_count, offset)
+    }
+
+    pub fn write(&self, (major, minor): DeviceId, buffer: *const u8, byte_count: usize, offset: usize) -> Result<(), &'static str> {
+        (self.driver(major)?.write)(minor, buffer, byte_count, offset)
+    }
+
+    fn driver(&self, major: u16) -> Result<&DeviceOperations, &'static str> {
+        self.drivers.get(&major).ok_or("fs: no driver registered for the major number")
+    }
+}
+
+/// Makes the driver handle every device node with the given major number
+pub fn register_driver(major: u16, operations: DeviceOperations) -> Result<(), &'static str> {
+    DRIVERS.lock().register(major, operations)
+}
+
+pub fn read_device(device_id: DeviceId, buffer: *mut u8, byte_count: usize, offset: usize) -> Result<(), &'static str> {
+    DRIVERS.lock().read(device_id, buffer, byte_count, offset)
+}
+
+pub fn write_device(device_id: DeviceId, buffer: *const u8, byte_count: usize, offset: usize) -> Result<(), &'static str> {
+    DRIVERS.lock().write(device_id, buffer, byte_count, offset)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::fs::device::{DeviceOperations, DeviceRegistry};
+
+    fn read_nothing(_minor: u16, _buffer: *mut u8, _byte_count: usize, _offset: usize) -> Result<(), &'static str> {
+        Ok(())
+    }
+
+    /// Writes the minor number to the first byte of the buffer, the buffer is used as an output
+    fn write_minor(minor: u16, buffer: *const u8, _byte_count: usize, _offset: usize) -> Result<(), &'static str> {
+        unsafe { (buffer as *mut u8).write(minor as u8) };
+        Ok(())
+    }
+
+    const OPERATIONS: DeviceOperations = DeviceOperations { read: read_nothing, write: write_minor };
+
+    #[test_case]
+    fn write_reaches_driver_of_major_with_minor() {
+        // GIVEN
+        let mut registry = DeviceRegistry::new();
+        registry.register(10, OPERATIONS).unwrap();
+        let mut buffer = [0u8; 1];
+
+        // WHEN
+        let result = registry.write((10, 7), buffer.as_mut_ptr(), 1, 0);
+
+        // THEN
+        assert_eq!(result, Ok(()));
+        assert_eq!(buffer, [7]);
+    }
+
+    #[test_case]
+    fn unknown_major_is_rejected() {
+        // GIVEN
+        let registry = DeviceRegistry::new();
+
+        // WHEN
+        let result = registry.read((3, 0), core::ptr::null_mut(), 0, 0);
+
+        // THEN
+        assert_eq!(result, Err("fs: no driver registered for the major number"));
+    }
+
+    #[test_case]
+    fn major_cannot_be_registered_twice() {
+        // GIVEN
+        let mut registry = DeviceRegistry::new();
+        registry.register(10, OPERATIONS).unwrap();
+
+        // WHEN
+        let result = registry.register(10, OPERATIONS);
+
+        // THEN
+        assert_eq!(result, Err("fs: major number already registered"));
+    }
+}
@@ -0,0 +1,230 @@
+use alloc::vec;
+use alloc::vec::Vec;
+use core::ffi::c_void;
+use core::mem::size_of;
+use crate::drivers::BlockDevice;
+use crate::fs::ext2::Ext2FileSystem;
+use crate::fs::ext2::block::{Superblock, SUPERBLOCK_OFFSET};
+
+/// Block and inode allocation. Bitmaps are updated through the dirty blocks and the free counts of
+/// the block groups and of the superblock are kept in sync with them in memory until `flush_counts`.
+///
+/// Only the primary superblock and block group descriptor table are kept up to date, the backup
+/// copies stored in other block groups are left as they were.
+impl Ext2FileSystem {
+    /// Marks the first free block as used and returns its number
+    pub(crate) fn allocate_block(&mut self, drive: &mut impl BlockDevice) -> Result<usize, &'static str> {
+        let blocks_per_group = self.superblock.block_group_block_count.read() as usize;
+        let first_data_block = self.superblock.superblock_block_number.read() as usize;
+        let block_count = self.superblock.block_count.read() as usize;
+
+        for group in 0..self.block_groups.len() {
+            if self.block_groups[group].unallocated_block_count.read() == 0 {
+                continue;
+            }
+
+            let group_block_count = blocks_per_group.min(block_count - first_data_block - group * blocks_per_group);
+            let bitmap_block = self.block_groups[group].block_bitmap.read() as usize;
+            let Some(index) = self.allocate_in_bitmap(drive, bitmap_block, group_block_count) else { continue };
+
+            unsafe {
+                let descriptor = &self.block_groups[group];
+                descriptor.unallocated_block_count.write(descriptor.unallocated_block_count.read() - 1);
+                self.superblock.unallocated_blocks.write(self.superblock.unallocated_blocks.read() - 1);
+            }
+
+            return Ok(first_data_block + group * blocks_per_group + index);
+        }
+
+        Err("ext2: no free block left")
+    }
+
+    /// Marks the block as free
+    pub(crate) fn free_block(&mut self, drive: &mut impl BlockDevice, block_number: usize) -> Result<(), &'static str> {
+        let blocks_per_group = self.superblock.block_group_block_count.read() as usize;
+        let first_data_block = self.superblock.superblock_block_number.read() as usize;
+
+        if block_number < first_data_block || block_number >= self.superblock.block_count.read() as usize {
+            return Err("ext2: block number out of range");
+        }
+
+        let group = (block_number - first_data_block) / blocks_per_group;
+        let bitmap_block = self.block_groups[group].block_bitmap.read() as usize;
+        self.free_in_bitmap(drive, bitmap_block, (block_number - first_data_block) % blocks_per_group)
+            .ok_or("ext2: block is already free")?;
+
+        unsafe {
+            let descriptor = &self.block_groups[group];
+            descriptor.unallocated_block_count.write(descriptor.unallocated_block_count.read() + 1);
+            self.superblock.unallocated_blocks.write(self.superblock.unallocated_blocks.read() + 1);
+        }
+
+        Ok(())
+    }
+
+    /// Marks the first free inode as used and returns its id
+    pub(crate) fn allocate_inode(&mut self, drive: &mut impl BlockDevice, is_directory: bool) -> Result<usize, &'static str> {
+        let inodes_per_group = self.superblock.block_group_inode_count.read() as usize;
+
+        for group in 0..self.block_groups.len() {
+            if self.block_groups[group].unallocated_inode_count.read() == 0 {
+                continue;
+            }
+
+            let bitmap_block = self.block_groups[group].inode_usage_bitmap_address.read() as usize;
+            let Some(index) = self.allocate_in_bitmap(drive, bitmap_block, inodes_per_group) else { continue };
+
+            unsafe {
+                let descriptor = &self.block_groups[group];
+                descriptor.unallocated_inode_count.write(descriptor.unallocated_inode_count.read() - 1);
+                if is_directory {
+                    descriptor.directory_count.write(descriptor.directory_count.read() + 1);
+                }
+                self.superblock.unallocated_inodes.write(self.superblock.unallocated_inodes.read() - 1);
+            }
+
+            // Inode ids start at 1
+            return Ok(group * inodes_per_group + index + 1);
+        }
+
+        Err("ext2: no free inode left")
+    }
+
+    /// Marks the inode as free
+    pub(crate) fn free_inode(&mut self, drive: &mut impl BlockDevice, inode_id: usize, is_directory: bool) -> Result<(), &'static str> {
+        let inodes_per_group = self.superblock.block_group_inode_count.read() as usize;
+
+        if inode_id == 0 || inode_id > self.superblock.inode_count.read() as usize {
+            return Err("ext2: inode id out of range");
+        }
+
+        let group = (inode_id - 1) / inodes_per_group;
+        let bitmap_block = self.block_groups[group].inode_usage_bitmap_address.read() as usize;
+        self.free_in_bitmap(drive, bitmap_block, (inode_id - 1) % inodes_per_group)
+            .ok_or("ext2: inode is already free")?;
+
+        unsafe {
+            let descriptor = &self.block_groups[group];
+            descriptor.unallocated_inode_count.write(descriptor.unallocated_inode_count.read() + 1);
+            if is_directory {
+                descriptor.directory_count.write(descriptor.directory_count.read() - 1);
+            }
+            self.superblock.unallocated_inodes.write(self.superblock.unallocated_inodes.read() + 1);
+        }
+
+        Ok(())
+    }
+
+    /// Writes the free counts back to the primary superblock and block group descriptor table
+    pub fn flush_counts(&mut self, drive: &mut impl BlockDevice) {
+        for (index, descriptor) in self.block_groups.iter().enumerate() {
+            descriptor.write_table_entry(drive, &self.superblock, index);
+        }
+
+        let superblock_address = &mut self.superblock as *mut Superblock as *mut c_void;
+        drive.write_to_device(SUPERBLOCK_OFFSET as u64, size_of::<Superblock>() as u64, superblock_address);
+    }
+
+    /// Sets the first clear bit among the first bit_count ones of the bitmap and returns its index
+    fn allocate_in_bitmap(&mut self, drive: &mut impl BlockDevice, bitmap_block: usize, bit_count: usize) -> Option<usize> {
+        let mut bitmap = self.read_block(drive, bitmap_block);
+
+        let index = (0..bit_count).find(|index| bitmap[index / 8] & (1 << (index % 8)) == 0)?;
+        bitmap[index / 8] |= 1 << (index % 8);
+        self.mark_block_dirty(bitmap_block, bitmap);
+
+        Some(index)
+    }
+
+    /// Clears the bit of the bitmap, returns None if it was not set
+    fn free_in_bitmap(&mut self, drive: &mut impl BlockDevice, bitmap_block: usize, index: usize) -> Option<()> {
+        let mut bitmap = self.read_block(drive, bitmap_block);
+
+        if bitmap[index / 8] & (1 << (index % 8)) == 0 {
+            return None;
+        }
+        bitmap[index / 8] &= !(1 << (index % 8));
+        self.mark_block_dirty(bitmap_block, bitmap);
+
+        Some(())
+    }
+
+    /// Reads a block, preferring its dirty contents when it was modified
+    pub(crate) fn read_block(&self, drive: &mut impl BlockDevice, block_number: usize) -> Vec<u8> {
+        if let Some(contents) = self.dirty_blocks.get(&block_number) {
+            return contents.clone();
+        }
+
+        let mut contents = vec![0u8; self.superblock.block_size()];
+        drive.read_from_device(self.superblock.block_address(block_number) as u64, contents.len() as u64, contents.as_mut_ptr() as *mut c_void);
+
+        contents
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::fs::ext2::block::SUPERBLOCK_OFFSET;
+    use crate::fs::ext2::test_image::{BLOCK_SIZE, mount, TestImage};
+
+    /// Image in which blocks 1 to 8 hold the metadata and inodes 1 to 10 are reserved
+    fn image() -> TestImage {
+        TestImage::new(64, 32)
+    }
+
+    #[test_case]
+    fn allocation_decrements_free_counts() {
+        // GIVEN
+        let mut device = image();
+        let mut file_system = mount(&mut device);
+
+        // WHEN
+        let block = file_system.allocate_block(&mut device);
+        let inode = file_system.allocate_inode(&mut device, true);
+        file_system.flush_counts(&mut device);
+
+        // THEN
+        assert_eq!(block, Ok(9));
+        assert_eq!(inode, Ok(11));
+        assert_eq!(device.read_u32(SUPERBLOCK_OFFSET as usize + 12), 54);
+        assert_eq!(device.read_u32(SUPERBLOCK_OFFSET as usize + 16), 21);
+        assert_eq!(device.read_u16(2 * BLOCK_SIZE + 12), 54);
+        assert_eq!(device.read_u16(2 * BLOCK_SIZE + 14), 21);
+        assert_eq!(device.read_u16(2 * BLOCK_SIZE + 16), 1);
+    }
+
+    #[test_case]
+    fn freeing_restores_free_counts() {
+        // GIVEN
+        let mut device = image();
+        let mut file_system = mount(&mut device);
+        let block = file_system.allocate_block(&mut device).unwrap();
+        let inode = file_system.allocate_inode(&mut device, false).unwrap();
+
+        // WHEN
+        file_system.free_block(&mut device, block).unwrap();
+        file_system.free_inode(&mut device, inode, false).unwrap();
+        file_system.flush_counts(&mut device);
+
+        // THEN
+        assert_eq!(device.read_u32(SUPERBLOCK_OFFSET as usize + 12), 55);
+        assert_eq!(device.read_u32(SUPERBLOCK_OFFSET as usize + 16), 22);
+        assert_eq!(device.read_u16(2 * BLOCK_SIZE + 12), 55);
+        assert_eq!(device.read_u16(2 * BLOCK_SIZE + 14), 22);
+        assert_eq!(file_system.allocate_block(&mut device), Ok(block));
+    }
+
+    #[test_case]
+    fn freeing_free_block_fails_without_changing_counts() {
+        // GIVEN
+        let mut device = image();
+        let mut file_system = mount(&mut device);
+
+        // WHEN
+        let result = file_system.free_block(&mut device, 20);
+
+        // THEN
+        assert_eq!(result, Err("ext2: block is already free"));
+        assert_eq!(file_system.superblock.unallocated_blocks.read(), 55);
+    }
+}
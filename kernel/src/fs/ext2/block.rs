@@ -1,11 +1,15 @@
 use core::ffi::c_void;
 use core::mem::{MaybeUninit, size_of};
 use bitflags::bitflags;
-use volatile_register::RO;
-use crate::drivers::pci::ahci::AHCIDevice;
+use volatile_register::{RO, RW};
+use crate::drivers::BlockDevice;
+use crate::utils::any_as_u8_slice;
+use crate::utils::crc32c::crc32c_update;
 
 const EXT2_SIGNATURE: u16 = 0xEF53;
-const SUPERBLOCK_OFFSET: u16 = 1024;
+pub(crate) const SUPERBLOCK_OFFSET: u16 = 1024;
+/// Offset of the checksum within the superblock, it covers every byte before it
+pub(super) const SUPERBLOCK_CHECKSUM_OFFSET: usize = 1020;
 
 #[repr(C)]
 pub(crate) struct Superblock {
@@ -17,9 +21,9 @@ pub(crate) struct Superblock {
     pub(crate) superuser_blocks: RO<u32>,
     /// 32bit value indicating the total number of free blocks, including the number of reserved blocks (see
     /// s_r_blocks_count). This is a sum of all free blocks of all the block groups.
-    pub(crate) unallocated_blocks: RO<u32>,
+    pub(crate) unallocated_blocks: RW<u32>,
     /// 32bit value indicating the total number of free inodes. This is a sum of all free inodes of all the block groups.
-    pub(crate) unallocated_inodes: RO<u32>,
+    pub(crate) unallocated_inodes: RW<u32>,
     /// 32bit value identifying the first data block, in other word the id of the block containing the superblock
     /// structure.
     pub(crate) superblock_block_number: RO<u32>,
@@ -40,7 +44,7 @@ pub(crate) struct Superblock {
     /// Unix time, as defined by POSIX, of the last time the file system was mounted.
     pub(crate) last_mount_time: RO<u32>,
     /// Unix time, as defined by POSIX, of the last write access to the file system.
-    pub(crate) last_write_time: RO<u32>,
+    pub(crate) last_write_time: RW<u32>,
     /// 16bit value indicating how many time the file system was mounted since the last time it was fully verified.
     pub(crate) mount_count: RO<u16>,
     /// 16bit value indicating the maximum number of times that the file system may be mounted before a full
@@ -54,7 +58,7 @@ pub(crate) struct Superblock {
     /// When mounting the file system, if a valid of EXT2_ERROR_FS is encountered it means the file system
     /// was not cleanly unmounted and most likely contain errors that will need to be fixed. Typically under Linux
     /// this means running fsck.
-    pub(crate) file_system_state: RO<FileSystemState>,
+    pub(crate) file_system_state: RW<FileSystemState>,
     /// 16bit value indicating what the file system driver should do when an error is detected
     pub(crate) error_detection_mechanism: RO<ErrorHandlingMethod>,
     /// 16bit value identifying the minor revision level within its revision level
@@ -141,18 +145,41 @@ pub(crate) struct Superblock {
     pub(crate) default_mount_options: RO<u32>,
     /// A 32bit value indicating the block group ID of the first meta block group
     pub(crate) first_meta_bg: RO<u32>,
-    _unused: RO<[u8; 760]>,
+    _unused: RO<[u8; 756]>,
+    /// CRC32C of the superblock, only maintained when the METADATA_CSUM feature is set
+    pub(crate) checksum: RO<u32>,
 }
 impl Superblock {
-    pub(crate) fn read_from_disk(drive: &mut AHCIDevice) -> Superblock {
-        let mut superblock = MaybeUninit::<Superblock>::uninit();
+    pub(crate) fn read_from_disk(drive: &mut impl BlockDevice) -> Result<Superblock, &'static str> {
+        let mut superblock = MaybeUninit::<Superblock>::zeroed();
 
         drive.read_from_device(SUPERBLOCK_OFFSET as u64, size_of::<Superblock>() as u64, superblock.as_mut_ptr() as *mut c_void);
         let superblock = unsafe { superblock.assume_init() };
 
-        assert_eq!(superblock.ext2_signature.read(), EXT2_SIGNATURE);
+        if superblock.ext2_signature.read() != EXT2_SIGNATURE {
+            return Err("ext2: invalid superblock signature");
+        }
+
+        Ok(superblock)
+    }
+
+    /// Checks the superblock against its checksum on file systems with the METADATA_CSUM feature
+    pub(crate) fn verify_checksum(&self) -> Result<(), &'static str> {
+        if !self.read_only_compatible_features.read().contains(ReadOnlyCompatibleFeatures::METADATA_CSUM) {
+            return Ok(());
+        }
+
+        if self.compute_checksum() != self.checksum.read() {
+            return Err("ext2: superblock checksum mismatch");
+        }
 
-        superblock
+        Ok(())
+    }
+
+    fn compute_checksum(&self) -> u32 {
+        let bytes = unsafe { any_as_u8_slice(self) };
+
+        crc32c_update(!0, &bytes[..SUPERBLOCK_CHECKSUM_OFFSET])
     }
 
     pub(crate) fn block_group_count(&self) -> usize {
@@ -171,13 +198,32 @@ impl Superblock {
         }
     }
 
-    pub(crate) fn block_size_bytes(&self) -> usize {
+    /// Whether regular files store the upper 32 bits of their size in `Inode::dir_acl`
+    pub(crate) fn has_large_files(&self) -> bool {
+        matches!(self.version_major.read(), RevisionLevel::Dynamicrevision)
+            && self.read_only_compatible_features.read().contains(ReadOnlyCompatibleFeatures::LARGE_FILE)
+    }
+
+    /// Size in bytes of a block on this file system
+    pub(crate) fn block_size(&self) -> usize {
         1024 << self.log_block_size.read()
     }
+
+    /// Byte offset from the start of the device of the given block
+    pub(crate) fn block_address(&self, block_number: usize) -> usize {
+        block_number * self.block_size()
+    }
+
+    /// Number of device sectors making up a single block
+    pub(crate) fn sectors_per_block(&self, sector_size: usize) -> usize {
+        assert_eq!(self.block_size() % sector_size, 0, "ext2: block size is not a multiple of the sector size");
+
+        self.block_size() / sector_size
+    }
 }
 
 #[repr(u16)]
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
 pub(crate) enum FileSystemState {
     Clean = 1,
     Error = 2,
@@ -233,6 +279,7 @@ bitflags! {
         const SPARSE_SUPER = 1 << 0;
         const LARGE_FILE = 1 << 1;
         const BTREE_DIR = 1 << 4;
+        const METADATA_CSUM = 1 << 10;
     }
 
     #[derive(Copy, Clone)]
@@ -256,11 +303,11 @@ pub(crate) struct BlockGroupDescriptor {
     /// 32bit block id of the first block of the “inode table” for the group represented
     pub(crate) inode_table_block_address: RO<u32>,
     /// 16bit value indicating the total number of free blocks for the represented group.
-    pub(crate) unallocated_block_count: RO<u16>,
+    pub(crate) unallocated_block_count: RW<u16>,
     /// 16bit value indicating the total number of free inodes for the represented group.
-    pub(crate) unallocated_inode_count: RO<u16>,
+    pub(crate) unallocated_inode_count: RW<u16>,
     /// 16bit value indicating the number of inodes allocated to directories for the represented group.
-    pub(crate) directory_count: RO<u16>,
+    pub(crate) directory_count: RW<u16>,
 
     /// 16bit value used for padding the structure on a 32bit boundary.
     _pad: RO<u16>,
@@ -269,12 +316,123 @@ pub(crate) struct BlockGroupDescriptor {
 }
 
 impl BlockGroupDescriptor {
-    pub(crate) fn read_table_entry(drive: &mut AHCIDevice, superblock: &Superblock, index: usize) -> Self {
-        let first_entry_address = (1024 << superblock.log_block_size.read()) * if superblock.log_block_size.read() == 0 { 2 } else { 1 };
-        let offset = first_entry_address + index * size_of::<BlockGroupDescriptor>();
+    pub(crate) fn read_table_entry(drive: &mut impl BlockDevice, superblock: &Superblock, index: usize) -> Self {
+        let offset = Self::table_entry_address(superblock, index);
 
         let mut entry = MaybeUninit::<BlockGroupDescriptor>::uninit();
         drive.read_from_device(offset as u64, size_of::<BlockGroupDescriptor>() as u64, entry.as_mut_ptr() as *mut c_void);
         unsafe { entry.assume_init() }
     }
-}
\ No newline at end of file
+
+    /// Writes the descriptor back to the primary block group descriptor table
+    pub(crate) fn write_table_entry(&self, drive: &mut impl BlockDevice, superblock: &Superblock, index: usize) {
+        let offset = Self::table_entry_address(superblock, index);
+        drive.write_to_device(offset as u64, size_of::<BlockGroupDescriptor>() as u64, self as *const BlockGroupDescriptor as *mut c_void);
+    }
+
+    /// Byte offset of the given entry of the table, which starts on the block right after the one
+    /// holding the superblock
+    fn table_entry_address(superblock: &Superblock, index: usize) -> usize {
+        let first_entry_address = superblock.block_address(superblock.superblock_block_number.read() as usize + 1);
+
+        first_entry_address + index * size_of::<BlockGroupDescriptor>()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::mem::size_of;
+    use crate::drivers::pci::ahci::sector_span;
+    use crate::fs::ext2::block::{Superblock, SUPERBLOCK_CHECKSUM_OFFSET};
+    use crate::fs::ext2::test_image::TestImage;
+    use crate::utils::crc32c::crc32c;
+
+    const SECTOR_SIZE: usize = 512;
+
+    #[test_case]
+    fn superblock_checksum_is_at_end_of_superblock() {
+        // THEN
+        assert_eq!(size_of::<Superblock>(), 1024);
+        assert_eq!(SUPERBLOCK_CHECKSUM_OFFSET, 1024 - 4);
+    }
+
+    #[test_case]
+    fn valid_superblock_checksum_is_accepted() {
+        // GIVEN
+        let superblock = TestImage::new(8, 16).with_metadata_checksum(|bytes| !crc32c(bytes)).superblock();
+
+        // WHEN
+        let result = superblock.verify_checksum();
+
+        // THEN
+        assert_eq!(result, Ok(()));
+    }
+
+    #[test_case]
+    fn corrupted_superblock_checksum_is_rejected() {
+        // GIVEN
+        let superblock = TestImage::new(8, 16).with_metadata_checksum(|bytes| !crc32c(bytes) ^ 1).superblock();
+
+        // WHEN
+        let result = superblock.verify_checksum();
+
+        // THEN
+        assert_eq!(result, Err("ext2: superblock checksum mismatch"));
+    }
+
+    #[test_case]
+    fn superblock_without_metadata_checksums_is_not_verified() {
+        // GIVEN
+        let superblock = TestImage::new(8, 16).superblock();
+
+        // WHEN
+        let result = superblock.verify_checksum();
+
+        // THEN
+        assert_eq!(result, Ok(()));
+    }
+
+    #[test_case]
+    fn block_size_from_log_block_size() {
+        for (log_block_size, expected_block_size) in [(0, 1024), (1, 2048), (2, 4096)] {
+            // GIVEN
+            let superblock = TestImage::new(8, 16).with_log_block_size(log_block_size).superblock();
+
+            // WHEN
+            let block_size = superblock.block_size();
+
+            // THEN
+            assert_eq!(block_size, expected_block_size);
+            assert_eq!(superblock.block_address(3), 3 * expected_block_size);
+        }
+    }
+
+    #[test_case]
+    fn block_read_spans_expected_sectors() {
+        for log_block_size in 0..=2 {
+            // GIVEN
+            let superblock = TestImage::new(8, 16).with_log_block_size(log_block_size).superblock();
+            let block_address = superblock.block_address(5);
+
+            // WHEN
+            let (start_sector, sector_count) = sector_span(block_address as u64, superblock.block_size() as u64, SECTOR_SIZE as u64);
+
+            // THEN
+            assert_eq!(start_sector as usize, block_address / SECTOR_SIZE);
+            assert_eq!(sector_count as usize, superblock.sectors_per_block(SECTOR_SIZE));
+        }
+    }
+
+    #[test_case]
+    fn unaligned_read_spans_extra_sector() {
+        // GIVEN
+        let byte_offset = 1024 + 100;
+
+        // WHEN
+        let (start_sector, sector_count) = sector_span(byte_offset, SECTOR_SIZE as u64, SECTOR_SIZE as u64);
+
+        // THEN
+        assert_eq!(start_sector, 2);
+        assert_eq!(sector_count, 2);
+    }
+}
@@ -1,7 +1,14 @@
 use alloc::string::String;
+use alloc::vec::Vec;
+use crate::fs::validate_name;
 use volatile_register::{RO};
 use core::str;
 
+/// Size of the fixed part of a directory entry preceding the name
+const DIRECTORY_ENTRY_HEADER_SIZE: usize = 8;
+/// Longest name a directory entry can hold, `name_len` is a single byte
+const MAX_ENTRY_NAME_LENGTH: usize = 255;
+
 #[repr(C)]
 pub(crate) struct DirectoryEntry {
     /// 32bit inode number of the file entry. A value of 0 indicate that the entry is not used.
@@ -27,6 +34,80 @@ impl DirectoryEntry {
     }
 }
 
+/// Walks the raw contents of a directory, which may span multiple blocks, and yields the name,
+/// inode number and file type of every used entry. Entries never cross a block boundary, the last
+/// entry of a block has its rec_len extended up to the end of that block.
+pub(crate) struct DirectoryEntryIterator<'a> {
+    directory_data: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> DirectoryEntryIterator<'a> {
+    pub(crate) fn new(directory_data: &'a [u8]) -> Self {
+        Self { directory_data, offset: 0 }
+    }
+}
+
+impl Iterator for DirectoryEntryIterator<'_> {
+    type Item = (String, u32, FileType);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.offset + DIRECTORY_ENTRY_HEADER_SIZE <= self.directory_data.len() {
+            let entry = &self.directory_data[self.offset..];
+
+            let inode = u32::from_le_bytes([entry[0], entry[1], entry[2], entry[3]]);
+            let rec_len = u16::from_le_bytes([entry[4], entry[5]]) as usize;
+            let name_len = entry[6] as usize;
+            let file_type = FileType::from_raw(entry[7]);
+
+            if rec_len < DIRECTORY_ENTRY_HEADER_SIZE {
+                break;
+            }
+            self.offset += rec_len;
+
+            // An inode number of 0 marks an unused entry
+            let Some(name) = entry.get(DIRECTORY_ENTRY_HEADER_SIZE..DIRECTORY_ENTRY_HEADER_SIZE + name_len) else {
+                break;
+            };
+            if inode != 0 {
+                return Some((String::from_utf8_lossy(name).into_owned(), inode, file_type));
+            }
+        }
+
+        // Stop for good on a malformed entry
+        self.offset = self.directory_data.len();
+        None
+    }
+}
+
+/// Scans the raw contents of a directory for an entry with the given name and returns its inode number
+pub(crate) fn find_directory_entry(directory_data: &[u8], name: &str) -> Option<u32> {
+    DirectoryEntryIterator::new(directory_data)
+        .find(|(entry_name, _, _)| entry_name == name)
+        .map(|(_, inode, _)| inode)
+}
+
+/// Encodes a directory entry for the given name, with its rec_len covering only the entry itself
+/// rounded up to the 4 bytes alignment
+pub(crate) fn encode_directory_entry(inode: u32, name: &str, file_type: FileType) -> Result<Vec<u8>, &'static str> {
+    validate_name(name)?;
+    if name.len() > MAX_ENTRY_NAME_LENGTH {
+        return Err("ext2: file name is longer than 255 bytes");
+    }
+
+    let rec_len = (DIRECTORY_ENTRY_HEADER_SIZE + name.len()).next_multiple_of(4);
+
+    let mut entry = Vec::with_capacity(rec_len);
+    entry.extend_from_slice(&inode.to_le_bytes());
+    entry.extend_from_slice(&(rec_len as u16).to_le_bytes());
+    entry.push(name.len() as u8);
+    entry.push(file_type as u8);
+    entry.extend_from_slice(name.as_bytes());
+    entry.resize(rec_len, 0);
+
+    Ok(entry)
+}
+
 #[repr(u8)]
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
 pub(crate) enum FileType {
@@ -38,4 +119,154 @@ pub(crate) enum FileType {
     Buffer = 5,
     Socket = 6,
     SymbolicLink = 7,
-}
\ No newline at end of file
+}
+
+impl FileType {
+    fn from_raw(value: u8) -> Self {
+        match value {
+            1 => FileType::RegularFile,
+            2 => FileType::Directory,
+            3 => FileType::CharacterDevice,
+            4 => FileType::BlockDevice,
+            5 => FileType::Buffer,
+            6 => FileType::Socket,
+            7 => FileType::SymbolicLink,
+            _ => FileType::Unknown,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec;
+    use alloc::vec::Vec;
+    use alloc::string::String;
+    use crate::fs::ext2::directory::{DirectoryEntryIterator, encode_directory_entry, FileType, find_directory_entry};
+
+    const BLOCK_SIZE: usize = 1024;
+
+    fn push_entry(directory_data: &mut Vec<u8>, inode: u32, rec_len: u16, name: &str) {
+        push_typed_entry(directory_data, inode, rec_len, name, FileType::RegularFile);
+    }
+
+    fn push_typed_entry(directory_data: &mut Vec<u8>, inode: u32, rec_len: u16, name: &str, file_type: FileType) {
+        let entry_start = directory_data.len();
+
+        directory_data.extend_from_slice(&inode.to_le_bytes());
+        directory_data.extend_from_slice(&rec_len.to_le_bytes());
+        directory_data.push(name.len() as u8);
+        directory_data.push(file_type as u8);
+        directory_data.extend_from_slice(name.as_bytes());
+        directory_data.resize(entry_start + rec_len as usize, 0);
+    }
+
+    #[test_case]
+    fn find_directory_entry_in_second_block() {
+        // GIVEN
+        let mut directory_data = Vec::new();
+        push_entry(&mut directory_data, 2, 12, ".");
+        push_entry(&mut directory_data, 2, 12, "..");
+        push_entry(&mut directory_data, 11, (BLOCK_SIZE - 24) as u16, "first.txt"); // Fills the first block exactly
+        push_entry(&mut directory_data, 12, 20, "second.txt");
+        push_entry(&mut directory_data, 0, 20, "deleted.txt");
+        push_entry(&mut directory_data, 13, (BLOCK_SIZE - 40) as u16, "third.txt");
+
+        // WHEN
+        let first = find_directory_entry(&directory_data, "first.txt");
+        let second = find_directory_entry(&directory_data, "second.txt");
+        let third = find_directory_entry(&directory_data, "third.txt");
+
+        // THEN
+        assert_eq!(directory_data.len(), 2 * BLOCK_SIZE);
+        assert_eq!(first, Some(11));
+        assert_eq!(second, Some(12));
+        assert_eq!(third, Some(13));
+    }
+
+    #[test_case]
+    fn find_directory_entry_skips_unused_and_missing_entries() {
+        // GIVEN
+        let mut directory_data = Vec::new();
+        push_entry(&mut directory_data, 0, 24, "deleted.txt");
+        push_entry(&mut directory_data, 14, (BLOCK_SIZE - 24) as u16, "file.txt");
+        directory_data.extend_from_slice(&vec![0u8; BLOCK_SIZE]);
+
+        // WHEN
+        let deleted = find_directory_entry(&directory_data, "deleted.txt");
+        let missing = find_directory_entry(&directory_data, "missing.txt");
+
+        // THEN
+        assert_eq!(deleted, None);
+        assert_eq!(missing, None);
+    }
+
+    #[test_case]
+    fn directory_entry_iterator_yields_used_entries_in_order() {
+        // GIVEN
+        let mut directory_data = Vec::new();
+        push_typed_entry(&mut directory_data, 2, 12, ".", FileType::Directory);
+        push_typed_entry(&mut directory_data, 2, 12, "..", FileType::Directory);
+        push_typed_entry(&mut directory_data, 0, 20, "deleted.txt", FileType::RegularFile);
+        push_typed_entry(&mut directory_data, 12, 12, "docs", FileType::Directory);
+        push_typed_entry(&mut directory_data, 13, (BLOCK_SIZE - 56) as u16, "file.txt", FileType::RegularFile);
+        push_typed_entry(&mut directory_data, 14, BLOCK_SIZE as u16, "link", FileType::SymbolicLink);
+
+        // WHEN
+        let entries: Vec<(String, u32, FileType)> = DirectoryEntryIterator::new(&directory_data).collect();
+
+        // THEN
+        assert_eq!(entries, vec![
+            (String::from("."), 2, FileType::Directory),
+            (String::from(".."), 2, FileType::Directory),
+            (String::from("docs"), 12, FileType::Directory),
+            (String::from("file.txt"), 13, FileType::RegularFile),
+            (String::from("link"), 14, FileType::SymbolicLink),
+        ]);
+    }
+
+    #[test_case]
+    fn directory_entry_iterator_stops_on_malformed_entry() {
+        // GIVEN
+        let mut directory_data = Vec::new();
+        push_entry(&mut directory_data, 11, 12, "a.txt");
+        push_entry(&mut directory_data, 12, 0, "");
+        directory_data.resize(BLOCK_SIZE, 0);
+
+        // WHEN
+        let mut entries = DirectoryEntryIterator::new(&directory_data);
+
+        // THEN
+        assert_eq!(entries.next().map(|(_, inode, _)| inode), Some(11));
+        assert_eq!(entries.next(), None);
+        assert_eq!(entries.next(), None);
+    }
+
+    #[test_case]
+    fn encoded_directory_entry_can_be_found() {
+        // WHEN
+        let entry = encode_directory_entry(21, "notes.txt", FileType::RegularFile).unwrap();
+
+        // THEN
+        assert_eq!(entry.len(), 20);
+        assert_eq!(entry[4..8], [20, 0, 9, FileType::RegularFile as u8]);
+        assert_eq!(find_directory_entry(&entry, "notes.txt"), Some(21));
+    }
+
+    #[test_case]
+    fn encode_directory_entry_rejects_invalid_names() {
+        // GIVEN
+        let too_long = "a".repeat(256);
+
+        // WHEN
+        let long = encode_directory_entry(21, &too_long, FileType::RegularFile);
+        let slash = encode_directory_entry(21, "a/b", FileType::RegularFile);
+        let nul = encode_directory_entry(21, "a\0b", FileType::RegularFile);
+        let empty = encode_directory_entry(21, "", FileType::RegularFile);
+
+        // THEN
+        assert_eq!(long, Err("ext2: file name is longer than 255 bytes"));
+        assert_eq!(slash, Err("fs: file name contains a '/'"));
+        assert_eq!(nul, Err("fs: file name contains a NUL byte"));
+        assert_eq!(empty, Err("fs: file name is empty"));
+    }
+}
@@ -1,12 +1,21 @@
+use alloc::string::String;
 use alloc::vec;
 use alloc::vec::Vec;
 use core::ffi::c_void;
 use core::mem::{MaybeUninit, size_of};
+use core::{ptr, slice};
 use bitflags::bitflags;
-use volatile_register::RO;
+use volatile_register::{RO, RW};
+use crate::drivers::{AsyncBlockDevice, BlockDevice};
 use crate::drivers::pci::ahci::AHCIDevice;
+use crate::fs::ext2::Ext2FileSystem;
 use crate::fs::ext2::block::{BlockGroupDescriptor, Superblock};
-use crate::fs::ext2::directory::{DirectoryEntry};
+use crate::fs::ext2::directory::{DirectoryEntryIterator, FileType, find_directory_entry};
+
+/// Number of block pointers in `Inode::block` that point directly to data
+pub(crate) const DIRECT_BLOCK_COUNT: usize = 12;
+/// Bits of `Inode::mode` holding the file format
+const FILE_FORMAT_MASK: u16 = 0xF000;
 
 #[repr(C)]
 pub(crate) struct Inode {
@@ -17,7 +26,7 @@ pub(crate) struct Inode {
     /// In revision 0, (signed) 32bit value indicating the size of the file in bytes. In revision 1 and later revisions,
     /// and only for regular files, this represents the lower 32-bit of the file size; the upper 32-bit is located in
     /// the dir_acl.
-    pub(crate) size: RO<u32>,
+    pub(crate) size: RW<u32>,
     /// 32bit value representing the number of seconds since january 1st 1970 of the last time this inode was
     /// accessed.
     pub(crate) atime: RO<u32>,
@@ -36,7 +45,7 @@ pub(crate) struct Inode {
     /// 32-bit value representing the total number of 512-bytes blocks reserved to contain the data of this inode,
     /// regardless if these blocks are used or not. The block numbers of these reserved blocks are contained in
     /// the i_block array.
-    pub(crate) blocks: RO<u32>,
+    pub(crate) blocks: RW<u32>,
     /// 32bit value indicating how the ext2 implementation should behave when accessing the data for this inode.
     pub(crate) flags: RO<InodeFlags>,
     /// 32bit OS dependant value.
@@ -46,7 +55,7 @@ pub(crate) struct Inode {
     /// block containing an array of block ID containing the data. Therefore, the 13th block of the file will be the
     /// first block ID contained in the indirect block. With a 1KiB block size, blocks 13 to 268 of the file data
     /// are contained in this indirect block.
-    pub(crate) block: RO<[u32; 15]>,
+    pub(crate) block: RW<[u32; 15]>,
     /// 32bit value used to indicate the file version (used by NFS).
     pub(crate) generation: RO<u32>,
     /// 32bit value indicating the block number containing the extended attributes. In revision 0 this value is
@@ -54,7 +63,7 @@ pub(crate) struct Inode {
     pub(crate) file_acl: RO<u32>,
     /// In revision 0 this 32bit value is always 0. In revision 1, for regular files this 32bit value contains the high
     /// 32 bits of the 64bit file size.
-    pub(crate) dir_acl: RO<u32>,
+    pub(crate) dir_acl: RW<u32>,
     /// 32bit value indicating the location of the file fragment.
     pub(crate) faddr: RO<u32>,
     /// 96bit OS dependant structure.
@@ -116,102 +125,178 @@ bitflags! {
     }
 }
 
+/// Inodes are plain on-disk data, copying their bytes copies them
+impl Clone for Inode {
+    fn clone(&self) -> Self {
+        unsafe { ptr::read(self) }
+    }
+}
+
 impl Inode {
-    pub(crate) fn get_from_id(drive: &mut AHCIDevice, superblock: &Superblock, inode_id: usize) -> Self {
+    pub(crate) fn get_from_id(drive: &mut impl BlockDevice, superblock: &Superblock, inode_id: usize) -> Self {
         let group_id = Inode::get_containing_block_group_id(superblock, inode_id);
         let inode_index = Self::get_local_table_index(superblock, inode_id);
 
         let block_group_descriptor = BlockGroupDescriptor::read_table_entry(drive, superblock, group_id);
         let table_address = block_group_descriptor.inode_table_block_address.read();
 
-        let containing_block = inode_index * superblock.inode_size() as usize / (1024 << superblock.log_block_size.read()) as usize;
+        let containing_block = inode_index * superblock.inode_size() as usize / superblock.block_size();
 
         let inode_address = table_address as usize + containing_block; // block
-        let inode_address_bytes = inode_address * (1024 << superblock.log_block_size.read()) + inode_index * superblock.inode_size() as usize;
+        let inode_address_bytes = superblock.block_address(inode_address) + (inode_index * superblock.inode_size() as usize) % superblock.block_size();
 
         let mut inode = MaybeUninit::<Inode>::uninit();
         drive.read_from_device(inode_address_bytes as u64, size_of::<Inode>() as u64, inode.as_mut_ptr() as *mut c_void);
         unsafe { inode.assume_init() }
     }
 
+    /// Prints the names of the entries of the directory
     pub(crate) fn print_content(&self, drive: &mut AHCIDevice, superblock: &Superblock) {
-        let initial_address = self.block.read()[0] * (1024 << superblock.log_block_size.read());
-        let mut file_address = initial_address;
-
-        // TODO: Support multi block files
-        // Read the content of the pointed block
-        loop {
-            let mut file = MaybeUninit::<DirectoryEntry>::uninit();
-            drive.read_from_device(file_address as u64, size_of::<DirectoryEntry>() as u64, file.as_mut_ptr() as *mut c_void);
-            let file = unsafe { file.assume_init() };
-
-            file.name();
-            print!(" ");
-
-            file_address += file.rec_len.read() as u32;
-
-            // Break if the next file is outside the current block
-            if file_address - initial_address >= (1024 << superblock.log_block_size.read()) {
-                break;
-            }
+        for (name, _, _) in self.list_directory(drive, superblock) {
+            print!("{} ", name);
         }
 
         println!("");
     }
 
+    /// Returns the name, inode number and file type of every entry of the directory
+    pub(crate) fn list_directory(&self, drive: &mut impl BlockDevice, superblock: &Superblock) -> Vec<(String, u32, FileType)> {
+        let inode_data = self.get_content(drive, superblock);
+
+        DirectoryEntryIterator::new(&inode_data).collect()
+    }
+
     /// Looks for an inode with the given name in the current inode's children.
     /// Returns None if the requested Inode was not present
-    pub(crate) fn find_child_inode(&self, drive: &mut AHCIDevice, superblock: &Superblock, name: &str) -> Option<Inode> {
-        if matches!(self.mode.read(), InodeMode::DIRECTORY) {
+    pub(crate) fn find_child_inode(&self, drive: &mut impl BlockDevice, superblock: &Superblock, name: &str) -> Option<Inode> {
+        if !self.is_directory() {
             panic!("ext2: not a directory")
         }
 
-        let mut inode_data = self.get_content(drive, superblock);
+        self.find_child_inode_id(drive, superblock, name).map(|inode_id| Self::get_from_id(drive, superblock, inode_id))
+    }
 
-        let mut read_bytes = 0;
-        while read_bytes < inode_data.len() {
-            let directory_entry_pointer = (inode_data.as_mut_ptr() as usize + read_bytes) as *mut DirectoryEntry;
-            let directory_entry = unsafe { &*directory_entry_pointer };
+    /// Looks for an entry with the given name in the current directory and returns its inode id
+    pub(crate) fn find_child_inode_id(&self, drive: &mut impl BlockDevice, superblock: &Superblock, name: &str) -> Option<usize> {
+        let inode_data = self.get_content(drive, superblock);
 
-            if directory_entry.name() == name {
-                return Some(Self::get_from_id(drive, superblock, directory_entry.inode.read() as usize));
-            }
+        find_directory_entry(&inode_data, name).map(|inode_id| inode_id as usize)
+    }
+
+    pub(crate) fn is_directory(&self) -> bool {
+        self.mode.read().bits() & FILE_FORMAT_MASK == InodeMode::DIRECTORY.bits()
+    }
 
-            read_bytes += directory_entry.rec_len.read() as usize;
+    pub(crate) fn is_regular_file(&self) -> bool {
+        self.mode.read().bits() & FILE_FORMAT_MASK == InodeMode::REGULAR_FILE.bits()
+    }
+
+    /// Size in bytes of the file. When the file system supports large files, the upper 32 bits of
+    /// the size of regular files are stored in `dir_acl`.
+    pub(crate) fn size(&self, superblock: &Superblock) -> u64 {
+        let lower_size = self.size.read() as u64;
+
+        if self.is_regular_file() && superblock.has_large_files() {
+            (self.dir_acl.read() as u64) << 32 | lower_size
+        }
+        else {
+            lower_size
         }
+    }
+
+    /// Reads every data block of the inode and returns the contents truncated to the inode's size.
+    /// Holes, blocks whose pointer is zero, read as zeros.
+    pub(crate) fn get_content(&self, drive: &mut impl BlockDevice, superblock: &Superblock) -> Vec<u8> {
+        let block_size = superblock.block_size();
+        let data_blocks = self.data_blocks(drive, superblock);
+
+        let mut inode_data = vec![0u8; data_blocks.len() * block_size];
+        for (index, block_number) in data_blocks.iter().enumerate() {
+            // The buffer is zeroed already, and block 0 holds the boot record and superblock
+            if *block_number == 0 {
+                continue;
+            }
 
+            let write_address = (inode_data.as_mut_ptr() as usize + index * block_size) as *mut c_void;
+            drive.read_from_device(superblock.block_address(*block_number as usize) as u64, block_size as u64, write_address);
+        }
 
-        None
+        inode_data.truncate(self.size(superblock) as usize);
+        inode_data
     }
 
-    pub(crate) fn get_content(&self, drive: &mut AHCIDevice, superblock: &Superblock) -> Vec<u8> {
-        let file_start_address = self.block.read()[0] as usize * superblock.block_size_bytes();
+    /// Same as `get_content`, but awaits the reads of the data blocks so other tasks can run while
+    /// they complete. Block pointers are still read synchronously.
+    pub(crate) async fn get_content_async(&self, drive: &mut impl AsyncBlockDevice, superblock: &Superblock) -> Result<Vec<u8>, &'static str> {
+        let block_size = superblock.block_size();
+        let data_blocks = self.data_blocks(drive, superblock);
 
-        let mut inode_data = vec![0u8; self.size.read() as usize];
-        for block_number in 0..self.adjusted_block_count(superblock) {
-            // First 12 blocks, direct indexing
-            if block_number < 12 {
-                let write_address = (inode_data.as_mut_ptr() as usize + block_number * superblock.block_size_bytes()) as *mut c_void;
-                drive.read_from_device(file_start_address as u64, size_of::<DirectoryEntry>() as u64, write_address);
+        let mut inode_data = vec![0u8; data_blocks.len() * block_size];
+        for (block_content, block_number) in inode_data.chunks_mut(block_size).zip(data_blocks) {
+            if block_number == 0 {
+                continue;
             }
 
-            // 13th block, indirect indexing
-            else if block_number == 12 {
-                unimplemented!();
+            let block = drive.read_async(superblock.block_address(block_number as usize) as u64, block_size as u64).await?;
+            block_content[..block.len()].copy_from_slice(&block);
+        }
+
+        inode_data.truncate(self.size(superblock) as usize);
+        Ok(inode_data)
+    }
+
+    /// Returns the numbers of the blocks holding the inode's data, in file order
+    fn data_blocks(&self, drive: &mut impl BlockDevice, superblock: &Superblock) -> Vec<u32> {
+        let block_count = (self.size(superblock) as usize).div_ceil(superblock.block_size());
+        let block_pointers = self.block.read();
+
+        // First 12 blocks, direct indexing
+        let mut data_blocks = block_pointers[..block_count.min(DIRECT_BLOCK_COUNT)].to_vec();
+
+        // 13th, 14th and 15th blocks, singly, doubly and triply indirect indexing
+        for (level, block_number) in (1..=3).zip(&block_pointers[DIRECT_BLOCK_COUNT..]) {
+            if data_blocks.len() >= block_count {
+                break;
             }
 
-            // 14th block, doubly indirect indexing
-            else if block_number == 13 {
-                unimplemented!();
+            Self::read_indirect(drive, superblock, *block_number, level, block_count, &mut data_blocks);
+        }
+
+        data_blocks
+    }
+
+    /// Appends the data block numbers reachable through an indirect block with the given level of
+    /// indirection until `block_count` blocks are known. A zero pointer is a hole, every data block
+    /// it would have covered is reported as block 0.
+    fn read_indirect(drive: &mut impl BlockDevice, superblock: &Superblock, block_number: u32, level: u32, block_count: usize, data_blocks: &mut Vec<u32>) {
+        let pointers_per_block = superblock.block_size() / size_of::<u32>();
+
+        if block_number == 0 {
+            let hole_size = pointers_per_block.pow(level).min(block_count - data_blocks.len());
+            data_blocks.resize(data_blocks.len() + hole_size, 0);
+            return;
+        }
+
+        for block_pointer in Self::read_block_pointers(drive, superblock, block_number) {
+            if data_blocks.len() >= block_count {
+                break;
             }
 
-            // 15h block, triply indirect indexing
-            else if block_number == 14 {
-                unimplemented!();
+            if level == 1 {
+                data_blocks.push(block_pointer);
+            }
+            else {
+                Self::read_indirect(drive, superblock, block_pointer, level - 1, block_count, data_blocks);
             }
         }
+    }
 
-        inode_data
+    /// Reads a block containing an array of block numbers
+    fn read_block_pointers(drive: &mut impl BlockDevice, superblock: &Superblock, block_number: u32) -> Vec<u32> {
+        let mut block_pointers = vec![0u32; superblock.block_size() / size_of::<u32>()];
+        drive.read_from_device(superblock.block_address(block_number as usize) as u64, superblock.block_size() as u64, block_pointers.as_mut_ptr() as *mut c_void);
+
+        block_pointers
     }
 
     fn get_containing_block_group_id(superblock: &Superblock, inode_id: usize) -> usize {
@@ -223,6 +308,293 @@ impl Inode {
     }
 
     fn adjusted_block_count(&self, superblock: &Superblock) -> usize {
-        (self.blocks.read() as usize * 512) / superblock.block_size_bytes()
+        (self.blocks.read() as usize * 512) / superblock.block_size()
+    }
+}
+
+/// Inode write-back. Inode table blocks go through the dirty blocks, so inodes read with
+/// `read_inode` see the changes made before the file system is unmounted.
+impl Ext2FileSystem {
+    pub(crate) fn read_inode(&self, drive: &mut impl BlockDevice, inode_id: usize) -> Inode {
+        let (block_number, offset) = self.inode_location(inode_id);
+        let contents = self.read_block(drive, block_number);
+
+        unsafe { ptr::read_unaligned(contents[offset..offset + size_of::<Inode>()].as_ptr() as *const Inode) }
+    }
+
+    pub(crate) fn write_inode(&mut self, drive: &mut impl BlockDevice, inode_id: usize, inode: &Inode) {
+        let (block_number, offset) = self.inode_location(inode_id);
+        let mut contents = self.read_block(drive, block_number);
+
+        let inode_bytes = unsafe { slice::from_raw_parts(inode as *const Inode as *const u8, size_of::<Inode>()) };
+        contents[offset..offset + size_of::<Inode>()].copy_from_slice(inode_bytes);
+        self.mark_block_dirty(block_number, contents);
+        self.inode_cache.lock().invalidate(inode_id);
+    }
+
+    /// Number of the inode table block holding the inode and byte offset of the inode in that block
+    fn inode_location(&self, inode_id: usize) -> (usize, usize) {
+        let group_id = Inode::get_containing_block_group_id(&self.superblock, inode_id);
+        let table_offset = Inode::get_local_table_index(&self.superblock, inode_id) * self.superblock.inode_size() as usize;
+        let table_block = self.block_groups[group_id].inode_table_block_address.read() as usize;
+
+        (table_block + table_offset / self.superblock.block_size(), table_offset % self.superblock.block_size())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::collections::BTreeMap;
+    use alloc::sync::Arc;
+    use alloc::task::Wake;
+    use alloc::vec;
+    use alloc::vec::Vec;
+    use core::ffi::c_void;
+    use core::future::{Future, poll_fn};
+    use core::mem::size_of;
+    use core::pin::pin;
+    use core::{ptr, slice};
+    use core::sync::atomic::{AtomicUsize, Ordering};
+    use core::task::{Context, Poll, Waker};
+    use crate::drivers::{AsyncBlockDevice, BlockDevice};
+    use crate::fs::ext2::inode::{Inode, InodeMode};
+    use crate::fs::ext2::test_image::TestImage;
+
+    const BLOCK_SIZE: usize = 1024;
+
+    /// Block device holding a few 1KiB blocks in memory, every other block reads as zeros until it is
+    /// written
+    struct MemoryDevice {
+        blocks: BTreeMap<usize, Vec<u8>>,
+    }
+
+    impl MemoryDevice {
+        fn write_block_pointers(&mut self, block_number: usize, pointers: &[u32]) {
+            let mut block = vec![0u8; BLOCK_SIZE];
+            for (index, pointer) in pointers.iter().enumerate() {
+                block[index * 4..index * 4 + 4].copy_from_slice(&pointer.to_le_bytes());
+            }
+
+            self.blocks.insert(block_number, block);
+        }
+    }
+
+    impl BlockDevice for MemoryDevice {
+        fn read_from_device(&mut self, byte_offset: u64, byte_count: u64, buffer: *mut c_void) -> usize {
+            let buffer = unsafe { slice::from_raw_parts_mut(buffer as *mut u8, byte_count as usize) };
+            for (index, byte) in buffer.iter_mut().enumerate() {
+                let offset = byte_offset as usize + index;
+                *byte = self.blocks.get(&(offset / BLOCK_SIZE)).map_or(0, |block| block[offset % BLOCK_SIZE]);
+            }
+
+            byte_count as usize
+        }
+
+        fn write_to_device(&mut self, byte_offset: u64, byte_count: u64, buffer: *mut c_void) {
+            let buffer = unsafe { slice::from_raw_parts(buffer as *const u8, byte_count as usize) };
+            for (index, byte) in buffer.iter().enumerate() {
+                let offset = byte_offset as usize + index;
+                self.blocks.entry(offset / BLOCK_SIZE).or_insert_with(|| vec![0u8; BLOCK_SIZE])[offset % BLOCK_SIZE] = *byte;
+            }
+        }
     }
-}
\ No newline at end of file
+
+    impl AsyncBlockDevice for MemoryDevice {
+        /// Every transfer stays pending for one poll, as if the drive was still processing it
+        fn read_async(&mut self, byte_offset: u64, byte_count: u64) -> impl Future<Output = Result<Vec<u8>, &'static str>> {
+            let mut data = vec![0u8; byte_count as usize];
+            self.read_from_device(byte_offset, byte_count, data.as_mut_ptr() as *mut c_void);
+
+            let mut data = Some(data);
+            let mut is_transferring = true;
+            poll_fn(move |context| {
+                if is_transferring {
+                    is_transferring = false;
+                    context.waker().wake_by_ref();
+                    return Poll::Pending;
+                }
+
+                Poll::Ready(Ok(data.take().unwrap()))
+            })
+        }
+    }
+
+    /// Counts the wakeups of the future it is handed to
+    struct WakeCounter(AtomicUsize);
+
+    impl Wake for WakeCounter {
+        fn wake(self: Arc<Self>) {
+            self.0.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    fn inode_with_size(mode: InodeMode, size: u32, size_high: u32) -> Inode {
+        inode_with_blocks(mode, size, size_high, [0; 15])
+    }
+
+    fn inode_with_blocks(mode: InodeMode, size: u32, size_high: u32, block_pointers: [u32; 15]) -> Inode {
+        let mut raw_inode = [0u8; size_of::<Inode>()];
+        raw_inode[0..2].copy_from_slice(&mode.bits().to_le_bytes()); // mode
+        raw_inode[4..8].copy_from_slice(&size.to_le_bytes()); // size
+        for (index, pointer) in block_pointers.iter().enumerate() {
+            raw_inode[40 + index * 4..44 + index * 4].copy_from_slice(&pointer.to_le_bytes()); // block
+        }
+        raw_inode[108..112].copy_from_slice(&size_high.to_le_bytes()); // dir_acl
+
+        unsafe { ptr::read_unaligned(raw_inode.as_ptr() as *const Inode) }
+    }
+
+    #[test_case]
+    fn large_file_size_combines_both_halves() {
+        // GIVEN
+        let superblock = TestImage::new(8, 16).with_large_files(true).superblock();
+        let inode = inode_with_size(InodeMode::REGULAR_FILE | InodeMode::USER_READ, 0x1000, 0x2);
+
+        // WHEN
+        let size = inode.size(&superblock);
+
+        // THEN
+        assert_eq!(size, 0x2_0000_1000);
+    }
+
+    #[test_case]
+    fn size_ignores_high_word_without_large_file_feature() {
+        // GIVEN
+        let superblock = TestImage::new(8, 16).with_large_files(false).superblock();
+        let inode = inode_with_size(InodeMode::REGULAR_FILE, 0x1000, 0x2);
+
+        // WHEN
+        let size = inode.size(&superblock);
+
+        // THEN
+        assert_eq!(size, 0x1000);
+    }
+
+    #[test_case]
+    fn directory_size_ignores_dir_acl() {
+        // GIVEN
+        let superblock = TestImage::new(8, 16).with_large_files(true).superblock();
+        let inode = inode_with_size(InodeMode::DIRECTORY, 0x400, 0x7);
+
+        // WHEN
+        let size = inode.size(&superblock);
+
+        // THEN
+        assert_eq!(size, 0x400);
+    }
+
+    #[test_case]
+    fn reads_file_spanning_doubly_indirect_block() {
+        // GIVEN
+        // Every data block of the file is one of four blocks on the device, filled with its own number
+        let data_block = |file_block: usize| (100 + file_block % 4) as u32;
+        let mut device = MemoryDevice { blocks: BTreeMap::new() };
+        for block_number in 100..104 {
+            device.blocks.insert(block_number, vec![block_number as u8; BLOCK_SIZE]);
+        }
+
+        let file_block_count = 12 + 256 + 3;
+        let mut block_pointers = [0u32; 15];
+        for (file_block, pointer) in block_pointers.iter_mut().enumerate().take(12) {
+            *pointer = data_block(file_block);
+        }
+        block_pointers[12] = 10;
+        block_pointers[13] = 11;
+        device.write_block_pointers(10, &(12..268).map(data_block).collect::<Vec<u32>>());
+        device.write_block_pointers(11, &[20]);
+        device.write_block_pointers(20, &(268..file_block_count).map(data_block).collect::<Vec<u32>>());
+
+        let size = (file_block_count - 1) * BLOCK_SIZE + 10;
+        let superblock = TestImage::new(8, 16).with_large_files(false).superblock();
+        let inode = inode_with_blocks(InodeMode::REGULAR_FILE, size as u32, 0, block_pointers);
+
+        // WHEN
+        let content = inode.get_content(&mut device, &superblock);
+
+        // THEN
+        assert_eq!(content.len(), size);
+        for (file_block, block_content) in content.chunks(BLOCK_SIZE).enumerate() {
+            assert!(block_content.iter().all(|&byte| byte == data_block(file_block) as u8), "wrong content in block {}", file_block);
+        }
+    }
+
+    #[test_case]
+    fn async_read_yields_then_returns_file_content() {
+        // GIVEN
+        let mut device = MemoryDevice { blocks: BTreeMap::new() };
+        device.blocks.insert(50, vec![0xAA; BLOCK_SIZE]);
+        device.blocks.insert(51, vec![0xBB; BLOCK_SIZE]);
+
+        let mut block_pointers = [0u32; 15];
+        block_pointers[0] = 50;
+        block_pointers[1] = 51;
+        let superblock = TestImage::new(8, 16).with_large_files(false).superblock();
+        let size = 2 * BLOCK_SIZE - 10;
+        let inode = inode_with_blocks(InodeMode::REGULAR_FILE, size as u32, 0, block_pointers);
+
+        let wakes = Arc::new(WakeCounter(AtomicUsize::new(0)));
+        let waker = Waker::from(wakes.clone());
+        let mut context = Context::from_waker(&waker);
+        let mut read = pin!(inode.get_content_async(&mut device, &superblock));
+
+        // WHEN
+        let mut pending_polls = 0;
+        let content = loop {
+            match read.as_mut().poll(&mut context) {
+                Poll::Ready(content) => break content.unwrap(),
+                Poll::Pending => pending_polls += 1,
+            }
+        };
+
+        // THEN
+        assert_eq!(pending_polls, 2);
+        assert_eq!(wakes.0.load(Ordering::Relaxed), 2);
+        assert_eq!(content.len(), size);
+        assert!(content[..BLOCK_SIZE].iter().all(|&byte| byte == 0xAA));
+        assert!(content[BLOCK_SIZE..].iter().all(|&byte| byte == 0xBB));
+    }
+
+    #[test_case]
+    fn holes_read_as_zeros() {
+        // GIVEN
+        let mut device = MemoryDevice { blocks: BTreeMap::new() };
+        device.blocks.insert(0, vec![0xEE; BLOCK_SIZE]);
+        device.blocks.insert(50, vec![0xAA; BLOCK_SIZE]);
+        device.blocks.insert(51, vec![0xBB; BLOCK_SIZE]);
+
+        let mut block_pointers = [0u32; 15];
+        block_pointers[0] = 50;
+        block_pointers[2] = 51;
+        let superblock = TestImage::new(8, 16).with_large_files(false).superblock();
+        let inode = inode_with_blocks(InodeMode::REGULAR_FILE, 3 * BLOCK_SIZE as u32, 0, block_pointers);
+
+        // WHEN
+        let content = inode.get_content(&mut device, &superblock);
+
+        // THEN
+        assert_eq!(content.len(), 3 * BLOCK_SIZE);
+        assert!(content[..BLOCK_SIZE].iter().all(|&byte| byte == 0xAA));
+        assert!(content[BLOCK_SIZE..2 * BLOCK_SIZE].iter().all(|&byte| byte == 0));
+        assert!(content[2 * BLOCK_SIZE..].iter().all(|&byte| byte == 0xBB));
+    }
+
+    #[test_case]
+    fn missing_indirect_block_is_a_hole() {
+        // GIVEN
+        let mut device = MemoryDevice { blocks: BTreeMap::new() };
+        device.blocks.insert(0, vec![0xEE; BLOCK_SIZE]);
+        device.blocks.insert(50, vec![0xAA; BLOCK_SIZE]);
+
+        let mut block_pointers = [50u32; 15];
+        block_pointers[12] = 0;
+        let superblock = TestImage::new(8, 16).with_large_files(false).superblock();
+        let inode = inode_with_blocks(InodeMode::REGULAR_FILE, 14 * BLOCK_SIZE as u32, 0, block_pointers);
+
+        // WHEN
+        let content = inode.get_content(&mut device, &superblock);
+
+        // THEN
+        assert!(content[..12 * BLOCK_SIZE].iter().all(|&byte| byte == 0xAA));
+        assert!(content[12 * BLOCK_SIZE..].iter().all(|&byte| byte == 0));
+    }
+}
@@ -0,0 +1,103 @@
+use alloc::collections::VecDeque;
+use crate::fs::ext2::inode::Inode;
+
+/// Number of inodes kept in memory by default, enough for the directories of a few deep paths
+const DEFAULT_CAPACITY: usize = 32;
+
+/// Bounded cache of parsed inodes keyed by inode number. Once full, the least recently used inode
+/// is evicted.
+pub(crate) struct InodeCache {
+    /// Cached inodes, from the least to the most recently used
+    entries: VecDeque<(usize, Inode)>,
+    capacity: usize,
+}
+
+impl InodeCache {
+    pub(crate) fn new(capacity: usize) -> Self {
+        Self { entries: VecDeque::with_capacity(capacity), capacity }
+    }
+
+    /// Returns a copy of the cached inode and marks it as the most recently used
+    pub(crate) fn get(&mut self, inode_id: usize) -> Option<Inode> {
+        let position = self.entries.iter().position(|(id, _)| *id == inode_id)?;
+        let entry = self.entries.remove(position)?;
+        let inode = entry.1.clone();
+        self.entries.push_back(entry);
+
+        Some(inode)
+    }
+
+    pub(crate) fn insert(&mut self, inode_id: usize, inode: Inode) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        self.invalidate(inode_id);
+        if self.entries.len() == self.capacity {
+            self.entries.pop_front();
+        }
+
+        self.entries.push_back((inode_id, inode));
+    }
+
+    /// Drops the cached copy of an inode, so that it is read again on its next use
+    pub(crate) fn invalidate(&mut self, inode_id: usize) {
+        self.entries.retain(|(id, _)| *id != inode_id);
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.entries.len()
+    }
+}
+
+impl Default for InodeCache {
+    fn default() -> Self {
+        Self::new(DEFAULT_CAPACITY)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::mem::MaybeUninit;
+    use crate::fs::ext2::inode::Inode;
+    use crate::fs::ext2::inode_cache::InodeCache;
+
+    fn inode_with_size(size: u32) -> Inode {
+        let inode = unsafe { MaybeUninit::<Inode>::zeroed().assume_init() };
+        unsafe { inode.size.write(size) };
+
+        inode
+    }
+
+    #[test_case]
+    fn least_recently_used_inode_is_evicted() {
+        // GIVEN
+        let mut cache = InodeCache::new(2);
+        cache.insert(2, inode_with_size(1));
+        cache.insert(12, inode_with_size(2));
+        cache.get(2);
+
+        // WHEN
+        cache.insert(13, inode_with_size(3));
+
+        // THEN
+        assert_eq!(cache.len(), 2);
+        assert_eq!(cache.get(2).map(|inode| inode.size.read()), Some(1));
+        assert!(cache.get(12).is_none());
+        assert_eq!(cache.get(13).map(|inode| inode.size.read()), Some(3));
+    }
+
+    #[test_case]
+    fn invalidated_inode_is_no_longer_cached() {
+        // GIVEN
+        let mut cache = InodeCache::new(4);
+        cache.insert(2, inode_with_size(1));
+
+        // WHEN
+        cache.invalidate(2);
+
+        // THEN
+        assert!(cache.get(2).is_none());
+        assert_eq!(cache.len(), 0);
+    }
+}
@@ -3,47 +3,99 @@
 mod block;
 mod inode;
 mod directory;
+mod allocation;
+mod truncate;
+mod inode_cache;
+#[cfg(test)]
+mod test_image;
 
+use alloc::collections::BTreeMap;
 use alloc::vec::Vec;
-use core::ops::ControlFlow;
+use core::ffi::c_void;
+use core::mem::size_of;
+use spin::Mutex;
+use crate::drivers::{AsyncBlockDevice, BlockDevice};
 use crate::drivers::pci::ahci::AHCIDevice;
-use crate::fs::ext2::block::{Superblock};
+use crate::drivers::rtc;
+use crate::fs::ext2::block::{BlockGroupDescriptor, FileSystemState, Superblock, SUPERBLOCK_OFFSET};
 use crate::fs::ext2::inode::{Inode};
+use crate::fs::ext2::inode_cache::InodeCache;
 
 const ROOT_INODE_ID: usize = 2;
 
+/// File system mounted at boot along with the drive holding it, unmounted before powering off
+static MOUNTED_FILE_SYSTEM: Mutex<Option<(AHCIDevice, Ext2FileSystem)>> = Mutex::new(None);
+
 pub struct Ext2FileSystem {
     pub superblock: Superblock,
     pub root_inode: Inode,
+    /// In memory copy of the block group descriptor table, written back by `flush_counts`
+    block_groups: Vec<BlockGroupDescriptor>,
+    /// Metadata blocks, such as inode tables and bitmaps, modified in memory but not yet written
+    /// back to the drive, keyed by block number
+    dirty_blocks: BTreeMap<usize, Vec<u8>>,
+    /// Inodes read while resolving paths, kept so that walking the same directories again does
+    /// not read them from the drive
+    inode_cache: Mutex<InodeCache>,
 }
 impl Ext2FileSystem {
+    /// Stores the new contents of a block, to be written back when the file system is unmounted
+    pub(crate) fn mark_block_dirty(&mut self, block_number: usize, contents: Vec<u8>) {
+        assert_eq!(contents.len(), self.superblock.block_size(), "ext2: dirty block contents must span a whole block");
+
+        self.dirty_blocks.insert(block_number, contents);
+    }
+
+    /// Writes every dirty block back to the drive, then marks the file system as cleanly unmounted
+    /// and writes back the superblock. The superblock is written last so that an interrupted unmount
+    /// leaves the file system marked as not clean.
+    pub fn unmount(&mut self, drive: &mut impl BlockDevice) {
+        info!("ext2: unmounting file system...");
+
+        for (block_number, contents) in self.dirty_blocks.iter_mut() {
+            drive.write_to_device(self.superblock.block_address(*block_number) as u64, contents.len() as u64, contents.as_mut_ptr() as *mut c_void);
+        }
+        self.dirty_blocks.clear();
+
+        unsafe {
+            self.superblock.file_system_state.write(FileSystemState::Clean);
+            self.superblock.last_write_time.write(rtc::unix_time());
+        }
+
+        let superblock_address = &mut self.superblock as *mut Superblock as *mut c_void;
+        drive.write_to_device(SUPERBLOCK_OFFSET as u64, size_of::<Superblock>() as u64, superblock_address);
+    }
+
     /// Checks whether a certain file is present on the current file system and returns its inode if it is.
     /// The provided path needs to be absolute relative to the current file system.
-    pub fn find_file(&self, drive: &mut AHCIDevice, path: &str) -> Option<Inode> {
+    pub fn find_file(&self, drive: &mut impl BlockDevice, path: &str) -> Option<Inode> {
         if path.as_bytes()[0] != b'/' {
             panic!("ext2: expected an absolute path");
         }
 
-        let mut path_iter = path[1..].split('/');
+        let inode_id = resolve_path(path, |directory_id, name| {
+            let directory = self.get_inode(drive, directory_id);
+            if !directory.is_directory() {
+                return None;
+            }
 
-        // This manual first iteration necessary to avoid ownership issues and since Inodes cannot be cloned
-        // There might be a better way though, but I haven't found it
-        let first_name = path_iter.next().unwrap();
-        let current_inode = self.root_inode.find_child_inode(drive, &self.superblock, first_name).unwrap();
+            directory.find_child_inode_id(drive, &self.superblock, name)
+        })?;
 
-        let inode = path_iter.try_fold(current_inode, |current_inode, current_name| {
-            if let Some(found_inode) = current_inode.find_child_inode(drive, &self.superblock, current_name) {
-                ControlFlow::Continue(found_inode)
-            }
-            else {
-                ControlFlow::Break(())
-            }
-        });
+        Some(self.get_inode(drive, inode_id))
+    }
 
-        match inode {
-            ControlFlow::Continue(inode) => Some(inode),
-            ControlFlow::Break(()) => None,
+    /// Returns the inode with the given id, reading it only when it is not cached. Misses go through
+    /// the dirty blocks, so that an inode written since the last write-back is not read stale.
+    pub(crate) fn get_inode(&self, drive: &mut impl BlockDevice, inode_id: usize) -> Inode {
+        if let Some(inode) = self.inode_cache.lock().get(inode_id) {
+            return inode;
         }
+
+        let inode = self.read_inode(drive, inode_id);
+        self.inode_cache.lock().insert(inode_id, inode.clone());
+
+        inode
     }
 
     /// Checks whether a certain file is present on the current file system.
@@ -58,16 +110,298 @@ impl Ext2FileSystem {
 
         inode.map(|inode| inode.get_content(drive, &self.superblock))
     }
+
+    /// Retrieves the given inode and awaits the reads of its contents, so that other tasks keep
+    /// running while a large file is read
+    pub async fn get_file_contents_async(&self, drive: &mut impl AsyncBlockDevice, path: &str) -> Result<Vec<u8>, &'static str> {
+        let inode = self.find_file(drive, path).ok_or("ext2: file not found")?;
+
+        inode.get_content_async(drive, &self.superblock).await
+    }
 }
 
-pub fn mount_filesystem(drive: &mut AHCIDevice) -> Ext2FileSystem {
-    info!("ext2: mounting file system...");
+/// Walks an absolute path one component at a time from the root inode and returns the id of the
+/// inode it leads to. `lookup` returns the id of the entry with the given name in a directory.
+fn resolve_path(path: &str, mut lookup: impl FnMut(usize, &str) -> Option<usize>) -> Option<usize> {
+    path.split('/').try_fold(ROOT_INODE_ID, |inode_id, component| match component {
+        "" | "." => Some(inode_id),
+        // The root directory is its own parent
+        ".." if inode_id == ROOT_INODE_ID => Some(ROOT_INODE_ID),
+        // Every other directory stores its parent's inode in its ".." entry
+        ".." => lookup(inode_id, ".."),
+        name => lookup(inode_id, name),
+    })
+}
+
+pub fn mount_filesystem(drive: &mut AHCIDevice) -> Result<Ext2FileSystem, &'static str> {
+    info!("ext2: mounting file system on {}...", drive.id());
+
+    mount_from_device(drive)
+}
 
-    let superblock = Superblock::read_from_disk(drive);
+/// Reads the superblock, root inode and block group descriptors of the file system on the device
+fn mount_from_device(drive: &mut impl BlockDevice) -> Result<Ext2FileSystem, &'static str> {
+    let superblock = Superblock::read_from_disk(drive)?;
+    superblock.verify_checksum()?;
     let root_inode = Inode::get_from_id(drive, &superblock, ROOT_INODE_ID);
+    // Every path is resolved from the root, a corrupted root inode would only fail later on
+    if !root_inode.is_directory() {
+        return Err("ext2: root inode is not a directory");
+    }
+
+    let block_groups = (0..superblock.block_group_count())
+        .map(|index| BlockGroupDescriptor::read_table_entry(drive, &superblock, index))
+        .collect();
 
-    Ext2FileSystem {
+    Ok(Ext2FileSystem {
         superblock,
-        root_inode
+        root_inode,
+        block_groups,
+        dirty_blocks: BTreeMap::new(),
+        inode_cache: Default::default(),
+    })
+}
+
+/// Mounts the file system of the first drive holding a valid ext2 superblock, and returns it along
+/// with the index of that drive
+/// Keeps the file system and its drive until the machine is powered off or rebooted
+pub fn keep_mounted(drive: AHCIDevice, file_system: Ext2FileSystem) {
+    *MOUNTED_FILE_SYSTEM.lock() = Some((drive, file_system));
+}
+
+/// Writes back and unmounts the file system kept mounted, if any
+pub fn unmount_mounted_filesystem() {
+    if let Some((mut drive, mut file_system)) = MOUNTED_FILE_SYSTEM.lock().take() {
+        file_system.unmount(&mut drive);
+    }
+}
+
+pub fn mount_first_filesystem(drives: &mut [AHCIDevice]) -> Option<(usize, Ext2FileSystem)> {
+    drives.iter_mut().enumerate().find_map(|(index, drive)| match mount_filesystem(drive) {
+        Ok(fs) => Some((index, fs)),
+        Err(err) => {
+            warn!("{} on {}", err, drive.id());
+            None
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::collections::BTreeMap;
+    use alloc::vec;
+    use alloc::vec::Vec;
+    use core::ffi::c_void;
+    use core::mem::{MaybeUninit, size_of};
+    use core::{ptr, slice};
+    use crate::drivers::BlockDevice;
+    use crate::fs::ext2::{Ext2FileSystem, mount_from_device, resolve_path, ROOT_INODE_ID};
+    use crate::fs::ext2::block::{FileSystemState, Superblock, SUPERBLOCK_OFFSET};
+    use crate::fs::ext2::directory::FileType;
+    use crate::fs::ext2::inode::{Inode, InodeMode};
+    use crate::fs::ext2::test_image::TestImage;
+
+    const FILES_INODE_ID: usize = 12;
+    const FILE_INODE_ID: usize = 13;
+
+    /// Directory lookup over the tree /files/file.txt
+    fn lookup(directory_id: usize, name: &str) -> Option<usize> {
+        match (directory_id, name) {
+            (ROOT_INODE_ID, "files") => Some(FILES_INODE_ID),
+            (FILES_INODE_ID, "..") => Some(ROOT_INODE_ID),
+            (FILES_INODE_ID, "file.txt") => Some(FILE_INODE_ID),
+            _ => None,
+        }
+    }
+
+    #[test_case]
+    fn resolve_path_through_parent_directory() {
+        // WHEN
+        let direct = resolve_path("/files/file.txt", lookup);
+        let through_parent = resolve_path("/files/../files/file.txt", lookup);
+
+        // THEN
+        assert_eq!(direct, Some(FILE_INODE_ID));
+        assert_eq!(through_parent, direct);
+    }
+
+    #[test_case]
+    fn resolve_path_parent_of_root_is_root() {
+        // WHEN
+        let inode_id = resolve_path("/../files/./file.txt", lookup);
+
+        // THEN
+        assert_eq!(inode_id, Some(FILE_INODE_ID));
+    }
+
+    #[test_case]
+    fn resolve_path_missing_component() {
+        // WHEN
+        let inode_id = resolve_path("/files/../file.txt", lookup);
+
+        // THEN
+        assert_eq!(inode_id, None);
+    }
+
+    /// Block device recording the writes it receives, reading back what was written and zeros elsewhere
+    struct RecordingDevice {
+        writes: Vec<(u64, Vec<u8>)>,
+    }
+
+    impl BlockDevice for RecordingDevice {
+        fn read_from_device(&mut self, byte_offset: u64, byte_count: u64, buffer: *mut c_void) -> usize {
+            let buffer = unsafe { slice::from_raw_parts_mut(buffer as *mut u8, byte_count as usize) };
+            buffer.fill(0);
+            for (write_offset, bytes) in &self.writes {
+                for (index, byte) in bytes.iter().enumerate() {
+                    if let Some(target) = (write_offset + index as u64).checked_sub(byte_offset).and_then(|offset| buffer.get_mut(offset as usize)) {
+                        *target = *byte;
+                    }
+                }
+            }
+
+            byte_count as usize
+        }
+
+        fn write_to_device(&mut self, byte_offset: u64, byte_count: u64, buffer: *mut c_void) {
+            let bytes = unsafe { slice::from_raw_parts(buffer as *const u8, byte_count as usize) };
+            self.writes.push((byte_offset, bytes.to_vec()));
+        }
+    }
+
+    /// Image holding nothing but a root inode with the given mode
+    fn image_with_root_mode(mode: InodeMode) -> TestImage {
+        let mut image = TestImage::new(8, 16);
+        image.write_inode(ROOT_INODE_ID, mode, 0, &[], 0);
+
+        image
+    }
+
+    /// Image holding the tree /files/file.txt, the directories' entries are on blocks 8 and 9
+    fn image_with_file_tree() -> TestImage {
+        let mut image = TestImage::new(10, 16);
+        image.write_inode(ROOT_INODE_ID, InodeMode::DIRECTORY, 1024, &[8], 1);
+        image.write_inode(FILES_INODE_ID, InodeMode::DIRECTORY, 1024, &[9], 1);
+        image.write_inode(FILE_INODE_ID, InodeMode::REGULAR_FILE, 0, &[], 0);
+        image.write_directory_entry(8, FILES_INODE_ID, "files", FileType::Directory);
+        image.write_directory_entry(9, FILE_INODE_ID, "file.txt", FileType::RegularFile);
+
+        image
+    }
+
+    #[test_case]
+    fn mount_fails_when_root_inode_is_a_regular_file() {
+        // GIVEN
+        let mut device = image_with_root_mode(InodeMode::REGULAR_FILE | InodeMode::USER_READ);
+
+        // WHEN
+        let result = mount_from_device(&mut device);
+
+        // THEN
+        assert_eq!(result.err(), Some("ext2: root inode is not a directory"));
+    }
+
+    #[test_case]
+    fn mount_succeeds_when_root_inode_is_a_directory() {
+        // GIVEN
+        let mut device = image_with_root_mode(InodeMode::DIRECTORY | InodeMode::USER_READ);
+
+        // WHEN
+        let file_system = mount_from_device(&mut device).unwrap();
+
+        // THEN
+        assert!(file_system.root_inode.is_directory());
+        assert_eq!(file_system.block_groups.len(), 1);
+    }
+
+    #[test_case]
+    fn second_lookup_of_a_path_reads_cached_inodes() {
+        // GIVEN
+        let mut device = image_with_file_tree();
+        let file_system = mount_from_device(&mut device).unwrap();
+
+        // WHEN
+        let reads_before = device.reads;
+        let first_lookup = file_system.find_file(&mut device, "/files/file.txt");
+        let first_lookup_reads = device.reads - reads_before;
+        let second_lookup = file_system.find_file(&mut device, "/files/file.txt");
+        let second_lookup_reads = device.reads - reads_before - first_lookup_reads;
+
+        // THEN
+        assert!(first_lookup.is_some_and(|inode| inode.is_regular_file()));
+        assert!(second_lookup.is_some_and(|inode| inode.is_regular_file()));
+        assert!(second_lookup_reads < first_lookup_reads);
+    }
+
+    #[test_case]
+    fn written_inode_is_read_again() {
+        // GIVEN
+        let mut device = image_with_file_tree();
+        let mut file_system = mount_from_device(&mut device).unwrap();
+        let cached_inode = file_system.find_file(&mut device, "/files/file.txt").unwrap();
+
+        // WHEN
+        let inode = file_system.read_inode(&mut device, FILE_INODE_ID);
+        unsafe { inode.size.write(4321) };
+        file_system.write_inode(&mut device, FILE_INODE_ID, &inode);
+        let reread_inode = file_system.find_file(&mut device, "/files/file.txt").unwrap();
+
+        // THEN
+        assert_eq!(cached_inode.size.read(), 0);
+        assert_eq!(reread_inode.size.read(), 4321);
+    }
+
+    /// Builds a mounted file system with 1KiB blocks whose superblock is marked as in error
+    fn mounted_file_system() -> Ext2FileSystem {
+        let mut raw_superblock = [0u8; size_of::<Superblock>()];
+        raw_superblock[20..24].copy_from_slice(&1u32.to_le_bytes()); // superblock_block_number
+        raw_superblock[58..60].copy_from_slice(&2u16.to_le_bytes()); // file_system_state
+        raw_superblock[60..62].copy_from_slice(&1u16.to_le_bytes()); // error_detection_mechanism
+
+        Ext2FileSystem {
+            superblock: unsafe { ptr::read_unaligned(raw_superblock.as_ptr() as *const Superblock) },
+            root_inode: unsafe { MaybeUninit::<Inode>::zeroed().assume_init() },
+            block_groups: Vec::new(),
+            dirty_blocks: BTreeMap::new(),
+            inode_cache: Default::default(),
+        }
+    }
+
+    #[test_case]
+    fn unmount_writes_back_dirty_blocks_then_superblock() {
+        // GIVEN
+        let mut file_system = mounted_file_system();
+        file_system.mark_block_dirty(5, vec![0xAA; 1024]);
+        file_system.mark_block_dirty(9, vec![0xBB; 1024]);
+        let mut device = RecordingDevice { writes: Vec::new() };
+
+        // WHEN
+        file_system.unmount(&mut device);
+
+        // THEN
+        assert_eq!(device.writes.len(), 3);
+        assert_eq!(device.writes[0], (5 * 1024, vec![0xAA; 1024]));
+        assert_eq!(device.writes[1], (9 * 1024, vec![0xBB; 1024]));
+
+        let (superblock_offset, superblock_bytes) = &device.writes[2];
+        assert_eq!(*superblock_offset, SUPERBLOCK_OFFSET as u64);
+        assert_eq!(superblock_bytes.len(), size_of::<Superblock>());
+        assert_eq!(superblock_bytes[58..60], (FileSystemState::Clean as u16).to_le_bytes());
+        assert!(file_system.dirty_blocks.is_empty());
+    }
+
+    #[test_case]
+    fn unmount_without_dirty_blocks_only_writes_superblock() {
+        // GIVEN
+        let mut file_system = mounted_file_system();
+        let mut device = RecordingDevice { writes: Vec::new() };
+
+        // WHEN
+        file_system.unmount(&mut device);
+
+        // THEN
+        assert_eq!(device.writes.len(), 1);
+        assert_eq!(device.writes[0].0, SUPERBLOCK_OFFSET as u64);
+        assert_eq!(file_system.superblock.file_system_state.read(), FileSystemState::Clean);
     }
 }
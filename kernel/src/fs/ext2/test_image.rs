@@ -0,0 +1,187 @@
+use alloc::collections::BTreeMap;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::ffi::c_void;
+use core::mem::{MaybeUninit, size_of};
+use core::{ptr, slice};
+use crate::drivers::BlockDevice;
+use crate::fs::ext2::Ext2FileSystem;
+use crate::fs::ext2::block::{BlockGroupDescriptor, ReadOnlyCompatibleFeatures, Superblock, SUPERBLOCK_CHECKSUM_OFFSET, SUPERBLOCK_OFFSET};
+use crate::fs::ext2::directory::{encode_directory_entry, FileType};
+use crate::fs::ext2::inode::{Inode, InodeMode};
+
+pub(super) const BLOCK_SIZE: usize = 1024;
+pub(super) const BLOCK_BITMAP_BLOCK: usize = 3;
+pub(super) const INODE_BITMAP_BLOCK: usize = 4;
+pub(super) const INODE_TABLE_BLOCK: usize = 5;
+const INODE_SIZE: usize = 128;
+/// Inodes 1 to 10 are reserved by revision 0 file systems
+const RESERVED_INODE_COUNT: usize = 10;
+
+/// Single block group ext2 image with 1KiB blocks, counting the reads it receives. Blocks 1 to 4 hold
+/// the superblock, the block group descriptor table and the block and inode bitmaps, followed by the
+/// inode table on block 5.
+pub(super) struct TestImage {
+    pub(super) bytes: Vec<u8>,
+    pub(super) reads: usize,
+}
+
+impl TestImage {
+    /// Image whose metadata blocks and reserved inodes are the only ones used
+    pub(super) fn new(block_count: usize, inode_count: usize) -> Self {
+        let mut image = Self { bytes: vec![0u8; block_count * BLOCK_SIZE], reads: 0 };
+
+        let superblock = SUPERBLOCK_OFFSET as usize;
+        image.write_u32(superblock, inode_count as u32); // inode_count
+        image.write_u32(superblock + 4, block_count as u32); // block_count
+        image.write_u32(superblock + 12, (block_count - 1) as u32); // unallocated_blocks
+        image.write_u32(superblock + 16, inode_count as u32); // unallocated_inodes
+        image.write_u32(superblock + 20, 1); // superblock_block_number
+        image.write_u32(superblock + 32, block_count as u32); // block_group_block_count
+        image.write_u32(superblock + 40, inode_count as u32); // block_group_inode_count
+        image.write_u16(superblock + 56, 0xEF53); // ext2_signature
+        image.write_u16(superblock + 58, 1); // file_system_state
+        image.write_u16(superblock + 60, 1); // error_detection_mechanism
+
+        let descriptor = 2 * BLOCK_SIZE;
+        image.write_u32(descriptor, BLOCK_BITMAP_BLOCK as u32); // block_bitmap
+        image.write_u32(descriptor + 4, INODE_BITMAP_BLOCK as u32); // inode_usage_bitmap_address
+        image.write_u32(descriptor + 8, INODE_TABLE_BLOCK as u32); // inode_table_block_address
+        image.write_u16(descriptor + 12, (block_count - 1) as u16); // unallocated_block_count
+        image.write_u16(descriptor + 14, inode_count as u16); // unallocated_inode_count
+
+        let inode_table_block_count = (inode_count * INODE_SIZE).div_ceil(BLOCK_SIZE);
+        for block in 1..INODE_TABLE_BLOCK + inode_table_block_count {
+            image.mark_block_used(block);
+        }
+        for inode_id in 1..=RESERVED_INODE_COUNT.min(inode_count) {
+            image.mark_inode_used(inode_id);
+        }
+
+        image
+    }
+
+    /// Declares blocks of `1024 << log_block_size` bytes, the superblock then starts block 0 unless
+    /// blocks are 1KiB. Only the superblock reflects it, the rest of the image keeps 1KiB blocks.
+    pub(super) fn with_log_block_size(mut self, log_block_size: u32) -> Self {
+        let superblock = SUPERBLOCK_OFFSET as usize;
+        self.write_u32(superblock + 20, if log_block_size == 0 { 1 } else { 0 }); // superblock_block_number
+        self.write_u32(superblock + 24, log_block_size); // log_block_size
+
+        self
+    }
+
+    /// Makes the file system a revision 1 one, with or without the large file feature
+    pub(super) fn with_large_files(mut self, large_files: bool) -> Self {
+        let superblock = SUPERBLOCK_OFFSET as usize;
+        self.write_u32(superblock + 76, 1); // version_major
+        if large_files {
+            let features = self.read_u32(superblock + 100) | ReadOnlyCompatibleFeatures::LARGE_FILE.bits();
+            self.write_u32(superblock + 100, features); // read_only_compatible_features
+        }
+
+        self
+    }
+
+    /// Enables the metadata checksum feature and stores the checksum computed by `checksum` over the
+    /// rest of the superblock. It covers every field set so far, other options must come before it.
+    pub(super) fn with_metadata_checksum(mut self, checksum: impl FnOnce(&[u8]) -> u32) -> Self {
+        let superblock = SUPERBLOCK_OFFSET as usize;
+        let features = self.read_u32(superblock + 100) | ReadOnlyCompatibleFeatures::METADATA_CSUM.bits();
+        self.write_u32(superblock + 100, features); // read_only_compatible_features
+        let checksum = checksum(&self.bytes[superblock..superblock + SUPERBLOCK_CHECKSUM_OFFSET]);
+        self.write_u32(superblock + SUPERBLOCK_CHECKSUM_OFFSET, checksum); // checksum
+
+        self
+    }
+
+    /// Returns a copy of the superblock of the image
+    pub(super) fn superblock(&self) -> Superblock {
+        let superblock_bytes = &self.bytes[SUPERBLOCK_OFFSET as usize..SUPERBLOCK_OFFSET as usize + size_of::<Superblock>()];
+
+        unsafe { ptr::read_unaligned(superblock_bytes.as_ptr() as *const Superblock) }
+    }
+
+    /// Sets the bit of the block in the block bitmap and updates the free block counts
+    pub(super) fn mark_block_used(&mut self, block: usize) {
+        self.bytes[BLOCK_BITMAP_BLOCK * BLOCK_SIZE + (block - 1) / 8] |= 1 << ((block - 1) % 8);
+
+        let superblock = SUPERBLOCK_OFFSET as usize;
+        self.write_u32(superblock + 12, self.read_u32(superblock + 12) - 1);
+        self.write_u16(2 * BLOCK_SIZE + 12, self.read_u16(2 * BLOCK_SIZE + 12) - 1);
+    }
+
+    /// Sets the bit of the inode in the inode bitmap and updates the free inode counts
+    pub(super) fn mark_inode_used(&mut self, inode_id: usize) {
+        self.bytes[INODE_BITMAP_BLOCK * BLOCK_SIZE + (inode_id - 1) / 8] |= 1 << ((inode_id - 1) % 8);
+
+        let superblock = SUPERBLOCK_OFFSET as usize;
+        self.write_u32(superblock + 16, self.read_u32(superblock + 16) - 1);
+        self.write_u16(2 * BLOCK_SIZE + 14, self.read_u16(2 * BLOCK_SIZE + 14) - 1);
+    }
+
+    /// Writes an inode whose `blocks` count covers `block_count` blocks
+    pub(super) fn write_inode(&mut self, inode_id: usize, mode: InodeMode, size: usize, block_pointers: &[usize], block_count: usize) {
+        let inode = INODE_TABLE_BLOCK * BLOCK_SIZE + (inode_id - 1) * INODE_SIZE;
+        self.write_u16(inode, mode.bits()); // mode
+        self.write_u32(inode + 4, size as u32); // size
+        self.write_u32(inode + 28, (block_count * BLOCK_SIZE / 512) as u32); // blocks
+        for (index, pointer) in block_pointers.iter().enumerate() {
+            self.write_u32(inode + 40 + index * 4, *pointer as u32); // block
+        }
+    }
+
+    /// Fills the block with a single directory entry
+    pub(super) fn write_directory_entry(&mut self, block_number: usize, inode_id: usize, name: &str, file_type: FileType) {
+        let mut entry = encode_directory_entry(inode_id as u32, name, file_type).unwrap();
+        entry[4..6].copy_from_slice(&(BLOCK_SIZE as u16).to_le_bytes()); // rec_len
+
+        self.bytes[block_number * BLOCK_SIZE..block_number * BLOCK_SIZE + entry.len()].copy_from_slice(&entry);
+    }
+
+    pub(super) fn read_u32(&self, offset: usize) -> u32 {
+        u32::from_le_bytes(self.bytes[offset..offset + 4].try_into().unwrap())
+    }
+
+    pub(super) fn read_u16(&self, offset: usize) -> u16 {
+        u16::from_le_bytes(self.bytes[offset..offset + 2].try_into().unwrap())
+    }
+
+    pub(super) fn write_u32(&mut self, offset: usize, value: u32) {
+        self.bytes[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+    }
+
+    pub(super) fn write_u16(&mut self, offset: usize, value: u16) {
+        self.bytes[offset..offset + 2].copy_from_slice(&value.to_le_bytes());
+    }
+}
+
+impl BlockDevice for TestImage {
+    fn read_from_device(&mut self, byte_offset: u64, byte_count: u64, buffer: *mut c_void) -> usize {
+        let buffer = unsafe { slice::from_raw_parts_mut(buffer as *mut u8, byte_count as usize) };
+        buffer.copy_from_slice(&self.bytes[byte_offset as usize..(byte_offset + byte_count) as usize]);
+        self.reads += 1;
+
+        byte_count as usize
+    }
+
+    fn write_to_device(&mut self, byte_offset: u64, byte_count: u64, buffer: *mut c_void) {
+        let buffer = unsafe { slice::from_raw_parts(buffer as *const u8, byte_count as usize) };
+        self.bytes[byte_offset as usize..(byte_offset + byte_count) as usize].copy_from_slice(buffer);
+    }
+}
+
+/// Builds the file system of the image without going through the checks of a real mount, and
+/// without reading the root inode
+pub(super) fn mount(image: &mut TestImage) -> Ext2FileSystem {
+    let superblock = image.superblock();
+    let block_groups = vec![BlockGroupDescriptor::read_table_entry(image, &superblock, 0)];
+
+    Ext2FileSystem {
+        superblock,
+        root_inode: unsafe { MaybeUninit::<Inode>::zeroed().assume_init() },
+        block_groups,
+        dirty_blocks: BTreeMap::new(),
+        inode_cache: Default::default(),
+    }
+}
@@ -0,0 +1,257 @@
+use core::mem::size_of;
+use crate::drivers::BlockDevice;
+use crate::fs::ext2::{Ext2FileSystem, resolve_path};
+use crate::fs::ext2::directory::find_directory_entry;
+use crate::fs::ext2::inode::DIRECT_BLOCK_COUNT;
+
+/// Size in bytes of the units `Inode::blocks` is counted in
+const INODE_BLOCK_UNIT: usize = 512;
+
+/// Progress of a truncation through the block pointers of an inode
+struct Truncation {
+    /// Number of data blocks of the file left once truncated
+    kept_block_count: usize,
+    /// Number of data and indirect blocks freed so far
+    freed_block_count: usize,
+    /// Last data block of the file once truncated, unless it is a hole
+    last_block: Option<usize>,
+}
+
+impl Ext2FileSystem {
+    /// Sets the size of the file at the given absolute path. When shrinking, the data blocks past the
+    /// new end, and the indirect blocks left without any, are freed and the end of the last block is
+    /// zeroed. When growing, the new part of the file is left as a hole reading as zeros.
+    pub fn truncate(&mut self, drive: &mut impl BlockDevice, path: &str, new_size: u64) -> Result<(), &'static str> {
+        let inode_id = resolve_path(path, |directory_id, name| {
+            let directory = self.read_inode(drive, directory_id);
+            if !directory.is_directory() {
+                return None;
+            }
+
+            find_directory_entry(&directory.get_content(drive, &self.superblock), name).map(|inode_id| inode_id as usize)
+        }).ok_or("ext2: file not found")?;
+
+        let inode = self.read_inode(drive, inode_id);
+        if !inode.is_regular_file() {
+            return Err("ext2: only regular files can be truncated");
+        }
+        if new_size > u32::MAX as u64 && !self.superblock.has_large_files() {
+            return Err("ext2: file size too large for the file system");
+        }
+
+        let block_size = self.superblock.block_size();
+        if new_size < inode.size(&self.superblock) {
+            let mut truncation = Truncation {
+                kept_block_count: (new_size as usize).div_ceil(block_size),
+                freed_block_count: 0,
+                last_block: None,
+            };
+
+            let mut block_pointers = inode.block.read();
+            let mut first_block = 0;
+            for (index, block_pointer) in block_pointers.iter_mut().enumerate() {
+                // Direct pointers have no indirection, the last three have one, two and three levels
+                let level = index.saturating_sub(DIRECT_BLOCK_COUNT - 1) as u32;
+
+                if self.truncate_block(drive, *block_pointer as usize, level, first_block, &mut truncation)? {
+                    *block_pointer = 0;
+                }
+                first_block += self.pointers_per_block().pow(level);
+            }
+
+            // Growing the file again later must not bring back the old contents of the last block
+            if let Some(last_block) = truncation.last_block.filter(|_| new_size as usize % block_size != 0) {
+                let mut contents = self.read_block(drive, last_block);
+                contents[new_size as usize % block_size..].fill(0);
+                self.mark_block_dirty(last_block, contents);
+            }
+
+            let freed_units = (truncation.freed_block_count * block_size / INODE_BLOCK_UNIT) as u32;
+            let blocks = inode.blocks.read().checked_sub(freed_units).ok_or("ext2: inode block count is smaller than the blocks it points to")?;
+            unsafe {
+                inode.block.write(block_pointers);
+                inode.blocks.write(blocks);
+            }
+        }
+
+        unsafe {
+            inode.size.write(new_size as u32);
+            if self.superblock.has_large_files() {
+                inode.dir_acl.write((new_size >> 32) as u32);
+            }
+        }
+        self.write_inode(drive, inode_id, &inode);
+
+        Ok(())
+    }
+
+    /// Frees the block if it only covers data blocks past the end of the truncated file, starting with
+    /// `first_block`, along with the blocks it points to when it is an indirect block. Otherwise, the
+    /// pointers of an indirect block to freed blocks are cleared. Returns whether the block was freed.
+    fn truncate_block(&mut self, drive: &mut impl BlockDevice, block_number: usize, level: u32, first_block: usize, truncation: &mut Truncation) -> Result<bool, &'static str> {
+        // Holes have nothing to free
+        if block_number == 0 {
+            return Ok(false);
+        }
+
+        if level > 0 {
+            let block_span = self.pointers_per_block().pow(level - 1);
+            let mut contents = self.read_block(drive, block_number);
+
+            let mut cleared_pointer = false;
+            for (index, pointer) in contents.chunks_exact_mut(size_of::<u32>()).enumerate() {
+                let child = u32::from_le_bytes(pointer.try_into().unwrap()) as usize;
+                if self.truncate_block(drive, child, level - 1, first_block + index * block_span, truncation)? {
+                    pointer.fill(0);
+                    cleared_pointer = true;
+                }
+            }
+
+            if first_block < truncation.kept_block_count && cleared_pointer {
+                self.mark_block_dirty(block_number, contents);
+            }
+        }
+        else if first_block + 1 == truncation.kept_block_count {
+            truncation.last_block = Some(block_number);
+        }
+
+        if first_block < truncation.kept_block_count {
+            return Ok(false);
+        }
+
+        self.free_block(drive, block_number)?;
+        truncation.freed_block_count += 1;
+
+        Ok(true)
+    }
+
+    fn pointers_per_block(&self) -> usize {
+        self.superblock.block_size() / size_of::<u32>()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::fs::ext2::Ext2FileSystem;
+    use crate::fs::ext2::directory::FileType;
+    use crate::fs::ext2::inode::InodeMode;
+    use crate::fs::ext2::test_image::{BLOCK_BITMAP_BLOCK, BLOCK_SIZE, mount, TestImage};
+
+    const ROOT_ENTRIES_BLOCK: usize = 9;
+    const FILE_INODE_ID: usize = 12;
+    /// Data blocks of the file, the first 12 are pointed to directly and the last 2 through the
+    /// indirect block
+    const FILE_BLOCKS: [usize; 14] = [20, 21, 22, 23, 24, 25, 26, 27, 28, 29, 30, 31, 32, 33];
+    const INDIRECT_BLOCK: usize = 40;
+
+    /// Image holding a root directory, whose entries are in block 9, with a single 14 blocks long file
+    /// named "file"
+    fn image() -> TestImage {
+        let mut image = TestImage::new(64, 32);
+        for block in [ROOT_ENTRIES_BLOCK].into_iter().chain(FILE_BLOCKS).chain([INDIRECT_BLOCK]) {
+            image.mark_block_used(block);
+        }
+
+        image.write_inode(2, InodeMode::DIRECTORY, BLOCK_SIZE, &[ROOT_ENTRIES_BLOCK], 1);
+        image.write_directory_entry(ROOT_ENTRIES_BLOCK, FILE_INODE_ID, "file", FileType::RegularFile);
+
+        let mut file_pointers = FILE_BLOCKS[..12].to_vec();
+        file_pointers.push(INDIRECT_BLOCK);
+        image.write_inode(FILE_INODE_ID, InodeMode::REGULAR_FILE, FILE_BLOCKS.len() * BLOCK_SIZE, &file_pointers, FILE_BLOCKS.len() + 1);
+        for (index, block) in FILE_BLOCKS[12..].iter().enumerate() {
+            image.write_u32(INDIRECT_BLOCK * BLOCK_SIZE + index * 4, *block as u32);
+        }
+        for block in FILE_BLOCKS {
+            image.bytes[block * BLOCK_SIZE..(block + 1) * BLOCK_SIZE].fill(0xAA);
+        }
+
+        image
+    }
+
+    fn is_block_used(file_system: &Ext2FileSystem, device: &mut TestImage, block: usize) -> bool {
+        let bitmap = file_system.read_block(device, BLOCK_BITMAP_BLOCK);
+
+        bitmap[(block - 1) / 8] & (1 << ((block - 1) % 8)) != 0
+    }
+
+    #[test_case]
+    fn shrinking_mid_block_frees_blocks_past_the_end() {
+        // GIVEN
+        let mut device = image();
+        let mut file_system = mount(&mut device);
+        let free_blocks = file_system.superblock.unallocated_blocks.read();
+
+        // WHEN
+        let result = file_system.truncate(&mut device, "/file", 10 * BLOCK_SIZE as u64 + 100);
+
+        // THEN
+        let inode = file_system.read_inode(&mut device, FILE_INODE_ID);
+        let last_block = file_system.read_block(&mut device, 30);
+        assert_eq!(result, Ok(()));
+        assert_eq!(inode.size(&file_system.superblock), 10 * BLOCK_SIZE as u64 + 100);
+        assert_eq!(inode.blocks.read(), 11 * 2);
+        assert_eq!(inode.block.read()[10..13], [30, 0, 0]);
+        assert!(is_block_used(&file_system, &mut device, 30));
+        for block in [31, 32, 33, INDIRECT_BLOCK] {
+            assert!(!is_block_used(&file_system, &mut device, block));
+        }
+        assert_eq!(file_system.superblock.unallocated_blocks.read(), free_blocks + 4);
+        assert!(last_block[..100].iter().all(|byte| *byte == 0xAA));
+        assert!(last_block[100..].iter().all(|byte| *byte == 0));
+    }
+
+    #[test_case]
+    fn shrinking_at_block_boundary_keeps_partly_used_indirect_block() {
+        // GIVEN
+        let mut device = image();
+        let mut file_system = mount(&mut device);
+        let free_blocks = file_system.superblock.unallocated_blocks.read();
+
+        // WHEN
+        let result = file_system.truncate(&mut device, "/file", 13 * BLOCK_SIZE as u64);
+
+        // THEN
+        let inode = file_system.read_inode(&mut device, FILE_INODE_ID);
+        let indirect_block = file_system.read_block(&mut device, INDIRECT_BLOCK);
+        assert_eq!(result, Ok(()));
+        assert_eq!(inode.size(&file_system.superblock), 13 * BLOCK_SIZE as u64);
+        assert_eq!(inode.blocks.read(), 14 * 2);
+        assert_eq!(inode.block.read()[12], INDIRECT_BLOCK as u32);
+        assert_eq!(indirect_block[..8], [32, 0, 0, 0, 0, 0, 0, 0]);
+        assert!(is_block_used(&file_system, &mut device, 32));
+        assert!(!is_block_used(&file_system, &mut device, 33));
+        assert_eq!(file_system.superblock.unallocated_blocks.read(), free_blocks + 1);
+        assert!(file_system.read_block(&mut device, 32).iter().all(|byte| *byte == 0xAA));
+    }
+
+    #[test_case]
+    fn growing_leaves_a_hole() {
+        // GIVEN
+        let mut device = image();
+        let mut file_system = mount(&mut device);
+        let free_blocks = file_system.superblock.unallocated_blocks.read();
+
+        // WHEN
+        let result = file_system.truncate(&mut device, "/file", 20 * BLOCK_SIZE as u64);
+
+        // THEN
+        let inode = file_system.read_inode(&mut device, FILE_INODE_ID);
+        assert_eq!(result, Ok(()));
+        assert_eq!(inode.size(&file_system.superblock), 20 * BLOCK_SIZE as u64);
+        assert_eq!(inode.blocks.read(), 15 * 2);
+        assert_eq!(file_system.superblock.unallocated_blocks.read(), free_blocks);
+    }
+
+    #[test_case]
+    fn truncating_directory_fails() {
+        // GIVEN
+        let mut device = image();
+        let mut file_system = mount(&mut device);
+
+        // WHEN
+        let result = file_system.truncate(&mut device, "/", 0);
+
+        // THEN
+        assert_eq!(result, Err("ext2: only regular files can be truncated"));
+    }
+}
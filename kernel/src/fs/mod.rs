@@ -6,13 +6,18 @@ use alloc::vec::Vec;
 use core::ops::ControlFlow;
 use conquer_once::spin::OnceCell;
 use spin::Mutex;
+use crate::fs::device::{DeviceId, read_device};
 use crate::fs::ramfs::RamfsNode;
+use crate::HHDM_OFFSET;
+use crate::memory::{MemoryManager, PAGE_SIZE, VirtualAddress};
+use crate::memory::virtual_memory::paging::entry::EntryFlags;
 
+pub mod device;
 pub mod ext2;
 pub mod ramfs;
 
-const MAX_FILENAME_LENGTH: usize = 256;
-const MAX_PATH_LENGTH: usize = 4096;
+pub(crate) const MAX_FILENAME_LENGTH: usize = 256;
+pub(crate) const MAX_PATH_LENGTH: usize = 4096;
 
 pub(crate) type VfsNodeRef = Arc<Mutex<Box<dyn VfsNode + Send>>>;
 pub(crate) type VfsNodeWeakRef = Weak<Mutex<Box<dyn VfsNode + Send>>>;
@@ -23,6 +28,14 @@ pub trait VfsNode {
     fn name(&self) -> &String;
     fn parent(&self) -> &Option<VfsNodeWeakRef>;
     fn children(&mut self) -> &mut Vec<VfsNodeRef>;
+    /// The size in bytes of the node's contents
+    fn size(&self) -> usize;
+    /// Whether the node lists other nodes rather than holding contents
+    fn is_directory(&self) -> bool;
+    /// Major and minor number of the device behind the node, if it is a device node
+    fn device_id(&self) -> Option<DeviceId> {
+        None
+    }
 
     fn open(&self, );
     fn close(&self, );
@@ -42,24 +55,32 @@ impl Vfs {
                 name: String::from("/"),
                 parent: None,
                 children: Vec::new(),
+                content: Vec::new(),
+                is_directory: true,
             }) as Box<dyn VfsNode + Send>));
 
             let current_directory = Arc::new(Mutex::new(Box::new(RamfsNode {
                 name: String::from("."),
                 parent: Some(Arc::downgrade(&root_node)),
                 children: Vec::new(),
+                content: Vec::new(),
+                is_directory: true,
             }) as Box<dyn VfsNode + Send>));
 
             let previous_directory = Arc::new(Mutex::new(Box::new(RamfsNode {
                 name: String::from(".."),
                 parent: Some(Arc::downgrade(&root_node)),
                 children: Vec::new(),
+                content: Vec::new(),
+                is_directory: true,
             }) as Box<dyn VfsNode + Send>));
 
             let dev_directory =  Arc::new(Mutex::new(Box::new(RamfsNode {
                 name: String::from("dev"),
                 parent: Some(Arc::downgrade(&root_node)),
                 children: Vec::new(),
+                content: Vec::new(),
+                is_directory: true,
             }) as Box<dyn VfsNode + Send>));
 
             {
@@ -78,16 +99,69 @@ impl Vfs {
         ROOT_DIRECTORY.try_get().expect("fs: virtual file system not initialized")
     }
 
+    /// Creates an empty ramfs node at the given absolute path, its parent directory must exist
+    pub fn create(path: &str) -> Result<(), &'static str> {
+        Self::create_file(path, Vec::new())
+    }
+
+    /// Creates a ramfs file holding the given contents at the given absolute path, its parent
+    /// directory must exist
+    pub fn create_file(path: &str, content: Vec<u8>) -> Result<(), &'static str> {
+        let (parent, name) = Self::find_parent(path)?;
+
+        Self::create_ramfs_node(parent, name, content, false)
+    }
+
+    /// Creates an empty ramfs directory at the given absolute path, its parent directory must exist
+    pub fn create_directory(path: &str) -> Result<(), &'static str> {
+        let (parent, name) = Self::find_parent(path)?;
+
+        Self::create_ramfs_node(parent, name, Vec::new(), true)
+    }
+
+    /// Removes the node at the given absolute path, along with its children, from its parent directory
+    pub fn remove(path: &str) -> Result<(), &'static str> {
+        let (parent, name) = Self::find_parent(path)?;
+        let mut parent = parent.lock();
+        let children = parent.children();
+        let index = children.iter().position(|child| child.lock().name() == name).ok_or("fs: file not found")?;
+
+        children.remove(index);
+        Ok(())
+    }
+
+    /// Splits an absolute path into the node of its parent directory and the name of its last component
+    fn find_parent(path: &str) -> Result<(VfsNodeRef, &str), &'static str> {
+        validate_path(path)?;
+
+        let (parent_path, name) = path.trim_end_matches('/').rsplit_once('/').ok_or("fs: path is not absolute")?;
+        let parent = match parent_path {
+            "" => Some(Self::root_directory().clone()),
+            _ => Self::find_from_absolute_path(parent_path),
+        }.ok_or("fs: parent directory not found")?;
+
+        Ok((parent, name))
+    }
+
     /// Creates a new ramfs node with the specified characteristics and adds it to the designated
     /// parent
-    pub fn create_child_node(parent: VfsNodeRef, name: &str) {
+    pub fn create_child_node(parent: VfsNodeRef, name: &str) -> Result<(), &'static str> {
+        Self::create_ramfs_node(parent, name, Vec::new(), false)
+    }
+
+    fn create_ramfs_node(parent: VfsNodeRef, name: &str, content: Vec<u8>, is_directory: bool) -> Result<(), &'static str> {
+        validate_name(name)?;
+
         let child = Arc::new(Mutex::new(Box::new(RamfsNode {
             name: String::from(name),
             parent: Some(Arc::downgrade(&parent)),
             children: Vec::new(),
+            content,
+            is_directory,
         }) as Box<dyn VfsNode + Send> ));
 
         Self::insert_child_node(parent, child);
+        Ok(())
     }
 
     /// Inserts the given node as a child of the designated parent
@@ -126,6 +200,31 @@ impl Vfs {
         Self::find_descendent(Self::root_directory().clone(), path)
     }
 
+    /// Finds the node at the given path, either absolute or relative to `directory`. "." and ".."
+    /// components are followed, the parent of the root directory is the root directory itself.
+    pub fn resolve_path(directory: VfsNodeRef, path: &str) -> Option<VfsNodeRef> {
+        let start = if path.starts_with('/') { Self::root_directory().clone() } else { directory };
+
+        path.split('/').try_fold(start, |node, component| match component {
+            "" | "." => Some(node),
+            ".." => Some(Self::parent(node.clone()).and_then(|parent| parent.upgrade()).unwrap_or(node)),
+            name => Self::find_child(node, name),
+        })
+    }
+
+    /// Reads from the node, going through the driver registered for its major number if it is a
+    /// device node
+    pub fn read_node(node: &VfsNodeRef, buffer: *mut u8, byte_count: usize, offset: usize) -> Result<(), &'static str> {
+        let node = node.lock();
+        match node.device_id() {
+            Some(device_id) => read_device(device_id, buffer, byte_count, offset),
+            None => {
+                node.read(buffer, byte_count, offset);
+                Ok(())
+            }
+        }
+    }
+
     /// Returns the parent of a given node
     pub fn parent(node: VfsNodeRef) -> Option<VfsNodeWeakRef> {
         node.lock().parent().clone()
@@ -153,4 +252,210 @@ impl Vfs {
 
         directory_entries.iter().skip(1).map(|entry| format!("/{}", entry) ).collect()
     }
-}
\ No newline at end of file
+
+    /// Maps the contents of the file at the given absolute path into newly allocated virtual memory
+    /// and returns the base address of the mapping. The pages are filled through the direct
+    /// mapping of physical memory so the requested flags do not need to include `WRITABLE`.
+    pub fn map_file(path: &str, flags: EntryFlags) -> Option<VirtualAddress> {
+        let node = Self::find_from_absolute_path(path)?;
+        let node = node.lock();
+
+        let size = node.size();
+        if size == 0 {
+            return None;
+        }
+
+        let base_address = MemoryManager::vmm_alloc(size, flags)?;
+
+        for page_offset in (0..size.div_ceil(PAGE_SIZE)).map(|page| page * PAGE_SIZE) {
+            let frame_address = MemoryManager::translate(base_address + page_offset)
+                .expect("fs: mapped file page is not present") + *HHDM_OFFSET;
+
+            // Frames are not zeroed on allocation, clear the tail of the last page
+            unsafe { (frame_address as *mut u8).write_bytes(0, PAGE_SIZE) };
+            node.read(frame_address as *mut u8, PAGE_SIZE.min(size - page_offset), page_offset);
+        }
+
+        Some(base_address)
+    }
+
+    /// Releases a mapping of the given size previously created with `map_file`
+    pub fn unmap_file(address: VirtualAddress, size: usize) -> Result<(), &'static str> {
+        MemoryManager::vmm_free(size, address)
+    }
+}
+
+/// Checks that a name can be used for a single directory entry
+pub(crate) fn validate_name(name: &str) -> Result<(), &'static str> {
+    if name.is_empty() {
+        return Err("fs: file name is empty");
+    }
+    if name.len() > MAX_FILENAME_LENGTH {
+        return Err("fs: file name is longer than 256 bytes");
+    }
+    if name.contains('/') {
+        return Err("fs: file name contains a '/'");
+    }
+    if name.contains('\0') {
+        return Err("fs: file name contains a NUL byte");
+    }
+
+    Ok(())
+}
+
+/// Checks that a path is absolute, fits in `MAX_PATH_LENGTH` and only has valid components
+pub(crate) fn validate_path(path: &str) -> Result<(), &'static str> {
+    if path.len() > MAX_PATH_LENGTH {
+        return Err("fs: path is longer than 4096 bytes");
+    }
+    if !path.starts_with('/') {
+        return Err("fs: path is not absolute");
+    }
+
+    path.split('/').filter(|component| !component.is_empty()).try_for_each(validate_name)
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::boxed::Box;
+    use alloc::string::String;
+    use alloc::sync::Arc;
+    use alloc::vec::Vec;
+    use spin::Mutex;
+    use alloc::format;
+    use crate::fs::{MAX_FILENAME_LENGTH, MAX_PATH_LENGTH, validate_name, validate_path, Vfs, VfsNode};
+    use crate::fs::ramfs::RamfsNode;
+    use crate::memory::PAGE_SIZE;
+    use crate::memory::virtual_memory::paging::entry::EntryFlags;
+    use crate::utils::tests::assert_buffers_eq;
+
+    #[test_case]
+    fn map_file_copies_file_contents() {
+        // GIVEN
+        let content: Vec<u8> = (0..PAGE_SIZE + 100).map(|i| (i % 251) as u8).collect();
+        let file = Arc::new(Mutex::new(Box::new(RamfsNode {
+            name: String::from("map_file_test"),
+            parent: Some(Arc::downgrade(Vfs::root_directory())),
+            children: Vec::new(),
+            content: content.clone(),
+            is_directory: false,
+        }) as Box<dyn VfsNode + Send>));
+        Vfs::insert_child_node(Vfs::root_directory().clone(), file);
+
+        // WHEN
+        let mapping = Vfs::map_file("/map_file_test", EntryFlags::empty()).expect("could not map file");
+
+        // THEN
+        let mapped_content = unsafe { core::slice::from_raw_parts(mapping as *const u8, content.len()) };
+        assert_buffers_eq(mapped_content, content.as_slice());
+
+        Vfs::unmap_file(mapping, content.len()).unwrap();
+        Vfs::remove("/map_file_test").unwrap();
+    }
+
+    #[test_case]
+    fn remove_detaches_node_from_parent() {
+        // GIVEN
+        Vfs::create("/dev/remove_test").unwrap();
+
+        // WHEN
+        let removed = Vfs::remove("/dev/remove_test");
+        let missing = Vfs::remove("/dev/remove_test");
+
+        // THEN
+        assert_eq!(removed, Ok(()));
+        assert_eq!(missing, Err("fs: file not found"));
+        assert!(Vfs::find_from_absolute_path("/dev/remove_test").is_none());
+    }
+
+    #[test_case]
+    fn create_adds_node_under_parent() {
+        // WHEN
+        let result = Vfs::create("/dev/create_test");
+
+        // THEN
+        assert_eq!(result, Ok(()));
+        assert!(Vfs::find_from_absolute_path("/dev/create_test").is_some());
+
+        Vfs::remove("/dev/create_test").unwrap();
+    }
+
+    #[test_case]
+    fn resolve_path_follows_relative_components() {
+        // GIVEN
+        Vfs::create("/dev/resolve_test").unwrap();
+        let dev = Vfs::find_from_absolute_path("/dev").unwrap();
+
+        // WHEN
+        let relative = Vfs::resolve_path(dev.clone(), "resolve_test").unwrap();
+        let through_parent = Vfs::resolve_path(dev.clone(), "../dev/./resolve_test").unwrap();
+        let absolute = Vfs::resolve_path(dev.clone(), "/dev").unwrap();
+        let above_root = Vfs::resolve_path(dev.clone(), "../..").unwrap();
+
+        // THEN
+        assert_eq!(Vfs::get_absolute_path(relative), "/dev/resolve_test");
+        assert_eq!(Vfs::get_absolute_path(through_parent), "/dev/resolve_test");
+        assert_eq!(Vfs::get_absolute_path(absolute), "/dev");
+        assert_eq!(Vfs::get_absolute_path(above_root), "/");
+        assert!(Vfs::resolve_path(dev, "missing").is_none());
+
+        Vfs::remove("/dev/resolve_test").unwrap();
+    }
+
+    #[test_case]
+    fn create_without_parent_fails() {
+        // WHEN
+        let result = Vfs::create("/missing/create_test");
+
+        // THEN
+        assert_eq!(result, Err("fs: parent directory not found"));
+    }
+
+    #[test_case]
+    fn invalid_names_are_rejected() {
+        // GIVEN
+        let too_long = "a".repeat(MAX_FILENAME_LENGTH + 1);
+
+        // WHEN
+        let empty = validate_name("");
+        let long = validate_name(&too_long);
+        let slash = validate_name("a/b");
+        let nul = validate_name("a\0b");
+        let longest = validate_name(&too_long[1..]);
+
+        // THEN
+        assert_eq!(empty, Err("fs: file name is empty"));
+        assert_eq!(long, Err("fs: file name is longer than 256 bytes"));
+        assert_eq!(slash, Err("fs: file name contains a '/'"));
+        assert_eq!(nul, Err("fs: file name contains a NUL byte"));
+        assert_eq!(longest, Ok(()));
+    }
+
+    #[test_case]
+    fn invalid_paths_are_rejected() {
+        // GIVEN
+        let too_long = format!("/{}", "a/".repeat(MAX_PATH_LENGTH / 2));
+
+        // WHEN
+        let long = validate_path(&too_long);
+        let relative = validate_path("dev/fb0");
+        let nul = validate_path("/dev/f\0b0");
+        let long_component = validate_path(&format!("/dev/{}", "a".repeat(MAX_FILENAME_LENGTH + 1)));
+
+        // THEN
+        assert_eq!(long, Err("fs: path is longer than 4096 bytes"));
+        assert_eq!(relative, Err("fs: path is not absolute"));
+        assert_eq!(nul, Err("fs: file name contains a NUL byte"));
+        assert_eq!(long_component, Err("fs: file name is longer than 256 bytes"));
+    }
+
+    #[test_case]
+    fn create_child_node_rejects_invalid_name() {
+        // WHEN
+        let result = Vfs::create_child_node(Vfs::root_directory().clone(), "a/b");
+
+        // THEN
+        assert_eq!(result, Err("fs: file name contains a '/'"));
+        assert!(Vfs::find_child(Vfs::root_directory().clone(), "a/b").is_none());
+    }
+}
@@ -6,6 +6,8 @@ pub struct RamfsNode {
     pub(super) name: String,
     pub(super) parent: Option<VfsNodeWeakRef>,
     pub(super) children: Vec<VfsNodeRef>,
+    pub(super) content: Vec<u8>,
+    pub(super) is_directory: bool,
 }
 
 impl VfsNode for RamfsNode {
@@ -21,6 +23,14 @@ impl VfsNode for RamfsNode {
         &mut self.children
     }
 
+    fn size(&self) -> usize {
+        self.content.len()
+    }
+
+    fn is_directory(&self) -> bool {
+        self.is_directory
+    }
+
     fn open(&self) {
         panic!("fs: cannot invoke method 'open' a ramfs node");
     }
@@ -29,8 +39,13 @@ impl VfsNode for RamfsNode {
         panic!("fs: cannot invoke method 'close' on a ramfs node");
     }
 
-    fn read(&self, _buffer: *mut u8, _byte_count: usize, _offset: usize) {
-        unimplemented!()
+    fn read(&self, buffer: *mut u8, byte_count: usize, offset: usize) {
+        if offset >= self.content.len() {
+            return;
+        }
+
+        let byte_count = byte_count.min(self.content.len() - offset);
+        unsafe { buffer.copy_from_nonoverlapping(self.content.as_ptr().add(offset), byte_count) };
     }
 
     fn write(&self, _buffer: *const u8, _byte_count: usize, _offset: usize) {
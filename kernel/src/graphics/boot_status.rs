@@ -0,0 +1,98 @@
+use alloc::format;
+use alloc::string::String;
+use crate::graphics::framebuffer_device::Writer;
+
+/// Number of cells of the progress bar drawn on the status line
+const PROGRESS_BAR_WIDTH: usize = 20;
+
+/// Stages of `init`, in the order they complete
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum InitStage {
+    MemoryInit,
+    Framebuffer,
+    Interrupts,
+    Pci,
+    Filesystem,
+    Input,
+    Ready,
+}
+
+impl InitStage {
+    pub const ALL: [InitStage; 7] = [
+        InitStage::MemoryInit,
+        InitStage::Framebuffer,
+        InitStage::Interrupts,
+        InitStage::Pci,
+        InitStage::Filesystem,
+        InitStage::Input,
+        InitStage::Ready,
+    ];
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            InitStage::MemoryInit => "memory",
+            InitStage::Framebuffer => "framebuffer",
+            InitStage::Interrupts => "interrupts",
+            InitStage::Pci => "PCI",
+            InitStage::Filesystem => "file system",
+            InitStage::Input => "input",
+            InitStage::Ready => "ready",
+        }
+    }
+
+    /// Percentage of the boot done once this stage is complete
+    pub fn progress(&self) -> usize {
+        let completed_stages = InitStage::ALL.iter().position(|stage| stage == self).unwrap() + 1;
+
+        completed_stages * 100 / InitStage::ALL.len()
+    }
+}
+
+/// Text of the status line once the given stage is complete
+pub fn status_line(stage: InitStage) -> String {
+    let filled_cells = stage.progress() * PROGRESS_BAR_WIDTH / 100;
+
+    format!("boot [{:<width$}] {:>3}% {}", "#".repeat(filled_cells), stage.progress(), stage.label(), width = PROGRESS_BAR_WIDTH)
+}
+
+/// Updates the status line pinned to the top of the screen. Stages completed before the console is
+/// set up are only shown once a later stage completes.
+pub fn complete_stage(stage: InitStage) {
+    if let Some(writer) = Writer::instance() {
+        writer.lock().set_status_line(&status_line(stage));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::graphics::boot_status::{InitStage, status_line};
+
+    #[test_case]
+    fn stages_have_labels() {
+        // WHEN
+        let labels = InitStage::ALL.map(|stage| stage.label());
+
+        // THEN
+        assert_eq!(labels, ["memory", "framebuffer", "interrupts", "PCI", "file system", "input", "ready"]);
+    }
+
+    #[test_case]
+    fn progress_grows_to_completion_with_each_stage() {
+        // WHEN
+        let progress = InitStage::ALL.map(|stage| stage.progress());
+
+        // THEN
+        assert_eq!(progress, [14, 28, 42, 57, 71, 85, 100]);
+    }
+
+    #[test_case]
+    fn status_line_shows_progress_bar_and_stage() {
+        // WHEN
+        let first_stage = status_line(InitStage::MemoryInit);
+        let last_stage = status_line(InitStage::Ready);
+
+        // THEN
+        assert_eq!(first_stage, "boot [##                  ]  14% memory");
+        assert_eq!(last_stage, "boot [####################] 100% ready");
+    }
+}
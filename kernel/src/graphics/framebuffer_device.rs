@@ -1,18 +1,38 @@
 use alloc::{vec};
+use alloc::string::String;
 use alloc::vec::Vec;
 use core::fmt::Write;
+use core::sync::atomic::{AtomicBool, Ordering};
 use conquer_once::spin::OnceCell;
-use rlibc::{memcpy, memmove};
+use rlibc::memmove;
 use spin::Mutex;
-use crate::{FRAMEBUFFER_REQUEST, serial_println};
-use crate::drivers::fbdev::FB_DEVICES;
+use crate::serial_println;
+use crate::drivers::fbdev::{FB_DEVICES, FrameBufferDevice, PixelFormat};
 use crate::fs::{VfsNode};
+use crate::drivers::pit::ticks_to_ms;
 use crate::graphics::fonts::{FONT, FONT_HEIGHT, FONT_WIDTH};
-use crate::serial::serial_print;
+use crate::graphics::text_mode::early_print;
 
 const DEFAULT_COLOR_CODE: ColorCode = ColorCode::new(Rgb8(0xFFFFFF), Rgb8(0));
 
+/// Time in milliseconds the cursor stays visible, then hidden
+const CURSOR_BLINK_INTERVAL_MS: u64 = 500;
+/// Height in pixels of the underline cursor drawn at the bottom of the cell
+const CURSOR_HEIGHT: usize = 2;
+
+/// Largest factor glyphs can be scaled up by
+const MAX_FONT_SCALE: usize = 4;
+
+/// Distance in columns between two tab stops
+const TAB_WIDTH: usize = 8;
+/// Glyph drawn in place of control characters that have no special handling (■ in code page 437)
+const CONTROL_CHARACTER_PLACEHOLDER: u8 = 0xFE;
+
 static INSTANCE: OnceCell<Mutex<Writer>> = OnceCell::uninit();
+/// Output of `print!` while it is being captured by `capture_output` instead of displayed
+static CAPTURED_OUTPUT: Mutex<Option<String>> = Mutex::new(None);
+/// Whether `capture_output` is running, checked before taking the `CAPTURED_OUTPUT` lock
+static CAPTURING: AtomicBool = AtomicBool::new(false);
 
 pub enum LogLevel {
     Info,
@@ -21,6 +41,18 @@ pub enum LogLevel {
     Ok,
 }
 
+impl LogLevel {
+    /// Text of the header printed before the message
+    fn label(&self) -> &'static str {
+        match self {
+            LogLevel::Info => "INFO",
+            LogLevel::Warning => "WARN",
+            LogLevel::Error => "FAIL",
+            LogLevel::Ok => " OK ",
+        }
+    }
+}
+
 #[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
 pub struct Rgb8(pub u32);
 
@@ -63,6 +95,13 @@ pub struct Writer {
 
     buffer_pixel_height: usize,
     buffer_pixel_width: usize,
+
+    /// Whether the cursor is currently drawn on the screen
+    cursor_drawn: bool,
+    /// Every glyph pixel is drawn as a square of this size
+    font_scale: usize,
+    /// Text pinned to the top row, which is then left out of the scrolling
+    status_line: Option<String>,
 }
 
 impl Writer {
@@ -72,20 +111,25 @@ impl Writer {
 
     /// This function is unsafe because it should only be called once the heap is set up
     pub unsafe fn init() -> Result<(), &'static str> {
-        if FB_DEVICES.lock().len() <= 0 {
-            return Err("no framebuffer found");
-        }
+        let devices = FB_DEVICES.lock();
+        let framebuffer = devices.first().ok_or("no framebuffer found")?;
+
+        let writer = Self::new(framebuffer.screen_info.width as usize, framebuffer.screen_info.height as usize);
+
+        INSTANCE.try_init_once(|| Mutex::new(writer)).or(Err("Cannot initialize the framebuffer more than once"))
+    }
 
-        let framebuffer = &FB_DEVICES.lock()[0];
+    /// Draws an image on the screen the console is written to, see `FrameBufferDevice::blit_image`
+    pub fn blit_image(&self, x: isize, y: isize, width: usize, height: usize, pixels: &[u32], format: PixelFormat) -> Result<(), &'static str> {
+        FB_DEVICES.lock().first().ok_or("no framebuffer found")?.blit_image(x, y, width, height, pixels, format)
+    }
 
-        let buffer_pixel_width = framebuffer.screen_info.width as usize;
-        let buffer_pixel_height = framebuffer.screen_info.height as usize;
-        let buffer_width = buffer_pixel_width / FONT_WIDTH;
-        let buffer_height = buffer_pixel_height / FONT_HEIGHT;
+    fn new(buffer_pixel_width: usize, buffer_pixel_height: usize) -> Self {
+        let (buffer_width, buffer_height) = grid_size(buffer_pixel_width, buffer_pixel_height, 1);
 
         let screen_buffer = vec![vec![None; buffer_width]; buffer_height];
 
-        let writer = Self {
+        Self {
             color_code: DEFAULT_COLOR_CODE,
             buffer_width,
             buffer_height,
@@ -93,92 +137,223 @@ impl Writer {
             screen_buffer,
             buffer_pixel_width,
             buffer_pixel_height,
-        };
+            cursor_drawn: false,
+            font_scale: 1,
+            status_line: None,
+        }
+    }
 
-        INSTANCE.try_init_once(|| Mutex::new(writer)).or(Err("Cannot initialize the framebuffer more than once"))
+    /// Pins the text to the top row of the screen with inverted colors, it stays in place while the
+    /// rest of the console scrolls. Text longer than the screen is cut.
+    pub fn set_status_line(&mut self, text: &str) {
+        let inverted = ColorCode::new(self.color_code.background, self.color_code.foreground);
+        let mut cells = text.bytes().chain(core::iter::repeat(b' ')).take(self.buffer_width);
+
+        for col in 0..self.buffer_width {
+            self.write_at(ScreenChar::new(cells.next().unwrap_or(b' '), inverted), col, 0);
+        }
+
+        self.status_line = Some(String::from(text));
+    }
+
+    /// Number of rows at the top of the screen that do not scroll
+    fn pinned_rows(&self) -> usize {
+        if self.status_line.is_some() { 1 } else { 0 }
+    }
+
+    /// Scales the font by an integer factor, the screen is cleared and its grid recomputed
+    pub fn set_font_scale(&mut self, font_scale: usize) -> Result<(), &'static str> {
+        if !(1..=MAX_FONT_SCALE).contains(&font_scale) {
+            return Err("console: font scale must be between 1 and 4");
+        }
+
+        self.resize_grid(font_scale);
+        self.clear_screen();
+
+        Ok(())
+    }
+
+    /// Sets the color the screen is cleared to and characters are drawn on, the screen is cleared
+    pub fn set_background(&mut self, color: Rgb8) {
+        self.color_code.background = color;
+        self.clear_screen();
+    }
+
+    fn resize_grid(&mut self, font_scale: usize) {
+        (self.buffer_width, self.buffer_height) = grid_size(self.buffer_pixel_width, self.buffer_pixel_height, font_scale);
+        self.font_scale = font_scale;
+        self.screen_buffer = vec![vec![None; self.buffer_width]; self.buffer_height];
+        self.column_position = 0;
+    }
+
+    /// Returns the column and row of the cell where the next character will be written
+    fn cursor_position(&self) -> (usize, usize) {
+        (self.column_position.min(self.buffer_width - 1), self.buffer_height - 1)
+    }
+
+    /// Shows or hides the cursor according to the blink phase at the given PIT tick count. This runs
+    /// in the timer interrupt, the tick is skipped when the interrupted code holds the framebuffers.
+    pub fn update_cursor(&mut self, ticks: u64) {
+        let Some(framebuffers) = FB_DEVICES.try_lock() else { return };
+
+        if let Some(framebuffer) = framebuffers.first() {
+            self.set_cursor_drawn(framebuffer, is_cursor_visible(ticks));
+        }
+    }
+
+    fn show_cursor(&mut self) {
+        if let Some(framebuffer) = FB_DEVICES.lock().first() {
+            self.set_cursor_drawn(framebuffer, true);
+        }
+    }
+
+    fn hide_cursor(&mut self) {
+        if let Some(framebuffer) = FB_DEVICES.lock().first() {
+            self.set_cursor_drawn(framebuffer, false);
+        }
+    }
+
+    /// Draws or erases the cursor, unless it already is in the requested state
+    fn set_cursor_drawn(&mut self, framebuffer: &FrameBufferDevice, drawn: bool) {
+        if self.cursor_drawn == drawn {
+            return;
+        }
+
+        let (col, row) = self.cursor_position();
+        match (drawn, self.screen_buffer[row][col]) {
+            (true, _) => draw_cursor(framebuffer, self.color_code.foreground, col, row, self.font_scale),
+            // Redraw the cell to restore the glyph the cursor was covering
+            (false, Some(screen_char)) => draw_char(framebuffer, screen_char, col, row, self.font_scale),
+            (false, None) => draw_cursor(framebuffer, self.color_code.background, col, row, self.font_scale),
+        }
+
+        self.cursor_drawn = drawn;
     }
 
     fn write_char(&mut self, screen_char: ScreenChar) {
+        match screen_char.ascii_character {
+            b'\n' => self.new_line(),
+            b'\r' => self.column_position = 0,
+            b'\t' => {
+                let next_tab_stop = (self.column_position / TAB_WIDTH + 1) * TAB_WIDTH;
+                self.column_position = next_tab_stop.min(self.buffer_width);
+            }
+            0x08 => self.erase_previous_char(),
+            byte if byte.is_ascii_control() => {
+                self.write_glyph(ScreenChar::new(CONTROL_CHARACTER_PLACEHOLDER, screen_char.color_code));
+            }
+            _ => self.write_glyph(screen_char),
+        }
+    }
+
+    /// Draws the character at the current position, wrapping to a new line if the current one is full
+    fn write_glyph(&mut self, screen_char: ScreenChar) {
+        if self.column_position >= self.buffer_width {
+            self.new_line();
+        }
+
         let row = self.buffer_height - 1;
         let col = self.column_position;
 
-        self.column_position += 1;
-
         self.write_at(screen_char, col, row);
+        self.column_position += 1;
     }
 
     fn write_at(&mut self, screen_char: ScreenChar, col: usize, row: usize) {
-        match screen_char.ascii_character {
-            b'\n' => self.new_line(),
-            _ => {
-                if self.column_position >= self.buffer_width {
-                    self.new_line();
-                }
-
-                draw_char(screen_char, col, row);
-                self.screen_buffer[row][col] = Some(screen_char);
-            }
+        if let Some(framebuffer) = FB_DEVICES.lock().first() {
+            draw_char(framebuffer, screen_char, col, row, self.font_scale);
         }
+        self.screen_buffer[row][col] = Some(screen_char);
     }
 
     fn clear_char(&mut self) {
+        self.hide_cursor();
+        self.erase_previous_char();
+        self.show_cursor();
+    }
+
+    /// Moves the cursor back one column and erases the character there
+    fn erase_previous_char(&mut self) {
+        if self.column_position == 0 {
+            return;
+        }
+
         let row = self.buffer_height - 1;
-        let col = self.column_position - 1;
+        let col = self.column_position.min(self.buffer_width) - 1;
 
-        self.column_position -= 1;
+        self.column_position = col;
 
         self.clear_at(col, row);
     }
 
     fn clear_at(&mut self, col: usize, row: usize) {
-        if let Some(framebuffer_response) = FRAMEBUFFER_REQUEST.get_response() {
-            if let Some(framebuffer) = framebuffer_response.framebuffers().next() {
-                let empty_row = vec![0; FONT_WIDTH];
-
-                for pixel_row in 0..FONT_HEIGHT {
-                    let pixel_offset = ((row * FONT_HEIGHT) + pixel_row) * framebuffer.pitch() as usize + (col * FONT_WIDTH * 4);
-                    unsafe { memcpy(framebuffer.addr().add(pixel_offset), empty_row.as_ptr() as *const u8, empty_row.len() * 4); }
-                }
+        if let Some(framebuffer) = FB_DEVICES.lock().first() {
+            let (cell_width, cell_height) = cell_size(self.font_scale);
+            let empty_row = vec![self.background_pixel(framebuffer); cell_width];
 
-                self.screen_buffer[row][col] = None;
+            for pixel_row in 0..cell_height {
+                let pixel_offset = ((row * cell_height) + pixel_row) * framebuffer.screen_info.pitch as usize + (col * cell_width * 4);
+                framebuffer.write(empty_row.as_ptr() as *const u8, empty_row.len() * 4, pixel_offset);
             }
+
+            self.screen_buffer[row][col] = None;
         }
     }
 
     fn clear_row(&mut self, row: usize) {
-        if let Some(framebuffer_response) = FRAMEBUFFER_REQUEST.get_response() {
-            if let Some(framebuffer) = framebuffer_response.framebuffers().next() {
-                let empty_row = vec![0; self.buffer_width * FONT_WIDTH];
+        if let Some(framebuffer) = FB_DEVICES.lock().first() {
+            let (cell_width, cell_height) = cell_size(self.font_scale);
+            let empty_row = vec![self.background_pixel(framebuffer); self.buffer_width * cell_width];
 
-                for pixel_row in 0..FONT_HEIGHT {
-                    let pixel_offset = ((row * FONT_HEIGHT) + pixel_row) * framebuffer.pitch() as usize;
-                    unsafe { memcpy(framebuffer.addr().add(pixel_offset), empty_row.as_ptr() as *const u8, empty_row.len() * 4); }
-                }
+            for pixel_row in 0..cell_height {
+                let pixel_offset = ((row * cell_height) + pixel_row) * framebuffer.screen_info.pitch as usize;
+                framebuffer.write(empty_row.as_ptr() as *const u8, empty_row.len() * 4, pixel_offset);
+            }
 
-                for col in 0..self.buffer_width {
-                    self.screen_buffer[row][col] = None;
-                }
+            for col in 0..self.buffer_width {
+                self.screen_buffer[row][col] = None;
             }
         }
     }
 
     pub fn clear_screen(&mut self) {
-        if let Some(framebuffer_response) = FRAMEBUFFER_REQUEST.get_response() {
-            if let Some(framebuffer) = framebuffer_response.framebuffers().next() {
-                self.screen_buffer = vec![vec![None; self.buffer_width]; self.buffer_height];
-                self.column_position = 0;
-                unsafe { framebuffer.addr().write_bytes(0, (framebuffer.height() * framebuffer.width() * 4) as usize) }
-            }
+        if let Some(framebuffer) = FB_DEVICES.lock().first() {
+            self.screen_buffer = vec![vec![None; self.buffer_width]; self.buffer_height];
+            self.column_position = 0;
+            self.cursor_drawn = false;
+            self.fill_background(framebuffer);
+        }
+
+        if let Some(status_line) = self.status_line.take() {
+            self.set_status_line(&status_line);
+        }
+    }
+
+    /// Fills every pixel of the framebuffer with the background color
+    fn fill_background(&self, framebuffer: &FrameBufferDevice) {
+        let screen_info = &framebuffer.screen_info;
+        let scanrow = vec![screen_info.native_pixel(self.color_code.background.0 << 8); screen_info.width as usize];
+
+        for pixel_row in 0..screen_info.height as usize {
+            framebuffer.write(scanrow.as_ptr() as *const u8, scanrow.len() * 4, pixel_row * screen_info.pitch as usize);
         }
     }
 
+    /// Background color in the pixel format of the framebuffer
+    fn background_pixel(&self, framebuffer: &FrameBufferDevice) -> u32 {
+        framebuffer.screen_info.native_pixel(self.color_code.background.0 << 8)
+    }
+
     fn new_line(&mut self) {
-        if let Some(framebuffer_response) = FRAMEBUFFER_REQUEST.get_response() {
-            if let Some(framebuffer) = framebuffer_response.framebuffers().next() {
-                let pixel_offset = (1 * FONT_HEIGHT) * framebuffer.pitch() as usize;
-                let start_row = unsafe { framebuffer.addr().add(pixel_offset) };
-                unsafe { memmove(framebuffer.addr(), start_row, (framebuffer.width() * framebuffer.height() * 4 - framebuffer.width() * FONT_HEIGHT as u64 * 4) as usize); }
-            }
+        if let Some(framebuffer) = FB_DEVICES.lock().first() {
+            let screen_info = &framebuffer.screen_info;
+            let (_, cell_height) = cell_size(self.font_scale);
+            let pixel_offset = cell_height * screen_info.pitch as usize;
+            // Pinned rows are left in place, only the rows below them scroll
+            let pinned_offset = self.pinned_rows() * pixel_offset;
+            let scroll_top = (screen_info.address + pinned_offset) as *mut u8;
+            let start_row = unsafe { scroll_top.add(pixel_offset) };
+            unsafe { memmove(scroll_top, start_row, (screen_info.width * screen_info.height * 4 - screen_info.width * cell_height as u64 * 4) as usize - pinned_offset); }
         }
 
         self.clear_row(self.buffer_height - 1);
@@ -207,41 +382,79 @@ impl Writer {
 
 impl Write for Writer {
     fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        self.hide_cursor();
+
         for byte in s.bytes() {
             self.write_char(ScreenChar::new(byte, self.color_code));
         }
 
+        self.show_cursor();
+
         Ok(())
     }
 }
 
-fn draw_char(screen_char: ScreenChar, column: usize, row: usize) {
-    if let Some(framebuffer_response) = FRAMEBUFFER_REQUEST.get_response() {
-        if let Some(framebuffer) = framebuffer_response.framebuffers().next() {
-            let mask = [128, 64, 32, 16, 8, 4, 2, 1];
-            let glyph = FONT[screen_char.ascii_character as usize];
-
-            for (cy, glyph) in glyph.iter().enumerate().take(FONT_HEIGHT) {
-                let mut scanrow: [u32; FONT_WIDTH] = [0; FONT_WIDTH];
-                for (cx, mask) in mask.iter().enumerate().take(FONT_WIDTH) {
-                    let color = if glyph & mask == 0 {
-                        screen_char.color_code.background
-                    } else {
-                        screen_char.color_code.foreground
-                    };
-
-                    scanrow[cx] = color.0;
-                }
+fn draw_char(framebuffer: &FrameBufferDevice, screen_char: ScreenChar, column: usize, row: usize, font_scale: usize) {
+    let mask = [128, 64, 32, 16, 8, 4, 2, 1];
+    let glyph = FONT[screen_char.ascii_character as usize];
+    let (origin_x, origin_y) = cell_origin(column, row, font_scale);
+
+    for (cy, glyph) in glyph.iter().enumerate().take(FONT_HEIGHT) {
+        let mut scanrow: [u32; FONT_WIDTH * MAX_FONT_SCALE] = [0; FONT_WIDTH * MAX_FONT_SCALE];
+        for (cx, mask) in mask.iter().enumerate().take(FONT_WIDTH) {
+            let color = if glyph & mask == 0 {
+                screen_char.color_code.background
+            } else {
+                screen_char.color_code.foreground
+            };
+
+            scanrow[cx * font_scale..(cx + 1) * font_scale].fill(color.0);
+        }
 
-                let c = column * FONT_WIDTH;
-                let r = cy + row * FONT_HEIGHT;
-                let pixel_offset = r * framebuffer.pitch() as usize + c * 4;
-                FB_DEVICES.lock()[0].write(scanrow.as_ptr() as *const u8, scanrow.len() * 4, pixel_offset)
-            }
+        for scaled_row in 0..font_scale {
+            let r = origin_y + cy * font_scale + scaled_row;
+            let pixel_offset = r * framebuffer.screen_info.pitch as usize + origin_x * 4;
+            framebuffer.write(scanrow.as_ptr() as *const u8, FONT_WIDTH * font_scale * 4, pixel_offset)
         }
     }
 }
 
+/// Draws an underline cursor of the given color at the bottom of a cell
+fn draw_cursor(framebuffer: &FrameBufferDevice, color: Rgb8, column: usize, row: usize, font_scale: usize) {
+    let scanrow: [u32; FONT_WIDTH * MAX_FONT_SCALE] = [color.0; FONT_WIDTH * MAX_FONT_SCALE];
+    let (cell_width, cell_height) = cell_size(font_scale);
+    let (origin_x, origin_y) = cell_origin(column, row, font_scale);
+
+    for cy in (cell_height - CURSOR_HEIGHT * font_scale)..cell_height {
+        let pixel_offset = (origin_y + cy) * framebuffer.screen_info.pitch as usize + origin_x * 4;
+        framebuffer.write(scanrow.as_ptr() as *const u8, cell_width * 4, pixel_offset)
+    }
+}
+
+/// Size in pixels of a character cell at the given font scale
+fn cell_size(font_scale: usize) -> (usize, usize) {
+    (FONT_WIDTH * font_scale, FONT_HEIGHT * font_scale)
+}
+
+/// Pixel coordinates of the top left corner of a character cell
+fn cell_origin(column: usize, row: usize, font_scale: usize) -> (usize, usize) {
+    let (cell_width, cell_height) = cell_size(font_scale);
+
+    (column * cell_width, row * cell_height)
+}
+
+/// Number of columns and rows of characters fitting on a screen of the given size in pixels
+fn grid_size(pixel_width: usize, pixel_height: usize, font_scale: usize) -> (usize, usize) {
+    let (cell_width, cell_height) = cell_size(font_scale);
+
+    (pixel_width / cell_width, pixel_height / cell_height)
+}
+
+/// Whether the blinking cursor is in its visible phase at the given PIT tick count
+fn is_cursor_visible(ticks: u64) -> bool {
+    (ticks_to_ms(ticks) / CURSOR_BLINK_INTERVAL_MS) % 2 == 0
+}
+
 pub fn backspace() {
     let writer = Writer::instance();
     match writer {
@@ -313,68 +526,316 @@ macro_rules! ok {
     });
 }
 
+/// Runs `f` and returns what it printed with `print!` instead of displaying it
+pub fn capture_output(f: impl FnOnce()) -> String {
+    *CAPTURED_OUTPUT.lock() = Some(String::new());
+    CAPTURING.store(true, Ordering::Release);
+    f();
+    CAPTURING.store(false, Ordering::Release);
+
+    CAPTURED_OUTPUT.lock().take().unwrap_or_default()
+}
+
+/// Appends to the output of a running `capture_output`, returns whether the text was captured
+fn write_captured(args: core::fmt::Arguments) -> bool {
+    if !CAPTURING.load(Ordering::Acquire) {
+        return false;
+    }
+
+    match CAPTURED_OUTPUT.lock().as_mut() {
+        Some(output) => {
+            output.write_fmt(args).unwrap();
+            true
+        }
+        None => false,
+    }
+}
+
 #[doc(hidden)]
 pub fn _print(args: core::fmt::Arguments) {
+    if write_captured(args) {
+        return;
+    }
+
     let writer = Writer::instance();
     match writer {
         Some(writer) => {
             writer.lock().write_fmt(args).unwrap()
         }
         None => {
-            serial_print(args);
+            early_print(args);
         }
     }
 }
 
 #[doc(hidden)]
 pub fn _print_header(header_type: LogLevel) {
+    if write_captured(format_args!("[ {} ] ", header_type.label())) {
+        return;
+    }
+
     let writer = Writer::instance();
 
     match writer {
         Some(writer) => {
             let mut writer = writer.lock();
+            let background = writer.color_code.background;
 
             match header_type {
                 LogLevel::Info => {
                     writer.write_str("[ ").unwrap();
 
-                    writer.color_code = ColorCode::new(Rgb8(0x5b616b), Rgb8(0));
+                    writer.color_code = ColorCode::new(Rgb8(0x5b616b), background);
                     writer.write_str("INFO").unwrap();
-                    writer.color_code = DEFAULT_COLOR_CODE;
+                    writer.color_code = ColorCode::new(DEFAULT_COLOR_CODE.foreground, background);
 
                     writer.write_str(" ] ").unwrap();
                 }
                 LogLevel::Warning => {
                     writer.write_str("[ ").unwrap();
 
-                    writer.color_code = ColorCode::new(Rgb8(0xFFFF00), Rgb8(0));
+                    writer.color_code = ColorCode::new(Rgb8(0xFFFF00), background);
                     writer.write_str("WARN").unwrap();
-                    writer.color_code = DEFAULT_COLOR_CODE;
+                    writer.color_code = ColorCode::new(DEFAULT_COLOR_CODE.foreground, background);
 
                     writer.write_str(" ] ").unwrap();
                 }
                 LogLevel::Error => {
                     writer.write_str("[ ").unwrap();
 
-                    writer.color_code = ColorCode::new(Rgb8(0xFF4100), Rgb8(0));
+                    writer.color_code = ColorCode::new(Rgb8(0xFF4100), background);
                     writer.write_str("FAIL").unwrap();
-                    writer.color_code = DEFAULT_COLOR_CODE;
+                    writer.color_code = ColorCode::new(DEFAULT_COLOR_CODE.foreground, background);
 
                     writer.write_str(" ] ").unwrap();
                 }
                 LogLevel::Ok => {
                     writer.write_str("[ ").unwrap();
 
-                    writer.color_code = ColorCode::new(Rgb8(0x00FF00), Rgb8(0));
+                    writer.color_code = ColorCode::new(Rgb8(0x00FF00), background);
                     writer.write_str(" OK ").unwrap();
-                    writer.color_code = DEFAULT_COLOR_CODE;
+                    writer.color_code = ColorCode::new(DEFAULT_COLOR_CODE.foreground, background);
 
                     writer.write_str(" ] ").unwrap();
                 }
             }
         },
         None => {
-            serial_println!("buffer uninitialized");
+            early_print(format_args!("[ {} ] ", header_type.label()));
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::drivers::pit::TICK_FREQUENCY;
+    use crate::graphics::fonts::{FONT_HEIGHT, FONT_WIDTH};
+    use core::fmt::Write;
+    use crate::graphics::framebuffer_device::{capture_output, cell_origin, CONTROL_CHARACTER_PLACEHOLDER, grid_size, is_cursor_visible, Rgb8, Writer};
+    use alloc::string::String;
+    use alloc::vec;
+    use crate::drivers::fbdev::{FrameBufferDevice, FrameBufferScreenInfo};
+
+    #[test_case]
+    fn captured_output_contains_log_headers() {
+        // WHEN
+        let output = capture_output(|| {
+            crate::info!("loaded {} files", 3);
+            print!("done");
+        });
+
+        // THEN
+        assert_eq!(output, "[ INFO ] loaded 3 files\ndone");
+    }
+
+    #[test_case]
+    fn cursor_blinks_every_half_second() {
+        // GIVEN
+        let ticks_per_half_second = TICK_FREQUENCY as u64 / 2;
+
+        // THEN
+        assert!(is_cursor_visible(0));
+        assert!(is_cursor_visible(ticks_per_half_second - 1));
+        assert!(!is_cursor_visible(ticks_per_half_second));
+        assert!(!is_cursor_visible(2 * ticks_per_half_second - 1));
+        assert!(is_cursor_visible(2 * ticks_per_half_second));
+    }
+
+    #[test_case]
+    fn cursor_follows_column_position() {
+        // GIVEN
+        let mut writer = Writer::new(10 * FONT_WIDTH, 4 * FONT_HEIGHT);
+
+        // WHEN
+        let initial_position = writer.cursor_position();
+        writer.column_position = 3;
+        let moved_position = writer.cursor_position();
+        writer.column_position = 10;
+        let end_of_line_position = writer.cursor_position();
+
+        // THEN
+        assert_eq!(initial_position, (0, 3));
+        assert_eq!(moved_position, (3, 3));
+        assert_eq!(end_of_line_position, (9, 3));
+    }
+
+    #[test_case]
+    fn tab_advances_to_next_tab_stop() {
+        // GIVEN
+        let mut writer = Writer::new(20 * FONT_WIDTH, 4 * FONT_HEIGHT);
+
+        // WHEN
+        writer.write_str("ab\t").unwrap();
+        let after_first_tab = writer.column_position;
+        writer.write_str("\t").unwrap();
+        let after_second_tab = writer.column_position;
+        writer.write_str("\t").unwrap();
+        let after_last_tab = writer.column_position;
+
+        // THEN
+        assert_eq!(after_first_tab, 8);
+        assert_eq!(after_second_tab, 16);
+        assert_eq!(after_last_tab, 20);
+    }
+
+    #[test_case]
+    fn status_line_is_pinned_to_top_row() {
+        // GIVEN
+        let mut writer = Writer::new(6 * FONT_WIDTH, 4 * FONT_HEIGHT);
+
+        // WHEN
+        writer.set_status_line("boot 100%");
+
+        // THEN
+        let top_row: String = writer.screen_buffer[0].iter().map(|cell| cell.unwrap().ascii_character as char).collect();
+        assert_eq!(top_row, "boot 1");
+        assert_eq!(writer.pinned_rows(), 1);
+    }
+
+    #[test_case]
+    fn carriage_return_moves_to_first_column() {
+        // GIVEN
+        let mut writer = Writer::new(20 * FONT_WIDTH, 4 * FONT_HEIGHT);
+
+        // WHEN
+        writer.write_str("abc\r").unwrap();
+
+        // THEN
+        assert_eq!(writer.column_position, 0);
+        assert_eq!(writer.cursor_position(), (0, 3));
+        assert!(writer.screen_buffer[3][0].is_some());
+    }
+
+    #[test_case]
+    fn backspace_erases_previous_character() {
+        // GIVEN
+        let mut writer = Writer::new(20 * FONT_WIDTH, 4 * FONT_HEIGHT);
+
+        // WHEN
+        writer.write_str("abc\x08").unwrap();
+
+        // THEN
+        assert_eq!(writer.column_position, 2);
+        assert!(writer.screen_buffer[3][1].is_some());
+        assert!(writer.screen_buffer[3][2].is_none());
+    }
+
+    #[test_case]
+    fn backspace_on_first_column_does_nothing() {
+        // GIVEN
+        let mut writer = Writer::new(20 * FONT_WIDTH, 4 * FONT_HEIGHT);
+
+        // WHEN
+        writer.write_str("\x08").unwrap();
+
+        // THEN
+        assert_eq!(writer.column_position, 0);
+    }
+
+    #[test_case]
+    fn other_control_characters_use_placeholder() {
+        // GIVEN
+        let mut writer = Writer::new(20 * FONT_WIDTH, 4 * FONT_HEIGHT);
+
+        // WHEN
+        writer.write_str("\x01").unwrap();
+
+        // THEN
+        assert_eq!(writer.column_position, 1);
+        assert_eq!(writer.screen_buffer[3][0].map(|screen_char| screen_char.ascii_character), Some(CONTROL_CHARACTER_PLACEHOLDER));
+    }
+
+    #[test_case]
+    fn grid_shrinks_with_font_scale() {
+        // GIVEN
+        let (pixel_width, pixel_height) = (1920, 1080);
+
+        // WHEN
+        let grids = [1, 2, 3].map(|font_scale| grid_size(pixel_width, pixel_height, font_scale));
+
+        // THEN
+        assert_eq!(grids, [(240, 67), (120, 33), (80, 22)]);
+    }
+
+    #[test_case]
+    fn cell_origin_uses_scaled_cell_size() {
+        // WHEN
+        let unscaled = cell_origin(3, 2, 1);
+        let doubled = cell_origin(3, 2, 2);
+        let tripled = cell_origin(3, 2, 3);
+
+        // THEN
+        assert_eq!(unscaled, (3 * FONT_WIDTH, 2 * FONT_HEIGHT));
+        assert_eq!(doubled, (6 * FONT_WIDTH, 4 * FONT_HEIGHT));
+        assert_eq!(tripled, (9 * FONT_WIDTH, 6 * FONT_HEIGHT));
+    }
+
+    #[test_case]
+    fn font_scale_resizes_grid_and_cursor() {
+        // GIVEN
+        let mut writer = Writer::new(20 * FONT_WIDTH, 4 * FONT_HEIGHT);
+        writer.write_str("abc").unwrap();
+
+        // WHEN
+        writer.resize_grid(2);
+
+        // THEN
+        assert_eq!((writer.buffer_width, writer.buffer_height), (10, 2));
+        assert_eq!(writer.screen_buffer.len(), 2);
+        assert_eq!(writer.screen_buffer[1].len(), 10);
+        assert_eq!(writer.cursor_position(), (0, 1));
+    }
+
+    #[test_case]
+    fn font_scale_out_of_range_is_rejected() {
+        // GIVEN
+        let mut writer = Writer::new(20 * FONT_WIDTH, 4 * FONT_HEIGHT);
+
+        // WHEN
+        let too_small = writer.set_font_scale(0);
+        let too_large = writer.set_font_scale(5);
+
+        // THEN
+        assert!(too_small.is_err());
+        assert!(too_large.is_err());
+        assert_eq!(writer.font_scale, 1);
+    }
+
+    #[test_case]
+    fn clearing_fills_framebuffer_with_native_background_pixels() {
+        // GIVEN
+        let mut pixels = vec![0u32; 3 * 2 + 2];
+        let screen_info = FrameBufferScreenInfo {
+            address: pixels.as_mut_ptr() as usize, width: 3, height: 2, pitch: 4 * 4, bpp: 32, red_shift: 0, green_shift: 8, blue_shift: 16,
+        };
+        let framebuffer = FrameBufferDevice::new(screen_info, String::from("fbtest"));
+        let mut writer = Writer::new(20 * FONT_WIDTH, 4 * FONT_HEIGHT);
+        writer.color_code.background = Rgb8(0x123456);
+
+        // WHEN
+        writer.fill_background(&framebuffer);
+
+        // THEN
+        assert_eq!(pixels, [0x563412, 0x563412, 0x563412, 0, 0x563412, 0x563412, 0x563412, 0]);
+    }
+}
@@ -1,4 +1,8 @@
 #[macro_use]
 pub mod framebuffer_device;
 pub mod fonts;
-pub mod writer;
\ No newline at end of file
+pub mod writer;
+pub mod panic_screen;
+pub mod screenshot;
+pub mod boot_status;
+pub mod text_mode;
@@ -0,0 +1,215 @@
+use core::arch::asm;
+use core::fmt;
+use core::fmt::{Display, Formatter, Write};
+use core::mem::size_of;
+use core::panic::PanicInfo;
+use core::slice;
+use crate::serial_println;
+use crate::drivers::fbdev::FB_DEVICES;
+use crate::graphics::fonts::{FONT, FONT_HEIGHT, FONT_WIDTH};
+use crate::graphics::framebuffer_device::{Rgb8, Writer};
+use crate::serial::SERIAL1;
+
+const PANIC_FOREGROUND: Rgb8 = Rgb8(0xFFFFFF);
+const PANIC_BACKGROUND: Rgb8 = Rgb8(0x8B0000);
+
+/// Number of stack words included in the panic report
+const STACK_DUMP_WORDS: usize = 8;
+
+/// Registers captured at the start of the panic handler
+#[derive(Debug, Copy, Clone)]
+pub struct PanicContext {
+    pub instruction_pointer: usize,
+    pub stack_pointer: usize,
+    pub base_pointer: usize,
+}
+
+impl PanicContext {
+    #[inline(always)]
+    pub fn capture() -> Self {
+        let (instruction_pointer, stack_pointer, base_pointer): (usize, usize, usize);
+        unsafe {
+            asm!(
+                "lea {}, [rip]",
+                "mov {}, rsp",
+                "mov {}, rbp",
+                out(reg) instruction_pointer,
+                out(reg) stack_pointer,
+                out(reg) base_pointer,
+            );
+        }
+
+        Self { instruction_pointer, stack_pointer, base_pointer }
+    }
+
+    /// Words at the top of the stack when the context was captured
+    pub fn stack_words(&self) -> &'static [usize] {
+        unsafe { slice::from_raw_parts(self.stack_pointer as *const usize, STACK_DUMP_WORDS) }
+    }
+}
+
+/// Panic details, formatted the same way on the panic screen and on the serial port
+pub struct PanicReport<'a> {
+    pub message: &'a dyn Display,
+    pub context: PanicContext,
+    pub stack: &'a [usize],
+}
+
+impl Display for PanicReport<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        writeln!(f, "KERNEL PANIC")?;
+        writeln!(f, "panic: {}", self.message)?;
+        writeln!(f, "rip: 0x{:016X}  rsp: 0x{:016X}  rbp: 0x{:016X}",
+                 self.context.instruction_pointer, self.context.stack_pointer, self.context.base_pointer)?;
+        writeln!(f, "stack:")?;
+
+        for (index, word) in self.stack.iter().enumerate() {
+            writeln!(f, "  0x{:016X}: 0x{:016X}", self.context.stack_pointer + index * size_of::<usize>(), word)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Writes text straight to the write-combining framebuffer mapping. It neither allocates nor takes
+/// the `Writer` lock, which may have been held when the panic happened
+struct PanicScreen {
+    address: *mut u8,
+    pitch: usize,
+    columns: usize,
+    rows: usize,
+    column: usize,
+    row: usize,
+}
+
+impl PanicScreen {
+    fn draw_char(&self, character: u8) {
+        let glyph = FONT[character as usize];
+
+        for (glyph_row, glyph_bits) in glyph.iter().enumerate().take(FONT_HEIGHT) {
+            let pixel_row = self.row * FONT_HEIGHT + glyph_row;
+
+            for glyph_column in 0..FONT_WIDTH {
+                let color = if glyph_bits & (0x80 >> glyph_column) == 0 { PANIC_BACKGROUND } else { PANIC_FOREGROUND };
+                let pixel_offset = pixel_row * self.pitch + (self.column * FONT_WIDTH + glyph_column) * 4;
+
+                unsafe { (self.address.add(pixel_offset) as *mut u32).write_volatile(color.0) };
+            }
+        }
+    }
+}
+
+impl Write for PanicScreen {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for byte in s.bytes() {
+            if self.row >= self.rows {
+                break;
+            }
+
+            match byte {
+                b'\n' => {
+                    self.column = 0;
+                    self.row += 1;
+                },
+                _ => {
+                    if self.column >= self.columns {
+                        self.column = 0;
+                        self.row += 1;
+                        if self.row >= self.rows {
+                            break;
+                        }
+                    }
+
+                    self.draw_char(if byte.is_ascii() { byte } else { b'?' });
+                    self.column += 1;
+                },
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Reports a panic on the serial port and, once the console is up, on a full-screen panic screen
+#[inline(always)]
+pub fn report_panic(info: &PanicInfo) {
+    let context = PanicContext::capture();
+    unsafe { asm!("cli") };
+
+    let report = PanicReport { message: info, context, stack: context.stack_words() };
+
+    // Nothing else runs anymore, the serial port may have been held by the code that panicked
+    if SERIAL1.is_locked() {
+        unsafe { SERIAL1.force_unlock() };
+    }
+    serial_println!("{}", report);
+
+    if FB_DEVICES.is_locked() {
+        unsafe { FB_DEVICES.force_unlock() };
+    }
+
+    if Writer::instance().is_some() {
+        show_panic_screen(&report);
+    }
+}
+
+/// Fills the screen with the panic color and renders the report on it
+pub fn show_panic_screen(report: &PanicReport) {
+    let Some(screen_info) = FB_DEVICES.lock().first().map(|framebuffer| framebuffer.screen_info.clone()) else {
+        return;
+    };
+
+    let address = screen_info.address as *mut u8;
+    let pitch = screen_info.pitch as usize;
+    for pixel_row in 0..screen_info.height as usize {
+        for pixel_column in 0..screen_info.width as usize {
+            unsafe { (address.add(pixel_row * pitch + pixel_column * 4) as *mut u32).write_volatile(PANIC_BACKGROUND.0) };
+        }
+    }
+
+    let mut screen = PanicScreen {
+        address,
+        pitch,
+        columns: screen_info.width as usize / FONT_WIDTH,
+        rows: screen_info.height as usize / FONT_HEIGHT,
+        column: 0,
+        row: 0,
+    };
+    let _ = write!(screen, "{}", report);
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::format;
+    use crate::graphics::panic_screen::{PanicContext, PanicReport};
+
+    #[test_case]
+    fn panic_report_contains_structured_fields() {
+        // GIVEN
+        let context = PanicContext { instruction_pointer: 0xFFFFFFFF80012345, stack_pointer: 0xFFFF800000010000, base_pointer: 0 };
+        let stack = [0xDEAD, 0xBEEF];
+        let report = PanicReport { message: &"out of frames", context, stack: &stack };
+
+        // WHEN
+        let text = format!("{}", report);
+
+        // THEN
+        assert_eq!(text, "KERNEL PANIC\n\
+                          panic: out of frames\n\
+                          rip: 0xFFFFFFFF80012345  rsp: 0xFFFF800000010000  rbp: 0x0000000000000000\n\
+                          stack:\n  \
+                          0xFFFF800000010000: 0x000000000000DEAD\n  \
+                          0xFFFF800000010008: 0x000000000000BEEF\n");
+    }
+
+    #[test_case]
+    fn captured_context_points_into_the_stack() {
+        // WHEN
+        let context = PanicContext::capture();
+
+        // THEN
+        assert!(context.instruction_pointer >= crate::KERNEL_START_VMA_ADDRESS);
+        assert_eq!(context.stack_words().len(), 8);
+        assert_eq!(context.stack_pointer % 8, 0);
+    }
+}
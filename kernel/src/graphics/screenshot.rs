@@ -0,0 +1,132 @@
+// http://www.paulbourke.net/dataformats/tga/
+
+use alloc::vec;
+use alloc::vec::Vec;
+use crate::drivers::fbdev::FrameBufferDevice;
+use crate::fs::VfsNode;
+
+/// Size of the TGA header, no image id nor color map follow it
+const TGA_HEADER_SIZE: usize = 18;
+/// Uncompressed true-color image
+const TGA_IMAGE_TYPE_TRUE_COLOR: u8 = 2;
+/// Set in the image descriptor when the first row stored is the top of the image
+const TGA_TOP_LEFT_ORIGIN: u8 = 1 << 5;
+/// Screenshots are stored as 24-bit BGR pixels
+const SCREENSHOT_BPP: u8 = 24;
+/// Largest screenshot taken, it is built on the heap before being written to the ramfs
+const MAX_SCREENSHOT_SIZE: usize = 256 * 1024;
+
+/// Builds the header of an uncompressed true-color TGA image stored top row first
+pub fn tga_header(width: u16, height: u16, bpp: u8) -> [u8; TGA_HEADER_SIZE] {
+    let mut header = [0u8; TGA_HEADER_SIZE];
+    header[2] = TGA_IMAGE_TYPE_TRUE_COLOR;
+    header[12..14].copy_from_slice(&width.to_le_bytes());
+    header[14..16].copy_from_slice(&height.to_le_bytes());
+    header[16] = bpp;
+    header[17] = TGA_TOP_LEFT_ORIGIN;
+
+    header
+}
+
+/// Captures the contents of a 32 bpp framebuffer as a 24-bit TGA image. Framebuffers whose image
+/// would not fit in `MAX_SCREENSHOT_SIZE` are refused.
+pub fn capture(framebuffer: &FrameBufferDevice) -> Result<Vec<u8>, &'static str> {
+    let screen_info = &framebuffer.screen_info;
+    if screen_info.bpp != 32 {
+        return Err("screenshot: only 32 bpp framebuffers are supported");
+    }
+
+    let (width, height) = (screen_info.width as usize, screen_info.height as usize);
+    let image_size = TGA_HEADER_SIZE + width * height * (SCREENSHOT_BPP / 8) as usize;
+    if width > u16::MAX as usize || height > u16::MAX as usize || image_size > MAX_SCREENSHOT_SIZE {
+        return Err("screenshot: the framebuffer is too large to be captured");
+    }
+
+    let mut image = Vec::new();
+    image.try_reserve_exact(image_size).map_err(|_| "screenshot: not enough memory to capture the framebuffer")?;
+    image.extend_from_slice(&tga_header(width as u16, height as u16, SCREENSHOT_BPP));
+
+    let mut row = vec![0u32; width];
+    for y in 0..height {
+        framebuffer.read(row.as_mut_ptr() as *mut u8, width * 4, y * screen_info.pitch as usize);
+
+        for pixel in &row {
+            image.push((pixel >> screen_info.blue_shift) as u8);
+            image.push((pixel >> screen_info.green_shift) as u8);
+            image.push((pixel >> screen_info.red_shift) as u8);
+        }
+    }
+
+    Ok(image)
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::string::String;
+    use alloc::vec;
+    use crate::drivers::fbdev::{FrameBufferDevice, FrameBufferScreenInfo};
+    use crate::graphics::screenshot::{capture, tga_header};
+
+    #[test_case]
+    fn tga_header_encodes_dimensions_and_depth() {
+        // WHEN
+        let header = tga_header(640, 480, 24);
+
+        // THEN
+        assert_eq!(header, [0, 0, 2, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x80, 0x02, 0xE0, 0x01, 24, 0x20]);
+    }
+
+    #[test_case]
+    fn capture_converts_framebuffer_rows_to_bgr() {
+        // GIVEN
+        // Two rows of two pixels, padded to a pitch of three pixels
+        let mut pixels = vec![
+            0x0000_00FFu32, 0x0000_FF00, 0xDEAD_BEEF,
+            0x00FF_0000, 0x0012_3456, 0xDEAD_BEEF,
+        ];
+        let screen_info = FrameBufferScreenInfo {
+            address: pixels.as_mut_ptr() as usize, width: 2, height: 2, pitch: 3 * 4, bpp: 32, red_shift: 0, green_shift: 8, blue_shift: 16,
+        };
+        let framebuffer = FrameBufferDevice::new(screen_info, String::from("fbshot"));
+
+        // WHEN
+        let image = capture(&framebuffer).unwrap();
+
+        // THEN
+        assert_eq!(image[..18], tga_header(2, 2, 24));
+        assert_eq!(image[18..], [
+            0x00, 0x00, 0xFF, 0x00, 0xFF, 0x00,
+            0xFF, 0x00, 0x00, 0x12, 0x34, 0x56,
+        ]);
+    }
+
+    #[test_case]
+    fn capture_rejects_framebuffer_larger_than_a_tga_image() {
+        // GIVEN
+        let screen_info = FrameBufferScreenInfo {
+            address: 0, width: 70000, height: 1, pitch: 70000 * 4, bpp: 32, red_shift: 16, green_shift: 8, blue_shift: 0,
+        };
+        let framebuffer = FrameBufferDevice::new(screen_info, String::from("fbhuge"));
+
+        // WHEN
+        let result = capture(&framebuffer);
+
+        // THEN
+        assert_eq!(result, Err("screenshot: the framebuffer is too large to be captured"));
+    }
+
+    #[test_case]
+    fn capture_rejects_framebuffer_larger_than_the_limit() {
+        // GIVEN
+        let screen_info = FrameBufferScreenInfo {
+            address: 0, width: 1024, height: 768, pitch: 1024 * 4, bpp: 32, red_shift: 16, green_shift: 8, blue_shift: 0,
+        };
+        let framebuffer = FrameBufferDevice::new(screen_info, String::from("fbhuge"));
+
+        // WHEN
+        let result = capture(&framebuffer);
+
+        // THEN
+        assert_eq!(result, Err("screenshot: the framebuffer is too large to be captured"));
+    }
+}
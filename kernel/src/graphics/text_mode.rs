@@ -0,0 +1,157 @@
+// https://wiki.osdev.org/Printing_To_Screen
+
+use core::fmt;
+use core::fmt::Write;
+use spin::Mutex;
+use crate::HHDM_OFFSET;
+use crate::memory::PhysicalAddress;
+use crate::serial::serial_print;
+
+/// Physical address of the legacy VGA text buffer
+const VGA_BUFFER_ADDRESS: PhysicalAddress = 0xB8000;
+const VGA_WIDTH: usize = 80;
+const VGA_HEIGHT: usize = 25;
+/// Glyph drawn in place of bytes that are not printable ASCII (■ in code page 437)
+const PLACEHOLDER_CHARACTER: u8 = 0xFE;
+
+/// Writer to the VGA text buffer, created on the first early message
+static TEXT_MODE_WRITER: Mutex<Option<TextModeWriter>> = Mutex::new(None);
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+#[repr(u8)]
+pub enum VgaColor {
+    Black = 0,
+    Blue = 1,
+    Green = 2,
+    Cyan = 3,
+    Red = 4,
+    Magenta = 5,
+    Brown = 6,
+    LightGray = 7,
+    DarkGray = 8,
+    LightBlue = 9,
+    LightGreen = 10,
+    LightCyan = 11,
+    LightRed = 12,
+    Pink = 13,
+    Yellow = 14,
+    White = 15,
+}
+
+/// Attribute byte of a cell, the background color is in the upper nibble
+pub const fn attribute(foreground: VgaColor, background: VgaColor) -> u8 {
+    (background as u8) << 4 | foreground as u8
+}
+
+/// Cell of the text buffer, the character in the low byte and its attribute in the high byte
+pub const fn encode_cell(character: u8, foreground: VgaColor, background: VgaColor) -> u16 {
+    (attribute(foreground, background) as u16) << 8 | character as u16
+}
+
+/// Console on the 80x25 VGA text buffer, writing on the bottom row and scrolling up
+pub struct TextModeWriter {
+    buffer: *mut u16,
+    column_position: usize,
+    foreground: VgaColor,
+    background: VgaColor,
+}
+
+// The buffer is only accessed through the writer, which is behind a lock
+unsafe impl Send for TextModeWriter {}
+
+impl TextModeWriter {
+    /// # Safety
+    /// The buffer must be valid for reads and writes of `VGA_WIDTH * VGA_HEIGHT` cells
+    pub unsafe fn new(buffer: *mut u16) -> Self {
+        Self { buffer, column_position: 0, foreground: VgaColor::LightGray, background: VgaColor::Black }
+    }
+
+    fn write_byte(&mut self, byte: u8) {
+        match byte {
+            b'\n' => self.new_line(),
+            b'\r' => self.column_position = 0,
+            byte => {
+                if self.column_position >= VGA_WIDTH {
+                    self.new_line();
+                }
+
+                let character = if byte.is_ascii_graphic() || byte == b' ' { byte } else { PLACEHOLDER_CHARACTER };
+                self.write_cell(VGA_HEIGHT - 1, self.column_position, encode_cell(character, self.foreground, self.background));
+                self.column_position += 1;
+            }
+        }
+    }
+
+    fn new_line(&mut self) {
+        for row in 1..VGA_HEIGHT {
+            for col in 0..VGA_WIDTH {
+                self.write_cell(row - 1, col, self.read_cell(row, col));
+            }
+        }
+
+        let blank = encode_cell(b' ', self.foreground, self.background);
+        (0..VGA_WIDTH).for_each(|col| self.write_cell(VGA_HEIGHT - 1, col, blank));
+        self.column_position = 0;
+    }
+
+    fn read_cell(&self, row: usize, col: usize) -> u16 {
+        unsafe { self.buffer.add(row * VGA_WIDTH + col).read_volatile() }
+    }
+
+    fn write_cell(&mut self, row: usize, col: usize, cell: u16) {
+        unsafe { self.buffer.add(row * VGA_WIDTH + col).write_volatile(cell) }
+    }
+}
+
+impl Write for TextModeWriter {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        s.bytes().for_each(|byte| self.write_byte(byte));
+
+        Ok(())
+    }
+}
+
+/// Prints the messages sent before the framebuffer console is up. They always go to the serial
+/// port, and with the `vga-text-console` feature to the VGA text buffer as well.
+pub fn early_print(args: fmt::Arguments) {
+    serial_print(args);
+
+    if cfg!(feature = "vga-text-console") {
+        let mut text_mode_writer = TEXT_MODE_WRITER.lock();
+        let text_mode_writer = text_mode_writer.get_or_insert_with(|| unsafe { TextModeWriter::new((*HHDM_OFFSET + VGA_BUFFER_ADDRESS) as *mut u16) });
+        text_mode_writer.write_fmt(args).unwrap();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec;
+    use core::fmt::Write;
+    use crate::graphics::text_mode::{attribute, encode_cell, TextModeWriter, VGA_HEIGHT, VGA_WIDTH, VgaColor};
+
+    #[test_case]
+    fn cell_holds_character_and_attribute() {
+        // WHEN
+        let cell = encode_cell(b'A', VgaColor::Yellow, VgaColor::Blue);
+        let default_attribute = attribute(VgaColor::LightGray, VgaColor::Black);
+
+        // THEN
+        assert_eq!(cell, 0x1E41);
+        assert_eq!(default_attribute, 0x07);
+    }
+
+    #[test_case]
+    fn writer_scrolls_previous_line_up() {
+        // GIVEN
+        let mut buffer = vec![0u16; VGA_WIDTH * VGA_HEIGHT];
+        let mut writer = unsafe { TextModeWriter::new(buffer.as_mut_ptr()) };
+
+        // WHEN
+        writer.write_str("ok\nA\x01").unwrap();
+
+        // THEN
+        let last_row = (VGA_HEIGHT - 1) * VGA_WIDTH;
+        assert_eq!(buffer[last_row - VGA_WIDTH..last_row - VGA_WIDTH + 2], [0x076F, 0x076B]);
+        assert_eq!(buffer[last_row..last_row + 3], [0x0741, 0x07FE, 0x0720]);
+    }
+}
@@ -50,11 +50,8 @@ impl FramebufferWriter {
 
     /// This function is unsafe because it should only be called once the heap is set up
     pub unsafe fn init() -> Result<(), &'static str> {
-        if FB_DEVICES.lock().len() <= 0 {
-            return Err("no framebuffer found");
-        }
-
-        let framebuffer_device = &FB_DEVICES.lock()[0];
+        let devices = FB_DEVICES.lock();
+        let framebuffer_device = devices.first().ok_or("no framebuffer found")?;
 
         let buffer_width = framebuffer_device.screen_info.width as usize;
         let buffer_height = framebuffer_device.screen_info.height as usize;
@@ -101,7 +98,8 @@ impl FramebufferWriter {
     }
 
     fn swap_buffers(&self) {
-        let framebuffer_device = &FB_DEVICES.lock()[0];
-        framebuffer_device.write(self.back_buffer.as_ptr() as *const u8, self.buffer_width * self.buffer_height, 0);
+        if let Some(framebuffer_device) = FB_DEVICES.lock().first() {
+            framebuffer_device.write(self.back_buffer.as_ptr() as *const u8, self.buffer_width * self.buffer_height, 0);
+        }
     }
 }
\ No newline at end of file
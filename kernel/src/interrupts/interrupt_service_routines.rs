@@ -1,10 +1,24 @@
 use core::arch::asm;
 use core::fmt;
-use core::fmt::Formatter;
-use crate::drivers::ps2::keyboard::{PS2Keyboard};
-use crate::interrupts::{MASTER_PIC_COMMAND_PORT, PIC_EOI};
+use core::sync::atomic::{AtomicU64, Ordering};
+use core::fmt::{Display, Formatter};
+use bitflags::bitflags;
+use crate::arch::x86_64::registers::cr2;
+use crate::drivers::{pit, watchdog};
+use crate::drivers::ps2::keyboard::{DataPortSource, ScancodeSource};
+use crate::graphics::framebuffer_device::Writer;
+use crate::interrupts::InterruptController;
+use crate::interrupts::local_apic::record_spurious_interrupt;
+use crate::memory::stack::is_kernel_stack_guard_address;
 use crate::task::keyboard::add_scancode;
 
+static BREAKPOINTS: AtomicU64 = AtomicU64::new(0);
+
+/// Number of breakpoint interrupts handled since boot
+pub fn breakpoint_count() -> u64 {
+    BREAKPOINTS.load(Ordering::Relaxed)
+}
+
 pub type HandlerFuncWithoutErrCode = extern "x86-interrupt" fn(InterruptStackFrame);
 pub type HandlerFuncWithErrCode = extern "x86-interrupt" fn(InterruptStackFrame, error_code: u64);
 
@@ -29,6 +43,73 @@ impl fmt::Debug for InterruptStackFrame {
     }
 }
 
+bitflags! {
+    /// Error code pushed by the CPU on a page fault
+    #[derive(Copy, Clone, Debug, Eq, PartialEq)]
+    pub struct PageFaultErrorCode: u64 {
+        const PROTECTION_VIOLATION = 1 << 0;
+        const WRITE = 1 << 1;
+        const USER_MODE = 1 << 2;
+        /// A paging structure entry has a reserved bit set
+        const RESERVED_BIT = 1 << 3;
+        const INSTRUCTION_FETCH = 1 << 4;
+        const PROTECTION_KEY = 1 << 5;
+        const SHADOW_STACK = 1 << 6;
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum PageFaultKind {
+    /// The kernel wrote to a present page that is mapped read-only. Since `CR0.WP` is set, this
+    /// usually means an accidental write to `.rodata` or to a page table
+    KernelWriteProtect,
+    /// The accessed page is not mapped
+    NotPresent,
+    /// A page table entry on the way to the page has a reserved bit set, the tables are corrupted
+    ReservedBit,
+    Other,
+}
+
+impl PageFaultKind {
+    pub fn from_error_code(error_code: PageFaultErrorCode) -> Self {
+        if !error_code.contains(PageFaultErrorCode::PROTECTION_VIOLATION) {
+            PageFaultKind::NotPresent
+        }
+        else if error_code.contains(PageFaultErrorCode::RESERVED_BIT) {
+            PageFaultKind::ReservedBit
+        }
+        else if error_code.contains(PageFaultErrorCode::WRITE) && !error_code.contains(PageFaultErrorCode::USER_MODE) {
+            PageFaultKind::KernelWriteProtect
+        }
+        else {
+            PageFaultKind::Other
+        }
+    }
+}
+
+/// Description of a page fault as reported by the page fault handler
+struct PageFaultReport {
+    error_code: PageFaultErrorCode,
+    faulting_address: usize,
+    instruction_pointer: u64,
+    /// Whether the faulting address is in the guard page below the kernel stack
+    stack_overflow: bool,
+}
+
+impl Display for PageFaultReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match PageFaultKind::from_error_code(self.error_code) {
+            _ if self.stack_overflow => write!(f, "kernel stack overflow into guard page at 0x{:X}", self.faulting_address)?,
+            PageFaultKind::KernelWriteProtect => write!(f, "kernel write to read-only page 0x{:X}", self.faulting_address)?,
+            PageFaultKind::NotPresent => write!(f, "access to unmapped page 0x{:X}", self.faulting_address)?,
+            PageFaultKind::ReservedBit => write!(f, "reserved bit set in a page table entry of page 0x{:X}", self.faulting_address)?,
+            PageFaultKind::Other => write!(f, "access to page 0x{:X}", self.faulting_address)?,
+        }
+
+        write!(f, " from instruction 0x{:X} (error code 0x{:X})", self.instruction_pointer, self.error_code.bits())
+    }
+}
+
 pub extern "x86-interrupt" fn division_error_handler(stack_frame: InterruptStackFrame) {
     error!("Caught a division error interrupt!");
     println!("{:#?}", stack_frame);
@@ -48,6 +129,7 @@ pub extern "x86-interrupt" fn non_maskable_interrupt_handler(stack_frame: Interr
 }
 
 pub extern "x86-interrupt" fn breakpoint_handler(stack_frame: InterruptStackFrame) {
+    BREAKPOINTS.fetch_add(1, Ordering::Relaxed);
     error!("Caught a breakpoint interrupt!");
     println!("{:#?}", stack_frame);
 }
@@ -107,7 +189,14 @@ pub extern "x86-interrupt" fn general_protection_fault_handler(stack_frame: Inte
 }
 
 pub extern "x86-interrupt" fn page_fault_handler(stack_frame: InterruptStackFrame, error_code: u64) {
-    error!("Caught a page fault interrupt! Error code 0x{:X}", error_code);
+    let report = PageFaultReport {
+        error_code: PageFaultErrorCode::from_bits_retain(error_code),
+        faulting_address: cr2(),
+        instruction_pointer: stack_frame.instruction_pointer,
+        stack_overflow: is_kernel_stack_guard_address(cr2()),
+    };
+
+    error!("Caught a page fault interrupt! {}", report);
     println!("{:#?}", stack_frame);
     unsafe { asm!("hlt;"); };
 }
@@ -157,44 +246,182 @@ pub extern "x86-interrupt" fn security_exception_handler(stack_frame: InterruptS
     println!("{:#?}", stack_frame);
 }
 
-pub extern "x86-interrupt" fn irq0_handler(stack_frame: InterruptStackFrame) {
-    println!("Caught IRQ0!");
-    println!("{:#?}", stack_frame);
+pub extern "x86-interrupt" fn irq0_handler(_stack_frame: InterruptStackFrame) {
+    let ticks = pit::tick();
+    watchdog::check_sections(ticks);
+
+    // The interrupted code might be holding the writer, in which case the cursor is updated on a later tick
+    if let Some(mut writer) = Writer::instance().and_then(|writer| writer.try_lock()) {
+        writer.update_cursor(ticks);
+    }
+
+    InterruptController::send_eoi(0);
 }
 
 pub extern "x86-interrupt" fn irq1_handler() {
-    let scancode = PS2Keyboard::interrupt_read_byte();
-    add_scancode(scancode);
+    if let Some(scancode) = DataPortSource.next_scancode() {
+        add_scancode(scancode);
+    }
 
-    MASTER_PIC_COMMAND_PORT.lock().write(PIC_EOI).unwrap();
+    InterruptController::send_eoi(1);
 }
 
 pub extern "x86-interrupt" fn irq2_handler(stack_frame: InterruptStackFrame) {
     println!("Caught IRQ2!");
     println!("{:#?}", stack_frame);
+
+    InterruptController::send_eoi(2);
 }
 
 pub extern "x86-interrupt" fn irq3_handler(stack_frame: InterruptStackFrame) {
     println!("Caught IRQ3!");
     println!("{:#?}", stack_frame);
+
+    InterruptController::send_eoi(3);
 }
 
 pub extern "x86-interrupt" fn irq4_handler(stack_frame: InterruptStackFrame) {
     println!("Caught IRQ4!");
     println!("{:#?}", stack_frame);
+
+    InterruptController::send_eoi(4);
 }
 
 pub extern "x86-interrupt" fn irq5_handler(stack_frame: InterruptStackFrame) {
     println!("Caught IRQ5!");
     println!("{:#?}", stack_frame);
+
+    InterruptController::send_eoi(5);
 }
 
 pub extern "x86-interrupt" fn irq6_handler(stack_frame: InterruptStackFrame) {
     println!("Caught IRQ6!");
     println!("{:#?}", stack_frame);
+
+    InterruptController::send_eoi(6);
 }
 
 pub extern "x86-interrupt" fn irq7_handler(stack_frame: InterruptStackFrame) {
+    // Spurious IRQ, the master PIC does not expect an EOI
+    if !InterruptController::is_in_service(7) {
+        return;
+    }
+
     println!("Caught IRQ7!");
     println!("{:#?}", stack_frame);
-}
\ No newline at end of file
+
+    InterruptController::send_eoi(7);
+}
+
+pub extern "x86-interrupt" fn irq15_handler(stack_frame: InterruptStackFrame) {
+    // Spurious IRQ, only the master PIC saw an interrupt on its cascade line
+    if !InterruptController::is_in_service(15) {
+        InterruptController::send_eoi(2);
+        return;
+    }
+
+    println!("Caught IRQ15!");
+    println!("{:#?}", stack_frame);
+
+    InterruptController::send_eoi(15);
+}
+
+/// Spurious interrupts are not in service in the local APIC, so no EOI is sent
+pub extern "x86-interrupt" fn spurious_interrupt_handler(_stack_frame: InterruptStackFrame) {
+    record_spurious_interrupt();
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::format;
+    use crate::interrupts::interrupt_service_routines::{PageFaultErrorCode, PageFaultKind, PageFaultReport};
+
+    #[test_case]
+    fn kernel_write_to_present_page_is_write_protect_violation() {
+        // GIVEN
+        let error_code = PageFaultErrorCode::PROTECTION_VIOLATION | PageFaultErrorCode::WRITE;
+
+        // WHEN
+        let kind = PageFaultKind::from_error_code(error_code);
+
+        // THEN
+        assert_eq!(kind, PageFaultKind::KernelWriteProtect);
+    }
+
+    #[test_case]
+    fn fault_on_missing_page_is_not_present() {
+        // GIVEN
+        let error_code = PageFaultErrorCode::WRITE;
+
+        // WHEN
+        let kind = PageFaultKind::from_error_code(error_code);
+
+        // THEN
+        assert_eq!(kind, PageFaultKind::NotPresent);
+    }
+
+    #[test_case]
+    fn user_write_to_present_page_is_not_kernel_write_protect() {
+        // GIVEN
+        let error_code = PageFaultErrorCode::PROTECTION_VIOLATION | PageFaultErrorCode::WRITE | PageFaultErrorCode::USER_MODE;
+
+        // WHEN
+        let kind = PageFaultKind::from_error_code(error_code);
+
+        // THEN
+        assert_eq!(kind, PageFaultKind::Other);
+    }
+
+    #[test_case]
+    fn reserved_bit_fault_is_reported_as_such() {
+        // GIVEN
+        let report = PageFaultReport {
+            error_code: PageFaultErrorCode::PROTECTION_VIOLATION | PageFaultErrorCode::WRITE | PageFaultErrorCode::RESERVED_BIT,
+            faulting_address: 0xFFFFC90000001000,
+            instruction_pointer: 0xFFFFFFFF80012345,
+            stack_overflow: false,
+        };
+
+        // WHEN
+        let kind = PageFaultKind::from_error_code(report.error_code);
+        let message = format!("{}", report);
+
+        // THEN
+        assert_eq!(kind, PageFaultKind::ReservedBit);
+        assert_eq!(message, "reserved bit set in a page table entry of page 0xFFFFC90000001000 from instruction 0xFFFFFFFF80012345 (error code 0xB)");
+    }
+
+    #[test_case]
+    fn write_protect_report_names_target_and_instruction() {
+        // GIVEN
+        let report = PageFaultReport {
+            error_code: PageFaultErrorCode::PROTECTION_VIOLATION | PageFaultErrorCode::WRITE,
+            faulting_address: 0xFFFFFFFF80201000,
+            instruction_pointer: 0xFFFFFFFF80012345,
+            stack_overflow: false,
+        };
+
+        // WHEN
+        let message = format!("{}", report);
+
+        // THEN
+        assert_eq!(message, "kernel write to read-only page 0xFFFFFFFF80201000 from instruction 0xFFFFFFFF80012345 (error code 0x3)");
+    }
+
+    #[test_case]
+    fn guard_page_fault_is_reported_as_stack_overflow() {
+        // GIVEN
+        let report = PageFaultReport {
+            error_code: PageFaultErrorCode::WRITE,
+            faulting_address: 0xFFFF900000000FF8,
+            instruction_pointer: 0xFFFFFFFF80012345,
+            stack_overflow: true,
+        };
+
+        // WHEN
+        let message = format!("{}", report);
+
+        // THEN
+        assert_eq!(message, "kernel stack overflow into guard page at 0xFFFF900000000FF8 from instruction 0xFFFFFFFF80012345 (error code 0x2)");
+    }
+}
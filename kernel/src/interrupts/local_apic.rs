@@ -0,0 +1,76 @@
+// https://www.intel.com/content/www/us/en/developer/articles/technical/intel-sdm.html (Vol. 3A, 11.9)
+
+use core::sync::atomic::{AtomicU64, Ordering};
+use crate::arch::x86_64::registers::{IA32_APIC_BASE, read_msr, write_msr};
+use crate::memory::{MemoryManager, PAGE_SIZE, PhysicalAddress};
+use crate::memory::virtual_memory::paging::entry::EntryFlags;
+
+/// Vector the local APIC delivers spurious interrupts to
+pub const SPURIOUS_INTERRUPT_VECTOR: u8 = 0xFF;
+
+/// Offset of the spurious interrupt vector register from the local APIC base
+const SPURIOUS_INTERRUPT_VECTOR_REGISTER: usize = 0xF0;
+/// Bit of the spurious interrupt vector register software enabling the local APIC
+const APIC_SOFTWARE_ENABLE: u32 = 1 << 8;
+
+/// Bit of `IA32_APIC_BASE` globally enabling the local APIC
+const APIC_GLOBAL_ENABLE: u64 = 1 << 11;
+const APIC_BASE_ADDRESS_MASK: u64 = 0x000F_FFFF_FFFF_F000;
+
+static SPURIOUS_INTERRUPTS: AtomicU64 = AtomicU64::new(0);
+
+/// Counts a spurious interrupt, called by the handler registered at `SPURIOUS_INTERRUPT_VECTOR`
+pub fn record_spurious_interrupt() {
+    SPURIOUS_INTERRUPTS.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Number of spurious interrupts received since boot
+pub fn spurious_interrupt_count() -> u64 {
+    SPURIOUS_INTERRUPTS.load(Ordering::Relaxed)
+}
+
+/// Value of the spurious interrupt vector register delivering spurious interrupts to the given
+/// vector with the local APIC enabled
+pub fn spurious_interrupt_vector_register(vector: u8) -> u32 {
+    vector as u32 | APIC_SOFTWARE_ENABLE
+}
+
+/// Enables the local APIC of the current processor, with spurious interrupts delivered to the given
+/// vector. The handler of that vector must not send an EOI.
+pub fn enable(spurious_vector: u8) -> Result<(), &'static str> {
+    let apic_base = unsafe { read_msr(IA32_APIC_BASE) };
+    unsafe { write_msr(IA32_APIC_BASE, apic_base | APIC_GLOBAL_ENABLE) };
+
+    let physical_address = (apic_base & APIC_BASE_ADDRESS_MASK) as PhysicalAddress;
+    let registers = MemoryManager::map_physical(physical_address, PAGE_SIZE, EntryFlags::WRITABLE | EntryFlags::NO_CACHE | EntryFlags::NO_EXECUTE)?;
+
+    let sivr = (registers + SPURIOUS_INTERRUPT_VECTOR_REGISTER) as *mut u32;
+    unsafe { sivr.write_volatile(spurious_interrupt_vector_register(spurious_vector)) };
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::interrupts::local_apic::{spurious_interrupt_vector_register, SPURIOUS_INTERRUPT_VECTOR};
+
+    #[test_case]
+    fn sivr_holds_vector_and_enable_bit() {
+        // WHEN
+        let value = spurious_interrupt_vector_register(SPURIOUS_INTERRUPT_VECTOR);
+
+        // THEN
+        assert_eq!(value, 0x1FF);
+    }
+
+    #[test_case]
+    fn sivr_vector_stays_in_low_byte() {
+        // WHEN
+        let value = spurious_interrupt_vector_register(0x3F);
+
+        // THEN
+        assert_eq!(value & 0xFF, 0x3F);
+        assert_ne!(value & 1 << 8, 0);
+        assert_eq!(value & !0x1FF, 0);
+    }
+}
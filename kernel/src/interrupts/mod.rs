@@ -2,14 +2,16 @@ use core::arch::asm;
 use core::sync::atomic::{compiler_fence, Ordering};
 use spin::Mutex;
 use crate::arch::x86_64::port_manager::{io_wait, Port};
-use crate::arch::x86_64::port_manager::ReadWriteStatus::{ReadWrite, WriteOnly};
+use crate::arch::x86_64::port_manager::ReadWriteStatus::ReadWrite;
 use crate::interrupts::interrupt_descriptor_table::*;
 use crate::interrupts::interrupt_service_routines::*;
+pub use crate::interrupts::interrupt_service_routines::breakpoint_count;
 use crate::memory::VirtualAddress;
 
 mod interrupt_descriptor_table;
 mod interrupt_service_routines;
 pub mod global_descriptor_table;
+pub mod local_apic;
 
 const MASTER_PIC_COMMAND_ADDRESS: u16 = 0x20;
 const MASTER_PIC_DATA_ADDRESS: u16 = 0x21;
@@ -17,11 +19,15 @@ const SLAVE_PIC_COMMAND_ADDRESS: u16 = 0xA0;
 const SLAVE_PIC_DATA_ADDRESS: u16 = 0xA1;
 
 const PIC_EOI: u8 = 0x20;
+/// OCW3 making the next read of the command port return the in service register
+const PIC_READ_ISR: u8 = 0x0B;
+/// The PIC registers are 8 bits wide
+const PIC_PORT_WIDTH: usize = 1;
 
-static MASTER_PIC_COMMAND_PORT: Mutex<Port<u8>> = Mutex::new(Port::new(MASTER_PIC_COMMAND_ADDRESS, WriteOnly));
-static MASTER_PIC_DATA_PORT: Mutex<Port<u8>> = Mutex::new(Port::new(MASTER_PIC_DATA_ADDRESS, ReadWrite));
-static SLAVE_PIC_COMMAND_PORT: Mutex<Port<u8>> = Mutex::new(Port::new(SLAVE_PIC_COMMAND_ADDRESS, WriteOnly));
-static SLAVE_PIC_DATA_PORT: Mutex<Port<u8>> = Mutex::new(Port::new(SLAVE_PIC_DATA_ADDRESS, ReadWrite));
+static MASTER_PIC_COMMAND_PORT: Mutex<Port<u8>> = Mutex::new(Port::with_width(MASTER_PIC_COMMAND_ADDRESS, ReadWrite, PIC_PORT_WIDTH));
+static MASTER_PIC_DATA_PORT: Mutex<Port<u8>> = Mutex::new(Port::with_width(MASTER_PIC_DATA_ADDRESS, ReadWrite, PIC_PORT_WIDTH));
+static SLAVE_PIC_COMMAND_PORT: Mutex<Port<u8>> = Mutex::new(Port::with_width(SLAVE_PIC_COMMAND_ADDRESS, ReadWrite, PIC_PORT_WIDTH));
+static SLAVE_PIC_DATA_PORT: Mutex<Port<u8>> = Mutex::new(Port::with_width(SLAVE_PIC_DATA_ADDRESS, ReadWrite, PIC_PORT_WIDTH));
 
 pub static INTERRUPT_CONTROLLER: Mutex<InterruptController> = Mutex::new(InterruptController {
     master_pic_mask: 0xFF,
@@ -60,6 +66,12 @@ impl InterruptController {
         Self::enable_external_interrupts()
     }
 
+    pub fn enable_timer_interrupts(&mut self) {
+        info!("pit: enabling timer interrupts");
+        self.master_pic_mask &= 0b11111110;
+        Self::set_irq_masks(self.master_pic_mask, self.slave_pic_mask);
+    }
+
     pub fn enable_keyboard_interrupts(&mut self) {
         info!("ps2: enabling keyboard input");
         self.master_pic_mask &= 0b11111101;
@@ -109,6 +121,9 @@ impl InterruptController {
         IDT.set_irq_entry(0x25, GateDescriptor::new(irq5_handler as VirtualAddress));
         IDT.set_irq_entry(0x26, GateDescriptor::new(irq6_handler as VirtualAddress));
         IDT.set_irq_entry(0x27, GateDescriptor::new(irq7_handler as VirtualAddress));
+        IDT.set_irq_entry(0x2F, GateDescriptor::new(irq15_handler as VirtualAddress));
+
+        IDT.set_irq_entry(local_apic::SPURIOUS_INTERRUPT_VECTOR as usize, GateDescriptor::new(spurious_interrupt_handler as VirtualAddress));
     }
 
     fn remap_pic(offset_one: u8, offset_two: u8) {
@@ -152,6 +167,43 @@ impl InterruptController {
         SLAVE_PIC_DATA_PORT.lock().write(slave_pic_mask).unwrap();
     }
 
+    /// Signals the end of the given IRQ to the PICs. IRQs of the slave PIC go through the cascade
+    /// line of the master, so both have to be acknowledged for them.
+    pub fn send_eoi(irq: u8) {
+        for &address in Self::eoi_command_ports(irq) {
+            let port = match address {
+                SLAVE_PIC_COMMAND_ADDRESS => &SLAVE_PIC_COMMAND_PORT,
+                _ => &MASTER_PIC_COMMAND_PORT,
+            };
+
+            port.lock().write(PIC_EOI).unwrap();
+        }
+    }
+
+    /// Command ports receiving the EOI of the IRQ, in the order they are written to
+    fn eoi_command_ports(irq: u8) -> &'static [u16] {
+        match irq {
+            8.. => &[SLAVE_PIC_COMMAND_ADDRESS, MASTER_PIC_COMMAND_ADDRESS],
+            _ => &[MASTER_PIC_COMMAND_ADDRESS],
+        }
+    }
+
+    /// Whether the PIC owning the IRQ has it in service. The PICs raise IRQ 7 and IRQ 15 without
+    /// setting their in service bit when the interrupting line drops before the CPU acknowledges it,
+    /// such spurious IRQs must not be acknowledged.
+    pub fn is_in_service(irq: u8) -> bool {
+        let port = match irq {
+            8.. => &SLAVE_PIC_COMMAND_PORT,
+            _ => &MASTER_PIC_COMMAND_PORT,
+        };
+
+        let mut port = port.lock();
+        port.write(PIC_READ_ISR).unwrap();
+        let in_service_register = port.read().unwrap();
+
+        is_irq_set(in_service_register, irq)
+    }
+
     fn set_irq_masks(master_mask: u8, slave_mask: u8) {
         MASTER_PIC_DATA_PORT.lock().write(master_mask).unwrap();
         SLAVE_PIC_DATA_PORT.lock().write(slave_mask).unwrap();
@@ -172,3 +224,45 @@ impl InterruptController {
         unsafe { asm!("cli"); }
     }
 }
+
+/// Whether the bit of the IRQ is set in a register of the PIC owning it
+fn is_irq_set(register: u8, irq: u8) -> bool {
+    register & (1 << (irq % 8)) != 0
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::interrupts::{InterruptController, is_irq_set, MASTER_PIC_COMMAND_ADDRESS, SLAVE_PIC_COMMAND_ADDRESS};
+
+    #[test_case]
+    fn master_irq_eoi_goes_to_master_only() {
+        // WHEN
+        let ports = [0, 1, 7].map(InterruptController::eoi_command_ports);
+
+        // THEN
+        assert!(ports.iter().all(|ports| *ports == [MASTER_PIC_COMMAND_ADDRESS]));
+    }
+
+    #[test_case]
+    fn slave_irq_eoi_goes_to_slave_then_master() {
+        // WHEN
+        let ports = [8, 12, 15].map(InterruptController::eoi_command_ports);
+
+        // THEN
+        assert!(ports.iter().all(|ports| *ports == [SLAVE_PIC_COMMAND_ADDRESS, MASTER_PIC_COMMAND_ADDRESS]));
+    }
+
+    #[test_case]
+    fn irq_bit_is_looked_up_in_the_register_of_its_pic() {
+        // GIVEN
+        let in_service_register = 0b1000_0001;
+
+        // WHEN
+        let master = [0, 1, 7].map(|irq| is_irq_set(in_service_register, irq));
+        let slave = [8, 9, 15].map(|irq| is_irq_set(in_service_register, irq));
+
+        // THEN
+        assert_eq!(master, [true, false, true]);
+        assert_eq!(slave, [true, false, true]);
+    }
+}
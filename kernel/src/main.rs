@@ -15,36 +15,42 @@
 #![feature(btree_extract_if)]
 #![feature(custom_test_frameworks)]
 #![feature(int_roundings)]
+#![feature(alloc_error_handler)]
 #![test_runner(crate::test_runner)]
 #![reexport_test_harness_main = "test_main"]
 
 extern crate downcast_rs;
 extern crate alloc;
 
-use alloc::string::String;
+use alloc::format;
+use core::fmt;
+use core::fmt::{Display, Formatter};
 use core::panic::PanicInfo;
 use lazy_static::lazy_static;
 use limine::BaseRevision;
 use limine::request::{FramebufferRequest, HhdmRequest, MemoryMapRequest};
+use limine::response::{FramebufferResponse, MemoryMapResponse};
 use x86_64::registers::model_specific::Efer;
 use x86_64::registers::control::{Cr0, Cr0Flags, EferFlags};
 use drivers::ps2::init_ps2_controller;
 use drivers::ps2::keyboard::PS2Keyboard;
 use drivers::ps2::PS2DeviceType;
-use fs::ext2::mount_filesystem;
+use fs::ext2::{keep_mounted, mount_first_filesystem};
 use drivers::fbdev::FrameBufferDevice;
 use fs::Vfs;
+use graphics::boot_status::{complete_stage, InitStage};
 use graphics::framebuffer_device::Writer;
 use interrupts::{INTERRUPT_CONTROLLER, InterruptController};
 use memory::{MemoryManager, VirtualAddress};
-use task::keyboard::print_key_inputs;
+use memory::stack::switch_to_kernel_stack;
+use task::keyboard::{dispatch_key_events, print_key_inputs};
 use task::executor::Executor;
-use task::Task;
+use task::{Task, TaskContext};
 use utils::hcf;
 use crate::drivers::cpuid::CPUInfo;
 
 #[cfg(test)]
-use crate::utils::tests::{exit_qemu, QemuExitCode, Testable};
+use crate::utils::tests::{exit_qemu, QemuExitCode, report_test_failure, run_tests, Testable};
 
 #[macro_use]
 mod graphics;
@@ -58,6 +64,8 @@ mod drivers;
 mod task;
 mod fs;
 mod debugger;
+mod time;
+mod version;
 
 pub const KERNEL_START_VMA_ADDRESS: VirtualAddress = 0xFFFFFFFF80000000;
 
@@ -76,30 +84,65 @@ pub static HHDM_REQUEST: HhdmRequest = HhdmRequest::new();
 unsafe extern fn _entry() {
     assert!(BASE_REVISION.is_supported());
 
-    init();
+    if let Err(err) = init(MEMORY_MAP_REQUEST.get_response(), FRAMEBUFFER_REQUEST.get_response()) {
+        boot_failure(err);
+    }
+
+    let Err(err) = switch_to_kernel_stack(kernel_main);
+    boot_failure(InitError::MemoryManager(err));
+}
 
+/// Continues execution once running on the guarded kernel stack
+extern "C" fn kernel_main() -> ! {
     #[cfg(test)]
     test_main();
 
+    if cfg!(feature = "serial-commands") {
+        debugger::remote::serve(&mut debugger::remote::Com1Transport);
+    }
+
     hcf();
 }
 
-unsafe fn init() {
-    if let Err(err) = MemoryManager::init(MEMORY_MAP_REQUEST.get_response().expect("could not retrieve the memory map")) {
-        panic!("{}", err);
-    };
+/// Errors that prevent the kernel from finishing its initialization
+#[derive(Debug, Eq, PartialEq)]
+pub enum InitError {
+    MemoryMapUnavailable,
+    MemoryManager(&'static str),
+    Console(&'static str),
+}
 
-    FRAMEBUFFER_REQUEST.get_response().expect("could not retrieve the frame buffer").framebuffers().for_each(|fbdev| {
-        FrameBufferDevice::init(&fbdev, String::from("fb0"));
-    });
-    //FramebufferWriter::init().expect("could not initialize the framebuffer");
+impl Display for InitError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            InitError::MemoryMapUnavailable => write!(f, "the bootloader did not provide a memory map"),
+            InitError::MemoryManager(err) => write!(f, "could not initialize the memory manager ({})", err),
+            InitError::Console(err) => write!(f, "could not initialize the console ({})", err),
+        }
+    }
+}
+
+/// Initializes every kernel subsystem. Failures of critical subsystems are returned while optional
+/// ones, like storage and input devices, only log a warning and are left disabled.
+unsafe fn init(memory_map: Option<&'static MemoryMapResponse>, framebuffer: Option<&'static FramebufferResponse>) -> Result<(), InitError> {
+    let memory_map = memory_map.ok_or(InitError::MemoryMapUnavailable)?;
 
-    Writer::init().expect("could not initialize the framebuffer");
+    MemoryManager::init(memory_map).map_err(InitError::MemoryManager)?;
+    complete_stage(InitStage::MemoryInit);
+
+    if cfg!(feature = "a20-check") {
+        if let Err(err) = arch::x86_64::a20::enable() {
+            warn!("{}", err);
+        }
+    }
+
+    init_console(framebuffer)?;
+    complete_stage(InitStage::Framebuffer);
 
     Vfs::init();
     FrameBufferDevice::register_devices();
 
-    info!("Toast version v0.0.1-x86_64");
+    info!("Toast version {}", version::version_string());
     CPUInfo::print_cpu_info();
 
     unsafe {
@@ -107,13 +150,38 @@ unsafe fn init() {
         Cr0::write(Cr0::read() | Cr0Flags::WRITE_PROTECT);
     }
 
+    if cfg!(feature = "audit-kernel-mappings") {
+        let leaked_pages = MemoryManager::audit_kernel_mappings();
+        if !leaked_pages.is_empty() {
+            warn!("mm: {} kernel pages are user accessible, first at 0x{:X}", leaked_pages.len(), leaked_pages[0]);
+        }
+    }
+
     InterruptController::init();
     //GlobalDescriptorTable::init();
 
+    drivers::pit::init();
+    INTERRUPT_CONTROLLER.lock().enable_timer_interrupts();
+    complete_stage(InitStage::Interrupts);
+
     // init_acpi(boot_info); // TODO: This broke at some point, fix it
 
-    let mut ahci_devices = drivers::pci::ahci::init();
-    let fs = mount_filesystem(&mut ahci_devices[0]);
+    let ahci_devices = drivers::pci::ahci::init();
+    complete_stage(InitStage::Pci);
+
+    match ahci_devices {
+        Ok(mut ahci_devices) => match mount_first_filesystem(&mut ahci_devices) {
+            Some((index, fs)) => {
+                let drive = ahci_devices.swap_remove(index);
+                info!("ext2: mounted file system from {} (model {}, serial {})", drive.id(),
+                      drive.model().unwrap_or_default(), drive.serial().unwrap_or_default());
+                keep_mounted(drive, fs);
+            },
+            None => warn!("ext2: no drive holds a valid file system, continuing without one"),
+        },
+        Err(err) => warn!("{}, continuing without a file system", err),
+    }
+    complete_stage(InitStage::Filesystem);
 
     /*
     let file_name = "/files/file.txt";
@@ -128,30 +196,78 @@ unsafe fn init() {
         let device = ps2_devices.0.unwrap();
         if let PS2DeviceType::MF2Keyboard = device.device_type() {
             let keyboard: PS2Keyboard = *device.downcast::<PS2Keyboard>().unwrap();
-            executor.spawn(Task::new(print_key_inputs(keyboard)));
+            executor.spawn(Task::with_context(print_key_inputs(), TaskContext::new(Vfs::root_directory().clone())));
+            executor.spawn(Task::new(dispatch_key_events(keyboard)));
             INTERRUPT_CONTROLLER.lock().enable_keyboard_interrupts();
         }
     }
+    complete_stage(InitStage::Input);
 
     /*
     print!(">");
 
     executor.run();*/
+
+    complete_stage(InitStage::Ready);
+    Ok(())
+}
+
+/// Where the console output ends up once it is initialized
+#[derive(Debug, Eq, PartialEq)]
+pub enum ConsoleOutput {
+    Framebuffer,
+    /// No framebuffer was found, only the serial port receives the output
+    SerialOnly,
+}
+
+/// Sets up the framebuffer devices and writes the console to the first one. Without any
+/// framebuffer, as on headless machines or when the bootloader does not answer the framebuffer
+/// request, the console stays on the serial port and boot continues.
+unsafe fn init_console(framebuffer: Option<&'static FramebufferResponse>) -> Result<ConsoleOutput, InitError> {
+    let mut framebuffer_count = 0;
+    framebuffer.into_iter().flat_map(|response| response.framebuffers()).enumerate().for_each(|(index, fbdev)| {
+        FrameBufferDevice::init(&fbdev, format!("fb{}", index));
+        framebuffer_count += 1;
+    });
+
+    if framebuffer_count == 0 {
+        warn!("boot: no framebuffer found, console output goes to the serial port only");
+        return Ok(ConsoleOutput::SerialOnly);
+    }
+
+    Writer::init().map_err(InitError::Console)?;
+    Ok(ConsoleOutput::Framebuffer)
+}
+
+/// Reports an initialization failure on the screen, if it is available, and on the serial port
+/// before halting
+fn boot_failure(err: InitError) -> ! {
+    serial_println!("boot: {}", err);
+
+    if let Some(writer) = Writer::instance() {
+        writer.lock().clear_screen();
+        error!("Toast could not boot: {}", err);
+        println!("The system has been halted.");
+    }
+
+    #[cfg(test)]
+    exit_qemu(QemuExitCode::Failure);
+
+    hcf();
 }
 
 #[cfg(not(test))]
 #[panic_handler]
 fn panic(info: &PanicInfo) -> ! {
-    error!("{}", info);
+    graphics::panic_screen::report_panic(info);
 
-    loop {}
+    hcf();
 }
 
 #[cfg(test)]
 #[panic_handler]
 fn panic(info: &PanicInfo) -> ! {
-    serial_println!("[failed]\n");
-    serial_println!("Error: {}\n", info);
+    report_test_failure(info);
     exit_qemu(QemuExitCode::Failure);
 
     loop {}
@@ -159,10 +275,35 @@ fn panic(info: &PanicInfo) -> ! {
 
 #[cfg(test)]
 pub fn test_runner(tests: &[&dyn Testable]) {
-    serial_println!("Running {} tests", tests.len());
-    for test in tests {
-        test.run();
-    }
+    run_tests(tests);
 
     exit_qemu(QemuExitCode::Success);
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{ConsoleOutput, init, init_console, InitError};
+    use crate::drivers::fbdev::FB_DEVICES;
+
+    #[test_case]
+    fn init_without_memory_map_fails() {
+        // WHEN
+        let result = unsafe { init(None, None) };
+
+        // THEN
+        assert_eq!(result, Err(InitError::MemoryMapUnavailable));
+    }
+
+    #[test_case]
+    fn init_console_without_framebuffer_falls_back_to_serial() {
+        // GIVEN
+        let device_count = FB_DEVICES.lock().len();
+
+        // WHEN
+        let result = unsafe { init_console(None) };
+
+        // THEN
+        assert_eq!(result, Ok(ConsoleOutput::SerialOnly));
+        assert_eq!(FB_DEVICES.lock().len(), device_count);
+    }
+}
@@ -1,36 +1,78 @@
-use core::ops::DerefMut;
+use alloc::vec::Vec;
+use core::ops::{DerefMut, Range};
 use conquer_once::spin::OnceCell;
 use limine::response::MemoryMapResponse;
 use spin::Mutex;
 use self::physical_memory::linear_frame_allocator::LinearFrameAllocator;
 use self::physical_memory::buddy_allocator::BuddyAllocator;
-use self::virtual_memory::paging::ActivePageTable;
+use self::virtual_memory::paging::{ActivePageTable, InactivePageTable, pat};
 use self::virtual_memory::paging::entry::EntryFlags;
 use self::virtual_memory::heap_allocator::init_heap;
-use crate::memory::physical_memory::{Frame, FrameAllocator};
-use crate::memory::virtual_memory::heap_allocator::HEAP_SIZE;
+use crate::memory::physical_memory::{Frame, FrameAllocator, FrameRange};
+use crate::memory::virtual_memory::heap_allocator::{HEAP_SIZE, heap_stats};
 use crate::memory::virtual_memory::paging::Page;
-use crate::memory::virtual_memory::VirtualMemoryManager;
+use crate::memory::virtual_memory::{USER_SPACE_END, VirtualMemoryManager};
 
 pub mod physical_memory;
 pub mod virtual_memory;
+pub mod stack;
 
 pub type PhysicalAddress = usize;
 pub type VirtualAddress = usize;
 
 pub const PAGE_SIZE: usize = 4096;
 
+/// Parses an address written either in hexadecimal with a `0x` prefix or in decimal. Signs are
+/// rejected, the digits must start right away.
+pub fn parse_address(value: &str) -> Option<usize> {
+    let (digits, radix) = match value.strip_prefix("0x").or_else(|| value.strip_prefix("0X")) {
+        Some(hex_digits) => (hex_digits, 16),
+        None => (value, 10),
+    };
+
+    match digits.starts_with(|c: char| c.is_digit(radix)) {
+        true => usize::from_str_radix(digits, radix).ok(),
+        false => None,
+    }
+}
+
+/// Memory usage of every allocator, taken at a single point in time
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct MemoryStats {
+    /// Bytes of physical memory managed by the frame allocator
+    pub physical_total: usize,
+    pub physical_allocated: usize,
+    pub physical_free: usize,
+    pub virtual_allocated: usize,
+    pub heap_live: usize,
+    pub heap_peak: usize,
+}
+
+/// Physically contiguous buffer shared with a device, which is handed the physical address while
+/// the kernel goes through the uncacheable mapping at the virtual one. The frames are also reachable
+/// through the cached HHDM, which must not be used to access them.
+#[derive(Debug, Eq, PartialEq)]
+pub struct DmaBuffer {
+    pub virtual_address: VirtualAddress,
+    pub physical_address: PhysicalAddress,
+    pub size: usize,
+}
+
 pub static INSTANCE: OnceCell<Mutex<MemoryManager>> = OnceCell::uninit();
 pub struct MemoryManager {
     pub frame_allocator: BuddyAllocator,
     pub active_page_table: ActivePageTable,
     pub virtual_memory_manager: VirtualMemoryManager,
+    /// Virtual ranges that must not be freed, such as buffers a device is accessing through DMA
+    pinned_ranges: Vec<Range<VirtualAddress>>,
 }
 
 impl MemoryManager {
     pub fn init(memory_map: &'static MemoryMapResponse) -> Result<(), &'static str>{
         serial_println!("mm: init...");
 
+        pat::init();
+
         let mut linear_allocator = LinearFrameAllocator::new(memory_map);
 
         //let mut active_page_table = setup_page_tables(memory_map, &mut linear_allocator);
@@ -40,6 +82,7 @@ impl MemoryManager {
         // Switch to the buddy allocator
         let mut buddy_allocator = BuddyAllocator::new(memory_map);
         buddy_allocator.set_allocated_frames(linear_allocator.allocated_frames())?;
+        buddy_allocator.set_poison_freed_frames(cfg!(feature = "poison-freed-frames"));
 
         let mut vmm = VirtualMemoryManager::new();
         vmm.allocate_pages(HEAP_SIZE / PAGE_SIZE)?;
@@ -48,6 +91,7 @@ impl MemoryManager {
             frame_allocator: buddy_allocator,
             active_page_table,
             virtual_memory_manager: vmm,
+            pinned_ranges: Vec::new(),
         };
 
         return match INSTANCE.try_init_once(|| Mutex::new(memory_manager)) {
@@ -68,22 +112,35 @@ impl MemoryManager {
         (memory_manager.frame_allocator.get_allocated_amount(), memory_manager.virtual_memory_manager.get_allocated_amount())
     }
 
+    /// Returns the usage of the frame allocator, virtual memory manager and heap. The memory manager
+    /// stays locked while the heap is read, so every figure describes the same moment.
+    pub fn memory_stats() -> MemoryStats {
+        let memory_manager = MemoryManager::instance().lock();
+        let heap = heap_stats();
+
+        MemoryStats {
+            physical_total: memory_manager.frame_allocator.get_total_amount(),
+            physical_allocated: memory_manager.frame_allocator.get_allocated_amount(),
+            physical_free: memory_manager.frame_allocator.get_free_amount(),
+            virtual_allocated: memory_manager.virtual_memory_manager.get_allocated_amount(),
+            heap_live: heap.live_bytes,
+            heap_peak: heap.peak_bytes,
+        }
+    }
+
     pub fn vmm_alloc(size: usize, flags: EntryFlags) -> Option<VirtualAddress> {
         let page_count = size.div_ceil(PAGE_SIZE);
 
         let mut memory_manager = MemoryManager::instance().lock();
 
         if let Ok(virtual_alloc) = memory_manager.virtual_memory_manager.allocate_pages(page_count) {
-            for i in 0..page_count {
-                let page_address = virtual_alloc + i * PAGE_SIZE;
-                let page = Page::containing_address(page_address);
-
-                if let Ok(frame) =  memory_manager.frame_allocator.allocate_frame() {
-                    memory_manager.vmm_map_to(page, frame, flags);
-                }
-                else {
-                    panic!("vmm: ran out of physical memory when allocating {} pages", size);
-                }
+            let Ok(frames) = memory_manager.frame_allocator.allocate_batch(page_count) else {
+                panic!("vmm: ran out of physical memory when allocating {} pages", size);
+            };
+
+            for (i, frame) in frames.into_iter().enumerate() {
+                let page = Page::containing_address(virtual_alloc + i * PAGE_SIZE);
+                memory_manager.vmm_map_to(page, frame, flags);
             }
 
             return Some(virtual_alloc)
@@ -92,12 +149,162 @@ impl MemoryManager {
         None
     }
 
+    /// Maps the physical range to a newly allocated virtual range with the given flags and returns the
+    /// virtual address corresponding to the start of the physical range
+    pub fn map_physical(address: PhysicalAddress, size: usize, flags: EntryFlags) -> Result<VirtualAddress, &'static str> {
+        if size == 0 {
+            return Err("vmm: cannot map an empty range");
+        }
+
+        let start_frame = Frame::containing_address(address);
+        let end_frame = Frame::containing_address(address + size - 1);
+        let page_count = end_frame.number - start_frame.number + 1;
+
+        let mut memory_manager = MemoryManager::instance().lock();
+        let memory_manager = memory_manager.deref_mut();
+
+        let virtual_start = memory_manager.virtual_memory_manager.allocate_pages(page_count)?;
+        let pages = Page::range_inclusive(Page::containing_address(virtual_start), Page::containing_address(virtual_start + (page_count - 1) * PAGE_SIZE));
+        memory_manager.active_page_table.map_range(pages, Frame::range_inclusive(start_frame, end_frame), flags, &mut memory_manager.frame_allocator);
+
+        Ok(virtual_start + address % PAGE_SIZE)
+    }
+
+    /// Allocates physically contiguous frames covering the size and maps them uncacheable in the
+    /// kernel address space, away from the identity map
+    pub fn alloc_dma(size: usize) -> Result<DmaBuffer, &'static str> {
+        if size == 0 {
+            return Err("mm: cannot allocate an empty DMA buffer");
+        }
+        let page_count = size.div_ceil(PAGE_SIZE);
+
+        let mut memory_manager = MemoryManager::instance().lock();
+        let memory_manager = memory_manager.deref_mut();
+
+        let frames = memory_manager.frame_allocator.allocate_contiguous(page_count)?;
+        let virtual_address = match memory_manager.virtual_memory_manager.allocate_pages(page_count) {
+            Ok(virtual_address) => virtual_address,
+            Err(err) => {
+                memory_manager.frame_allocator.deallocate_contiguous(frames)?;
+                return Err(err);
+            }
+        };
+
+        let pages = Page::range_inclusive(Page::containing_address(virtual_address), Page::containing_address(virtual_address + (page_count - 1) * PAGE_SIZE));
+        let flags = EntryFlags::WRITABLE | EntryFlags::NO_EXECUTE | EntryFlags::UNCACHEABLE;
+        memory_manager.active_page_table.map_range(pages, frames.iter(), flags, &mut memory_manager.frame_allocator);
+
+        Ok(DmaBuffer { virtual_address, physical_address: frames.start_address(), size })
+    }
+
+    /// Unmaps a buffer returned by `alloc_dma` and frees its frames
+    pub fn free_dma(buffer: DmaBuffer) -> Result<(), &'static str> {
+        let page_count = buffer.size.div_ceil(PAGE_SIZE);
+
+        let mut memory_manager = MemoryManager::instance().lock();
+        let memory_manager = memory_manager.deref_mut();
+
+        if memory_manager.pinned_ranges.iter().any(|pinned| pinned.start < buffer.virtual_address + buffer.size && buffer.virtual_address < pinned.end) {
+            return Err("vmm: cannot free a pinned range");
+        }
+
+        for page_number in 0..page_count {
+            let page = Page::containing_address(buffer.virtual_address + page_number * PAGE_SIZE);
+            memory_manager.active_page_table.unmap_no_dealloc(&page);
+        }
+
+        memory_manager.frame_allocator.deallocate_contiguous(FrameRange { start: Frame::containing_address(buffer.physical_address), count: page_count })?;
+        memory_manager.virtual_memory_manager.deallocate_pages(buffer.virtual_address, page_count * PAGE_SIZE)
+    }
+
+    /// Creates a new address space sharing the kernel half of the active page table
+    pub fn new_address_space() -> Result<InactivePageTable, &'static str> {
+        let mut memory_manager = MemoryManager::instance().lock();
+
+        let frame = memory_manager.frame_allocator.allocate_frame()?;
+        Ok(InactivePageTable::new_address_space(frame, &memory_manager.active_page_table))
+    }
+
+    /// Maps the page to the frame in the given address space. The page is always user accessible and
+    /// never global, and must lie in the lower half.
+    pub fn map_user_page(table: &mut InactivePageTable, page: Page, frame: Frame, flags: EntryFlags) -> Result<(), &'static str> {
+        if page.start_address() >= USER_SPACE_END {
+            return Err("vmm: user pages must be in the lower half");
+        }
+
+        let mut memory_manager = MemoryManager::instance().lock();
+        let memory_manager = memory_manager.deref_mut();
+
+        let flags = (flags | EntryFlags::USER_ACCESSIBLE) - EntryFlags::GLOBAL;
+        memory_manager.active_page_table.with(table, |mapper| {
+            mapper.map_user_to(page, frame, flags, &mut memory_manager.frame_allocator);
+        });
+
+        Ok(())
+    }
+
     pub fn vmm_zero_alloc(_size: usize, _flags: EntryFlags) -> Option<VirtualAddress> {
         unimplemented!()
     }
 
-    pub fn vmm_free(_size: usize, _address: VirtualAddress) {
-        unimplemented!();
+    /// Unmaps the pages covering the given range, returns their frames to the frame allocator and
+    /// releases the virtual range. Fails without freeing anything if the range overlaps a pinned one
+    pub fn vmm_free(size: usize, address: VirtualAddress) -> Result<(), &'static str> {
+        let page_count = size.div_ceil(PAGE_SIZE);
+
+        let mut memory_manager = MemoryManager::instance().lock();
+        let memory_manager = memory_manager.deref_mut();
+
+        let freed_range = address..address + page_count * PAGE_SIZE;
+        if memory_manager.pinned_ranges.iter().any(|pinned| pinned.start < freed_range.end && freed_range.start < pinned.end) {
+            return Err("vmm: cannot free a pinned range");
+        }
+
+        let pages = Page::range_inclusive(Page::containing_address(address), Page::containing_address(address + page_count * PAGE_SIZE - 1));
+        memory_manager.active_page_table.unmap_range(pages, &mut memory_manager.frame_allocator);
+
+        memory_manager.virtual_memory_manager.deallocate_pages(address, page_count * PAGE_SIZE)
+    }
+
+    /// Prevents the given range from being freed until it is unpinned
+    pub fn pin(address: VirtualAddress, size: usize) -> Result<(), &'static str> {
+        if size == 0 {
+            return Err("vmm: cannot pin an empty range");
+        }
+
+        MemoryManager::instance().lock().pinned_ranges.push(address..address + size);
+        Ok(())
+    }
+
+    /// Removes a pin previously created with the same address and size
+    pub fn unpin(address: VirtualAddress, size: usize) -> Result<(), &'static str> {
+        let mut memory_manager = MemoryManager::instance().lock();
+
+        let index = memory_manager.pinned_ranges.iter().position(|pinned| *pinned == (address..address + size))
+            .ok_or("vmm: range is not pinned")?;
+        memory_manager.pinned_ranges.swap_remove(index);
+
+        Ok(())
+    }
+
+    /// Returns the kernel pages that are mapped as user accessible, which should never happen
+    pub fn audit_kernel_mappings() -> Vec<VirtualAddress> {
+        MemoryManager::instance().lock().active_page_table.user_accessible_kernel_pages()
+    }
+
+    /// Translates a virtual address to the physical address it is mapped to in the active page table
+    pub fn translate(address: VirtualAddress) -> Option<PhysicalAddress> {
+        MemoryManager::instance().lock().active_page_table.translate(address)
+    }
+
+    /// Returns whether the address is canonical and mapped in the active page table
+    pub fn is_mapped(address: VirtualAddress) -> bool {
+        !(0x0000_8000_0000_0000..0xFFFF_8000_0000_0000).contains(&address) && MemoryManager::translate(address).is_some()
+    }
+
+    /// Returns the flags of the 4KiB page containing the address in the active page table
+    pub fn page_flags(address: VirtualAddress) -> Option<EntryFlags> {
+        MemoryManager::instance().lock().active_page_table.flags(Page::containing_address(address))
     }
 
     pub fn pmm_alloc(size: usize) -> Option<PhysicalAddress> {
@@ -158,7 +365,251 @@ impl MemoryManager {
         self.active_page_table.identity_map(frame, flags, &mut self.frame_allocator);
     }
 
+    /// Identity maps every frame between the two given frames, inclusively
+    pub fn pmm_identity_map_range(&mut self, start_frame: Frame, end_frame: Frame, flags: EntryFlags) {
+        let pages = Page::range_inclusive(Page::containing_address(start_frame.start_address()), Page::containing_address(end_frame.start_address()));
+        self.active_page_table.map_range(pages, Frame::range_inclusive(start_frame, end_frame), flags, &mut self.frame_allocator);
+    }
+
     fn vmm_map_to(&mut self, page: Page, frame: Frame, flags: EntryFlags) {
         self.active_page_table.map_to(page, frame, flags, &mut self.frame_allocator);
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::memory::{MemoryManager, parse_address, PAGE_SIZE};
+    use crate::memory::virtual_memory::heap_allocator::HEAP_SIZE;
+    use crate::memory::Frame;
+    use crate::memory::virtual_memory::paging::Page;
+    use crate::memory::virtual_memory::paging::pat::{MemoryType, pat_index, programmed_memory_type, WRITE_COMBINING_PAT_INDEX};
+    use crate::memory::virtual_memory::paging::entry::EntryFlags;
+
+    #[test_case]
+    fn parse_address_accepts_prefixed_hex() {
+        // GIVEN
+        let values = ["0x1000", "0XFFFF8000DEADBEEF", "0xffffffff80000000", "0x0"];
+
+        // WHEN
+        let addresses = values.map(parse_address);
+
+        // THEN
+        assert_eq!(addresses, [Some(0x1000), Some(0xFFFF8000DEADBEEF), Some(0xFFFFFFFF80000000), Some(0)]);
+    }
+
+    #[test_case]
+    fn parse_address_accepts_decimal() {
+        // GIVEN
+        let values = ["4096", "0", "18446744073709551615"];
+
+        // WHEN
+        let addresses = values.map(parse_address);
+
+        // THEN
+        assert_eq!(addresses, [Some(4096), Some(0), Some(usize::MAX)]);
+    }
+
+    #[test_case]
+    fn parse_address_rejects_invalid_strings() {
+        // GIVEN
+        let values = ["", "0x", "1000h", "DEADBEEF", "0xFFFFFFFFFFFFFFFFF", "-1", "0x-1", " 0x10", "+1", "0x+10"];
+
+        // WHEN
+        let addresses = values.map(parse_address);
+
+        // THEN
+        assert!(addresses.iter().all(|address| address.is_none()));
+    }
+
+    #[test_case]
+    fn is_mapped_ends_with_the_mapped_page() {
+        // GIVEN
+        let address = MemoryManager::vmm_alloc(2 * PAGE_SIZE, EntryFlags::WRITABLE).unwrap();
+        MemoryManager::vmm_free(PAGE_SIZE, address + PAGE_SIZE).unwrap();
+
+        // WHEN
+        let start_is_mapped = MemoryManager::is_mapped(address);
+        let end_is_mapped = MemoryManager::is_mapped(address + PAGE_SIZE - 1);
+        let past_end_is_mapped = MemoryManager::is_mapped(address + PAGE_SIZE);
+        let non_canonical_is_mapped = MemoryManager::is_mapped(0x0000_8000_0000_0000);
+
+        // THEN
+        assert!(start_is_mapped);
+        assert!(end_is_mapped);
+        assert!(!past_end_is_mapped);
+        assert!(!non_canonical_is_mapped);
+
+        MemoryManager::vmm_free(PAGE_SIZE, address).unwrap();
+    }
+
+    #[test_case]
+    fn pinned_range_cannot_be_freed_until_unpinned() {
+        // GIVEN
+        let size = 2 * PAGE_SIZE;
+        let address = MemoryManager::vmm_alloc(size, EntryFlags::WRITABLE).unwrap();
+        MemoryManager::pin(address + PAGE_SIZE, 16).unwrap();
+
+        // WHEN
+        let pinned_free = MemoryManager::vmm_free(size, address);
+        MemoryManager::unpin(address + PAGE_SIZE, 16).unwrap();
+        let unpinned_free = MemoryManager::vmm_free(size, address);
+
+        // THEN
+        assert_eq!(pinned_free, Err("vmm: cannot free a pinned range"));
+        assert_eq!(unpinned_free, Ok(()));
+        assert!(MemoryManager::translate(address).is_none());
+    }
+
+    #[test_case]
+    fn unpinning_unknown_range_fails() {
+        // WHEN
+        let result = MemoryManager::unpin(0xFFFF_9000_0000_0000, PAGE_SIZE);
+
+        // THEN
+        assert_eq!(result, Err("vmm: range is not pinned"));
+    }
+
+    #[test_case]
+    fn kernel_mappings_are_not_user_accessible() {
+        // WHEN
+        let leaked_pages = MemoryManager::audit_kernel_mappings();
+
+        // THEN
+        assert!(leaked_pages.is_empty(), "user accessible kernel pages: {:X?}", leaked_pages);
+    }
+
+    #[test_case]
+    fn audit_flags_user_accessible_kernel_page() {
+        // GIVEN
+        let address = MemoryManager::vmm_alloc(PAGE_SIZE, EntryFlags::WRITABLE | EntryFlags::USER_ACCESSIBLE).unwrap();
+
+        // WHEN
+        let leaked_pages = MemoryManager::audit_kernel_mappings();
+        MemoryManager::vmm_free(PAGE_SIZE, address).unwrap();
+
+        // THEN
+        assert_eq!(leaked_pages, [address]);
+        assert!(MemoryManager::audit_kernel_mappings().is_empty());
+    }
+
+    #[test_case]
+    fn write_combining_mapping_selects_programmed_pat_entry() {
+        // GIVEN
+        let frame = MemoryManager::pmm_alloc(PAGE_SIZE).unwrap();
+
+        // WHEN
+        let address = MemoryManager::map_physical(frame, PAGE_SIZE, EntryFlags::WRITABLE | EntryFlags::WRITE_COMBINING).unwrap();
+
+        // THEN
+        let flags = MemoryManager::instance().lock().active_page_table.flags(Page::containing_address(address)).unwrap();
+        assert_eq!(pat_index(flags), WRITE_COMBINING_PAT_INDEX);
+        assert_eq!(programmed_memory_type(WRITE_COMBINING_PAT_INDEX), MemoryType::WriteCombining as u8);
+
+        MemoryManager::vmm_free(PAGE_SIZE, address).unwrap();
+    }
+
+    #[test_case]
+    fn user_page_is_accessible_after_switching_address_space() {
+        // GIVEN
+        let mut address_space = MemoryManager::new_address_space().unwrap();
+        let frame = Frame::containing_address(MemoryManager::pmm_alloc(PAGE_SIZE).unwrap());
+        let page = Page::containing_address(0x40_0000);
+
+        // WHEN
+        MemoryManager::map_user_page(&mut address_space, page, frame, EntryFlags::WRITABLE | EntryFlags::GLOBAL).unwrap();
+
+        // THEN
+        let mut memory_manager = MemoryManager::instance().lock();
+        let kernel_space = memory_manager.active_page_table.switch(address_space);
+
+        let flags = memory_manager.active_page_table.flags(page).unwrap();
+        let value = unsafe {
+            let pointer = page.start_address() as *mut u64;
+            pointer.write_volatile(0xDEAD_BEEF);
+            pointer.read_volatile()
+        };
+
+        memory_manager.active_page_table.switch(kernel_space);
+
+        assert!(flags.contains(EntryFlags::USER_ACCESSIBLE));
+        assert!(!flags.contains(EntryFlags::GLOBAL));
+        assert_eq!(value, 0xDEAD_BEEF);
+    }
+
+    #[test_case]
+    fn user_page_cannot_be_mapped_in_higher_half() {
+        // GIVEN
+        let mut address_space = MemoryManager::new_address_space().unwrap();
+        let frame = Frame::containing_address(0);
+
+        // WHEN
+        let result = MemoryManager::map_user_page(&mut address_space, Page::containing_address(0xFFFF_8000_0000_0000), frame, EntryFlags::WRITABLE);
+
+        // THEN
+        assert_eq!(result, Err("vmm: user pages must be in the lower half"));
+    }
+
+    #[test_case]
+    fn memory_stats_are_consistent() {
+        // WHEN
+        let stats = MemoryManager::memory_stats();
+
+        // THEN
+        assert!(stats.physical_allocated > 0);
+        assert!(stats.physical_allocated <= stats.physical_total);
+        assert!(stats.physical_free <= stats.physical_total - stats.physical_allocated);
+        assert!(stats.virtual_allocated >= HEAP_SIZE);
+        assert!(stats.heap_live > 0);
+        assert!(stats.heap_live <= stats.heap_peak);
+        assert!(stats.heap_peak <= HEAP_SIZE);
+    }
+
+    #[test_case]
+    fn memory_stats_follow_allocations() {
+        // GIVEN
+        let before = MemoryManager::memory_stats();
+
+        // WHEN
+        let address = MemoryManager::vmm_alloc(2 * PAGE_SIZE, EntryFlags::WRITABLE).unwrap();
+        let during = MemoryManager::memory_stats();
+        MemoryManager::vmm_free(2 * PAGE_SIZE, address).unwrap();
+
+        // THEN
+        assert!(during.physical_allocated >= before.physical_allocated + 2 * PAGE_SIZE);
+        assert_eq!(during.virtual_allocated, before.virtual_allocated + 2 * PAGE_SIZE);
+        assert!(during.physical_free <= before.physical_free - 2 * PAGE_SIZE);
+    }
+
+    #[test_case]
+    fn dma_buffer_is_mapped_uncacheable_to_its_frames() {
+        // GIVEN
+        let buffer = MemoryManager::alloc_dma(3 * PAGE_SIZE).unwrap();
+
+        // WHEN
+        let translated = (0..3).map(|page| MemoryManager::translate(buffer.virtual_address + page * PAGE_SIZE + 8));
+        let flags = MemoryManager::page_flags(buffer.virtual_address).unwrap();
+        let value = unsafe {
+            ((buffer.virtual_address + PAGE_SIZE) as *mut u64).write_volatile(0xDEAD_BEEF);
+            ((buffer.virtual_address + PAGE_SIZE) as *const u64).read_volatile()
+        };
+
+        // THEN
+        assert!(translated.enumerate().all(|(page, address)| address == Some(buffer.physical_address + page * PAGE_SIZE + 8)));
+        assert_eq!(pat_index(flags), 3);
+        assert_eq!(programmed_memory_type(3), MemoryType::Uncacheable as u8);
+        assert_eq!(value, 0xDEAD_BEEF);
+
+        let virtual_address = buffer.virtual_address;
+        MemoryManager::free_dma(buffer).unwrap();
+        assert!(!MemoryManager::is_mapped(virtual_address));
+    }
+
+    #[test_case]
+    fn empty_dma_buffer_is_rejected() {
+        // WHEN
+        let result = MemoryManager::alloc_dma(0);
+
+        // THEN
+        assert_eq!(result, Err("mm: cannot allocate an empty DMA buffer"));
+    }
+}
@@ -1,10 +1,13 @@
-use alloc::collections::LinkedList;
+use alloc::collections::BTreeMap;
 use alloc::vec::Vec;
 use core::cmp::min;
-use limine::memory_map::{Entry, EntryType};
+use core::mem::size_of;
+use limine::memory_map::EntryType;
 use limine::response::MemoryMapResponse;
 use crate::memory::{Frame, PAGE_SIZE, PhysicalAddress};
-use crate::memory::physical_memory::FrameAllocator;
+use crate::memory::physical_memory::{FrameAllocator, FrameRange};
+use crate::memory::physical_memory::memory_map::sanitize_memory_map;
+use crate::HHDM_OFFSET;
 
 // Linker script symbols marking ELF sections
 extern "C" {
@@ -23,6 +26,9 @@ extern "C" {
 // Maximum allocation size, this allocator cannot allocate blocks larger than 2^MAX_ORDER pages
 const MAX_ORDER: usize = 10;
 
+/// Pattern written over freed frames when poisoning is enabled
+pub const FREED_FRAME_POISON: u32 = 0xDEADBEEF;
+
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
 enum BlockType {
     TopLevel,
@@ -30,10 +36,40 @@ enum BlockType {
     RightBuddy
 }
 
-type MemoryBlocks = [LinkedList<MemoryBlock>; MAX_ORDER + 1];
+/// Blocks of each order, keyed by their starting address so buddies can be looked up in O(log n)
+type MemoryBlocks = [BTreeMap<PhysicalAddress, MemoryBlock>; MAX_ORDER + 1];
 pub struct BuddyAllocator {
     memory_blocks: MemoryBlocks,
+    regions: Vec<MemoryRegion>,
     allocated_amount: usize,
+    /// Whether freed frames are overwritten with `FREED_FRAME_POISON` through the HHDM
+    poison_freed_frames: bool,
+}
+
+/// A contiguous range of usable physical memory and the memory node it belongs to. Every region
+/// is currently on node 0 since multiple nodes are not detected yet.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+struct MemoryRegion {
+    start_address: PhysicalAddress,
+    size: usize,
+    node: usize,
+}
+
+impl MemoryRegion {
+    fn contains_address(&self, address: PhysicalAddress) -> bool {
+        address >= self.start_address && address < self.start_address + self.size
+    }
+}
+
+/// Indicates where the frames of an allocation should preferably come from
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum AllocationHint {
+    /// No preference
+    Any,
+    /// Prefer the regions of the given memory node
+    Node(usize),
+    /// Prefer the region containing the given address
+    Address(PhysicalAddress),
 }
 
 #[derive(Debug, Copy, Clone, Eq, PartialEq)]
@@ -52,36 +88,57 @@ impl MemoryBlock {
 
 impl BuddyAllocator {
     pub fn new(memory_map: &'static MemoryMapResponse) -> Self {
-        let mut memory_blocks: MemoryBlocks = [
-            LinkedList::new(),
-            LinkedList::new(),
-            LinkedList::new(),
-            LinkedList::new(),
-            LinkedList::new(),
-            LinkedList::new(),
-            LinkedList::new(),
-            LinkedList::new(),
-            LinkedList::new(),
-            LinkedList::new(),
-            LinkedList::new(),
-        ];
-
-        // Fill the memory block lists
-        for area in memory_map.entries().iter().filter(|entry| entry.entry_type == EntryType::USABLE) {
-            Self::map_area(area, &mut memory_blocks);
+        let regions = sanitize_memory_map(memory_map.entries()).iter()
+            .filter(|entry| entry.entry_type == EntryType::USABLE)
+            .map(|entry| MemoryRegion { start_address: entry.base, size: entry.length, node: 0 })
+            .collect();
+
+        Self::with_regions(regions)
+    }
+
+    fn with_regions(regions: Vec<MemoryRegion>) -> Self {
+        let mut memory_blocks: MemoryBlocks = core::array::from_fn(|_| BTreeMap::new());
+
+        // Fill the memory block maps
+        for region in regions.iter() {
+            Self::map_area(region, &mut memory_blocks);
         }
 
         Self {
             memory_blocks,
+            regions,
             allocated_amount: 0,
+            poison_freed_frames: false,
         }
     }
 
+    /// Enables or disables overwriting frames with a poison pattern when they are deallocated. This
+    /// makes reads of stale frames obvious at the cost of writing every freed byte.
+    pub fn set_poison_freed_frames(&mut self, enabled: bool) {
+        self.poison_freed_frames = enabled;
+    }
+
     /// Returns the total amount of memory allocated by this allocator
     pub fn get_allocated_amount(&self) -> usize {
         self.allocated_amount
     }
 
+    /// Returns the amount of memory managed by this allocator, allocated or not
+    pub fn get_total_amount(&self) -> usize {
+        self.memory_blocks.iter().flat_map(|blocks| blocks.values())
+            .filter(|block| block.block_type == BlockType::TopLevel)
+            .map(|block| PAGE_SIZE * 2usize.pow(block.size_class as u32))
+            .sum()
+    }
+
+    /// Returns the amount of memory held by free blocks
+    pub fn get_free_amount(&self) -> usize {
+        self.memory_blocks.iter().flat_map(|blocks| blocks.values())
+            .filter(|block| !block.is_allocated)
+            .map(|block| PAGE_SIZE * 2usize.pow(block.size_class as u32))
+            .sum()
+    }
+
     pub fn display_memory(&self) {
         println!("{:?}", self.memory_blocks);
     }
@@ -102,11 +159,37 @@ impl BuddyAllocator {
     /// Allocates 2^order contiguous frames
     /// Returns the starting address of the allocated block
     pub fn allocate_frames(&mut self, order: usize) -> Result<PhysicalAddress, &'static str> {
+        self.allocate_frames_with_hint(order, AllocationHint::Any)
+    }
+
+    /// Allocates 2^order contiguous frames, taking them from the regions designated by the hint
+    /// when possible and falling back to any other region otherwise.
+    /// Returns the starting address of the allocated block
+    pub fn allocate_frames_with_hint(&mut self, order: usize, hint: AllocationHint) -> Result<PhysicalAddress, &'static str> {
         if order > MAX_ORDER {
             return Err("cannot allocate more than 10 contiguous frames")
         }
 
-        let first_free_block = self.memory_blocks[order].iter_mut().find(|block| !block.is_allocated);
+        let preferred_regions: Vec<MemoryRegion> = match hint {
+            AllocationHint::Any => Vec::new(),
+            AllocationHint::Node(node) => self.regions.iter().filter(|region| region.node == node).copied().collect(),
+            AllocationHint::Address(address) => self.regions.iter().filter(|region| region.contains_address(address)).copied().collect(),
+        };
+
+        if !preferred_regions.is_empty() {
+            let is_preferred = |block: &MemoryBlock| preferred_regions.iter().any(|region| region.contains_address(block.starting_address));
+
+            if let Ok(address) = self.allocate_frames_where(order, &is_preferred) {
+                return Ok(address);
+            }
+        }
+
+        self.allocate_frames_where(order, &|_| true)
+    }
+
+    /// Allocates 2^order contiguous frames from the blocks accepted by the filter
+    fn allocate_frames_where(&mut self, order: usize, filter: &dyn Fn(&MemoryBlock) -> bool) -> Result<PhysicalAddress, &'static str> {
+        let first_free_block = self.memory_blocks[order].values_mut().find(|block| !block.is_allocated && filter(block));
         if first_free_block.is_some() {
             let block = first_free_block.unwrap();
             block.is_allocated = true;
@@ -114,7 +197,7 @@ impl BuddyAllocator {
             self.allocated_amount += 2usize.pow(order as u32) * PAGE_SIZE;
             Ok(block.starting_address)
         } else {
-            let alloc = self.split_block(order + 1);
+            let alloc = self.split_block_where(order + 1, filter);
 
             if alloc.is_ok() {
                 self.allocated_amount += 2usize.pow(order as u32) * PAGE_SIZE;
@@ -124,10 +207,17 @@ impl BuddyAllocator {
         }
     }
 
+    /// Fills 2^order frames with `FREED_FRAME_POISON` through the higher half direct map
+    fn poison_frames(start_address: PhysicalAddress, order: usize) {
+        let word_count = 2usize.pow(order as u32) * PAGE_SIZE / size_of::<u32>();
+        let words = unsafe { core::slice::from_raw_parts_mut((start_address + *HHDM_OFFSET) as *mut u32, word_count) };
+
+        words.fill(FREED_FRAME_POISON);
+    }
+
     /// Deallocates 2^order contiguous frames
     pub fn deallocate_frames(&mut self, start_address: PhysicalAddress, order: usize) -> Result<(), &'static str> {
-        let memory_block = self.memory_blocks[order].iter_mut()
-            .find(|block| block.starting_address == start_address);
+        let memory_block = self.memory_blocks[order].get_mut(&start_address);
 
         if memory_block.is_none() {
             return Err("could not find the frame to deallocate");
@@ -140,6 +230,10 @@ impl BuddyAllocator {
 
             memory_block.is_allocated = false;
 
+            if self.poison_freed_frames {
+                Self::poison_frames(start_address, order);
+            }
+
             // Merge only if block is a buddy
             if memory_block.block_type == BlockType::TopLevel {
                 self.allocated_amount -= 2usize.pow(order as u32) * PAGE_SIZE;
@@ -152,8 +246,7 @@ impl BuddyAllocator {
                 memory_block.starting_address - PAGE_SIZE * 2usize.pow(memory_block.size_class as u32)
             };
 
-            let buddy = self.memory_blocks[order].iter_mut()
-                .find(|block| block.starting_address == buddy_address);
+            let buddy = self.memory_blocks[order].get_mut(&buddy_address);
 
             if buddy.is_none() {
                 return Err("could not find the frame to deallocate");
@@ -164,14 +257,10 @@ impl BuddyAllocator {
                 if !buddy.is_allocated {
                     let parent_block_address = min(start_address, buddy_address);
 
-                    let _extracted_buddy = self.memory_blocks[order]
-                        .extract_if(|block| block.starting_address == start_address);
-                    let _extracted_buddy = self.memory_blocks[order]
-                        .extract_if(|block| block.starting_address == buddy_address);
+                    self.memory_blocks[order].remove(&start_address);
+                    self.memory_blocks[order].remove(&buddy_address);
 
-                    let parent_block = self.memory_blocks[order + 1]
-                        .iter_mut()
-                        .find(|block| block.starting_address == parent_block_address);
+                    let parent_block = self.memory_blocks[order + 1].get_mut(&parent_block_address);
 
                     match parent_block {
                         Some(parent_block) => parent_block.is_allocated = false,
@@ -185,10 +274,71 @@ impl BuddyAllocator {
         Ok(())
     }
 
+    /// Frees frames allocated by `allocate_contiguous`
+    pub fn deallocate_contiguous(&mut self, range: FrameRange) -> Result<(), &'static str> {
+        for (start_address, order) in Self::contiguous_pieces(range.start_address(), range.count) {
+            self.deallocate_frames(start_address, order)?;
+        }
+
+        Ok(())
+    }
+
+    /// Splits the allocated block into buddies so that only its first `count` frames stay allocated.
+    /// The allocated frames end up in one block per bit set in `count`, from the largest to the
+    /// smallest, as listed by `contiguous_pieces`.
+    fn trim_block(&mut self, start_address: PhysicalAddress, order: usize, count: usize) {
+        let mut block_address = start_address;
+        let mut order = order;
+        let mut remaining = count;
+
+        while remaining > 0 && remaining < 1 << order {
+            order -= 1;
+            let half = 1 << order;
+
+            let left_buddy = MemoryBlock {
+                is_allocated: true,
+                starting_address: block_address,
+                size_class: order,
+                block_type: BlockType::LeftBuddy,
+            };
+
+            let right_buddy = MemoryBlock {
+                is_allocated: remaining > half,
+                starting_address: block_address + PAGE_SIZE * half,
+                size_class: order,
+                block_type: BlockType::RightBuddy,
+            };
+
+            self.memory_blocks[order].insert(left_buddy.starting_address, left_buddy);
+            self.memory_blocks[order].insert(right_buddy.starting_address, right_buddy);
+
+            // Keep splitting whichever buddy is only partly used
+            if remaining >= half {
+                block_address += PAGE_SIZE * half;
+                remaining -= half;
+            }
+        }
+
+        self.allocated_amount -= (count.next_power_of_two() - count) * PAGE_SIZE;
+    }
+
+    /// Start address and order of the blocks making up a range allocated by `allocate_contiguous`
+    fn contiguous_pieces(start_address: PhysicalAddress, count: usize) -> Vec<(PhysicalAddress, usize)> {
+        let mut pieces = Vec::new();
+        let mut address = start_address;
+
+        for order in (0..=MAX_ORDER).rev().filter(|order| count & 1 << order != 0) {
+            pieces.push((address, order));
+            address += PAGE_SIZE << order;
+        }
+
+        pieces
+    }
+
     /// Allocates a single frame at a given address. This is mostly used when transitioning from
     /// the linear allocator to this one.
     fn allocate_frame_at_address(&mut self, address: PhysicalAddress) -> Result<PhysicalAddress, &'static str> {
-        if self.memory_blocks[0].iter().any(|block| block.is_allocated && block.starting_address == address) {
+        if self.memory_blocks[0].get(&address).is_some_and(|block| block.is_allocated) {
             return Err("frame already allocated");
         }
 
@@ -196,7 +346,7 @@ impl BuddyAllocator {
         let mut current_block: Option<&mut MemoryBlock> = None;
         let mut current_order = 0;
         while current_block.is_none() && current_order <= MAX_ORDER {
-            current_block = self.memory_blocks[current_order].iter_mut().find(|block| block.contains_address(address));
+            current_block = Self::block_containing(&mut self.memory_blocks[current_order], address);
             current_order += 1;
         }
 
@@ -226,15 +376,15 @@ impl BuddyAllocator {
                 left_buddy.is_allocated = true;
                 current_block_clone = left_buddy;
 
-                self.memory_blocks[buddy_size_class].push_back(left_buddy);
-                self.memory_blocks[buddy_size_class].push_back(right_buddy);
+                self.memory_blocks[buddy_size_class].insert(left_buddy.starting_address, left_buddy);
+                self.memory_blocks[buddy_size_class].insert(right_buddy.starting_address, right_buddy);
             }
             else {
                 right_buddy.is_allocated = true;
                 current_block_clone = right_buddy;
 
-                self.memory_blocks[buddy_size_class].push_back(left_buddy);
-                self.memory_blocks[buddy_size_class].push_back(right_buddy);
+                self.memory_blocks[buddy_size_class].insert(left_buddy.starting_address, left_buddy);
+                self.memory_blocks[buddy_size_class].insert(right_buddy.starting_address, right_buddy);
             }
         }
 
@@ -242,15 +392,23 @@ impl BuddyAllocator {
         Ok(current_block_clone.starting_address)
     }
 
-    fn map_area(area: &Entry, memory_blocks: &mut MemoryBlocks) {
-        let mut block_start_address = area.base as PhysicalAddress;
+    /// Returns the block starting at or below the address if it contains it
+    fn block_containing(blocks: &mut BTreeMap<PhysicalAddress, MemoryBlock>, address: PhysicalAddress) -> Option<&mut MemoryBlock> {
+        blocks.range_mut(..=address).next_back()
+            .map(|(_, block)| block)
+            .filter(|block| block.contains_address(address))
+    }
+
+    fn map_area(area: &MemoryRegion, memory_blocks: &mut MemoryBlocks) {
+        let area_end_address = area.start_address + area.size;
+        let mut block_start_address = area.start_address;
         let mut block_end_address = block_start_address + PAGE_SIZE * 2usize.pow(MAX_ORDER as u32);
 
-        while block_start_address < (area.base + area.length) as PhysicalAddress {
+        while block_start_address < area_end_address {
             let mut current_order = MAX_ORDER as u32;
 
             // Find the largest block that fits
-            while block_end_address > (area.base + area.length) as PhysicalAddress {
+            while block_end_address > area_end_address {
                 // If no block order fits, no more blocks can be added for this area
                 if current_order == 0 {
                     return;
@@ -260,8 +418,8 @@ impl BuddyAllocator {
                 block_end_address = block_start_address + PAGE_SIZE * 2usize.pow(current_order);
             }
 
-            // Add the block to its corresponding list
-            memory_blocks[current_order as usize].push_back(MemoryBlock {
+            // Add the block to its corresponding map
+            memory_blocks[current_order as usize].insert(block_start_address, MemoryBlock {
                 is_allocated: false,
                 starting_address: block_start_address,
                 size_class: current_order as usize,
@@ -277,6 +435,11 @@ impl BuddyAllocator {
     /// Split a 2^order sized block into two 2^order-1 sized blocks, and sets the first one as allocated and returns it.
     /// The created blocks are added to the free_areas array at index order-1 and the original block is marked as allocated.
     fn split_block(&mut self, order: usize) -> Result<PhysicalAddress, &'static str> {
+        self.split_block_where(order, &|_| true)
+    }
+
+    /// Same as `split_block`, but only considers the blocks accepted by the filter
+    fn split_block_where(&mut self, order: usize, filter: &dyn Fn(&MemoryBlock) -> bool) -> Result<PhysicalAddress, &'static str> {
         if order == 0 {
             return Err("cannot split block further");
         }
@@ -285,7 +448,7 @@ impl BuddyAllocator {
         let mut first_free_block: Option<&mut MemoryBlock> = None;
         let mut current_order = order;
         while first_free_block.is_none() && current_order <= MAX_ORDER {
-            first_free_block = self.memory_blocks[current_order].iter_mut().find(|block| !block.is_allocated);
+            first_free_block = self.memory_blocks[current_order].values_mut().find(|block| !block.is_allocated && filter(block));
             current_order += 1;
         }
 
@@ -313,9 +476,9 @@ impl BuddyAllocator {
                         block_type: BlockType::RightBuddy
                     };
 
-                    // Add the two buddies to the linked list
-                    self.memory_blocks[buddy_size_class].push_back(left_buddy);
-                    self.memory_blocks[buddy_size_class].push_back(right_buddy);
+                    // Add the two buddies to the map of their order
+                    self.memory_blocks[buddy_size_class].insert(left_buddy.starting_address, left_buddy);
+                    self.memory_blocks[buddy_size_class].insert(right_buddy.starting_address, right_buddy);
 
                     // Return only the (allocated) left buddy
                     current_block_clone = left_buddy
@@ -339,15 +502,32 @@ impl FrameAllocator for BuddyAllocator {
     fn deallocate_frame(&mut self, frame: Frame) -> Result<(), &'static str> {
         self.deallocate_frames(frame.start_address(), 0)
     }
+
+    /// Takes the smallest block holding `count` frames and gives back the frames past the first
+    /// `count` ones instead of rounding the allocation up to a power of two
+    fn allocate_contiguous(&mut self, count: usize) -> Result<FrameRange, &'static str> {
+        if count == 0 {
+            return Err("pmm: cannot allocate zero frames");
+        }
+
+        let order = count.next_power_of_two().trailing_zeros() as usize;
+        let start_address = self.allocate_frames(order)?;
+        self.trim_block(start_address, order, count);
+
+        Ok(FrameRange { start: Frame::containing_address(start_address), count })
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use limine::memory_map::EntryType;
     use crate::memory::PAGE_SIZE;
-    use crate::memory::physical_memory::buddy_allocator::{BlockType, BuddyAllocator, MAX_ORDER, MemoryBlock};
+    use alloc::vec;
+    use alloc::vec::Vec;
+    use crate::memory::physical_memory::buddy_allocator::{AllocationHint, BlockType, BuddyAllocator, FREED_FRAME_POISON, MAX_ORDER, MemoryBlock, MemoryRegion};
     use crate::memory::physical_memory::FrameAllocator;
-    use crate::MEMORY_MAP_REQUEST;
+    use crate::memory::{Frame, MemoryManager};
+    use crate::{HHDM_OFFSET, MEMORY_MAP_REQUEST};
 
     #[test_case]
     fn allocation_too_large() {
@@ -436,14 +616,192 @@ mod tests {
         };
 
         let mut allocator = BuddyAllocator::new(memory_map);
-        allocator.memory_blocks[large_block_size].push_front(large_block);
+        allocator.memory_blocks[large_block_size].insert(large_block.starting_address, large_block);
 
         // WHEN
         let split_block = allocator.split_block(5);
 
         // THEN
         assert!(split_block.is_ok());
-        assert_eq!(allocator.memory_blocks[large_block_size - 1].contains(&expected_left_buddy), true);
-        assert_eq!(allocator.memory_blocks[large_block_size - 1].contains(&expected_right_buddy), true);
+        assert_eq!(allocator.memory_blocks[large_block_size - 1].get(&expected_left_buddy.starting_address), Some(&expected_left_buddy));
+        assert_eq!(allocator.memory_blocks[large_block_size - 1].get(&expected_right_buddy.starting_address), Some(&expected_right_buddy));
     }
-}
\ No newline at end of file
+
+    #[test_case]
+    fn hinted_allocation_uses_region_containing_hint() {
+        // GIVEN
+        let first_region = MemoryRegion { start_address: 0x100000, size: 64 * PAGE_SIZE, node: 0 };
+        let second_region = MemoryRegion { start_address: 0x200000, size: 64 * PAGE_SIZE, node: 0 };
+        let mut allocator = BuddyAllocator::with_regions(vec![first_region, second_region]);
+
+        // WHEN
+        let second_region_frame = allocator.allocate_frames_with_hint(0, AllocationHint::Address(0x200000 + 5 * PAGE_SIZE));
+        let first_region_frame = allocator.allocate_frames_with_hint(0, AllocationHint::Address(0x100000));
+
+        // THEN
+        assert!(second_region.contains_address(second_region_frame.unwrap()));
+        assert!(first_region.contains_address(first_region_frame.unwrap()));
+    }
+
+    #[test_case]
+    fn hinted_allocation_falls_back_when_region_exhausted() {
+        // GIVEN
+        let first_region = MemoryRegion { start_address: 0x100000, size: 64 * PAGE_SIZE, node: 0 };
+        let second_region = MemoryRegion { start_address: 0x200000, size: 64 * PAGE_SIZE, node: 0 };
+        let mut allocator = BuddyAllocator::with_regions(vec![first_region, second_region]);
+        let hint = AllocationHint::Address(second_region.start_address);
+
+        // WHEN
+        let first_allocation = allocator.allocate_frames_with_hint(6, hint);
+        let second_allocation = allocator.allocate_frames_with_hint(6, hint);
+
+        // THEN
+        assert_eq!(first_allocation, Ok(second_region.start_address));
+        assert_eq!(second_allocation, Ok(first_region.start_address));
+    }
+
+    #[test_case]
+    fn freed_frame_is_poisoned() {
+        // GIVEN
+        let mut memory_manager = MemoryManager::instance().lock();
+        let allocator = &mut memory_manager.frame_allocator;
+        let frame = allocator.allocate_frame().unwrap();
+        let contents = unsafe { core::slice::from_raw_parts_mut((frame.start_address() + *HHDM_OFFSET) as *mut u32, PAGE_SIZE / 4) };
+        contents.fill(0x12345678);
+
+        // WHEN
+        allocator.set_poison_freed_frames(true);
+        let deallocation = allocator.deallocate_frame(frame);
+        allocator.set_poison_freed_frames(cfg!(feature = "poison-freed-frames"));
+
+        // THEN
+        assert!(deallocation.is_ok());
+        assert!(contents.iter().all(|word| *word == FREED_FRAME_POISON));
+    }
+
+    #[test_case]
+    fn batch_allocation_is_freed_as_a_whole() {
+        // GIVEN
+        let region = MemoryRegion { start_address: 0x100000, size: 64 * PAGE_SIZE, node: 0 };
+        let mut allocator = BuddyAllocator::with_regions(vec![region]);
+
+        // WHEN
+        let frames = allocator.allocate_batch(10).unwrap();
+        let allocated_amount = allocator.get_allocated_amount();
+        let deallocation = allocator.deallocate_batch(&frames);
+
+        // THEN
+        assert_eq!(frames.len(), 10);
+        assert!(frames.iter().all(|frame| region.contains_address(frame.start_address())));
+        assert!(frames.iter().enumerate().all(|(index, frame)| !frames[index + 1..].contains(frame)));
+        assert_eq!(allocated_amount, 10 * PAGE_SIZE);
+        assert_eq!(deallocation, Ok(()));
+        assert_eq!(allocator.get_allocated_amount(), 0);
+    }
+
+    #[test_case]
+    fn contiguous_allocation_is_not_rounded_up() {
+        // GIVEN
+        let memory_map = MEMORY_MAP_REQUEST.get_response().expect("could not find the memory map");
+        let mut allocator = BuddyAllocator::new(memory_map);
+
+        // WHEN
+        let range = allocator.allocate_contiguous(3).unwrap();
+
+        // THEN
+        let frames: Vec<Frame> = range.iter().collect();
+        assert_eq!(frames.len(), 3);
+        assert!(frames.windows(2).all(|pair| pair[1].start_address() == pair[0].start_address() + PAGE_SIZE));
+        assert_eq!(allocator.get_allocated_amount(), 3 * PAGE_SIZE);
+
+        let fourth_frame = range.start_address() + 3 * PAGE_SIZE;
+        assert!(allocator.memory_blocks[0].get(&fourth_frame).is_some_and(|block| !block.is_allocated));
+    }
+
+    #[test_case]
+    fn contiguous_allocation_leaves_tail_available() {
+        // GIVEN
+        let memory_map = MEMORY_MAP_REQUEST.get_response().expect("could not find the memory map");
+        let mut allocator = BuddyAllocator::new(memory_map);
+
+        // WHEN
+        let range = allocator.allocate_contiguous(5).unwrap();
+
+        // THEN
+        let is_free = |address: usize, order: usize| allocator.memory_blocks[order].get(&address)
+            .is_some_and(|block| !block.is_allocated);
+        assert_eq!(range.count, 5);
+        assert_eq!(allocator.get_allocated_amount(), 5 * PAGE_SIZE);
+        assert!(is_free(range.start_address() + 5 * PAGE_SIZE, 0));
+        assert!(is_free(range.start_address() + 6 * PAGE_SIZE, 1));
+    }
+
+    #[test_case]
+    fn contiguous_allocation_can_be_freed() {
+        // GIVEN
+        let memory_map = MEMORY_MAP_REQUEST.get_response().expect("could not find the memory map");
+        let mut allocator = BuddyAllocator::new(memory_map);
+        let range = allocator.allocate_contiguous(7).unwrap();
+
+        // WHEN
+        let result = allocator.deallocate_contiguous(range);
+
+        // THEN
+        assert_eq!(result, Ok(()));
+        assert_eq!(allocator.get_allocated_amount(), 0);
+        assert_eq!(allocator.allocate_contiguous(0), Err("pmm: cannot allocate zero frames"));
+    }
+
+    #[test_case]
+    fn deallocation_merges_buddies_out_of_the_map() {
+        // GIVEN
+        let region = MemoryRegion { start_address: 0x100000, size: 64 * PAGE_SIZE, node: 0 };
+        let mut allocator = BuddyAllocator::with_regions(vec![region]);
+        let address = allocator.allocate_frames(0).unwrap();
+
+        // WHEN
+        let result = allocator.deallocate_frames(address, 0);
+
+        // THEN
+        assert_eq!(result, Ok(()));
+        assert!(allocator.memory_blocks[0].is_empty());
+        assert_eq!(allocator.memory_blocks[1].get(&address).map(|block| block.is_allocated), Some(false));
+        assert_eq!(allocator.allocate_frames(0), Ok(address));
+    }
+
+    #[test_case]
+    fn frame_at_address_is_found_in_containing_block() {
+        // GIVEN
+        let region = MemoryRegion { start_address: 0x100000, size: 64 * PAGE_SIZE, node: 0 };
+        let mut allocator = BuddyAllocator::with_regions(vec![region]);
+        let address = region.start_address + 5 * PAGE_SIZE;
+
+        // WHEN
+        let result = allocator.set_allocated_frames(vec![address]);
+
+        // THEN
+        assert_eq!(result, Ok(()));
+        assert_eq!(allocator.memory_blocks[0].get(&address).map(|block| block.is_allocated), Some(true));
+        assert_eq!(allocator.memory_blocks[0].get(&(address - PAGE_SIZE)).map(|block| block.is_allocated), Some(false));
+        assert_eq!(allocator.get_allocated_amount(), PAGE_SIZE);
+        assert_eq!(allocator.set_allocated_frames(vec![address]), Err("frame already allocated"));
+    }
+
+    #[test_case]
+    fn blocks_are_keyed_by_their_address() {
+        // GIVEN
+        let region = MemoryRegion { start_address: 0x100000, size: 64 * PAGE_SIZE, node: 0 };
+        let mut allocator = BuddyAllocator::with_regions(vec![region]);
+
+        // WHEN
+        let first_frame = allocator.allocate_frames(0).unwrap();
+        let second_frame = allocator.allocate_frames(0).unwrap();
+
+        // THEN
+        assert_eq!(first_frame, region.start_address);
+        assert_eq!(second_frame, region.start_address + PAGE_SIZE);
+        assert!(allocator.memory_blocks.iter()
+            .all(|blocks| blocks.iter().all(|(address, block)| *address == block.starting_address)));
+        assert_eq!(allocator.deallocate_frames(first_frame + PAGE_SIZE * 2, 0), Err("could not find the frame to deallocate"));
+    }
+}
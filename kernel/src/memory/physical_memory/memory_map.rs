@@ -0,0 +1,105 @@
+use alloc::vec::Vec;
+use limine::memory_map::{Entry, EntryType};
+use crate::memory::{PAGE_SIZE, PhysicalAddress};
+
+/// A memory map entry after adjacent entries of the same type were merged and usable memory was
+/// trimmed to whole pages
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct SanitizedEntry {
+    pub base: PhysicalAddress,
+    pub length: usize,
+    pub entry_type: EntryType,
+}
+
+impl SanitizedEntry {
+    pub fn end(&self) -> PhysicalAddress {
+        self.base + self.length
+    }
+}
+
+/// Cleans up the memory map given by the bootloader. Zero-length entries are dropped, adjacent
+/// entries of the same type are merged and usable regions are shrunk to page boundaries, dropping
+/// the ones smaller than a page.
+pub fn sanitize_memory_map(entries: &[&Entry]) -> Vec<SanitizedEntry> {
+    let mut sorted_entries: Vec<SanitizedEntry> = entries.iter()
+        .filter(|entry| entry.length > 0)
+        .map(|entry| SanitizedEntry { base: entry.base as PhysicalAddress, length: entry.length as usize, entry_type: entry.entry_type })
+        .collect();
+    sorted_entries.sort_by_key(|entry| entry.base);
+
+    let mut merged_entries: Vec<SanitizedEntry> = Vec::with_capacity(sorted_entries.len());
+    for entry in sorted_entries {
+        match merged_entries.last_mut() {
+            Some(previous) if previous.entry_type == entry.entry_type && previous.end() == entry.base => previous.length += entry.length,
+            _ => merged_entries.push(entry),
+        }
+    }
+
+    // Rounding only after merging keeps usable memory split across entries in the middle of a page
+    merged_entries.into_iter()
+        .filter_map(|entry| match entry.entry_type {
+            EntryType::USABLE => {
+                let base = entry.base.next_multiple_of(PAGE_SIZE);
+                let end = entry.end() - entry.end() % PAGE_SIZE;
+                (end > base).then_some(SanitizedEntry { base, length: end - base, entry_type: entry.entry_type })
+            }
+            _ => Some(entry),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec;
+    use limine::memory_map::{Entry, EntryType};
+    use crate::memory::physical_memory::memory_map::{sanitize_memory_map, SanitizedEntry};
+
+    #[test_case]
+    fn adjacent_entries_of_same_type_are_merged() {
+        // GIVEN
+        let first = Entry { base: 0x1000, length: 0x2000, entry_type: EntryType::USABLE };
+        let second = Entry { base: 0x3000, length: 0x1000, entry_type: EntryType::USABLE };
+        let reserved = Entry { base: 0x4000, length: 0x1000, entry_type: EntryType::RESERVED };
+        let empty = Entry { base: 0x5000, length: 0, entry_type: EntryType::RESERVED };
+        let other_reserved = Entry { base: 0x5000, length: 0x1000, entry_type: EntryType::RESERVED };
+
+        // WHEN
+        let entries = sanitize_memory_map(&[&first, &second, &reserved, &empty, &other_reserved]);
+
+        // THEN
+        assert_eq!(entries, vec![
+            SanitizedEntry { base: 0x1000, length: 0x3000, entry_type: EntryType::USABLE },
+            SanitizedEntry { base: 0x4000, length: 0x2000, entry_type: EntryType::RESERVED },
+        ]);
+    }
+
+    #[test_case]
+    fn usable_entries_are_rounded_to_pages() {
+        // GIVEN
+        let unaligned = Entry { base: 0x1800, length: 0x3000, entry_type: EntryType::USABLE };
+        let sub_page = Entry { base: 0x10100, length: 0x800, entry_type: EntryType::USABLE };
+        let reserved = Entry { base: 0x20010, length: 0x20, entry_type: EntryType::RESERVED };
+
+        // WHEN
+        let entries = sanitize_memory_map(&[&unaligned, &sub_page, &reserved]);
+
+        // THEN
+        assert_eq!(entries, vec![
+            SanitizedEntry { base: 0x2000, length: 0x2000, entry_type: EntryType::USABLE },
+            SanitizedEntry { base: 0x20010, length: 0x20, entry_type: EntryType::RESERVED },
+        ]);
+    }
+
+    #[test_case]
+    fn usable_entries_split_mid_page_are_merged_before_rounding() {
+        // GIVEN
+        let first = Entry { base: 0x1000, length: 0x800, entry_type: EntryType::USABLE };
+        let second = Entry { base: 0x1800, length: 0x1800, entry_type: EntryType::USABLE };
+
+        // WHEN
+        let entries = sanitize_memory_map(&[&second, &first]);
+
+        // THEN
+        assert_eq!(entries, vec![SanitizedEntry { base: 0x1000, length: 0x2000, entry_type: EntryType::USABLE }]);
+    }
+}
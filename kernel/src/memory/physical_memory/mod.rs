@@ -1,7 +1,9 @@
+use alloc::vec::Vec;
 use crate::memory::{PAGE_SIZE, PhysicalAddress};
 
 pub mod linear_frame_allocator;
 pub mod buddy_allocator;
+pub mod memory_map;
 mod static_buddy_allocator;
 mod static_linear_allocator;
 
@@ -52,7 +54,111 @@ impl Iterator for FrameIter {
     }
 }
 
+/// A span of physically contiguous frames
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct FrameRange {
+    pub start: Frame,
+    pub count: usize,
+}
+
+impl FrameRange {
+    pub fn start_address(&self) -> PhysicalAddress {
+        self.start.start_address()
+    }
+
+    /// Returns the last frame of the range
+    pub fn end(&self) -> Frame {
+        Frame { number: self.start.number + self.count - 1 }
+    }
+
+    pub fn iter(&self) -> FrameIter {
+        Frame::range_inclusive(self.start, self.end())
+    }
+}
+
 pub trait FrameAllocator {
     fn allocate_frame(&mut self) -> Result<Frame, &'static str>;
     fn deallocate_frame(&mut self, frame: Frame) -> Result<(), &'static str>;
-}
\ No newline at end of file
+
+    /// Allocates exactly `count` physically contiguous frames
+    fn allocate_contiguous(&mut self, _count: usize) -> Result<FrameRange, &'static str> {
+        Err("pmm: this allocator cannot allocate contiguous frames")
+    }
+
+    /// Allocates `count` frames that are not necessarily contiguous, so that a caller holding a lock
+    /// around the allocator gets them all at once. Either every frame is allocated or none is.
+    fn allocate_batch(&mut self, count: usize) -> Result<Vec<Frame>, &'static str> {
+        let mut frames = Vec::with_capacity(count);
+        for _ in 0..count {
+            match self.allocate_frame() {
+                Ok(frame) => frames.push(frame),
+                Err(err) => {
+                    self.deallocate_batch(&frames)?;
+                    return Err(err);
+                }
+            }
+        }
+
+        Ok(frames)
+    }
+
+    /// Frees frames returned by `allocate_batch`
+    fn deallocate_batch(&mut self, frames: &[Frame]) -> Result<(), &'static str> {
+        frames.iter().try_for_each(|frame| self.deallocate_frame(*frame))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec::Vec;
+    use crate::memory::physical_memory::{Frame, FrameAllocator};
+
+    /// Hands out the frames of a fixed pool, the ones given back are recorded
+    struct PoolAllocator {
+        next_frame: usize,
+        frame_count: usize,
+        freed: Vec<Frame>,
+    }
+
+    impl FrameAllocator for PoolAllocator {
+        fn allocate_frame(&mut self) -> Result<Frame, &'static str> {
+            if self.next_frame == self.frame_count {
+                return Err("pool: no frame left");
+            }
+
+            self.next_frame += 1;
+            Ok(Frame { number: self.next_frame - 1 })
+        }
+
+        fn deallocate_frame(&mut self, frame: Frame) -> Result<(), &'static str> {
+            self.freed.push(frame);
+            Ok(())
+        }
+    }
+
+    #[test_case]
+    fn batch_allocation_returns_distinct_frames() {
+        // GIVEN
+        let mut allocator = PoolAllocator { next_frame: 0, frame_count: 16, freed: Vec::new() };
+
+        // WHEN
+        let frames = allocator.allocate_batch(5).unwrap();
+
+        // THEN
+        assert_eq!(frames.len(), 5);
+        assert!(frames.iter().enumerate().all(|(index, frame)| !frames[index + 1..].contains(frame)));
+    }
+
+    #[test_case]
+    fn failed_batch_allocation_frees_the_frames_it_got() {
+        // GIVEN
+        let mut allocator = PoolAllocator { next_frame: 0, frame_count: 3, freed: Vec::new() };
+
+        // WHEN
+        let result = allocator.allocate_batch(4);
+
+        // THEN
+        assert_eq!(result, Err("pool: no frame left"));
+        assert_eq!(allocator.freed, [Frame { number: 0 }, Frame { number: 1 }, Frame { number: 2 }]);
+    }
+}
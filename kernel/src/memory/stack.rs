@@ -0,0 +1,119 @@
+use core::arch::asm;
+use core::ops::{DerefMut, Range};
+use conquer_once::spin::OnceCell;
+use crate::memory::{MemoryManager, PAGE_SIZE, VirtualAddress};
+use crate::memory::physical_memory::FrameAllocator;
+use crate::memory::virtual_memory::paging::entry::EntryFlags;
+use crate::memory::virtual_memory::paging::Page;
+
+/// Size of the stack the kernel runs on once initialized, Limine only guarantees 64KiB
+pub const KERNEL_STACK_SIZE: usize = 128 * 1024;
+
+static KERNEL_STACK: OnceCell<GuardedStack> = OnceCell::uninit();
+
+/// Stack allocated in the kernel allocation space with an unmapped guard page right below it, so
+/// that overflowing it faults instead of overwriting whatever is mapped there
+#[derive(Debug, Copy, Clone)]
+pub struct GuardedStack {
+    guard_page: VirtualAddress,
+    top: VirtualAddress,
+}
+
+impl GuardedStack {
+    pub fn allocate(size: usize) -> Result<Self, &'static str> {
+        let page_count = size.div_ceil(PAGE_SIZE);
+
+        let mut memory_manager = MemoryManager::instance().lock();
+        let memory_manager = memory_manager.deref_mut();
+
+        // The first page of the range is the guard and is left unmapped
+        let guard_page = memory_manager.virtual_memory_manager.allocate_pages(page_count + 1)?;
+        for page_index in 1..=page_count {
+            let page = Page::containing_address(guard_page + page_index * PAGE_SIZE);
+            let frame = memory_manager.frame_allocator.allocate_frame()?;
+
+            memory_manager.active_page_table.map_to(page, frame, EntryFlags::WRITABLE | EntryFlags::NO_EXECUTE, &mut memory_manager.frame_allocator);
+        }
+
+        Ok(Self {
+            guard_page,
+            top: guard_page + (page_count + 1) * PAGE_SIZE,
+        })
+    }
+
+    pub fn top(&self) -> VirtualAddress {
+        self.top
+    }
+
+    pub fn guard_page(&self) -> Range<VirtualAddress> {
+        self.guard_page..self.guard_page + PAGE_SIZE
+    }
+
+    /// Addresses usable as stack
+    pub fn range(&self) -> Range<VirtualAddress> {
+        self.guard_page + PAGE_SIZE..self.top
+    }
+}
+
+/// Allocates the guarded kernel stack and continues execution on it by calling `entry`
+pub fn switch_to_kernel_stack(entry: extern "C" fn() -> !) -> Result<!, &'static str> {
+    let stack = GuardedStack::allocate(KERNEL_STACK_SIZE)?;
+    KERNEL_STACK.try_init_once(|| stack).map_err(|_| "mm: the kernel stack can only be switched once")?;
+
+    unsafe {
+        asm!(
+            "mov rsp, {top}",
+            "xor rbp, rbp",
+            "call {entry}",
+            top = in(reg) stack.top(),
+            entry = in(reg) entry,
+            options(noreturn)
+        );
+    }
+}
+
+/// Returns the kernel stack, once execution has been moved to it
+pub fn kernel_stack() -> Option<&'static GuardedStack> {
+    KERNEL_STACK.get()
+}
+
+/// Whether the address is in the guard page of the kernel stack
+pub fn is_kernel_stack_guard_address(address: VirtualAddress) -> bool {
+    kernel_stack().is_some_and(|stack| stack.guard_page().contains(&address))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::arch::x86_64::registers::rsp;
+    use crate::memory::MemoryManager;
+    use crate::memory::stack::{is_kernel_stack_guard_address, kernel_stack, KERNEL_STACK_SIZE};
+
+    #[test_case]
+    fn tests_run_on_guarded_kernel_stack() {
+        // GIVEN
+        let stack = kernel_stack().expect("the kernel stack was not switched");
+
+        // WHEN
+        let stack_pointer = rsp();
+
+        // THEN
+        assert!(stack.range().contains(&stack_pointer));
+        assert_eq!(stack.range().len(), KERNEL_STACK_SIZE);
+    }
+
+    #[test_case]
+    fn kernel_stack_guard_page_is_unmapped() {
+        // GIVEN
+        let stack = kernel_stack().expect("the kernel stack was not switched");
+
+        // WHEN
+        let guard_translation = MemoryManager::translate(stack.guard_page().start);
+        let bottom_translation = MemoryManager::translate(stack.range().start);
+
+        // THEN
+        assert!(guard_translation.is_none());
+        assert!(bottom_translation.is_some());
+        assert!(is_kernel_stack_guard_address(stack.guard_page().end - 8));
+        assert!(!is_kernel_stack_guard_address(stack.range().start));
+    }
+}
@@ -0,0 +1,128 @@
+use core::alloc::Layout;
+use core::ptr;
+
+/// Hands out memory from a fixed buffer by moving a cursor forward. Single allocations cannot be
+/// reclaimed, the whole buffer is reused once every allocation was freed.
+pub struct BumpArena<const SIZE: usize> {
+    memory: [u8; SIZE],
+    next: usize,
+    live_allocations: usize,
+}
+
+impl<const SIZE: usize> BumpArena<SIZE> {
+    pub const fn new() -> Self {
+        Self {
+            memory: [0; SIZE],
+            next: 0,
+            live_allocations: 0,
+        }
+    }
+
+    /// Returns a block fitting the layout, or a null pointer if the arena is exhausted
+    pub fn allocate(&mut self, layout: Layout) -> *mut u8 {
+        let start = self.memory.as_ptr() as usize;
+        let address = (start + self.next).next_multiple_of(layout.align());
+        let Some(end) = address.checked_add(layout.size()).filter(|end| *end <= start + SIZE) else {
+            return ptr::null_mut();
+        };
+
+        self.next = end - start;
+        self.live_allocations += 1;
+        address as *mut u8
+    }
+
+    /// Releases a block, rewinding the arena once none is left
+    pub fn deallocate(&mut self, _ptr: *mut u8) {
+        self.live_allocations -= 1;
+        if self.live_allocations == 0 {
+            self.reset();
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.next = 0;
+        self.live_allocations = 0;
+    }
+
+    /// Returns whether the pointer was handed out by this arena
+    pub fn contains(&self, ptr: *const u8) -> bool {
+        self.memory.as_ptr_range().contains(&ptr)
+    }
+
+    pub fn used_bytes(&self) -> usize {
+        self.next
+    }
+
+    pub fn live_allocations(&self) -> usize {
+        self.live_allocations
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::alloc::Layout;
+    use crate::memory::virtual_memory::heap_allocator::bump_allocator::BumpArena;
+
+    #[test_case]
+    fn allocations_are_aligned_and_do_not_overlap() {
+        // GIVEN
+        let mut arena = BumpArena::<256>::new();
+
+        // WHEN
+        let first = arena.allocate(Layout::from_size_align(3, 1).unwrap());
+        let second = arena.allocate(Layout::from_size_align(16, 16).unwrap());
+
+        // THEN
+        assert!(arena.contains(first) && arena.contains(second));
+        assert_eq!(second as usize % 16, 0);
+        assert!(second as usize >= first as usize + 3);
+        assert_eq!(arena.live_allocations(), 2);
+    }
+
+    #[test_case]
+    fn exhausted_arena_returns_null() {
+        // GIVEN
+        let mut arena = BumpArena::<64>::new();
+        arena.allocate(Layout::from_size_align(48, 1).unwrap());
+
+        // WHEN
+        let allocation = arena.allocate(Layout::from_size_align(32, 1).unwrap());
+
+        // THEN
+        assert!(allocation.is_null());
+        assert_eq!(arena.live_allocations(), 1);
+    }
+
+    #[test_case]
+    fn arena_rewinds_once_everything_is_freed() {
+        // GIVEN
+        let mut arena = BumpArena::<64>::new();
+        let first = arena.allocate(Layout::from_size_align(8, 8).unwrap());
+        let second = arena.allocate(Layout::from_size_align(8, 8).unwrap());
+
+        // WHEN
+        arena.deallocate(first);
+        let used_after_first = arena.used_bytes();
+        arena.deallocate(second);
+
+        // THEN
+        assert!(used_after_first > 0);
+        assert_eq!(arena.used_bytes(), 0);
+        assert_eq!(arena.allocate(Layout::from_size_align(8, 8).unwrap()), first);
+    }
+
+    #[test_case]
+    fn reset_forgets_allocations() {
+        // GIVEN
+        let mut arena = BumpArena::<64>::new();
+        arena.allocate(Layout::from_size_align(60, 1).unwrap());
+
+        // WHEN
+        arena.reset();
+
+        // THEN
+        assert_eq!(arena.used_bytes(), 0);
+        assert_eq!(arena.live_allocations(), 0);
+        assert!(!arena.allocate(Layout::from_size_align(60, 1).unwrap()).is_null());
+    }
+}
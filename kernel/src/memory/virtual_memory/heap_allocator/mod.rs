@@ -1,7 +1,11 @@
+mod bump_allocator;
 mod slab_allocator;
 
+use core::alloc::Layout;
+use core::fmt;
+use core::fmt::{Display, Formatter};
 use crate::memory::{VirtualAddress};
-use crate::memory::virtual_memory::heap_allocator::slab_allocator::SlabAllocator;
+use crate::memory::virtual_memory::heap_allocator::slab_allocator::{HeapStats, SlabAllocator};
 use crate::memory::virtual_memory::paging::{ActivePageTable, Page};
 use crate::memory::virtual_memory::paging::entry::EntryFlags;
 use crate::memory::physical_memory::FrameAllocator;
@@ -28,6 +32,32 @@ impl<A> Locked<A> {
     }
 }
 
+/// Returns the current usage of the kernel heap
+pub fn heap_stats() -> HeapStats {
+    ALLOCATOR.lock().stats()
+}
+
+/// Describes an allocation the heap could not satisfy. Formatting it does not allocate.
+struct AllocErrorReport {
+    layout: Layout,
+    stats: HeapStats,
+}
+
+impl Display for AllocErrorReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "heap: could not allocate {} bytes with alignment {} ({} bytes live, {} bytes peak)",
+               self.layout.size(), self.layout.align(), self.stats.live_bytes, self.stats.peak_bytes)
+    }
+}
+
+#[alloc_error_handler]
+fn alloc_error_handler(layout: Layout) -> ! {
+    panic!("{}", AllocErrorReport { layout, stats: heap_stats() });
+}
+
+/// Maps the heap and hands off to it. Allocations made before this are served by a small bump arena
+/// inside the allocator, so the memory manager can allocate while bringing up the heap. The early
+/// allocations stay in the arena until they are freed.
 pub fn init_heap<A>(frame_allocator: &mut A, page_table: &mut ActivePageTable) where A: FrameAllocator {
     serial_println!("mm: initializing the heap...");
 
@@ -49,8 +79,9 @@ pub fn init_heap<A>(frame_allocator: &mut A, page_table: &mut ActivePageTable) w
         page_table.map_to(page, frame, flags, frame_allocator)
     }
 
-    unsafe {
-        ALLOCATOR.lock().init(HEAP_START, HEAP_SIZE);
+    let early_allocations = unsafe { ALLOCATOR.lock().init(HEAP_START, HEAP_SIZE) };
+    if early_allocations > 0 {
+        serial_println!("mm: {} early allocations are still live in the bump arena", early_allocations);
     }
 
     serial_println!("mm: heap starts at 0x{:X}", HEAP_START);
@@ -59,8 +90,15 @@ pub fn init_heap<A>(frame_allocator: &mut A, page_table: &mut ActivePageTable) w
 #[cfg(test)]
 mod tests {
     use alloc::boxed::Box;
+    use alloc::format;
     use alloc::vec::Vec;
+    use core::alloc::{GlobalAlloc, Layout};
+    use crate::memory::{MemoryManager, PAGE_SIZE};
     use crate::memory::virtual_memory::heap_allocator::HEAP_SIZE;
+    use crate::memory::virtual_memory::heap_allocator::Locked;
+    use crate::memory::virtual_memory::heap_allocator::AllocErrorReport;
+    use crate::memory::virtual_memory::heap_allocator::slab_allocator::{CanaryDamage, HeapStats, SlabAllocator};
+    use crate::memory::virtual_memory::paging::entry::EntryFlags;
 
     #[test_case]
     fn box_allocation() {
@@ -82,6 +120,91 @@ mod tests {
         assert_eq!(vec.iter().sum::<u64>(), (n - 1) * n / 2);
     }
 
+    #[test_case]
+    fn alloc_error_report_includes_layout_and_stats() {
+        // GIVEN
+        let report = AllocErrorReport {
+            layout: Layout::from_size_align(4096, 64).unwrap(),
+            stats: HeapStats { live_bytes: 1000, peak_bytes: 2000 },
+        };
+
+        // WHEN
+        let message = format!("{}", report);
+
+        // THEN
+        assert!(message.contains("4096 bytes"));
+        assert!(message.contains("alignment 64"));
+        assert!(message.contains("1000 bytes live"));
+        assert!(message.contains("2000 bytes peak"));
+    }
+
+    #[test_case]
+    fn early_allocations_are_handed_off_to_the_heap() {
+        // GIVEN
+        let allocator = Locked::new(SlabAllocator::new());
+        let layout = Layout::from_size_align(32, 8).unwrap();
+        let early = unsafe { allocator.alloc(layout) };
+        let heap_start = MemoryManager::vmm_alloc(PAGE_SIZE, EntryFlags::WRITABLE).unwrap();
+
+        // WHEN
+        let live_at_handoff = unsafe { allocator.lock().init(heap_start, PAGE_SIZE) };
+        let late = unsafe { allocator.alloc(layout) };
+        unsafe { allocator.dealloc(early, layout) };
+
+        // THEN
+        assert!(!early.is_null());
+        assert_eq!(live_at_handoff, 1);
+        assert!(allocator.lock().is_initialized());
+        assert!((heap_start..heap_start + PAGE_SIZE).contains(&(late as usize)));
+        assert_eq!(allocator.lock().early_allocations(), 0);
+        assert_eq!(allocator.lock().stats().live_bytes, 32);
+
+        unsafe { allocator.dealloc(late, layout) };
+        MemoryManager::vmm_free(PAGE_SIZE, heap_start).unwrap();
+    }
+
+    #[test_case]
+    fn write_past_the_end_damages_the_canary() {
+        // GIVEN
+        let allocator = Locked::new(SlabAllocator::with_guard_canaries(true));
+        let heap_start = MemoryManager::vmm_alloc(PAGE_SIZE, EntryFlags::WRITABLE).unwrap();
+        unsafe { allocator.lock().init(heap_start, PAGE_SIZE) };
+        let layout = Layout::from_size_align(24, 8).unwrap();
+        let intact = unsafe { allocator.alloc(layout) };
+        let overrun = unsafe { allocator.alloc(layout) };
+
+        // WHEN
+        unsafe { overrun.add(layout.size()).write(0) };
+
+        // THEN
+        assert_eq!(intact as usize % layout.align(), 0);
+        assert_eq!(unsafe { allocator.lock().check_canaries(intact, layout) }, Ok(()));
+        assert_eq!(unsafe { allocator.lock().check_canaries(overrun, layout) }, Err(CanaryDamage::Overrun));
+        unsafe { allocator.dealloc(intact, layout) };
+        assert_eq!(allocator.lock().stats().live_bytes, layout.size());
+
+        MemoryManager::vmm_free(PAGE_SIZE, heap_start).unwrap();
+    }
+
+    #[test_case]
+    fn write_before_the_start_damages_the_canary() {
+        // GIVEN
+        let allocator = Locked::new(SlabAllocator::with_guard_canaries(true));
+        let heap_start = MemoryManager::vmm_alloc(PAGE_SIZE, EntryFlags::WRITABLE).unwrap();
+        unsafe { allocator.lock().init(heap_start, PAGE_SIZE) };
+        let layout = Layout::from_size_align(100, 32).unwrap();
+        let allocation = unsafe { allocator.alloc(layout) };
+
+        // WHEN
+        unsafe { allocation.sub(1).write(0) };
+
+        // THEN
+        assert_eq!(allocation as usize % layout.align(), 0);
+        assert_eq!(unsafe { allocator.lock().check_canaries(allocation, layout) }, Err(CanaryDamage::Underrun));
+
+        MemoryManager::vmm_free(PAGE_SIZE, heap_start).unwrap();
+    }
+
     /*
     #[test_case]
     fn many_boxes() {
@@ -3,8 +3,14 @@ use core::{mem, ptr};
 use core::ptr::NonNull;
 use crate::memory::VirtualAddress;
 use super::Locked;
+use super::bump_allocator::BumpArena;
 
 const BLOCK_SIZES: &[usize] = &[8, 16, 32, 64, 128, 256, 512, 1024, 2048];
+/// Size of the arena serving the allocations made before the heap is mapped
+const EARLY_ARENA_SIZE: usize = 4096;
+/// Value written right before and right after every allocation when guard canaries are enabled
+const CANARY: u64 = 0xCA4A_21E5_CA4A_21E5;
+const CANARY_SIZE: usize = mem::size_of::<u64>();
 
 struct ListNode {
     next: Option<&'static mut ListNode>
@@ -14,20 +20,87 @@ pub struct SlabAllocator {
     list_heads: [Option<&'static mut ListNode>; BLOCK_SIZES.len()],
     fallback_allocator: linked_list_allocator::Heap,
     allocated_bytes: usize,
+    peak_allocated_bytes: usize,
+    /// Serves the allocations made during memory manager bring-up, until `init` hands off to the heap
+    early_arena: BumpArena<EARLY_ARENA_SIZE>,
+    is_initialized: bool,
+    /// Whether allocations are surrounded by canaries checked when they are freed
+    guard_canaries: bool,
+}
+
+/// Side of an allocation where the canary was overwritten
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum CanaryDamage {
+    /// Something wrote before the start of the allocation
+    Underrun,
+    /// Something wrote past the end of the allocation
+    Overrun,
+}
+
+/// Snapshot of the heap usage
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct HeapStats {
+    /// Bytes currently allocated
+    pub live_bytes: usize,
+    /// Highest amount of bytes allocated at once since the heap was initialized
+    pub peak_bytes: usize,
 }
 
 impl SlabAllocator {
     pub const fn new() -> Self {
+        Self::with_guard_canaries(cfg!(feature = "heap-canaries"))
+    }
+
+    /// Creates an allocator that surrounds every allocation with canaries when `guard_canaries` is set,
+    /// to find heap overruns when the allocation is freed
+    pub const fn with_guard_canaries(guard_canaries: bool) -> Self {
         const EMPTY: Option<&'static mut ListNode> = None;
         SlabAllocator {
             list_heads: [EMPTY; BLOCK_SIZES.len()],
             fallback_allocator: linked_list_allocator::Heap::empty(),
             allocated_bytes: 0,
+            peak_allocated_bytes: 0,
+            early_arena: BumpArena::new(),
+            is_initialized: false,
+            guard_canaries,
         }
     }
 
-    pub unsafe fn init(&mut self, heap_start: VirtualAddress, heap_size: usize) {
+    pub fn stats(&self) -> HeapStats {
+        HeapStats {
+            live_bytes: self.allocated_bytes,
+            peak_bytes: self.peak_allocated_bytes,
+        }
+    }
+
+    /// Hands off to the heap, later allocations no longer come from the early arena. Returns the
+    /// number of early allocations still live, they stay in the arena until they are freed.
+    pub unsafe fn init(&mut self, heap_start: VirtualAddress, heap_size: usize) -> usize {
         self.fallback_allocator.init(heap_start, heap_size);
+        self.is_initialized = true;
+
+        self.early_arena.live_allocations()
+    }
+
+    pub fn is_initialized(&self) -> bool {
+        self.is_initialized
+    }
+
+    /// Returns the number of live allocations made before the heap was initialized
+    pub fn early_allocations(&self) -> usize {
+        self.early_arena.live_allocations()
+    }
+
+    /// Checks that the canaries around an allocation returned with the given layout are intact
+    pub unsafe fn check_canaries(&self, ptr: *mut u8, layout: Layout) -> Result<(), CanaryDamage> {
+        if (ptr.sub(CANARY_SIZE) as *const u64).read_unaligned() != CANARY {
+            return Err(CanaryDamage::Underrun);
+        }
+        if (ptr.add(layout.size()) as *const u64).read_unaligned() != CANARY {
+            return Err(CanaryDamage::Overrun);
+        }
+
+        Ok(())
     }
 
     fn fallback_alloc(&mut self, layout: Layout) -> *mut u8 {
@@ -39,13 +112,12 @@ impl SlabAllocator {
 }
 
 unsafe impl GlobalAlloc for Locked<SlabAllocator> {
-    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+    unsafe fn alloc(&self, requested_layout: Layout) -> *mut u8 {
         let mut allocator = self.lock();
 
-        allocator.allocated_bytes += layout.size();
-        //serial_println!("Allocating {} bytes... {} bytes currently allocated", layout.size(), allocator.allocated_bytes);
-
-        match list_index(&layout) {
+        let layout = if allocator.guard_canaries { guarded_layout(requested_layout) } else { requested_layout };
+        let allocation = match list_index(&layout) {
+            _ if !allocator.is_initialized => allocator.early_arena.allocate(layout),
             Some(index) => {
                 match allocator.list_heads[index].take() {
                     Some(node) => {
@@ -62,15 +134,51 @@ unsafe impl GlobalAlloc for Locked<SlabAllocator> {
                 }
             }
             None => allocator.fallback_alloc(layout)
+        };
+
+        if allocation.is_null() {
+            return allocation;
+        }
+
+        allocator.allocated_bytes += requested_layout.size();
+        allocator.peak_allocated_bytes = allocator.peak_allocated_bytes.max(allocator.allocated_bytes);
+        //serial_println!("Allocating {} bytes... {} bytes currently allocated", layout.size(), allocator.allocated_bytes);
+
+        if !allocator.guard_canaries {
+            return allocation;
         }
+
+        let user_ptr = allocation.add(canary_offset(requested_layout));
+        (user_ptr.sub(CANARY_SIZE) as *mut u64).write_unaligned(CANARY);
+        (user_ptr.add(requested_layout.size()) as *mut u64).write_unaligned(CANARY);
+
+        user_ptr
     }
 
-    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+    unsafe fn dealloc(&self, ptr: *mut u8, requested_layout: Layout) {
         let mut allocator = self.lock();
 
-        allocator.allocated_bytes -= layout.size();
+        allocator.allocated_bytes -= requested_layout.size();
         //serial_println!("Deallocating {} bytes... {} bytes currently allocated", layout.size(), allocator.allocated_bytes);
 
+        let (ptr, layout) = if allocator.guard_canaries {
+            if let Err(damage) = allocator.check_canaries(ptr, requested_layout) {
+                // Reporting the panic may allocate
+                drop(allocator);
+                panic!("heap: canary {:?} detected on the {} bytes allocation at 0x{:X}", damage, requested_layout.size(), ptr as usize);
+            }
+
+            (ptr.sub(canary_offset(requested_layout)), guarded_layout(requested_layout))
+        }
+        else {
+            (ptr, requested_layout)
+        };
+
+        if allocator.early_arena.contains(ptr) {
+            allocator.early_arena.deallocate(ptr);
+            return;
+        }
+
         match list_index(&layout) {
             Some(index) => {
                 let new_node = ListNode {
@@ -92,6 +200,17 @@ unsafe impl GlobalAlloc for Locked<SlabAllocator> {
     }
 }
 
+/// Offset of the allocation from the start of its guarded block, leaves room for the first canary
+/// while keeping the allocation aligned
+fn canary_offset(layout: Layout) -> usize {
+    layout.align().max(CANARY_SIZE)
+}
+
+/// Layout of a block holding the allocation surrounded by its two canaries
+fn guarded_layout(layout: Layout) -> Layout {
+    Layout::from_size_align(canary_offset(layout) + layout.size() + CANARY_SIZE, layout.align().max(CANARY_SIZE)).unwrap()
+}
+
 fn list_index(layout: &Layout) -> Option<usize> {
     let required_block_size = layout.size().max(layout.align());
     BLOCK_SIZES.iter().position(|&s| s >= required_block_size)
@@ -4,9 +4,23 @@ use crate::memory::{PAGE_SIZE, VirtualAddress};
 pub mod paging;
 pub mod heap_allocator;
 
+pub const USER_SPACE_END: VirtualAddress = 0x0000800000000000;
 pub const KERNEL_ALLOCATION_SPACE_START: VirtualAddress = 0xFFFFC90000000000;
 pub const KERNEL_ALLOCATION_SPACE_END: VirtualAddress = 0xFFFFFFFEFFFFFFFF;
 pub const KERNEL_ALLOCATION_SPACE_SIZE: VirtualAddress = KERNEL_ALLOCATION_SPACE_END - KERNEL_ALLOCATION_SPACE_START;
+pub const DIRECT_MAPPING_START: VirtualAddress = 0xFFFF800000000000;
+pub const DIRECT_MAPPING_END: VirtualAddress = 0xFFFFC87FFFFFFFFF;
+pub const KERNEL_MAPPING_START: VirtualAddress = 0xFFFFFFFF80000000;
+
+/// Checks that a page may be mapped at the address. The direct mapping of physical memory and the
+/// kernel mapping are set up by the bootloader, mapping a page over them corrupts existing mappings.
+pub fn check_mappable(address: VirtualAddress) -> Result<(), &'static str> {
+    match address {
+        DIRECT_MAPPING_START..=DIRECT_MAPPING_END => Err("vmm: address is in the direct mapping of physical memory"),
+        KERNEL_MAPPING_START.. => Err("vmm: address is in the kernel mapping"),
+        _ => Ok(()),
+    }
+}
 
 /// # Tentative virtual memory map
 ///
@@ -114,50 +128,62 @@ impl VirtualMemoryManager {
         self.deallocate_pages(address, PAGE_SIZE)
     }
 
+    /// Frees a range of pages. The range does not need to match a prior allocation, freeing the head,
+    /// the tail or the middle of an allocation keeps the rest of it allocated. The freed range is
+    /// merged with the free regions on both of its sides.
     pub fn deallocate_pages(&mut self, start_address: VirtualAddress, size: usize) -> Result<(), &'static str> {
+        if size == 0 || start_address % PAGE_SIZE != 0 || size % PAGE_SIZE != 0 {
+            return Err("vmm: freed range must span whole pages");
+        }
+
+        let end_address = start_address.checked_add(size)
+            .filter(|end_address| start_address >= KERNEL_ALLOCATION_SPACE_START && *end_address <= KERNEL_ALLOCATION_SPACE_START + KERNEL_ALLOCATION_SPACE_SIZE)
+            .ok_or("vmm: freed range is outside of the kernel allocation space")?;
+
+        if self.overlaps_free_region(start_address, end_address) {
+            return Err("vmm: freed range is not allocated");
+        }
+
+        let (mut region_start, mut region_size) = (start_address, size);
+
         // If neighbouring left region is unallocated, merge it with the one currently being freed
-        if let Some(left_region) = self.free_addresses.range_mut(..start_address).next_back() {
-            // Check if region is a direct neighbour
-            if *left_region.0 + *left_region.1 == start_address {
-                // Increase the region's size
-                *left_region.1 += size;
-
-                // Sync the other tree
-                let removed_region = self.free_regions
-                    .remove_entry(&SizeKey{ size: *left_region.1 - size, index: *left_region.0});
-
-                return match removed_region {
-                    None => Err("vmm: fatal mismatch between vmemory trees when freeing page"),
-                    Some(removed_region) => {
-                        self.free_regions.insert(SizeKey { size: removed_region.0.size + size, index: removed_region.0.index }, removed_region.1);
-                        self.allocated_amount -= size;
-                        Ok(())
-                    }
-                }
+        if let Some((&left_start, &left_size)) = self.free_addresses.range(..start_address).next_back() {
+            if left_start + left_size == start_address {
+                self.remove_free_region(left_start, left_size)?;
+                region_start = left_start;
+                region_size += left_size;
             }
         }
 
-        return if let Some(removed_region) = self.free_addresses.remove_entry(&(start_address + size)) {
-            self.free_addresses.insert(start_address, removed_region.1 + size);
+        // Same with the neighbouring right region
+        if let Some(&right_size) = self.free_addresses.get(&end_address) {
+            self.remove_free_region(end_address, right_size)?;
+            region_size += right_size;
+        }
 
-            // Sync the other tree
-            self.free_regions.remove_entry(&SizeKey { size: removed_region.1, index: removed_region.0 });
-            self.free_regions.insert(SizeKey { size: removed_region.1 + size, index: start_address }, start_address);
+        self.free_addresses.insert(region_start, region_size);
+        self.free_regions.insert(SizeKey { size: region_size, index: region_start }, region_start);
+        self.allocated_amount -= size;
+
+        Ok(())
+    }
 
-            self.allocated_amount -= size;
+    /// Whether any page between the two addresses is free
+    fn overlaps_free_region(&self, start_address: VirtualAddress, end_address: VirtualAddress) -> bool {
+        let overlaps_left_region = self.free_addresses.range(..=start_address).next_back()
+            .is_some_and(|(&left_start, &left_size)| left_start + left_size > start_address);
 
-            Ok(())
-        }
-        else { // If both neighbouring regions are allocated, just reinsert the region
-            self.free_addresses.insert(start_address, size);
-            self.free_regions.insert(SizeKey { size, index: start_address }, start_address);
+        overlaps_left_region || self.free_addresses.range(start_address..end_address).next().is_some()
+    }
 
-            self.allocated_amount -= size;
+    /// Removes a free region from both trees
+    fn remove_free_region(&mut self, start_address: VirtualAddress, size: usize) -> Result<(), &'static str> {
+        self.free_addresses.remove(&start_address);
 
-            Ok(())
+        match self.free_regions.remove(&SizeKey { size, index: start_address }) {
+            Some(_) => Ok(()),
+            None => Err("vmm: fatal mismatch between vmemory trees when freeing page"),
         }
-
-        // TODO: Return Err if requested memory is already free
     }
 }
 
@@ -165,7 +191,7 @@ impl VirtualMemoryManager {
 mod tests {
     use alloc::collections::BTreeMap;
     use crate::memory::{PAGE_SIZE, VirtualAddress};
-    use crate::memory::virtual_memory::{KERNEL_ALLOCATION_SPACE_SIZE, KERNEL_ALLOCATION_SPACE_START, SizeKey, VirtualMemoryManager};
+    use crate::memory::virtual_memory::{check_mappable, DIRECT_MAPPING_END, DIRECT_MAPPING_START, KERNEL_ALLOCATION_SPACE_END, KERNEL_ALLOCATION_SPACE_SIZE, KERNEL_ALLOCATION_SPACE_START, KERNEL_MAPPING_START, SizeKey, VirtualMemoryManager};
 
     #[test_case]
     fn allocate_page_happy_path() {
@@ -291,6 +317,109 @@ mod tests {
         assert_region_trees_are_equal(&expected_regions_tree, &vmm.free_regions);
     }
 
+    #[test_case]
+    fn deallocation_of_allocation_head() {
+        // GIVEN
+        let mut vmm = VirtualMemoryManager::new();
+        let start = vmm.allocate_pages(8).unwrap();
+        let expected_addresses_tree = BTreeMap::from([
+            (start, 3 * PAGE_SIZE),
+            (start + 8 * PAGE_SIZE, KERNEL_ALLOCATION_SPACE_SIZE - 8 * PAGE_SIZE),
+        ]);
+
+        // WHEN
+        let dealloc = vmm.deallocate_pages(start, 3 * PAGE_SIZE);
+
+        // THEN
+        assert!(dealloc.is_ok());
+        assert_eq!(vmm.allocated_amount, 5 * PAGE_SIZE);
+        assert_vmm_trees_are_equivalent(&vmm.free_addresses, &vmm.free_regions);
+        assert_address_trees_are_equal(&expected_addresses_tree, &vmm.free_addresses);
+    }
+
+    #[test_case]
+    fn deallocation_of_allocation_tail_merges_right() {
+        // GIVEN
+        let mut vmm = VirtualMemoryManager::new();
+        let start = vmm.allocate_pages(8).unwrap();
+        let expected_addresses_tree = BTreeMap::from([
+            (start + 6 * PAGE_SIZE, KERNEL_ALLOCATION_SPACE_SIZE - 6 * PAGE_SIZE),
+        ]);
+
+        // WHEN
+        let dealloc = vmm.deallocate_pages(start + 6 * PAGE_SIZE, 2 * PAGE_SIZE);
+
+        // THEN
+        assert!(dealloc.is_ok());
+        assert_eq!(vmm.allocated_amount, 6 * PAGE_SIZE);
+        assert_vmm_trees_are_equivalent(&vmm.free_addresses, &vmm.free_regions);
+        assert_address_trees_are_equal(&expected_addresses_tree, &vmm.free_addresses);
+    }
+
+    #[test_case]
+    fn deallocation_of_allocation_middle() {
+        // GIVEN
+        let mut vmm = VirtualMemoryManager::new();
+        let start = vmm.allocate_pages(8).unwrap();
+        let expected_addresses_tree = BTreeMap::from([
+            (start + 2 * PAGE_SIZE, 4 * PAGE_SIZE),
+            (start + 8 * PAGE_SIZE, KERNEL_ALLOCATION_SPACE_SIZE - 8 * PAGE_SIZE),
+        ]);
+
+        // WHEN
+        let dealloc = vmm.deallocate_pages(start + 2 * PAGE_SIZE, 4 * PAGE_SIZE);
+
+        // THEN
+        assert!(dealloc.is_ok());
+        assert_eq!(vmm.allocated_amount, 4 * PAGE_SIZE);
+        assert_vmm_trees_are_equivalent(&vmm.free_addresses, &vmm.free_regions);
+        assert_address_trees_are_equal(&expected_addresses_tree, &vmm.free_addresses);
+    }
+
+    #[test_case]
+    fn deallocation_between_free_regions_merges_both_sides() {
+        // GIVEN
+        let mut vmm = VirtualMemoryManager::new();
+        let expected_addresses_tree = vmm.free_addresses.clone();
+        let start = vmm.allocate_pages(8).unwrap();
+        vmm.deallocate_pages(start, 2 * PAGE_SIZE).unwrap();
+        vmm.deallocate_pages(start + 4 * PAGE_SIZE, 4 * PAGE_SIZE).unwrap();
+
+        // WHEN
+        let dealloc = vmm.deallocate_pages(start + 2 * PAGE_SIZE, 2 * PAGE_SIZE);
+
+        // THEN
+        assert!(dealloc.is_ok());
+        assert_eq!(vmm.allocated_amount, 0);
+        assert_vmm_trees_are_equivalent(&vmm.free_addresses, &vmm.free_regions);
+        assert_address_trees_are_equal(&expected_addresses_tree, &vmm.free_addresses);
+    }
+
+    #[test_case]
+    fn deallocation_of_free_or_misaligned_range_fails() {
+        // GIVEN
+        let mut vmm = VirtualMemoryManager::new();
+        let start = vmm.allocate_pages(8).unwrap();
+        vmm.deallocate_pages(start + 4 * PAGE_SIZE, 2 * PAGE_SIZE).unwrap();
+        let expected_addresses_tree = vmm.free_addresses.clone();
+
+        // WHEN
+        let already_free = vmm.deallocate_pages(start + 4 * PAGE_SIZE, PAGE_SIZE);
+        let overlapping_free = vmm.deallocate_pages(start + 2 * PAGE_SIZE, 4 * PAGE_SIZE);
+        let past_allocation = vmm.deallocate_pages(start + 6 * PAGE_SIZE, 4 * PAGE_SIZE);
+        let misaligned = vmm.deallocate_pages(start + 0x10, PAGE_SIZE);
+        let outside_space = vmm.deallocate_pages(KERNEL_ALLOCATION_SPACE_START - PAGE_SIZE, PAGE_SIZE);
+
+        // THEN
+        assert_eq!(already_free, Err("vmm: freed range is not allocated"));
+        assert_eq!(overlapping_free, Err("vmm: freed range is not allocated"));
+        assert_eq!(past_allocation, Err("vmm: freed range is not allocated"));
+        assert_eq!(misaligned, Err("vmm: freed range must span whole pages"));
+        assert_eq!(outside_space, Err("vmm: freed range is outside of the kernel allocation space"));
+        assert_eq!(vmm.allocated_amount, 6 * PAGE_SIZE);
+        assert_address_trees_are_equal(&expected_addresses_tree, &vmm.free_addresses);
+    }
+
     fn assert_vmm_trees_are_equivalent(free_addresses: &BTreeMap<VirtualAddress, usize>, free_regions: &BTreeMap<SizeKey, VirtualAddress>) {
         assert_eq!(free_regions.len(), free_addresses.len());
         for address in free_addresses {
@@ -320,4 +449,24 @@ mod tests {
             assert_eq!(corresponding_region.unwrap(), first_address.1);
         }
     }
-}
\ No newline at end of file
+
+    #[test_case]
+    fn addresses_in_bootloader_mappings_are_not_mappable() {
+        // WHEN
+        let direct_mapping = [DIRECT_MAPPING_START, DIRECT_MAPPING_START + 0x1234_5000, DIRECT_MAPPING_END & !(PAGE_SIZE - 1)].map(check_mappable);
+        let kernel_mapping = [KERNEL_MAPPING_START, usize::MAX & !(PAGE_SIZE - 1)].map(check_mappable);
+
+        // THEN
+        assert!(direct_mapping.iter().all(|result| *result == Err("vmm: address is in the direct mapping of physical memory")));
+        assert!(kernel_mapping.iter().all(|result| *result == Err("vmm: address is in the kernel mapping")));
+    }
+
+    #[test_case]
+    fn kernel_allocation_space_and_identity_mapped_addresses_are_mappable() {
+        // WHEN
+        let results = [0x40_0000, KERNEL_ALLOCATION_SPACE_START, KERNEL_ALLOCATION_SPACE_END & !(PAGE_SIZE - 1)].map(check_mappable);
+
+        // THEN
+        assert_eq!(results, [Ok(()), Ok(()), Ok(())]);
+    }
+}
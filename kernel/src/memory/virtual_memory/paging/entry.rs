@@ -1,9 +1,23 @@
+use core::fmt;
+use core::fmt::{Display, Formatter};
 use bitflags::bitflags;
 use crate::memory::{Frame, VirtualAddress};
 
+/// Bits 12 to 51 of an entry, holding the physical address of the frame or table it points to
+const ADDRESS_MASK: usize = 0x000fffff_fffff000;
+
 pub struct Entry(pub(crate) VirtualAddress);
 
 impl Entry {
+    /// Wraps an entry read from a page table as a raw 64 bit value
+    pub fn from_raw(raw_entry: usize) -> Self {
+        Entry(raw_entry)
+    }
+
+    pub fn raw(&self) -> usize {
+        self.0
+    }
+
     pub fn is_unused(&self) -> bool {
         self.0 == 0
     }
@@ -13,27 +27,30 @@ impl Entry {
     }
 
     pub fn flags(&self) -> EntryFlags {
-        EntryFlags::from_bits_truncate(self.0)
+        EntryFlags::from_raw_entry(self.0)
+    }
+
+    /// Frame whose address is stored in the entry, whether the entry is present or not
+    pub fn frame(&self) -> Frame {
+        Frame::containing_address(self.0 & ADDRESS_MASK)
     }
 
     pub fn pointed_frame(&self) -> Option<Frame> {
         if self.flags().contains(EntryFlags::PRESENT) {
-            Some(Frame::containing_address(
-                self.0 & 0x000fffff_fffff000
-            ))
+            Some(self.frame())
         } else {
             None
         }
     }
 
     pub fn set(&mut self, frame: Frame, flags: EntryFlags) {
-        assert!(frame.start_address() & !0x000fffff_fffff000 == 0);
+        assert!(frame.start_address() & !ADDRESS_MASK == 0);
         self.0 = frame.start_address() | flags.bits();
     }
 }
 
 bitflags! {
-    #[derive(Copy, Clone)]
+    #[derive(Debug, Copy, Clone, Eq, PartialEq)]
     pub struct EntryFlags: usize {
         const PRESENT =         1 << 0;
         const WRITABLE =        1 << 1;
@@ -49,6 +66,17 @@ bitflags! {
 }
 
 impl EntryFlags {
+    /// Selects the PAT entry programmed as write-combining by `pat::init`. Only valid in the entry
+    /// of a 4KiB page, where the PAT bit takes the place of the huge page bit.
+    pub const WRITE_COMBINING: EntryFlags = EntryFlags::HUGE_PAGE;
+    /// Selects the PAT entry left as uncacheable by `pat::init`
+    pub const UNCACHEABLE: EntryFlags = EntryFlags::WRITE_THROUGH.union(EntryFlags::NO_CACHE);
+
+    /// Extracts the flags of a raw entry, leaving out the address and the bits available to the OS
+    pub fn from_raw_entry(raw_entry: usize) -> EntryFlags {
+        EntryFlags::from_bits_truncate(raw_entry & !ADDRESS_MASK)
+    }
+
     /*
     pub fn from_elf_section_flags(section: &ElfSectionHeader) -> EntryFlags {
         let mut flags = EntryFlags::empty();
@@ -68,4 +96,91 @@ impl EntryFlags {
 
         flags
     }*/
-}
\ No newline at end of file
+}
+
+/// Short name of each flag, in the order they are displayed
+const FLAG_TOKENS: [(EntryFlags, &str); 10] = [
+    (EntryFlags::PRESENT, "P"),
+    (EntryFlags::WRITABLE, "RW"),
+    (EntryFlags::USER_ACCESSIBLE, "US"),
+    (EntryFlags::WRITE_THROUGH, "WT"),
+    (EntryFlags::NO_CACHE, "CD"),
+    (EntryFlags::ACCESSED, "A"),
+    (EntryFlags::DIRTY, "D"),
+    (EntryFlags::HUGE_PAGE, "PS"),
+    (EntryFlags::NO_EXECUTE, "NX"),
+    (EntryFlags::GLOBAL, "G"),
+];
+
+impl Display for EntryFlags {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let mut tokens = FLAG_TOKENS.iter().filter(|(flag, _)| self.contains(*flag)).map(|(_, token)| *token);
+
+        match tokens.next() {
+            None => write!(f, "-"),
+            Some(first) => {
+                write!(f, "{}", first)?;
+                tokens.try_for_each(|token| write!(f, " {}", token))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::format;
+    use crate::memory::Frame;
+    use crate::memory::virtual_memory::paging::entry::{Entry, EntryFlags};
+
+    #[test_case]
+    fn flags_render_as_short_tokens() {
+        // GIVEN
+        let flags = EntryFlags::GLOBAL | EntryFlags::NO_EXECUTE | EntryFlags::USER_ACCESSIBLE | EntryFlags::WRITABLE | EntryFlags::PRESENT;
+
+        // WHEN
+        let rendered = format!("{}", flags);
+
+        // THEN
+        assert_eq!(rendered, "P RW US NX G");
+    }
+
+    #[test_case]
+    fn empty_flags_render_as_dash() {
+        // WHEN
+        let rendered = format!("{}", EntryFlags::empty());
+
+        // THEN
+        assert_eq!(rendered, "-");
+    }
+
+    #[test_case]
+    fn raw_entry_round_trips_through_frame_and_flags() {
+        // GIVEN
+        let raw_entry = 0x8000_0001_2345_6067;
+
+        // WHEN
+        let entry = Entry::from_raw(raw_entry);
+        let (frame, flags) = (entry.frame(), entry.flags());
+        let mut rebuilt = Entry::from_raw(0);
+        rebuilt.set(frame, flags);
+
+        // THEN
+        assert_eq!(frame, Frame::containing_address(0x1_2345_6000));
+        assert_eq!(flags, EntryFlags::NO_EXECUTE | EntryFlags::DIRTY | EntryFlags::ACCESSED | EntryFlags::USER_ACCESSIBLE | EntryFlags::WRITABLE | EntryFlags::PRESENT);
+        assert_eq!(rebuilt.raw(), raw_entry);
+    }
+
+    #[test_case]
+    fn bits_available_to_the_os_are_not_flags_nor_address() {
+        // GIVEN
+        let entry = Entry::from_raw(0x7FF0_0000_0000_0E01 | 0x1000);
+
+        // WHEN
+        let (frame, flags) = (entry.frame(), entry.flags());
+
+        // THEN
+        assert_eq!(frame, Frame::containing_address(0x1000));
+        assert_eq!(flags, EntryFlags::PRESENT);
+        assert_eq!(entry.pointed_frame(), Some(frame));
+    }
+}
@@ -1,12 +1,14 @@
+use alloc::vec::Vec;
 use core::arch::asm;
 use core::ptr::Unique;
 use crate::memory::{Frame, PAGE_SIZE, PhysicalAddress, VirtualAddress};
 use crate::memory::virtual_memory::paging::table::{Level4, Table};
-use crate::memory::virtual_memory::paging::{ENTRY_COUNT, Page};
+use crate::memory::virtual_memory::paging::{ENTRY_COUNT, Page, PageIter};
+use crate::memory::virtual_memory::check_mappable;
 use crate::memory::virtual_memory::paging::entry::EntryFlags;
 use crate::HHDM_OFFSET;
 use crate::arch::x86_64::registers::cr3;
-use crate::memory::physical_memory::FrameAllocator;
+use crate::memory::physical_memory::{FrameAllocator, FrameIter};
 
 pub struct Mapper {
     p4: Unique<Table<Level4>>,
@@ -84,10 +86,42 @@ impl Mapper {
             .or_else(huge_page)
     }
 
+    /// Returns the flags of the entry mapping the given 4KiB page, if it is mapped
+    pub fn flags(&self, page: Page) -> Option<EntryFlags> {
+        self.p4().next_table(page.p4_index())
+            .and_then(|p3| p3.next_table(page.p3_index()))
+            .and_then(|p2| p2.next_table(page.p2_index()))
+            .map(|p1| p1[page.p1_index()].flags())
+            .filter(|flags| flags.contains(EntryFlags::PRESENT))
+    }
+
+    /// Replaces the flags of an existing 4KiB mapping, keeping the frame it maps to, and flushes the
+    /// page from the TLB. The `PRESENT` flag is added by default.
+    pub fn update_flags(&mut self, page: Page, flags: EntryFlags) -> Result<(), &'static str> {
+        let entry = self.p4_mut()
+            .next_table_mut(page.p4_index())
+            .and_then(|p3| p3.next_table_mut(page.p3_index()))
+            .and_then(|p2| p2.next_table_mut(page.p2_index()))
+            .map(|p1| &mut p1[page.p1_index()])
+            .filter(|entry| entry.flags().contains(EntryFlags::PRESENT))
+            .ok_or("mapper: cannot update the flags of a page that is not mapped")?;
+
+        let frame = entry.frame();
+        entry.set(frame, flags | EntryFlags::PRESENT);
+
+        unsafe {
+            asm!("invlpg [{}]", in(reg) page.start_address());
+        }
+
+        Ok(())
+    }
+
     /// Maps the page to the frame with the provided flags.
     /// The `PRESENT` flag is added by default. Needs a
     /// `FrameAllocator` as it might need to create new page tables.
     pub fn map_to<A>(&mut self, page: Page, frame: Frame, flags: EntryFlags, allocator: &mut A) where A: FrameAllocator {
+        debug_assert_eq!(check_mappable(page.start_address()), Ok(()), "mapper: cannot map page 0x{:X}", page.start_address());
+
         let p4 = self.p4_mut();
         let p3 = p4.next_table_create(page.p4_index(), allocator);
         let p2 = p3.next_table_create(page.p3_index(), allocator);
@@ -97,6 +131,28 @@ impl Mapper {
         p1[page.p1_index()].set(frame, flags | EntryFlags::PRESENT);
     }
 
+    /// Maps the page to the frame with the provided flags and makes every table leading to it user
+    /// accessible, so that the final entry decides whether user mode can access the page
+    pub fn map_user_to<A>(&mut self, page: Page, frame: Frame, flags: EntryFlags, allocator: &mut A) where A: FrameAllocator {
+        let p4 = self.p4_mut();
+        let p3 = p4.next_user_table_create(page.p4_index(), allocator);
+        let p2 = p3.next_user_table_create(page.p3_index(), allocator);
+        let p1 = p2.next_user_table_create(page.p2_index(), allocator);
+
+        assert!(p1[page.p1_index()].is_unused());
+        p1[page.p1_index()].set(frame, flags | EntryFlags::PRESENT);
+    }
+
+    /// Maps each page of the range to the corresponding frame of the frame range with the provided
+    /// flags. The TLB is flushed once after the whole range has been mapped.
+    pub fn map_range<A>(&mut self, pages: PageIter, frames: FrameIter, flags: EntryFlags, allocator: &mut A) where A: FrameAllocator {
+        for (page, frame) in pages.zip(frames) {
+            self.map_to(page, frame, flags, allocator);
+        }
+
+        x86_64::instructions::tlb::flush_all();
+    }
+
     /// Maps the page to some free frame with the provided flags.
     /// The free frame is allocated from the given `FrameAllocator`.
     pub fn map<A>(&mut self, page: Page, flags: EntryFlags, allocator: &mut A) where A: FrameAllocator {
@@ -128,7 +184,29 @@ impl Mapper {
         allocator.deallocate_frame(frame).expect("could not deallocate");
     }
 
+    /// Unmaps every page of the range and adds the freed frames to the given `FrameAllocator`.
+    /// The TLB is flushed once after the whole range has been unmapped.
+    pub fn unmap_range<A>(&mut self, pages: PageIter, allocator: &mut A) where A: FrameAllocator {
+        for page in pages {
+            let frame = self.clear_entry(&page).unwrap();
+            allocator.deallocate_frame(frame).expect("could not deallocate");
+        }
+
+        x86_64::instructions::tlb::flush_all();
+    }
+
     pub fn unmap_no_dealloc(&mut self, page: &Page) -> Option<Frame> {
+        let frame = self.clear_entry(page);
+
+        unsafe {
+            asm!("invlpg [{}]", in(reg) page.start_address());
+        }
+
+        frame
+    }
+
+    /// Marks the page's entry as unused without flushing it from the TLB
+    fn clear_entry(&mut self, page: &Page) -> Option<Frame> {
         assert!(self.translate(page.start_address()).is_some());
 
         let p1 = self.p4_mut()
@@ -140,11 +218,42 @@ impl Mapper {
         let frame = p1[page.p1_index()].pointed_frame();
         p1[page.p1_index()].set_unused();
 
-        unsafe {
-            asm!("invlpg [{}]", in(reg) page.start_address());
+        frame
+    }
+
+    /// Returns the start address of every page in the higher half whose entry is user accessible.
+    /// Only the last level entry is checked, so pages reachable only through supervisor tables are
+    /// still reported.
+    pub fn user_accessible_kernel_pages(&self) -> Vec<VirtualAddress> {
+        let is_user_accessible = |flags: EntryFlags| flags.contains(EntryFlags::PRESENT | EntryFlags::USER_ACCESSIBLE);
+        let is_user_accessible_huge_page = |flags: EntryFlags| is_user_accessible(flags) && flags.contains(EntryFlags::HUGE_PAGE);
+
+        let mut pages = Vec::new();
+        for p4_index in ENTRY_COUNT / 2..ENTRY_COUNT {
+            let Some(p3) = self.p4().next_table(p4_index) else { continue };
+
+            for p3_index in 0..ENTRY_COUNT {
+                if is_user_accessible_huge_page(p3[p3_index].flags()) {
+                    pages.push(page_address(p4_index, p3_index, 0, 0));
+                }
+                let Some(p2) = p3.next_table(p3_index) else { continue };
+
+                for p2_index in 0..ENTRY_COUNT {
+                    if is_user_accessible_huge_page(p2[p2_index].flags()) {
+                        pages.push(page_address(p4_index, p3_index, p2_index, 0));
+                    }
+                    let Some(p1) = p2.next_table(p2_index) else { continue };
+
+                    for p1_index in 0..ENTRY_COUNT {
+                        if is_user_accessible(p1[p1_index].flags()) {
+                            pages.push(page_address(p4_index, p3_index, p2_index, p1_index));
+                        }
+                    }
+                }
+            }
         }
 
-        frame
+        pages
     }
 
     fn check_is_unmapped<A>(&mut self, page: Page, allocator: &mut A) -> bool where A: FrameAllocator {
@@ -155,4 +264,101 @@ impl Mapper {
 
         p1[page.p1_index()].is_unused()
     }
-}
\ No newline at end of file
+}
+
+/// Builds the canonical address of the page at the given table indices
+fn page_address(p4_index: usize, p3_index: usize, p2_index: usize, p1_index: usize) -> VirtualAddress {
+    let address = p4_index << 39 | p3_index << 30 | p2_index << 21 | p1_index << 12;
+
+    // Sign extend bit 47
+    if address & (1 << 47) != 0 { address | 0xFFFF_0000_0000_0000 } else { address }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::ops::DerefMut;
+    use crate::memory::{MemoryManager, PAGE_SIZE};
+    use crate::memory::physical_memory::{Frame, FrameAllocator};
+    use crate::memory::virtual_memory::paging::entry::EntryFlags;
+    use crate::memory::virtual_memory::paging::Page;
+    use crate::memory::virtual_memory::paging::mapper::page_address;
+
+    #[test_case]
+    fn map_range_maps_every_page() {
+        // GIVEN
+        let mut memory_manager = MemoryManager::instance().lock();
+        let memory_manager = memory_manager.deref_mut();
+        let virtual_start = memory_manager.virtual_memory_manager.allocate_pages(4).unwrap();
+        let physical_start = memory_manager.frame_allocator.allocate_frames(2).unwrap();
+        let pages = Page::range_inclusive(Page::containing_address(virtual_start), Page::containing_address(virtual_start + 3 * PAGE_SIZE));
+        let frames = Frame::range_inclusive(Frame::containing_address(physical_start), Frame::containing_address(physical_start + 3 * PAGE_SIZE));
+
+        // WHEN
+        memory_manager.active_page_table.map_range(pages, frames, EntryFlags::WRITABLE, &mut memory_manager.frame_allocator);
+
+        // THEN
+        assert_eq!(memory_manager.active_page_table.translate(virtual_start), Some(physical_start));
+        assert_eq!(memory_manager.active_page_table.translate(virtual_start + PAGE_SIZE + 0x10), Some(physical_start + PAGE_SIZE + 0x10));
+        assert_eq!(memory_manager.active_page_table.translate(virtual_start + 4 * PAGE_SIZE - 1), Some(physical_start + 4 * PAGE_SIZE - 1));
+
+        for page in Page::range_inclusive(Page::containing_address(virtual_start), Page::containing_address(virtual_start + 3 * PAGE_SIZE)) {
+            memory_manager.active_page_table.unmap_no_dealloc(&page);
+        }
+        memory_manager.frame_allocator.deallocate_frames(physical_start, 2).unwrap();
+        memory_manager.virtual_memory_manager.deallocate_pages(virtual_start, 4 * PAGE_SIZE).unwrap();
+    }
+
+    #[test_case]
+    fn update_flags_makes_page_read_only() {
+        // GIVEN
+        let mut memory_manager = MemoryManager::instance().lock();
+        let memory_manager = memory_manager.deref_mut();
+        let virtual_address = memory_manager.virtual_memory_manager.allocate_pages(1).unwrap();
+        let frame = memory_manager.frame_allocator.allocate_frame().unwrap();
+        let page = Page::containing_address(virtual_address);
+        memory_manager.active_page_table.map_to(page, frame, EntryFlags::WRITABLE | EntryFlags::NO_EXECUTE, &mut memory_manager.frame_allocator);
+        unsafe { (virtual_address as *mut u64).write_volatile(0xC0FFEE) };
+
+        // WHEN
+        let result = memory_manager.active_page_table.update_flags(page, EntryFlags::NO_EXECUTE);
+
+        // THEN
+        // The page fault handler halts, so the write fault itself cannot be triggered from a test.
+        // The page must no longer be writable while still mapping the same frame and contents.
+        assert_eq!(result, Ok(()));
+        let flags = memory_manager.active_page_table.flags(page).unwrap();
+        assert!(!flags.contains(EntryFlags::WRITABLE));
+        assert!(flags.contains(EntryFlags::PRESENT | EntryFlags::NO_EXECUTE));
+        assert_eq!(memory_manager.active_page_table.translate_page(page), Some(frame));
+        assert_eq!(unsafe { (virtual_address as *const u64).read_volatile() }, 0xC0FFEE);
+
+        memory_manager.active_page_table.unmap(page, &mut memory_manager.frame_allocator);
+        memory_manager.virtual_memory_manager.deallocate_pages(virtual_address, PAGE_SIZE).unwrap();
+    }
+
+    #[test_case]
+    fn update_flags_of_unmapped_page_fails() {
+        // GIVEN
+        let mut memory_manager = MemoryManager::instance().lock();
+        let memory_manager = memory_manager.deref_mut();
+        let virtual_address = memory_manager.virtual_memory_manager.allocate_pages(1).unwrap();
+
+        // WHEN
+        let result = memory_manager.active_page_table.update_flags(Page::containing_address(virtual_address), EntryFlags::WRITABLE);
+
+        // THEN
+        assert_eq!(result, Err("mapper: cannot update the flags of a page that is not mapped"));
+        memory_manager.virtual_memory_manager.deallocate_pages(virtual_address, PAGE_SIZE).unwrap();
+    }
+
+    #[test_case]
+    fn page_address_is_canonical() {
+        // WHEN
+        let lower_half = page_address(1, 2, 3, 4);
+        let higher_half = page_address(511, 510, 0, 1);
+
+        // THEN
+        assert_eq!(lower_half, 0x0000_0080_8060_4000);
+        assert_eq!(higher_half, 0xFFFF_FFFF_8000_1000);
+    }
+}
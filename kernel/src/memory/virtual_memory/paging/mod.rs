@@ -4,7 +4,8 @@ use limine::memory_map::EntryType;
 use limine::response::MemoryMapResponse;
 use crate::memory::{PAGE_SIZE, PhysicalAddress, VirtualAddress};
 use crate::memory::physical_memory::{Frame, FrameAllocator};
-use crate::memory::virtual_memory::paging::entry::EntryFlags;
+use crate::memory::virtual_memory::paging::entry::{Entry, EntryFlags};
+use crate::memory::virtual_memory::paging::table::{Level4, Table};
 use crate::memory::virtual_memory::paging::temporary_page::TemporaryPage;
 use crate::memory::virtual_memory::paging::mapper::Mapper;
 use crate::{HHDM_OFFSET, KERNEL_START_VMA_ADDRESS};
@@ -14,6 +15,7 @@ pub mod entry;
 pub mod table;
 pub mod temporary_page;
 pub mod mapper;
+pub mod pat;
 
 const ENTRY_COUNT: usize = 512;
 
@@ -108,28 +110,13 @@ impl ActivePageTable {
         }
     }
 
-    pub fn with<F>(&mut self, inactive_table: &mut InactivePageTable, temporary_page: &mut TemporaryPage, f: F)
+    /// Runs f with a mapper editing the inactive table. Tables are reached through the higher half
+    /// direct map, so unlike with a recursive mapping the active table is left untouched.
+    pub fn with<F>(&mut self, inactive_table: &mut InactivePageTable, f: F)
             where F: FnOnce(&mut Mapper) {
-        {
-            use x86_64::instructions::tlb;
-
-            let backup = Frame::containing_address(cr3());
-
-            // map temporary_page to current p4 table
-            let p4_table = temporary_page.map_table_frame(backup, self);
-
-            // overwrite recursive mapping
-            self.p4_mut()[511].set(inactive_table.p4_frame, EntryFlags::PRESENT | EntryFlags::WRITABLE);
-            tlb::flush_all();
+        let mut mapper = unsafe { Mapper::new_at(inactive_table.p4_frame.start_address() + *HHDM_OFFSET) };
 
-            // execute f in the new context
-            f(self);
-
-            p4_table[511].set(backup, EntryFlags::PRESENT | EntryFlags::WRITABLE);
-            tlb::flush_all();
-        }
-
-        temporary_page.unmap(self);
+        f(&mut mapper);
     }
 
     pub fn switch(&mut self, new_table: InactivePageTable) -> InactivePageTable {
@@ -139,6 +126,7 @@ impl ActivePageTable {
 
         unsafe {
             asm!("mov cr3, {}", in(reg) new_table.p4_frame.start_address() as u64);
+            self.mapper = Mapper::new();
         }
 
         old_table
@@ -160,6 +148,19 @@ impl InactivePageTable {
         temporary_page.unmap(active_table);
         InactivePageTable { p4_frame: frame }
     }
+
+    /// Creates an address space sharing the higher half of the active table, with an empty lower
+    /// half. Kernel tables created after this call are not shared.
+    pub fn new_address_space(frame: Frame, active_table: &ActivePageTable) -> InactivePageTable {
+        let table = unsafe { &mut *((frame.start_address() + *HHDM_OFFSET) as *mut Table<Level4>) };
+
+        table.zero();
+        for index in ENTRY_COUNT / 2..ENTRY_COUNT {
+            table[index] = Entry(active_table.p4()[index].0);
+        }
+
+        InactivePageTable { p4_frame: frame }
+    }
 }
 
 /// Maps the kernel structures in the higher half of virtual_memory memory
@@ -180,21 +181,15 @@ pub fn setup_page_tables<A>(memory_map: &'static MemoryMapResponse, allocator: &
     // Higher half direct mapping
     let start_frame = Frame::containing_address(0);
     let end_frame = Frame::containing_address(0xFFE00000);
-    for frame in Frame::range_inclusive(start_frame, end_frame) {
-        let page = Page::containing_address(frame.start_address() + *HHDM_OFFSET);
-        //serial_println!("MAPPING {:X} TO {:X}", page.start_address(), frame.start_address());
-
-        active_table.map_to(page, frame, EntryFlags::WRITABLE, allocator);
-    }
+    let pages = Page::range_inclusive(Page::containing_address(start_frame.start_address() + *HHDM_OFFSET), Page::containing_address(end_frame.start_address() + *HHDM_OFFSET));
+    active_table.map_range(pages, Frame::range_inclusive(start_frame, end_frame), EntryFlags::WRITABLE, allocator);
 
     // Kernel mapping
     for kernel_section in memory_map.entries().iter().filter(|entry| entry.entry_type == EntryType::KERNEL_AND_MODULES) {
         let start_frame = Frame::containing_address(kernel_section.base as PhysicalAddress);
         let end_frame = Frame::containing_address((kernel_section.base + kernel_section.length) as PhysicalAddress);
-        for frame in Frame::range_inclusive(start_frame, end_frame) {
-            let page = Page::containing_address(frame.start_address() + KERNEL_START_VMA_ADDRESS);
-            active_table.map_to(page, frame, EntryFlags::WRITABLE, allocator);
-        }
+        let pages = Page::range_inclusive(Page::containing_address(start_frame.start_address() + KERNEL_START_VMA_ADDRESS), Page::containing_address(end_frame.start_address() + KERNEL_START_VMA_ADDRESS));
+        active_table.map_range(pages, Frame::range_inclusive(start_frame, end_frame), EntryFlags::WRITABLE, allocator);
     }
 
     //active_table.with(&mut new_table, &mut temporary_page, |mapper| {
@@ -221,7 +216,7 @@ pub fn setup_page_tables<A>(memory_map: &'static MemoryMapResponse, allocator: &
 
         // Remapping the VGA buffer frame
         let vga_buffer_frame = Frame::containing_address(VGA_BUFFER_ADDRESS);
-        mapper.identity_map(vga_buffer_frame, EntryFlags::WRITABLE | EntryFlags::USER_ACCESSIBLE, allocator);
+        mapper.identity_map(vga_buffer_frame, EntryFlags::WRITABLE, allocator);
 
         // Remapping the multiboot info
         let multiboot_start = Frame::containing_address(boot_info.start_address());
@@ -0,0 +1,99 @@
+// https://www.intel.com/content/www/us/en/developer/articles/technical/intel-sdm.html (Vol. 3A, 12.12)
+
+use crate::arch::x86_64::registers::{IA32_PAT, read_msr, write_msr};
+use crate::memory::virtual_memory::paging::entry::EntryFlags;
+
+/// Bit selecting the upper half of the PAT in the entry of a 4KiB page. It shares its position with
+/// the huge page bit of higher level entries.
+const PAT_BIT: usize = 1 << 7;
+
+/// Index of the PAT entry selected by `EntryFlags::WRITE_COMBINING`
+pub const WRITE_COMBINING_PAT_INDEX: usize = 4;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+#[repr(u8)]
+pub enum MemoryType {
+    Uncacheable = 0x0,
+    WriteCombining = 0x1,
+    WriteThrough = 0x4,
+    WriteProtected = 0x5,
+    WriteBack = 0x6,
+    UncachedMinus = 0x7,
+}
+
+/// Power-on PAT layout, except for entry 4 which is write-combining instead of write-back. Entries
+/// 0 to 3 are left untouched so that mappings using only PWT and PCD keep their memory type.
+pub const PAT_LAYOUT: [MemoryType; 8] = [
+    MemoryType::WriteBack,
+    MemoryType::WriteThrough,
+    MemoryType::UncachedMinus,
+    MemoryType::Uncacheable,
+    MemoryType::WriteCombining,
+    MemoryType::WriteThrough,
+    MemoryType::UncachedMinus,
+    MemoryType::Uncacheable,
+];
+
+/// Programs the PAT with `PAT_LAYOUT`. Must run before any page is mapped write-combining.
+pub fn init() {
+    unsafe {
+        write_msr(IA32_PAT, pat_value(&PAT_LAYOUT));
+    }
+    x86_64::instructions::tlb::flush_all();
+}
+
+/// Reads the memory type currently programmed in the given PAT entry
+pub fn programmed_memory_type(index: usize) -> u8 {
+    let pat = unsafe { read_msr(IA32_PAT) };
+
+    (pat >> (index * 8)) as u8 & 0x7
+}
+
+/// Returns the PAT entry selected by the flags of a 4KiB page entry
+pub fn pat_index(flags: EntryFlags) -> usize {
+    let bits = flags.bits();
+
+    let write_through = (bits & EntryFlags::WRITE_THROUGH.bits() != 0) as usize;
+    let no_cache = (bits & EntryFlags::NO_CACHE.bits() != 0) as usize;
+    let pat = (bits & PAT_BIT != 0) as usize;
+
+    pat << 2 | no_cache << 1 | write_through
+}
+
+fn pat_value(layout: &[MemoryType; 8]) -> u64 {
+    layout.iter().enumerate().fold(0, |value, (index, memory_type)| value | (*memory_type as u64) << (index * 8))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::memory::virtual_memory::paging::entry::EntryFlags;
+    use crate::memory::virtual_memory::paging::pat::{MemoryType, PAT_LAYOUT, pat_index, pat_value, WRITE_COMBINING_PAT_INDEX};
+
+    #[test_case]
+    fn pat_value_places_each_entry_in_its_byte() {
+        // WHEN
+        let value = pat_value(&PAT_LAYOUT);
+
+        // THEN
+        assert_eq!(value, 0x0007_0401_0007_0406);
+    }
+
+    #[test_case]
+    fn write_combining_flags_select_write_combining_entry() {
+        // WHEN
+        let index = pat_index(EntryFlags::PRESENT | EntryFlags::WRITABLE | EntryFlags::WRITE_COMBINING);
+
+        // THEN
+        assert_eq!(index, WRITE_COMBINING_PAT_INDEX);
+        assert_eq!(PAT_LAYOUT[index], MemoryType::WriteCombining);
+    }
+
+    #[test_case]
+    fn default_flags_select_write_back_entry() {
+        // WHEN
+        let index = pat_index(EntryFlags::PRESENT | EntryFlags::WRITABLE);
+
+        // THEN
+        assert_eq!(PAT_LAYOUT[index], MemoryType::WriteBack);
+    }
+}
@@ -54,6 +54,20 @@ impl<L> Table<L> where L: HierarchicalLevel {
         }
         self.next_table_mut(index).unwrap()
     }
+
+    /// Same as `next_table_create`, but also makes the entry pointing to the next table user
+    /// accessible
+    pub fn next_user_table_create<A>(&mut self, index: usize, allocator: &mut A) -> &mut Table<L::NextLevel>
+        where A: FrameAllocator
+    {
+        self.next_table_create(index, allocator);
+
+        let entry = &mut self.entries[index];
+        let frame = entry.pointed_frame().unwrap();
+        entry.set(frame, entry.flags() | EntryFlags::USER_ACCESSIBLE);
+
+        self.next_table_mut(index).unwrap()
+    }
 }
 
 impl<L> Index<usize> for Table<L> where L: TableLevel {
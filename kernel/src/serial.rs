@@ -1,10 +1,29 @@
 use lazy_static::lazy_static;
 use spin::Mutex;
 use uart_16550::SerialPort;
+use crate::arch::x86_64::port_manager::Port;
+use crate::arch::x86_64::port_manager::ReadWriteStatus::ReadWrite;
+use crate::debugger::hexdump::Hexdump;
+
+const COM1_ADDRESS: u16 = 0x3F8;
+/// Frequency of the UART clock divided by 16, the baud rate obtained with a divisor of 1
+const MAX_BAUD_RATE: u32 = 115200;
+/// Set in the line control register to expose the divisor latch on the data and interrupt enable registers
+const DIVISOR_LATCH_ACCESS: u8 = 1 << 7;
+/// Set in the modem control register to route the transmitter back into the receiver
+const MODEM_CONTROL_LOOPBACK: u8 = 1 << 4;
+/// Set in the line status register when a received byte is waiting in the data register
+const LINE_STATUS_DATA_READY: u8 = 1;
+/// Set in the line status register when the transmitter can accept another byte
+const LINE_STATUS_TRANSMIT_EMPTY: u8 = 1 << 5;
+/// Number of line status polls before a byte is considered lost
+const LOOPBACK_POLL_LIMIT: usize = 100_000;
+/// Bytes sent through the loopback, covering alternating and extreme bit patterns
+const LOOPBACK_PATTERN: [u8; 6] = [0x00, 0xFF, 0x55, 0xAA, 0x0F, 0xF0];
 
 lazy_static! {
     pub static ref SERIAL1: Mutex<SerialPort> = {
-        let mut serial_port = unsafe { SerialPort::new(0x3F8) };
+        let mut serial_port = unsafe { SerialPort::new(COM1_ADDRESS) };
         serial_port.init();
         Mutex::new(serial_port)
     };
@@ -19,6 +38,121 @@ pub fn serial_print(args: ::core::fmt::Arguments) {
         .expect("Printing to serial failed");
 }
 
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum DataBits {
+    Five = 0b00,
+    Six = 0b01,
+    Seven = 0b10,
+    Eight = 0b11,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Parity {
+    None = 0b000,
+    Odd = 0b001,
+    Even = 0b011,
+    Mark = 0b101,
+    Space = 0b111,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum StopBits {
+    One = 0,
+    Two = 1,
+}
+
+/// Reprograms the baud rate and framing of the serial port. The baud rate must evenly divide 115200.
+pub fn configure(baud_rate: u32, data_bits: DataBits, parity: Parity, stop_bits: StopBits) -> Result<(), &'static str> {
+    let divisor = baud_rate_divisor(baud_rate)?;
+    let line_control = line_control_byte(data_bits, parity, stop_bits);
+
+    // Hold the port so nothing is sent while the divisor latch is exposed
+    let _serial_port = SERIAL1.lock();
+    let mut data_port = Port::<u8>::new(COM1_ADDRESS, ReadWrite);
+    let mut interrupt_enable_port = Port::<u8>::new(COM1_ADDRESS + 1, ReadWrite);
+    let mut line_control_port = Port::<u8>::new(COM1_ADDRESS + 3, ReadWrite);
+
+    line_control_port.write(DIVISOR_LATCH_ACCESS).unwrap();
+    data_port.write(divisor as u8).unwrap();
+    interrupt_enable_port.write((divisor >> 8) as u8).unwrap();
+    line_control_port.write(line_control).unwrap();
+
+    Ok(())
+}
+
+/// Checks that bytes written to the serial port can be read back with the UART in loopback mode.
+/// This does not depend on the backend of the port, with QEMU it works with the `-serial stdio`
+/// used by `make run-tests`.
+pub fn loopback_self_test() -> Result<(), &'static str> {
+    let _serial_port = SERIAL1.lock();
+    let mut data_port = Port::<u8>::new(COM1_ADDRESS, ReadWrite);
+    let mut line_status_port = Port::<u8>::new(COM1_ADDRESS + 5, ReadWrite);
+
+    set_loopback(true);
+
+    // Drop anything received before the loopback was enabled
+    while line_status_port.read().unwrap() & LINE_STATUS_DATA_READY != 0 {
+        data_port.read().unwrap();
+    }
+
+    let result = LOOPBACK_PATTERN.iter().try_for_each(|&byte| {
+        wait_for_line_status(&mut line_status_port, LINE_STATUS_TRANSMIT_EMPTY)?;
+        data_port.write(byte).unwrap();
+        wait_for_line_status(&mut line_status_port, LINE_STATUS_DATA_READY)?;
+
+        match data_port.read().unwrap() == byte {
+            true => Ok(()),
+            false => Err("serial: loopback returned a different byte"),
+        }
+    });
+
+    set_loopback(false);
+    result
+}
+
+/// Routes the port's output back to its input instead of the line, by toggling the loopback bit
+/// of the modem control register. The caller must hold `SERIAL1`.
+fn set_loopback(enabled: bool) {
+    let mut modem_control_port = Port::<u8>::new(COM1_ADDRESS + 4, ReadWrite);
+    let modem_control = modem_control_port.read().unwrap();
+    modem_control_port.write(modem_control_byte(modem_control, enabled)).unwrap();
+}
+
+fn modem_control_byte(modem_control: u8, loopback: bool) -> u8 {
+    match loopback {
+        true => modem_control | MODEM_CONTROL_LOOPBACK,
+        false => modem_control & !MODEM_CONTROL_LOOPBACK,
+    }
+}
+
+fn wait_for_line_status(line_status_port: &mut Port<u8>, status: u8) -> Result<(), &'static str> {
+    for _ in 0..LOOPBACK_POLL_LIMIT {
+        if line_status_port.read().unwrap() & status != 0 {
+            return Ok(());
+        }
+        core::hint::spin_loop();
+    }
+
+    Err("serial: loopback timed out")
+}
+
+fn baud_rate_divisor(baud_rate: u32) -> Result<u16, &'static str> {
+    if baud_rate == 0 || baud_rate > MAX_BAUD_RATE || MAX_BAUD_RATE % baud_rate != 0 {
+        return Err("serial: baud rate must evenly divide 115200");
+    }
+
+    Ok((MAX_BAUD_RATE / baud_rate) as u16)
+}
+
+fn line_control_byte(data_bits: DataBits, parity: Parity, stop_bits: StopBits) -> u8 {
+    data_bits as u8 | (stop_bits as u8) << 2 | (parity as u8) << 3
+}
+
+/// Writes a hex+ASCII dump of the given bytes to the serial port
+pub fn serial_hexdump(bytes: &[u8]) {
+    serial_print(format_args!("{}", Hexdump::new(bytes, 0)));
+}
+
 /// Prints to the host through the serial interface.
 #[macro_export]
 macro_rules! serial_print {
@@ -31,4 +165,60 @@ macro_rules! serial_println {
     () => ($crate::serial_print!("\n"));
     ($fmt:expr) => ($crate::serial_print!(concat!($fmt, "\n")));
     ($fmt:expr, $($arg:tt)*) => ($crate::serial_print!(concat!($fmt, "\n"), $($arg)*));
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::serial::{baud_rate_divisor, DataBits, line_control_byte, loopback_self_test, modem_control_byte, Parity, StopBits};
+
+    #[test_case]
+    fn divisor_for_standard_baud_rates() {
+        // WHEN
+        let divisors = [115200, 57600, 38400, 9600, 300].map(baud_rate_divisor);
+
+        // THEN
+        assert_eq!(divisors, [Ok(1), Ok(2), Ok(3), Ok(12), Ok(384)]);
+    }
+
+    #[test_case]
+    fn baud_rates_not_dividing_max_rate_are_rejected() {
+        // WHEN
+        let divisors = [0, 7000, 230400].map(baud_rate_divisor);
+
+        // THEN
+        assert!(divisors.iter().all(|divisor| divisor.is_err()));
+    }
+
+    #[test_case]
+    fn line_control_for_common_framings() {
+        // WHEN
+        let eight_n_one = line_control_byte(DataBits::Eight, Parity::None, StopBits::One);
+        let seven_e_one = line_control_byte(DataBits::Seven, Parity::Even, StopBits::One);
+        let five_o_two = line_control_byte(DataBits::Five, Parity::Odd, StopBits::Two);
+
+        // THEN
+        assert_eq!(eight_n_one, 0x03);
+        assert_eq!(seven_e_one, 0x1A);
+        assert_eq!(five_o_two, 0x0C);
+    }
+
+    #[test_case]
+    fn modem_control_loopback_bit_is_toggled() {
+        // WHEN
+        let enabled = modem_control_byte(0x0B, true);
+        let disabled = modem_control_byte(0x1B, false);
+
+        // THEN
+        assert_eq!(enabled, 0x1B);
+        assert_eq!(disabled, 0x0B);
+    }
+
+    #[test_case]
+    fn loopback_round_trips_byte_pattern() {
+        // WHEN
+        let result = loopback_self_test();
+
+        // THEN
+        assert_eq!(result, Ok(()));
+    }
+}
@@ -1,12 +1,35 @@
 #![allow(clippy::new_ret_no_self)]
 
-use alloc::collections::{BTreeMap};
+use alloc::collections::{BTreeMap, BTreeSet};
 use alloc::sync::Arc;
 use alloc::task::Wake;
 use core::task::{Waker, Context, Poll};
 use crossbeam_queue::ArrayQueue;
+use spin::Mutex;
+use crate::drivers::pit;
 use crate::interrupts::InterruptController;
-use crate::task::{Task, TaskId};
+use crate::task::{Task, TaskContext, TaskId};
+use crate::task::timer::TIMERS;
+
+/// Most tasks polled in a single pass of the executor, so that timers are serviced even when the
+/// queue never empties
+const MAX_TASKS_PER_PASS: usize = 32;
+
+/// Task being polled by the executor, used by futures that need to register wakeups
+static CURRENT_TASK_ID: Mutex<Option<TaskId>> = Mutex::new(None);
+
+/// Context of the task being polled, moved out of the task for the duration of the poll
+static CURRENT_TASK_CONTEXT: Mutex<Option<TaskContext>> = Mutex::new(None);
+
+pub(super) fn current_task_id() -> Option<TaskId> {
+    *CURRENT_TASK_ID.lock()
+}
+
+/// Runs the closure on the context of the task being polled. Returns None outside of a task or if
+/// the task has no context.
+pub fn with_task_context<R>(f: impl FnOnce(&mut TaskContext) -> R) -> Option<R> {
+    CURRENT_TASK_CONTEXT.lock().as_mut().map(f)
+}
 
 pub struct Executor {
     tasks: BTreeMap<TaskId, Task>,
@@ -32,8 +55,22 @@ impl Executor {
         self.task_queue.push(task_id).expect("queue full");
     }
 
+    /// Polls the tasks that were ready when the pass started, each at most once. Tasks woken during
+    /// the pass, including the ones waking themselves, wait for the next pass.
     fn run_ready_tasks(&mut self) {
-        while let Ok(task_id) = self.task_queue.pop() {
+        let batch_size = self.task_queue.len().min(MAX_TASKS_PER_PASS);
+        let mut polled = BTreeSet::new();
+
+        for _ in 0..batch_size {
+            let Ok(task_id) = self.task_queue.pop() else {
+                break;
+            };
+
+            // The task was woken more than once before this pass, a single poll covers every wakeup
+            if !polled.insert(task_id) {
+                continue;
+            }
+
             let task = match self.tasks.get_mut(&task_id) {
                 Some(task) => task,
                 None => continue,
@@ -41,7 +78,14 @@ impl Executor {
 
             let waker = self.waker_cache.entry(task_id).or_insert_with(|| TaskWaker::new(task_id, self.task_queue.clone()));
             let mut context = Context::from_waker(waker);
-            match task.poll(&mut context) {
+
+            *CURRENT_TASK_ID.lock() = Some(task_id);
+            *CURRENT_TASK_CONTEXT.lock() = task.context.take();
+            let poll_result = task.poll(&mut context);
+            task.context = CURRENT_TASK_CONTEXT.lock().take();
+            *CURRENT_TASK_ID.lock() = None;
+
+            match poll_result {
                 Poll::Ready(()) => {
                     self.tasks.remove(&task_id);
                     self.waker_cache.remove(&task_id);
@@ -52,8 +96,18 @@ impl Executor {
         }
     }
 
+    /// Queues the tasks whose timers are due at the current PIT tick
+    fn wake_due_timers(&mut self) {
+        for task_id in TIMERS.lock().pop_due(pit::ticks()) {
+            if let Some(waker) = self.waker_cache.get(&task_id) {
+                waker.wake_by_ref();
+            }
+        }
+    }
+
     pub fn run(&mut self) -> ! {
         loop {
+            self.wake_due_timers();
             self.run_ready_tasks();
             self.sleep_if_idle();
         }
@@ -96,4 +150,131 @@ impl Wake for TaskWaker {
     fn wake_by_ref(self: &Arc<Self>) {
         self.wake_task();
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::sync::Arc;
+    use core::future::poll_fn;
+    use core::sync::atomic::{AtomicUsize, Ordering};
+    use core::task::Poll;
+    use alloc::string::String;
+    use spin::Mutex;
+    use crate::fs::Vfs;
+    use crate::task::executor::{Executor, with_task_context};
+    use crate::task::{Task, TaskContext};
+
+    /// Task that wakes itself every time it is polled and never completes
+    fn self_waking_task(polls: Arc<AtomicUsize>) -> Task {
+        Task::new(poll_fn(move |context| {
+            polls.fetch_add(1, Ordering::Relaxed);
+            context.waker().wake_by_ref();
+            Poll::<()>::Pending
+        }))
+    }
+
+    fn counting_task(polls: Arc<AtomicUsize>) -> Task {
+        Task::new(poll_fn(move |_| {
+            polls.fetch_add(1, Ordering::Relaxed);
+            Poll::Ready(())
+        }))
+    }
+
+    #[test_case]
+    fn self_waking_task_does_not_starve_other_tasks() {
+        // GIVEN
+        let mut executor = Executor::new();
+        let busy_polls = Arc::new(AtomicUsize::new(0));
+        let other_polls = Arc::new(AtomicUsize::new(0));
+        executor.spawn(self_waking_task(busy_polls.clone()));
+        executor.spawn(counting_task(other_polls.clone()));
+
+        // WHEN
+        executor.run_ready_tasks();
+
+        // THEN
+        assert_eq!(other_polls.load(Ordering::Relaxed), 1);
+        assert_eq!(busy_polls.load(Ordering::Relaxed), 1);
+    }
+
+    #[test_case]
+    fn self_waking_task_is_polled_once_per_pass() {
+        // GIVEN
+        let mut executor = Executor::new();
+        let polls = Arc::new(AtomicUsize::new(0));
+        executor.spawn(self_waking_task(polls.clone()));
+
+        // WHEN
+        for _ in 0..3 {
+            executor.run_ready_tasks();
+        }
+
+        // THEN
+        assert_eq!(polls.load(Ordering::Relaxed), 3);
+    }
+
+    #[test_case]
+    fn task_woken_twice_is_polled_once() {
+        // GIVEN
+        let mut executor = Executor::new();
+        let polls = Arc::new(AtomicUsize::new(0));
+        let task = counting_task(polls.clone());
+        let task_id = task.id;
+        executor.spawn(task);
+        executor.task_queue.push(task_id).unwrap();
+
+        // WHEN
+        executor.run_ready_tasks();
+
+        // THEN
+        assert_eq!(polls.load(Ordering::Relaxed), 1);
+        assert!(executor.task_queue.is_empty());
+    }
+
+    #[test_case]
+    fn task_context_persists_across_polls() {
+        // GIVEN
+        let mut executor = Executor::new();
+        let observed_cwd = Arc::new(Mutex::new(None));
+        let observed = observed_cwd.clone();
+        let mut polls = 0;
+        let task = Task::with_context(poll_fn(move |context| {
+            polls += 1;
+            if polls == 1 {
+                with_task_context(|task_context| task_context.cwd = Vfs::find_from_absolute_path("/dev").unwrap());
+                context.waker().wake_by_ref();
+                return Poll::Pending;
+            }
+
+            *observed.lock() = with_task_context(|task_context| Vfs::get_absolute_path(task_context.cwd.clone()));
+            Poll::Ready(())
+        }), TaskContext::new(Vfs::root_directory().clone()));
+        executor.spawn(task);
+
+        // WHEN
+        executor.run_ready_tasks();
+        executor.run_ready_tasks();
+
+        // THEN
+        assert_eq!(*observed_cwd.lock(), Some(String::from("/dev")));
+        assert!(with_task_context(|_| ()).is_none());
+    }
+
+    #[test_case]
+    fn task_without_context_sees_none() {
+        // GIVEN
+        let mut executor = Executor::new();
+        let has_context = Arc::new(Mutex::new(None));
+        let observed = has_context.clone();
+        executor.spawn(Task::new(poll_fn(move |_| {
+            *observed.lock() = Some(with_task_context(|_| ()).is_some());
+            Poll::Ready(())
+        })));
+
+        // WHEN
+        executor.run_ready_tasks();
+
+        // THEN
+        assert_eq!(*has_context.lock(), Some(false));
+    }
+}
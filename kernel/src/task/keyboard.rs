@@ -1,10 +1,16 @@
+use alloc::sync::Arc;
+use alloc::vec::Vec;
 use core::pin::Pin;
 use core::task::{Context, Poll};
 use conquer_once::spin::OnceCell;
 use crossbeam_queue::ArrayQueue;
 use futures_util::{Stream, StreamExt};
 use futures_util::task::AtomicWaker;
-use crate::drivers::ps2::keyboard::PS2Keyboard;
+use spin::Mutex;
+use crate::debugger::{complete_command, run_command, run_debug_shell};
+use crate::debugger::line_editor::{Echo, LineEditor, LineEditorKey};
+use crate::drivers::ps2::keyboard::{KeyCode, KeyEvent, PS2Keyboard, ScancodeSource};
+use crate::graphics::framebuffer_device::Writer;
 
 static SCANCODE_QUEUE: OnceCell<ArrayQueue<u8>> = OnceCell::uninit();
 static WAKER: AtomicWaker = AtomicWaker::new();
@@ -57,10 +63,170 @@ impl Stream for ScancodeStream {
     }
 }
 
-pub async fn print_key_inputs(mut keyboard: PS2Keyboard) {
+/// Capacity of the event queue of each subscriber
+const EVENT_QUEUE_CAPACITY: usize = 100;
+
+static SUBSCRIBERS: Mutex<Vec<Arc<Subscriber>>> = Mutex::new(Vec::new());
+
+struct Subscriber {
+    queue: ArrayQueue<KeyEvent>,
+    waker: AtomicWaker,
+}
+
+/// Stream of the key events published after it subscribed. Every subscriber receives every event.
+pub struct KeyEventStream {
+    subscriber: Arc<Subscriber>,
+}
+
+impl KeyEventStream {
+    pub fn subscribe() -> Self {
+        let subscriber = Arc::new(Subscriber { queue: ArrayQueue::new(EVENT_QUEUE_CAPACITY), waker: AtomicWaker::new() });
+        SUBSCRIBERS.lock().push(subscriber.clone());
+
+        KeyEventStream { subscriber }
+    }
+}
+
+impl Stream for KeyEventStream {
+    type Item = KeyEvent;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<KeyEvent>> {
+        if let Ok(event) = self.subscriber.queue.pop() {
+            return Poll::Ready(Some(event));
+        }
+
+        self.subscriber.waker.register(cx.waker());
+        match self.subscriber.queue.pop() {
+            Ok(event) => {
+                self.subscriber.waker.take();
+                Poll::Ready(Some(event))
+            }
+            Err(crossbeam_queue::PopError) => Poll::Pending,
+        }
+    }
+}
+
+impl Drop for KeyEventStream {
+    fn drop(&mut self) {
+        SUBSCRIBERS.lock().retain(|subscriber| !Arc::ptr_eq(subscriber, &self.subscriber));
+    }
+}
+
+/// Sends the event to every subscriber
+fn publish(event: KeyEvent) {
+    for subscriber in SUBSCRIBERS.lock().iter() {
+        if subscriber.queue.push(event).is_err() {
+            warn!("key event queue full; dropping keyboard input");
+        } else {
+            subscriber.waker.wake();
+        }
+    }
+}
+
+/// Scancodes already queued are taken without waiting
+impl ScancodeSource for ScancodeStream {
+    fn next_scancode(&mut self) -> Option<u8> {
+        SCANCODE_QUEUE.try_get().ok()?.pop().ok()
+    }
+}
+
+/// Decodes the scancodes received by the keyboard and publishes the resulting key events
+pub async fn dispatch_key_events(mut keyboard: PS2Keyboard) {
     let mut scancodes = ScancodeStream::new();
 
     while let Some(scancode) = scancodes.next().await {
-        keyboard.print_key_input(scancode);
+        if let Some(event) = keyboard.decode(scancode) {
+            publish(event);
+        }
+
+        // The rest of a burst of scancodes is handled without going back through the executor
+        while let Some(event) = keyboard.read_event(&mut scancodes) {
+            publish(event);
+        }
+    }
+}
+
+/// How the console line editor displays the characters typed
+static ECHO: Mutex<Echo> = Mutex::new(Echo::On);
+
+/// Sets how the characters typed on the console are displayed, echo is turned off or masked while
+/// reading input such as a password
+pub fn set_echo(echo: Echo) {
+    *ECHO.lock() = echo;
+}
+
+/// Feeds the key presses to the console line editor, F12 opens the debug shell
+pub async fn print_key_inputs() {
+    let mut events = KeyEventStream::subscribe();
+    let mut line_editor = LineEditor::new().with_completion(">", complete_command);
+    let mut is_debug = false;
+
+    while let Some(event) = events.next().await {
+        if !event.pressed {
+            continue;
+        }
+
+        let key = match event.code {
+            KeyCode::Function(12) => {
+                is_debug = true;
+                run_debug_shell();
+                continue;
+            },
+            KeyCode::Enter => LineEditorKey::Enter,
+            KeyCode::Backspace => LineEditorKey::Backspace,
+            KeyCode::Tab => LineEditorKey::Tab,
+            KeyCode::Up => LineEditorKey::Up,
+            KeyCode::Down => LineEditorKey::Down,
+            KeyCode::Left => LineEditorKey::Left,
+            KeyCode::Right => LineEditorKey::Right,
+            KeyCode::Character(_) => LineEditorKey::Character(event.char.unwrap()),
+            _ => continue,
+        };
+
+        // Lines are only submitted as commands in the debug shell
+        if matches!(key, LineEditorKey::Enter) && !is_debug {
+            continue;
+        }
+
+        let Some(writer) = Writer::instance() else { continue };
+        line_editor.set_echo(*ECHO.lock());
+        let line = line_editor.handle_key(key, &mut *writer.lock());
+        if let Some(line) = line {
+            run_command(&line);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::drivers::ps2::keyboard::{KeyCode, KeyEvent, Modifiers};
+    use crate::task::keyboard::{KeyEventStream, publish, SUBSCRIBERS};
+
+    #[test_case]
+    fn every_subscriber_receives_published_events() {
+        // GIVEN
+        let first = KeyEventStream::subscribe();
+        let second = KeyEventStream::subscribe();
+        let event = KeyEvent { code: KeyCode::Character('x'), char: Some('x'), modifiers: Modifiers::empty(), pressed: true };
+
+        // WHEN
+        publish(event);
+
+        // THEN
+        assert_eq!(first.subscriber.queue.pop(), Ok(event));
+        assert_eq!(second.subscriber.queue.pop(), Ok(event));
     }
-}
\ No newline at end of file
+
+    #[test_case]
+    fn dropped_stream_unsubscribes() {
+        // GIVEN
+        let subscriber_count = SUBSCRIBERS.lock().len();
+        let stream = KeyEventStream::subscribe();
+
+        // WHEN
+        drop(stream);
+
+        // THEN
+        assert_eq!(SUBSCRIBERS.lock().len(), subscriber_count);
+    }
+}
@@ -1,15 +1,30 @@
 pub mod executor;
 pub mod keyboard;
+pub mod timer;
 
 use alloc::boxed::Box;
 use core::future::Future;
 use core::pin::Pin;
 use core::sync::atomic::{AtomicU64, Ordering};
 use core::task::{Context, Poll};
+use crate::fs::VfsNodeRef;
 
 pub struct Task {
     id: TaskId,
     future: Pin<Box<dyn Future<Output = ()>>>,
+    /// Handed to the future through `executor::with_task_context` while it is polled
+    context: Option<TaskContext>,
+}
+
+/// Per task state, such as the directory relative paths are resolved against
+pub struct TaskContext {
+    pub cwd: VfsNodeRef,
+}
+
+impl TaskContext {
+    pub fn new(cwd: VfsNodeRef) -> Self {
+        Self { cwd }
+    }
 }
 
 impl Task {
@@ -17,6 +32,14 @@ impl Task {
         Task {
             id: TaskId::new(),
             future: Box::pin(future),
+            context: None,
+        }
+    }
+
+    pub fn with_context(future: impl Future<Output = ()> + 'static, context: TaskContext) -> Task {
+        Task {
+            context: Some(context),
+            ..Task::new(future)
         }
     }
 
@@ -0,0 +1,129 @@
+use alloc::collections::BinaryHeap;
+use alloc::vec::Vec;
+use core::cmp::Reverse;
+use core::future::Future;
+use core::pin::Pin;
+use core::task::{Context, Poll};
+use spin::Mutex;
+use crate::drivers::pit;
+use crate::task::executor::current_task_id;
+use crate::task::TaskId;
+
+/// Wakeups requested by sleeping tasks, consumed by the executor on every scheduling pass
+pub(super) static TIMERS: Mutex<TimerQueue> = Mutex::new(TimerQueue::new());
+
+/// Min-heap of tasks to wake, ordered by wake tick. Timers due on the same tick fire in the
+/// order they were registered.
+pub(super) struct TimerQueue {
+    timers: BinaryHeap<Reverse<(u64, u64, TaskId)>>,
+    registration_count: u64,
+}
+
+impl TimerQueue {
+    pub(super) const fn new() -> Self {
+        Self {
+            timers: BinaryHeap::new(),
+            registration_count: 0,
+        }
+    }
+
+    pub(super) fn register(&mut self, wake_tick: u64, task_id: TaskId) {
+        self.timers.push(Reverse((wake_tick, self.registration_count, task_id)));
+        self.registration_count += 1;
+    }
+
+    /// Removes and returns the tasks whose wake tick is at or before `current_tick`, earliest first
+    pub(super) fn pop_due(&mut self, current_tick: u64) -> Vec<TaskId> {
+        let mut due_tasks = Vec::new();
+        while let Some(Reverse((wake_tick, _, task_id))) = self.timers.peek() {
+            if *wake_tick > current_tick {
+                break;
+            }
+
+            due_tasks.push(*task_id);
+            self.timers.pop();
+        }
+
+        due_tasks
+    }
+}
+
+/// Future completing once the given number of milliseconds have elapsed, rounded up to the next PIT tick
+pub fn sleep(ms: u64) -> Sleep {
+    Sleep {
+        wake_tick: pit::ticks() + pit::ms_to_ticks(ms),
+        is_registered: false,
+    }
+}
+
+pub struct Sleep {
+    wake_tick: u64,
+    is_registered: bool,
+}
+
+impl Future for Sleep {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, _context: &mut Context) -> Poll<()> {
+        if pit::ticks() >= self.wake_tick {
+            return Poll::Ready(());
+        }
+
+        if !self.is_registered {
+            let task_id = current_task_id().expect("sleep: must be awaited from a task run by the executor");
+            TIMERS.lock().register(self.wake_tick, task_id);
+            self.is_registered = true;
+        }
+
+        Poll::Pending
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec;
+    use alloc::vec::Vec;
+    use crate::task::TaskId;
+    use crate::task::timer::TimerQueue;
+
+    #[test_case]
+    fn timers_fire_in_tick_order_as_clock_advances() {
+        // GIVEN
+        let mut timers = TimerQueue::new();
+        timers.register(30, TaskId(1));
+        timers.register(10, TaskId(2));
+        timers.register(20, TaskId(3));
+        timers.register(10, TaskId(4));
+        timers.register(50, TaskId(5));
+
+        // WHEN
+        let fired: Vec<Vec<TaskId>> = [5, 10, 29, 30, 100].into_iter().map(|tick| timers.pop_due(tick)).collect();
+
+        // THEN
+        assert_eq!(fired, vec![
+            vec![],
+            vec![TaskId(2), TaskId(4)],
+            vec![TaskId(3)],
+            vec![TaskId(1)],
+            vec![TaskId(5)],
+        ]);
+    }
+
+    #[test_case]
+    fn many_timers_due_in_same_pass_fire_together() {
+        // GIVEN
+        let mut timers = TimerQueue::new();
+        for id in 0..50 {
+            timers.register(100 - id, TaskId(id));
+        }
+
+        // WHEN
+        let fired = timers.pop_due(100);
+
+        // THEN
+        assert_eq!(fired.len(), 50);
+        assert_eq!(fired[0], TaskId(49));
+        assert_eq!(fired[49], TaskId(0));
+        assert!(timers.pop_due(u64::MAX).is_empty());
+    }
+}
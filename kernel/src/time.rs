@@ -0,0 +1,20 @@
+use core::hint;
+use crate::drivers::hpet::Hpet;
+use crate::drivers::pit;
+
+/// Time elapsed since boot in nanoseconds. The HPET is used when it is available, otherwise the
+/// time is derived from the PIT ticks and only has a resolution of one tick.
+pub fn now_ns() -> u64 {
+    match Hpet::instance() {
+        Some(hpet) => hpet.now_ns(),
+        None => pit::ticks_to_ms(pit::ticks()) * 1_000_000,
+    }
+}
+
+/// Spins until the given number of nanoseconds have elapsed
+pub fn busy_sleep(duration_ns: u64) {
+    let start = now_ns();
+    while now_ns() - start < duration_ns {
+        hint::spin_loop();
+    }
+}
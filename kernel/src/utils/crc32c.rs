@@ -0,0 +1,74 @@
+// https://www.rfc-editor.org/rfc/rfc3720#appendix-B.4
+
+/// Reflected Castagnoli polynomial
+const POLYNOMIAL: u32 = 0x82F6_3B78;
+
+const TABLE: [u32; 256] = build_table();
+
+const fn build_table() -> [u32; 256] {
+    let mut table = [0; 256];
+    let mut index = 0;
+    while index < 256 {
+        let mut crc = index as u32;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 { crc >> 1 ^ POLYNOMIAL } else { crc >> 1 };
+            bit += 1;
+        }
+        table[index] = crc;
+        index += 1;
+    }
+
+    table
+}
+
+/// Feeds the bytes into a running CRC without the initial and final inversions, matching the
+/// `crc32c_le` helper ext4 checksums are defined with
+pub fn crc32c_update(crc: u32, bytes: &[u8]) -> u32 {
+    bytes.iter().fold(crc, |crc, byte| TABLE[((crc ^ *byte as u32) & 0xFF) as usize] ^ crc >> 8)
+}
+
+/// Standard CRC32C of the bytes
+pub fn crc32c(bytes: &[u8]) -> u32 {
+    !crc32c_update(!0, bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::utils::crc32c::{crc32c, crc32c_update};
+
+    #[test_case]
+    fn crc32c_matches_check_value() {
+        // WHEN
+        let crc = crc32c(b"123456789");
+
+        // THEN
+        assert_eq!(crc, 0xE306_9283);
+    }
+
+    #[test_case]
+    fn crc32c_matches_rfc3720_vectors() {
+        // GIVEN
+        let mut ascending = [0u8; 32];
+        ascending.iter_mut().enumerate().for_each(|(index, byte)| *byte = index as u8);
+        let descending: [u8; 32] = core::array::from_fn(|index| 31 - index as u8);
+
+        // THEN
+        assert_eq!(crc32c(&[0; 32]), 0x8A91_36AA);
+        assert_eq!(crc32c(&[0xFF; 32]), 0x62A8_AB43);
+        assert_eq!(crc32c(&ascending), 0x46DD_794E);
+        assert_eq!(crc32c(&descending), 0x113F_DB5C);
+    }
+
+    #[test_case]
+    fn crc32c_can_be_computed_in_pieces() {
+        // GIVEN
+        let bytes = b"toast ext2 metadata";
+
+        // WHEN
+        let crc = !crc32c_update(crc32c_update(!0, &bytes[..7]), &bytes[7..]);
+
+        // THEN
+        assert_eq!(crc, crc32c(bytes));
+    }
+}
@@ -2,6 +2,9 @@ use core::arch::asm;
 pub mod bitutils;
 pub mod tests;
 pub mod bitmap_btree;
+pub mod crc32c;
+pub mod ring_buffer;
+pub mod units;
 
 pub fn align_up(addr: usize, align: usize) -> usize {
     (addr + align - 1) & !(align - 1)
@@ -0,0 +1,273 @@
+use core::ops::Index;
+
+/// What a full ring buffer does with a new entry
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum OverflowPolicy {
+    /// The oldest entry is evicted to make room for the new one
+    Overwrite,
+    /// The new entry is rejected and the buffer is left unchanged
+    Drop,
+}
+
+/// Fixed capacity FIFO queue storing its entries inline, it never allocates
+#[derive(Debug, Clone)]
+pub struct RingBuffer<T, const N: usize> {
+    entries: [Option<T>; N],
+    /// Index in `entries` of the oldest entry
+    head: usize,
+    len: usize,
+    policy: OverflowPolicy,
+}
+
+impl<T, const N: usize> RingBuffer<T, N> {
+    pub const fn new(policy: OverflowPolicy) -> Self {
+        Self {
+            entries: [const { None }; N],
+            head: 0,
+            len: 0,
+            policy,
+        }
+    }
+
+    /// Appends an entry. When the buffer is full, returns the entry that was lost: the evicted
+    /// oldest entry with `Overwrite`, or the given one with `Drop`.
+    pub fn push(&mut self, value: T) -> Option<T> {
+        if N == 0 {
+            return Some(value);
+        }
+
+        if self.len < N {
+            self.entries[(self.head + self.len) % N] = Some(value);
+            self.len += 1;
+            return None;
+        }
+
+        match self.policy {
+            OverflowPolicy::Drop => Some(value),
+            OverflowPolicy::Overwrite => {
+                let evicted = self.entries[self.head].replace(value);
+                self.head = (self.head + 1) % N;
+                evicted
+            },
+        }
+    }
+
+    /// Removes and returns the oldest entry
+    pub fn pop(&mut self) -> Option<T> {
+        if self.len == 0 {
+            return None;
+        }
+
+        let value = self.entries[self.head].take();
+        self.head = (self.head + 1) % N;
+        self.len -= 1;
+
+        value
+    }
+
+    /// Entry at the given position, counted from the oldest one
+    pub fn get(&self, index: usize) -> Option<&T> {
+        if index >= self.len {
+            return None;
+        }
+
+        self.entries[(self.head + index) % N].as_ref()
+    }
+
+    /// Most recently pushed entry
+    pub fn last(&self) -> Option<&T> {
+        self.len.checked_sub(1).and_then(|index| self.get(index))
+    }
+
+    /// Iterates over the entries from the oldest to the newest
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = &T> + '_ {
+        (0..self.len).filter_map(|index| self.get(index))
+    }
+
+    pub fn clear(&mut self) {
+        while self.pop().is_some() {}
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.len == N
+    }
+
+    pub const fn capacity(&self) -> usize {
+        N
+    }
+}
+
+impl<T, const N: usize> Index<usize> for RingBuffer<T, N> {
+    type Output = T;
+
+    fn index(&self, index: usize) -> &T {
+        self.get(index).expect("ring buffer: index out of bounds")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec::Vec;
+    use crate::utils::ring_buffer::{OverflowPolicy, RingBuffer};
+
+    fn contents<const N: usize>(buffer: &RingBuffer<u32, N>) -> Vec<u32> {
+        buffer.iter().copied().collect()
+    }
+
+    #[test_case]
+    fn new_buffer_is_empty() {
+        // WHEN
+        let mut buffer = RingBuffer::<u32, 4>::new(OverflowPolicy::Overwrite);
+
+        // THEN
+        assert!(buffer.is_empty());
+        assert!(!buffer.is_full());
+        assert_eq!(buffer.len(), 0);
+        assert_eq!(buffer.capacity(), 4);
+        assert_eq!(buffer.last(), None);
+        assert_eq!(buffer.pop(), None);
+    }
+
+    #[test_case]
+    fn entries_are_popped_in_push_order() {
+        // GIVEN
+        let mut buffer = RingBuffer::<u32, 4>::new(OverflowPolicy::Drop);
+
+        // WHEN
+        let pushes: Vec<Option<u32>> = (1..=3).map(|value| buffer.push(value)).collect();
+
+        // THEN
+        assert_eq!(pushes, [None, None, None]);
+        assert_eq!(buffer.len(), 3);
+        assert_eq!(buffer.last(), Some(&3));
+        assert_eq!(buffer.pop(), Some(1));
+        assert_eq!(buffer.pop(), Some(2));
+        assert_eq!(buffer.pop(), Some(3));
+        assert_eq!(buffer.pop(), None);
+    }
+
+    #[test_case]
+    fn entries_wrap_around_the_end_of_the_storage() {
+        // GIVEN
+        let mut buffer = RingBuffer::<u32, 3>::new(OverflowPolicy::Drop);
+        buffer.push(1);
+        buffer.push(2);
+        buffer.pop();
+        buffer.pop();
+
+        // WHEN
+        buffer.push(3);
+        buffer.push(4);
+        buffer.push(5);
+
+        // THEN
+        assert!(buffer.is_full());
+        assert_eq!(contents(&buffer), [3, 4, 5]);
+        assert_eq!(buffer[0], 3);
+        assert_eq!(buffer[2], 5);
+        assert_eq!(buffer.get(3), None);
+        assert_eq!(buffer.iter().rev().copied().collect::<Vec<u32>>(), [5, 4, 3]);
+    }
+
+    #[test_case]
+    fn full_buffer_with_overwrite_policy_evicts_oldest_entry() {
+        // GIVEN
+        let mut buffer = RingBuffer::<u32, 3>::new(OverflowPolicy::Overwrite);
+        (1..=3).for_each(|value| { buffer.push(value); });
+
+        // WHEN
+        let first_eviction = buffer.push(4);
+        let second_eviction = buffer.push(5);
+
+        // THEN
+        assert_eq!(first_eviction, Some(1));
+        assert_eq!(second_eviction, Some(2));
+        assert_eq!(buffer.len(), 3);
+        assert_eq!(contents(&buffer), [3, 4, 5]);
+    }
+
+    #[test_case]
+    fn full_buffer_with_drop_policy_rejects_new_entry() {
+        // GIVEN
+        let mut buffer = RingBuffer::<u32, 3>::new(OverflowPolicy::Drop);
+        (1..=3).for_each(|value| { buffer.push(value); });
+
+        // WHEN
+        let rejected = buffer.push(4);
+
+        // THEN
+        assert_eq!(rejected, Some(4));
+        assert_eq!(contents(&buffer), [1, 2, 3]);
+    }
+
+    #[test_case]
+    fn overwriting_many_times_keeps_most_recent_entries() {
+        // GIVEN
+        let mut buffer = RingBuffer::<u32, 4>::new(OverflowPolicy::Overwrite);
+
+        // WHEN
+        let evicted: Vec<u32> = (0..100).filter_map(|value| buffer.push(value)).collect();
+
+        // THEN
+        assert_eq!(evicted, (0..96).collect::<Vec<u32>>());
+        assert_eq!(contents(&buffer), [96, 97, 98, 99]);
+    }
+
+    #[test_case]
+    fn popping_from_full_buffer_makes_room() {
+        // GIVEN
+        let mut buffer = RingBuffer::<u32, 2>::new(OverflowPolicy::Drop);
+        buffer.push(1);
+        buffer.push(2);
+
+        // WHEN
+        let popped = buffer.pop();
+        let pushed = buffer.push(3);
+
+        // THEN
+        assert_eq!(popped, Some(1));
+        assert_eq!(pushed, None);
+        assert_eq!(contents(&buffer), [2, 3]);
+    }
+
+    #[test_case]
+    fn clear_empties_buffer() {
+        // GIVEN
+        let mut buffer = RingBuffer::<u32, 3>::new(OverflowPolicy::Overwrite);
+        (1..=5).for_each(|value| { buffer.push(value); });
+
+        // WHEN
+        buffer.clear();
+
+        // THEN
+        assert!(buffer.is_empty());
+        assert_eq!(buffer.iter().count(), 0);
+        assert_eq!(buffer.push(6), None);
+        assert_eq!(contents(&buffer), [6]);
+    }
+
+    #[test_case]
+    fn zero_capacity_buffer_never_stores_entries() {
+        // GIVEN
+        let mut overwriting = RingBuffer::<u32, 0>::new(OverflowPolicy::Overwrite);
+        let mut dropping = RingBuffer::<u32, 0>::new(OverflowPolicy::Drop);
+
+        // WHEN
+        let overwritten = overwriting.push(1);
+        let dropped = dropping.push(2);
+
+        // THEN
+        assert_eq!(overwritten, Some(1));
+        assert_eq!(dropped, Some(2));
+        assert!(overwriting.is_empty() && overwriting.is_full());
+        assert_eq!(dropping.pop(), None);
+    }
+}
@@ -1,5 +1,10 @@
 use crate::arch::x86_64::port_manager::Port;
 use crate::arch::x86_64::port_manager::ReadWriteStatus::WriteOnly;
+use crate::serial::{loopback_self_test, serial_hexdump};
+use core::fmt;
+use core::fmt::{Display, Formatter};
+use core::panic::PanicInfo;
+use spin::Mutex;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(u32)]
@@ -13,13 +18,129 @@ pub fn exit_qemu(exit_code: QemuExitCode) {
     port.write(exit_code as u32).unwrap();
 }
 
+/// Test being run, reported as failed by the panic handler
+static CURRENT_TEST: Mutex<Option<(usize, &'static str)>> = Mutex::new(None);
+
 pub trait Testable {
+    fn name(&self) -> &'static str;
+
     fn run(&self);
 }
 impl<T> Testable for T where T: Fn() {
+    fn name(&self) -> &'static str {
+        core::any::type_name::<T>()
+    }
+
     fn run(&self) {
-        serial_print!("Running {}...\t", core::any::type_name::<T>());
         self();
-        serial_println!("[ok]");
     }
-}
\ No newline at end of file
+}
+
+/// Line of a TAP (Test Anything Protocol) report describing the outcome of one test
+pub struct TapResult {
+    pub number: usize,
+    pub name: &'static str,
+    pub passed: bool,
+}
+
+impl Display for TapResult {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        if !self.passed {
+            write!(f, "not ")?;
+        }
+
+        write!(f, "ok {} - {}", self.number, self.name)
+    }
+}
+
+/// Runs the tests, reporting their results over serial in the TAP format
+pub fn run_tests(tests: &[&dyn Testable]) {
+    // Every result goes through serial, so stop right away rather than lose them silently
+    if loopback_self_test().is_err() {
+        exit_qemu(QemuExitCode::Failure);
+        return;
+    }
+
+    serial_println!("TAP version 13");
+    serial_println!("1..{}", tests.len());
+
+    for (index, test) in tests.iter().enumerate() {
+        let number = index + 1;
+        *CURRENT_TEST.lock() = Some((number, test.name()));
+
+        test.run();
+
+        *CURRENT_TEST.lock() = None;
+        serial_println!("{}", TapResult { number, name: test.name(), passed: true });
+    }
+}
+
+/// Reports the test being run as failed, with the panic message as a TAP diagnostic
+pub fn report_test_failure(info: &PanicInfo) {
+    // The panic may have happened while the lock was held
+    if let Some(Some((number, name))) = CURRENT_TEST.try_lock().map(|current_test| *current_test) {
+        serial_println!("{}", TapResult { number, name, passed: false });
+    }
+
+    serial_println!("# {}", info);
+}
+
+/// Asserts that two buffers are equal. On mismatch, both buffers are dumped to the serial port
+/// before panicking so they can be compared from the test output.
+#[track_caller]
+pub fn assert_buffers_eq(left: &[u8], right: &[u8]) {
+    if left == right {
+        return;
+    }
+
+    serial_println!("\nleft ({} bytes):", left.len());
+    serial_hexdump(left);
+    serial_println!("right ({} bytes):", right.len());
+    serial_hexdump(right);
+
+    match left.iter().zip(right).position(|(left, right)| left != right) {
+        Some(offset) => panic!("buffers differ at offset 0x{:X}", offset),
+        None => panic!("buffers differ in length ({} != {})", left.len(), right.len()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::format;
+    use crate::utils::tests::{TapResult, Testable};
+
+    fn sample_test() {}
+
+    #[test_case]
+    fn passed_test_is_reported_as_ok() {
+        // GIVEN
+        let result = TapResult { number: 1, name: "toast::memory::tests::sample", passed: true };
+
+        // WHEN
+        let line = format!("{}", result);
+
+        // THEN
+        assert_eq!(line, "ok 1 - toast::memory::tests::sample");
+    }
+
+    #[test_case]
+    fn failed_test_is_reported_as_not_ok() {
+        // GIVEN
+        let result = TapResult { number: 2, name: "toast::memory::tests::sample", passed: false };
+
+        // WHEN
+        let line = format!("{}", result);
+
+        // THEN
+        assert_eq!(line, "not ok 2 - toast::memory::tests::sample");
+    }
+
+    #[test_case]
+    fn test_name_is_its_path() {
+        // WHEN
+        let name = sample_test.name();
+
+        // THEN
+        assert_eq!(name, "toast::utils::tests::tests::sample_test");
+    }
+}
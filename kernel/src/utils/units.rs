@@ -0,0 +1,77 @@
+use alloc::format;
+use alloc::string::String;
+
+/// Binary units sizes are shown in, from the smallest
+const UNITS: [(&str, u128); 4] = [("KiB", 1 << 10), ("MiB", 1 << 20), ("GiB", 1 << 30), ("TiB", 1 << 40)];
+
+/// Formats a byte count in the largest binary unit it reaches, with one decimal place. Counts
+/// below 1KiB are shown in bytes. Only integer math is used, the kernel does not touch the FPU.
+pub fn format_bytes(byte_count: usize) -> String {
+    let byte_count = byte_count as u128;
+    let Some(mut unit_index) = UNITS.iter().rposition(|(_, unit_size)| byte_count >= *unit_size) else {
+        return format!("{} B", byte_count);
+    };
+
+    let rounded_tenths = |unit_size: u128| (byte_count * 10 + unit_size / 2) / unit_size;
+
+    // Rounding up can reach the next unit, 1023.96KiB is shown as 1.0MiB rather than 1024.0KiB
+    if unit_index + 1 < UNITS.len() && rounded_tenths(UNITS[unit_index].1) >= 1024 * 10 {
+        unit_index += 1;
+    }
+
+    let (unit_name, unit_size) = UNITS[unit_index];
+    let tenths = rounded_tenths(unit_size);
+
+    format!("{}.{} {}", tenths / 10, tenths % 10, unit_name)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::utils::units::format_bytes;
+
+    #[test_case]
+    fn counts_below_one_kib_are_shown_in_bytes() {
+        // WHEN
+        let formatted = [0, 1, 1023].map(format_bytes);
+
+        // THEN
+        assert_eq!(formatted, ["0 B", "1 B", "1023 B"]);
+    }
+
+    #[test_case]
+    fn counts_are_shown_in_largest_unit_reached() {
+        // WHEN
+        let formatted = [1024, 1536, 1048576, 1000 * 1024, 5 * (1 << 30) + (1 << 29), 3 << 40].map(format_bytes);
+
+        // THEN
+        assert_eq!(formatted, ["1.0 KiB", "1.5 KiB", "1.0 MiB", "1000.0 KiB", "5.5 GiB", "3.0 TiB"]);
+    }
+
+    #[test_case]
+    fn decimal_place_is_rounded_to_nearest() {
+        // WHEN
+        // 1.04KiB, 1.05KiB and 1.96KiB
+        let formatted = [1065, 1076, 2007].map(format_bytes);
+
+        // THEN
+        assert_eq!(formatted, ["1.0 KiB", "1.1 KiB", "2.0 KiB"]);
+    }
+
+    #[test_case]
+    fn rounding_up_moves_to_next_unit() {
+        // WHEN
+        let formatted = [1048575, (1 << 30) - 1].map(format_bytes);
+
+        // THEN
+        assert_eq!(formatted, ["1.0 MiB", "1.0 GiB"]);
+    }
+
+    #[test_case]
+    fn largest_unit_is_not_exceeded() {
+        // WHEN
+        let formatted = format_bytes(usize::MAX);
+
+        // THEN
+        assert_eq!(formatted, "16777216.0 TiB");
+    }
+}
@@ -0,0 +1,61 @@
+use alloc::format;
+use alloc::string::String;
+
+/// Kernel release, following semantic versioning
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+/// Target triple the kernel was built for
+pub const BUILD_TARGET: &str = env!("TOAST_BUILD_TARGET");
+/// Abbreviated hash of the commit the kernel was built from, if git was available at build time
+pub const GIT_HASH: Option<&str> = option_env!("TOAST_GIT_HASH");
+/// Unix time at which the kernel was built
+pub const BUILD_TIMESTAMP: Option<&str> = option_env!("TOAST_BUILD_TIMESTAMP");
+
+/// Version string shown in the boot banner, e.g. `v0.1.0-x86_64`
+pub fn version_string() -> String {
+    let architecture = BUILD_TARGET.split('-').next().unwrap_or(BUILD_TARGET);
+
+    format!("v{}-{}", VERSION, architecture)
+}
+
+/// Full build description in the style of `uname -a`
+pub fn uname() -> String {
+    let mut description = format!("Toast {} {}", version_string(), BUILD_TARGET);
+
+    if let Some(git_hash) = GIT_HASH {
+        description.push_str(&format!(" git:{}", git_hash));
+    }
+    if let Some(build_timestamp) = BUILD_TIMESTAMP {
+        description.push_str(&format!(" built:{}", build_timestamp));
+    }
+
+    description
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec::Vec;
+    use crate::version::{uname, version_string};
+
+    #[test_case]
+    fn version_string_is_well_formed() {
+        // WHEN
+        let version = version_string();
+
+        // THEN
+        let (release, architecture) = version.strip_prefix('v').unwrap().split_once('-').unwrap();
+        let release_parts: Vec<&str> = release.split('.').collect();
+        assert_eq!(release_parts.len(), 3);
+        assert!(release_parts.iter().all(|part| !part.is_empty() && part.chars().all(|c| c.is_ascii_digit())));
+        assert_eq!(architecture, "x86_64");
+    }
+
+    #[test_case]
+    fn uname_starts_with_kernel_name_and_version() {
+        // WHEN
+        let description = uname();
+
+        // THEN
+        assert!(description.starts_with("Toast v"));
+        assert!(description.contains(&version_string()));
+    }
+}